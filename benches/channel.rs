@@ -0,0 +1,52 @@
+#![feature(test)]
+
+extern crate mithril;
+extern crate test;
+
+use mithril::channel::unbounded;
+use std::thread;
+use test::Bencher;
+
+/// messages per iteration, split over the sending threads
+const MESSAGES: u64 = 100_000;
+
+/// Sends `MESSAGES` u64 values from `threads` senders to one receiver.
+/// Compare `cargo bench --bench channel` with `cargo bench --bench channel --features flume-channels`.
+fn send_messages(threads: u64) {
+    let (sndr, rcvr) = unbounded();
+    let hnds: Vec<_> = (0..threads)
+        .map(|_| {
+            let sndr = sndr.clone();
+            thread::spawn(move || {
+                for i in 0..MESSAGES / threads {
+                    sndr.send(i).unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(sndr);
+
+    let mut received = 0;
+    while rcvr.recv().is_ok() {
+        received += 1;
+    }
+    assert_eq!(received, MESSAGES / threads * threads);
+    for hnd in hnds {
+        hnd.join().unwrap();
+    }
+}
+
+#[bench]
+fn bench_channel_1_thread(b: &mut Bencher) {
+    b.iter(|| send_messages(1));
+}
+
+#[bench]
+fn bench_channel_8_threads(b: &mut Bencher) {
+    b.iter(|| send_messages(8));
+}
+
+#[bench]
+fn bench_channel_64_threads(b: &mut Bencher) {
+    b.iter(|| send_messages(64));
+}
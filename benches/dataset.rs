@@ -0,0 +1,75 @@
+#![feature(test)]
+
+extern crate mithril;
+extern crate test;
+
+use mithril::randomx::memory::{CacheMode, CacheStats, SeedMemory, VmMemory, CACHE_LINE_SIZE};
+use std::sync::RwLock;
+use test::Bencher;
+
+/// dataset items of the benchmark memory (the real dataset has ~34M)
+const ITEMS: usize = 1 << 14;
+/// dataset reads per iteration, as many as one VM program run does
+const READS: usize = 2048;
+
+/// Reads random items like the VM does: the next item is prefetched with `lookahead`,
+/// then the current one is read. Prints the cache hit rate (items initialised by the
+/// prefetch count as misses) and how much of the dataset was initialised
+/// (`cargo bench --bench dataset -- --nocapture`).
+fn prefetch_reads(b: &mut Bencher, lookahead: usize) {
+    let mem = VmMemory {
+        seed_memory: SeedMemory::new_initialised(b"dataset bench key"),
+        dataset_memory: RwLock::new(vec![None; ITEMS]),
+        mode: CacheMode::FullLazy,
+    };
+    //xorshift, fixed seed for reproducible runs
+    let mut x: u64 = 0x2545f4914f6cdd1d;
+    let mut next_offset = move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        (x % ITEMS as u64) * CACHE_LINE_SIZE
+    };
+    let mut reg = [0; 8];
+    let mut stats = CacheStats::default();
+
+    b.iter(|| {
+        let mut offset = next_offset();
+        for _ in 0..READS {
+            let next = next_offset();
+            mem.dataset_prefetch(next, lookahead, &mut stats);
+            mem.dataset_read(offset, &mut reg, &mut stats);
+            offset = next;
+        }
+    });
+
+    let initialised = mem
+        .dataset_memory
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|item| item.is_some())
+        .count();
+    println!(
+        "lookahead {}: cache hit rate {:.2}%, {} of {} items initialised",
+        lookahead,
+        stats.hit_rate() * 100.0,
+        initialised,
+        ITEMS
+    );
+}
+
+#[bench]
+fn bench_dataset_read_no_prefetch(b: &mut Bencher) {
+    prefetch_reads(b, 0);
+}
+
+#[bench]
+fn bench_dataset_read_prefetch_1(b: &mut Bencher) {
+    prefetch_reads(b, 1);
+}
+
+#[bench]
+fn bench_dataset_read_prefetch_8(b: &mut Bencher) {
+    prefetch_reads(b, 8);
+}
@@ -4,8 +4,10 @@ extern crate mithril;
 extern crate test;
 
 use mithril::byte_string;
+use mithril::randomx::hash::{fill_aes_1rx4_u64, gen_program_aes_4rx4, hash_aes_1rx4_scalar};
+use mithril::randomx::m128::m128i;
 use mithril::randomx::memory::VmMemory;
-use mithril::randomx::vm::new_vm;
+use mithril::randomx::vm::{new_vm, SCRATCHPAD_SIZE};
 use std::sync::Arc;
 use test::Bencher;
 
@@ -40,3 +42,21 @@ fn bench_hash_full_memory(b: &mut Bencher) {
         vm.calculate_hash(&input3);
     });
 }
+
+/// The AES rounds of a hash: filling and hashing the scratchpad and generating
+/// the programs
+#[bench]
+fn bench_aes_rounds(b: &mut Bencher) {
+    let seed = [
+        m128i::from_u64(0x0123456789abcdef, 0xfedcba9876543210),
+        m128i::from_u64(0x1111111111111111, 0x2222222222222222),
+        m128i::from_u64(0x3333333333333333, 0x4444444444444444),
+        m128i::from_u64(0x5555555555555555, 0x6666666666666666),
+    ];
+    let mut scratchpad = vec![0; SCRATCHPAD_SIZE];
+    b.iter(|| {
+        fill_aes_1rx4_u64(&seed, &mut scratchpad);
+        let program = gen_program_aes_4rx4(&seed, 136);
+        (hash_aes_1rx4_scalar(&scratchpad), program)
+    });
+}
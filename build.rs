@@ -0,0 +1,63 @@
+use std::env;
+use std::fs;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// default for mithril_config::CONFIG_FILE_NAME, crates embedding mithril can
+// choose a different file name with MITHRIL_CONFIG_FILE_NAME at compile time
+const DEFAULT_CONFIG_FILE_NAME: &str = "default_config.toml";
+
+fn main() {
+    let file_name = env::var("MITHRIL_CONFIG_FILE_NAME")
+        .unwrap_or_else(|_| DEFAULT_CONFIG_FILE_NAME.to_string());
+    println!(
+        "cargo:rustc-env=MITHRIL_CONFIG_FILE_NAME_DEFAULT={}",
+        file_name
+    );
+    println!("cargo:rerun-if-env-changed=MITHRIL_CONFIG_FILE_NAME");
+
+    // build info for build_info::print_build_info
+    println!("cargo:rustc-env=MITHRIL_GIT_HASH={}", git_hash());
+    println!(
+        "cargo:rustc-env=MITHRIL_BUILD_TARGET={}",
+        env::var("TARGET").unwrap_or_else(|_| "unknown".to_string())
+    );
+    println!("cargo:rustc-env=MITHRIL_BUILD_DATE={}", build_date());
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    if let Ok(head) = fs::read_to_string(".git/HEAD") {
+        if let Some(reference) = head.trim().strip_prefix("ref: ") {
+            println!("cargo:rerun-if-changed=.git/{}", reference);
+        }
+    }
+}
+
+// short hash of the checked out commit, "unknown" outside of a git checkout
+fn git_hash() -> String {
+    Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+// UTC date (yyyy-mm-dd) of the build script run
+fn build_date() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    // days since 1970-01-01 to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -1,17 +1,20 @@
 extern crate bandit;
 extern crate dirs;
 extern crate num_cpus;
+extern crate serde_json;
 
-use std::fs::DirBuilder;
+use std::collections::HashMap;
+use std::fs::{self, DirBuilder};
+use std::hash::Hash;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use self::bandit::softmax::{AnnealingSoftmax, AnnealingSoftmaxConfig};
-use self::bandit::{BanditConfig, Identifiable};
+use self::bandit::{BanditConfig, Identifiable, MultiArmedBandit};
 
 const MAX_THREADS_PER_CPU: usize = 4;
 
-#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug)]
+#[derive(Hash, PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ThreadArm {
     pub num_threads: u64,
 }
@@ -22,7 +25,44 @@ impl Identifiable for ThreadArm {
     }
 }
 
-pub fn setup_bandit(log_file: String) -> AnnealingSoftmax<ThreadArm> {
+/// How `setup_bandit` selects the thread count (`worker.bandit_strategy`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BanditStrategy {
+    #[default]
+    AnnealingSoftmax,
+    /// `UcbBandit`, keeps exploring so it follows hashrates that drift over time
+    /// (e.g. thermal throttling)
+    Ucb1,
+}
+
+impl BanditStrategy {
+    /// Parses the config value, `annealing_softmax` or `ucb1`
+    pub fn from_name(name: &str) -> Option<BanditStrategy> {
+        match name {
+            "annealing_softmax" => Some(BanditStrategy::AnnealingSoftmax),
+            "ucb1" => Some(BanditStrategy::Ucb1),
+            _ => None,
+        }
+    }
+
+    /// Every strategy has its own state file, the states are not compatible
+    pub fn state_file(&self) -> PathBuf {
+        match self {
+            BanditStrategy::AnnealingSoftmax => state_file(),
+            BanditStrategy::Ucb1 => {
+                let mut state_file = mithril_folder();
+                state_file.push("ucb_bandit_state.json");
+                state_file
+            }
+        }
+    }
+}
+
+pub fn setup_bandit(
+    log_file: String,
+    strategy: BanditStrategy,
+) -> Box<dyn MultiArmedBandit<ThreadArm>> {
     let num_arms = num_cpus::get() * MAX_THREADS_PER_CPU;
     let mut arms = Vec::with_capacity(num_arms);
     for i in 1..num_arms {
@@ -31,6 +71,34 @@ pub fn setup_bandit(log_file: String) -> AnnealingSoftmax<ThreadArm> {
         })
     }
 
+    if strategy == BanditStrategy::Ucb1 {
+        return Box::new(setup_ucb_bandit(arms));
+    }
+    Box::new(setup_softmax_bandit(arms, log_file))
+}
+
+fn setup_ucb_bandit(arms: Vec<ThreadArm>) -> UcbBandit<ThreadArm> {
+    let state_file = BanditStrategy::Ucb1.state_file();
+    if !state_file.exists() {
+        info!("no UCB1 bandit state file found, using new bandit");
+        return UcbBandit::new(arms);
+    }
+    match UcbBandit::load_bandit(arms.clone(), &state_file) {
+        Ok(bandit) => {
+            info!("continuing with loaded UCB1 bandit state");
+            bandit
+        }
+        Err(err) => {
+            error!(
+                "loading UCB1 bandit state failed, using new bandit. error {:?}",
+                err
+            );
+            UcbBandit::new(arms)
+        }
+    }
+}
+
+fn setup_softmax_bandit(arms: Vec<ThreadArm>, log_file: String) -> AnnealingSoftmax<ThreadArm> {
     let state_file = state_file();
 
     let bandit_config = BanditConfig {
@@ -60,6 +128,122 @@ pub fn setup_bandit(log_file: String) -> AnnealingSoftmax<ThreadArm> {
     }
 }
 
+/// Pull count and reward sum of an arm
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct UcbArmStats {
+    pub pulls: u64,
+    pub reward_sum: f64,
+}
+
+/// UCB1 bandit: every arm is tried once, afterwards the arm with the highest
+/// `mean reward + sqrt(2 ln(total pulls) / arm pulls)` is selected.
+/// UCB1 assumes rewards in [0, 1], the means are divided by the highest reward seen
+/// so far so that hashrates of any size work. Unlike `AnnealingSoftmax` it does not
+/// write the auto tune log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UcbBandit<A> {
+    arms: Vec<A>,
+    stats: Vec<UcbArmStats>,
+    max_reward: f64,
+}
+
+/// Saved state of a `UcbBandit`, the stats by `Identifiable::ident` of the arm
+#[derive(Serialize, Deserialize)]
+struct UcbState {
+    stats: HashMap<String, UcbArmStats>,
+    max_reward: f64,
+}
+
+impl<A: Clone + Hash + Eq + Identifiable> UcbBandit<A> {
+    pub fn new(arms: Vec<A>) -> UcbBandit<A> {
+        let stats = vec![UcbArmStats::default(); arms.len()];
+        UcbBandit {
+            arms,
+            stats,
+            max_reward: 0.0,
+        }
+    }
+
+    /// Loads the state saved by `save_bandit`, arms without saved stats start untried
+    pub fn load_bandit(arms: Vec<A>, path: &Path) -> io::Result<UcbBandit<A>> {
+        let json = fs::read_to_string(path)?;
+        let state: UcbState = serde_json::from_str(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut bandit = UcbBandit::new(arms);
+        for (arm, stats) in bandit.arms.iter().zip(bandit.stats.iter_mut()) {
+            if let Some(saved) = state.stats.get(&arm.ident()) {
+                *stats = *saved;
+            }
+        }
+        bandit.max_reward = state.max_reward;
+        Ok(bandit)
+    }
+
+    /// Stats of every arm, in the order of the arms passed to `new`
+    pub fn arm_stats(&self) -> Vec<(A, UcbArmStats)> {
+        self.arms
+            .iter()
+            .cloned()
+            .zip(self.stats.iter().cloned())
+            .collect()
+    }
+
+    fn upper_confidence_bound(&self, stats: &UcbArmStats, total_pulls: u64) -> f64 {
+        let mean = stats.reward_sum / stats.pulls as f64;
+        let scaled_mean = if self.max_reward > 0.0 {
+            mean / self.max_reward
+        } else {
+            mean
+        };
+        scaled_mean + (2.0 * (total_pulls as f64).ln() / stats.pulls as f64).sqrt()
+    }
+}
+
+impl<A: Clone + Hash + Eq + Identifiable> MultiArmedBandit<A> for UcbBandit<A> {
+    fn select_arm(&self) -> A {
+        if let Some(untried) = self.stats.iter().position(|stats| stats.pulls == 0) {
+            return self.arms[untried].clone();
+        }
+        let total_pulls: u64 = self.stats.iter().map(|stats| stats.pulls).sum();
+        let mut best = 0;
+        let mut best_bound = f64::MIN;
+        for (i, stats) in self.stats.iter().enumerate() {
+            let bound = self.upper_confidence_bound(stats, total_pulls);
+            if bound > best_bound {
+                best = i;
+                best_bound = bound;
+            }
+        }
+        self.arms[best].clone()
+    }
+
+    fn update(&mut self, arm: A, reward: f64) {
+        match self.arms.iter().position(|a| *a == arm) {
+            Some(i) => {
+                self.stats[i].pulls += 1;
+                self.stats[i].reward_sum += reward;
+                self.max_reward = self.max_reward.max(reward);
+            }
+            None => error!("reward {} for unknown arm {}", reward, arm.ident()),
+        }
+    }
+
+    fn save_bandit(&self, path: &Path) -> io::Result<()> {
+        let state = UcbState {
+            stats: self
+                .arms
+                .iter()
+                .map(|arm| arm.ident())
+                .zip(self.stats.iter().cloned())
+                .collect(),
+            max_reward: self.max_reward,
+        };
+        let json = serde_json::to_string(&state)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, json)
+    }
+}
+
 pub fn ensure_mithril_folder_exists() -> io::Result<()> {
     let folder = mithril_folder();
     DirBuilder::new().recursive(true).create(folder)
@@ -0,0 +1,20 @@
+//! Version and build details for bug reports, set by `build.rs` at compile time.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// short hash of the built commit, "unknown" if not built from a git checkout
+pub const GIT_HASH: &str = env!("MITHRIL_GIT_HASH");
+pub const TARGET: &str = env!("MITHRIL_BUILD_TARGET");
+/// UTC date (yyyy-mm-dd) the build script last ran
+pub const BUILD_DATE: &str = env!("MITHRIL_BUILD_DATE");
+
+/// e.g. `mithril v0.1.0 (abc1234) target=x86_64-unknown-linux-gnu built 2024-01-01`
+pub fn build_info() -> String {
+    format!(
+        "mithril v{} ({}) target={} built {}",
+        VERSION, GIT_HASH, TARGET, BUILD_DATE
+    )
+}
+
+pub fn print_build_info() {
+    info!("{}", build_info());
+}
@@ -1,3 +1,54 @@
+extern crate subtle;
+
+use self::subtle::{Choice, ConstantTimeEq, ConstantTimeLess};
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum HexError {
+    OddLength(usize),
+    InvalidChar { index: usize, c: char },
+    TooShort { len: usize, min: usize },
+}
+
+impl fmt::Display for HexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HexError::OddLength(len) => write!(f, "hex string has odd length {}", len),
+            HexError::InvalidChar { index, c } => {
+                write!(f, "invalid hex char {:?} at index {}", c, index)
+            }
+            HexError::TooShort { len, min } => {
+                write!(
+                    f,
+                    "hex string has length {}, expected at least {}",
+                    len, min
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for HexError {}
+
+/// Like `string_to_u8_array`, but rejects odd length strings and non hex chars
+/// instead of returning the bytes decoded up to the problem.
+pub fn hex_to_bytes(hex: &str) -> Result<Vec<u8>, HexError> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(HexError::OddLength(hex.len()));
+    }
+    let mut bytes = Vec::with_capacity(hex.len() / 2);
+    let mut high = 0;
+    for (index, c) in hex.char_indices() {
+        let nibble = c.to_digit(16).ok_or(HexError::InvalidChar { index, c })? as u8;
+        if index.is_multiple_of(2) {
+            high = nibble << 4;
+        } else {
+            bytes.push(high | nibble);
+        }
+    }
+    Ok(bytes)
+}
+
 //TODO Rename to hex2_u8_array
 pub fn string_to_u8_array(hex: &str) -> Vec<u8> {
     let mut bytes = Vec::new();
@@ -53,3 +104,19 @@ pub fn u8_array_to_string(a: &[u8]) -> String {
 pub fn u128_to_string(u: u128) -> String {
     return format!("{:016x}", u);
 }
+
+/// Checks in constant time if `hash` meets `target`, i.e. `hash < target`
+/// with both interpreted as little-endian numbers (last byte is most significant).
+/// Slices of different length never match.
+pub fn ct_compare_hash(hash: &[u8], target: &[u8]) -> bool {
+    if hash.len() != target.len() {
+        return false;
+    }
+    let mut less = Choice::from(0);
+    let mut equal = Choice::from(1);
+    for (h, t) in hash.iter().zip(target.iter()).rev() {
+        less |= equal & h.ct_lt(t);
+        equal &= h.ct_eq(t);
+    }
+    bool::from(less)
+}
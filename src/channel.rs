@@ -0,0 +1,108 @@
+//! Channels between the miner threads. `crossbeam_channel` is used by default,
+//! with the `flume-channels` feature `flume` is used instead. The `select` helpers
+//! replace the `select!` macro that only exists in `crossbeam_channel`.
+
+#[cfg(not(feature = "flume-channels"))]
+use crossbeam_channel::select;
+#[cfg(not(feature = "flume-channels"))]
+pub use crossbeam_channel::{
+    bounded, unbounded, Receiver, RecvError, RecvTimeoutError, SendError, Sender, TrySendError,
+};
+#[cfg(feature = "flume-channels")]
+pub use flume::{
+    bounded, unbounded, Receiver, RecvError, RecvTimeoutError, SendError, Sender, TrySendError,
+};
+
+use std::time::Duration;
+
+/// Result of `select2`, the message (or error) of the receiver that was ready first
+pub enum Selected2<A, B> {
+    First(Result<A, RecvError>),
+    Second(Result<B, RecvError>),
+    Timeout,
+}
+
+/// Result of `select3`, the message (or error) of the receiver that was ready first
+pub enum Selected3<A, B, C> {
+    First(Result<A, RecvError>),
+    Second(Result<B, RecvError>),
+    Third(Result<C, RecvError>),
+    Timeout,
+}
+
+/// Waits until one of the receivers is ready or `timeout` passed (waits forever if `None`)
+#[cfg(not(feature = "flume-channels"))]
+pub fn select2<A, B>(
+    a: &Receiver<A>,
+    b: &Receiver<B>,
+    timeout: Option<Duration>,
+) -> Selected2<A, B> {
+    match timeout {
+        Some(timeout) => select! {
+            recv(a) -> msg => Selected2::First(msg),
+            recv(b) -> msg => Selected2::Second(msg),
+            default(timeout) => Selected2::Timeout,
+        },
+        None => select! {
+            recv(a) -> msg => Selected2::First(msg),
+            recv(b) -> msg => Selected2::Second(msg),
+        },
+    }
+}
+
+/// Waits until one of the receivers is ready or `timeout` passed (waits forever if `None`)
+#[cfg(feature = "flume-channels")]
+pub fn select2<A, B>(
+    a: &Receiver<A>,
+    b: &Receiver<B>,
+    timeout: Option<Duration>,
+) -> Selected2<A, B> {
+    let selector = flume::Selector::new()
+        .recv(a, Selected2::First)
+        .recv(b, Selected2::Second);
+    match timeout {
+        Some(timeout) => selector.wait_timeout(timeout).unwrap_or(Selected2::Timeout),
+        None => selector.wait(),
+    }
+}
+
+/// Waits until one of the receivers is ready or `timeout` passed (waits forever if `None`)
+#[cfg(not(feature = "flume-channels"))]
+pub fn select3<A, B, C>(
+    a: &Receiver<A>,
+    b: &Receiver<B>,
+    c: &Receiver<C>,
+    timeout: Option<Duration>,
+) -> Selected3<A, B, C> {
+    match timeout {
+        Some(timeout) => select! {
+            recv(a) -> msg => Selected3::First(msg),
+            recv(b) -> msg => Selected3::Second(msg),
+            recv(c) -> msg => Selected3::Third(msg),
+            default(timeout) => Selected3::Timeout,
+        },
+        None => select! {
+            recv(a) -> msg => Selected3::First(msg),
+            recv(b) -> msg => Selected3::Second(msg),
+            recv(c) -> msg => Selected3::Third(msg),
+        },
+    }
+}
+
+/// Waits until one of the receivers is ready or `timeout` passed (waits forever if `None`)
+#[cfg(feature = "flume-channels")]
+pub fn select3<A, B, C>(
+    a: &Receiver<A>,
+    b: &Receiver<B>,
+    c: &Receiver<C>,
+    timeout: Option<Duration>,
+) -> Selected3<A, B, C> {
+    let selector = flume::Selector::new()
+        .recv(a, Selected3::First)
+        .recv(b, Selected3::Second)
+        .recv(c, Selected3::Third);
+    match timeout {
+        Some(timeout) => selector.wait_timeout(timeout).unwrap_or(Selected3::Timeout),
+        None => selector.wait(),
+    }
+}
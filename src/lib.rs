@@ -7,38 +7,200 @@ extern crate hex;
 extern crate log;
 
 extern crate crossbeam_channel;
+#[cfg(feature = "flume-channels")]
+extern crate flume;
 #[macro_use]
 extern crate serde_derive;
 extern crate strum;
-
-use self::crossbeam_channel::{select, unbounded, Receiver};
+#[cfg(any(feature = "async-support", feature = "async-api"))]
+extern crate tokio;
+#[cfg(feature = "tracing-support")]
+extern crate tracing;
+#[cfg(feature = "websocket")]
+extern crate tungstenite;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
+
+#[cfg(feature = "async-api")]
+use std::fmt;
+#[cfg(feature = "async-api")]
+use std::future::Future;
 use std::io;
+#[cfg(feature = "async-api")]
+use std::pin::Pin;
 use std::sync::Once;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering},
+    Arc, Mutex,
 };
+#[cfg(feature = "async-api")]
+use std::task::{Context, Poll};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use std::time::SystemTime;
+#[cfg(feature = "async-api")]
+use tokio::sync::oneshot;
 
-use randomx::memory::VmMemoryAllocator;
-use stratum::{StratumAction, StratumClient};
+use channel::{select2, unbounded, Receiver, Selected2};
+use mithril_config::{MithrilConfig, MithrilConfigBuilder};
+use randomx::memory::{CacheMode, VmMemoryAllocator};
+use stratum::{StratumAction, StratumClient, StratumLoginResponse};
 use worker::worker_pool;
 use worker::worker_pool::WorkerPool;
 
+pub mod bandit_tools;
+pub mod build_info;
 pub mod byte_string;
+pub mod channel;
 pub mod metric;
 pub mod mithril_config;
 pub mod randomx;
+#[cfg(target_os = "linux")]
+pub mod sandbox;
 pub mod stratum;
 pub mod timer;
 pub mod worker;
 
 static INIT: Once = Once::new();
-static mut MINER_RUNNING: Option<Arc<AtomicBool>> = None;
-static mut MINER_THREAD: Option<thread::JoinHandle<()>> = None;
+/// The miner of `start_mining`, `stop_mining` and the `get_*` functions
+static mut DEFAULT_MINER: Option<MinerHandle> = None;
+
+/// values returned by `get_miner_status`
+const STATUS_STOPPED: i32 = 0;
+const STATUS_STARTING: i32 = 1;
+const STATUS_RUNNING: i32 = 2;
+const STATUS_RECONNECTING: i32 = 3;
+
+/// values returned by `start_mining`, `start_mining_with_json_config` and `start_miner`
+const START_ALREADY_RUNNING: i32 = 0;
+const START_SUCCESS: i32 = 1;
+const START_PARSE_ERROR: i32 = 2;
+const START_VALIDATION_ERROR: i32 = 3;
+const START_INVALID_HANDLE: i32 = 4;
+
+/// Running flag and statistics of one miner thread
+struct MinerState {
+    running: AtomicBool,
+    status: AtomicI32,
+    /// last measured hashrate in hashes/s, stored as `f64` bits
+    hashrate: AtomicU64,
+    /// hashes of all pool connections since the start
+    total_hashes: AtomicU64,
+    accepted_shares: AtomicU64,
+    rejected_shares: AtomicU64,
+}
+
+impl MinerState {
+    fn new() -> MinerState {
+        MinerState {
+            running: AtomicBool::new(false),
+            status: AtomicI32::new(STATUS_STOPPED),
+            hashrate: AtomicU64::new(0),
+            total_hashes: AtomicU64::new(0),
+            accepted_shares: AtomicU64::new(0),
+            rejected_shares: AtomicU64::new(0),
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+}
+
+/// One independent miner for the FFI, created with `create_miner` and freed with
+/// `destroy_miner`. Every handle has its own miner thread and pool connection.
+pub struct MinerHandle {
+    state: Arc<MinerState>,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl MinerHandle {
+    fn new() -> MinerHandle {
+        MinerHandle {
+            state: Arc::new(MinerState::new()),
+            thread: Mutex::new(None),
+        }
+    }
+
+    fn start<F>(&self, miner: F) -> i32
+    where
+        F: FnOnce(&MinerState) + Send + 'static,
+    {
+        let mut thread = self.thread.lock().unwrap();
+
+        // If already running, return
+        if !self.begin(&mut thread) {
+            return START_ALREADY_RUNNING;
+        }
+
+        // Start miner thread
+        let state = self.state.clone();
+        *thread = Some(thread::spawn(move || {
+            miner(&state);
+        }));
+
+        START_SUCCESS
+    }
+
+    /// Starts the miner thread with `config`, returns the codes of `start_miner`
+    pub fn start_with_config(&self, config: MithrilConfig) -> i32 {
+        self.start(move |miner| run_miner(config, miner))
+    }
+
+    /// 0 (stopped), 1 (starting), 2 (running) or 3 (reconnecting)
+    pub fn status(&self) -> i32 {
+        self.state.status.load(Ordering::Relaxed)
+    }
+
+    /// Shares accepted by the pool since the last start of this miner
+    pub fn accepted_shares(&self) -> u64 {
+        self.state.accepted_shares.load(Ordering::Relaxed)
+    }
+
+    /// Shares rejected by the pool since the last start of this miner
+    pub fn rejected_shares(&self) -> u64 {
+        self.state.rejected_shares.load(Ordering::Relaxed)
+    }
+
+    /// Marks the miner as starting and resets its statistics, false if it is already
+    /// running. `thread` is the locked `self.thread`.
+    fn begin(&self, thread: &mut Option<thread::JoinHandle<()>>) -> bool {
+        if self.state.is_running() {
+            return false;
+        }
+        // the thread of a miner that stopped itself (e.g. the sandbox failed)
+        if let Some(stopped) = thread.take() {
+            let _ = stopped.join();
+        }
+
+        // Set to running
+        self.state.running.store(true, Ordering::Relaxed);
+        self.state.status.store(STATUS_STARTING, Ordering::Relaxed);
+        self.state.total_hashes.store(0, Ordering::Relaxed);
+        self.state.accepted_shares.store(0, Ordering::Relaxed);
+        self.state.rejected_shares.store(0, Ordering::Relaxed);
+        true
+    }
+
+    /// Stops the miner and waits for its thread, false if it was not started
+    fn stop(&self) -> bool {
+        let mut thread = self.thread.lock().unwrap();
+        self.state.running.store(false, Ordering::Relaxed);
+
+        // Wait for thread to exit
+        let started = match thread.take() {
+            Some(thread) => {
+                let _ = thread.join();
+                true
+            }
+            None => false,
+        };
+        self.state.status.store(STATUS_STOPPED, Ordering::Relaxed);
+        self.state.hashrate.store(0, Ordering::Relaxed);
+        started
+    }
+}
 
 #[derive(Debug, PartialEq)]
 enum MainLoopExit {
@@ -55,15 +217,19 @@ fn start_main_event_loop(
     client_err_rcvr: &Receiver<std::io::Error>,
     stratum_rcvr: &Receiver<StratumAction>,
     metric: &metric::Metric,
-    running: &Arc<AtomicBool>,
+    cache_log_interval: Duration,
+    share_timeout: Duration,
+    miner: &MinerState,
 ) -> io::Result<MainLoopExit> {
     let mut last_time = Instant::now();
     let mut last_hash_count = 0;
+    let mut last_cache_log = Instant::now();
+    let mut last_share_warning: Option<Instant> = None;
     let mut last_hashrate_display = SystemTime::now();
     let hashrate_display_interval = Duration::from_millis(1000);
 
     loop {
-        if !running.load(Ordering::Relaxed) {
+        if !miner.is_running() {
             return Ok(MainLoopExit::Stop);
         }
 
@@ -80,12 +246,9 @@ fn start_main_event_loop(
             let elapsed_secs = current_time.duration_since(last_time).as_secs_f64();
 
             if elapsed_secs > 0.0 {
-                // Convert to kilo-hashes per second
-                let khs = (hash_diff as f64 / elapsed_secs) / 1000.0;
-                println!(
-                    "Hashrate: {:.2} kH/s ({} hashes in {:.1}s)",
-                    khs, hash_diff, elapsed_secs
-                );
+                let hashrate = hash_diff as f64 / elapsed_secs;
+                miner.hashrate.store(hashrate.to_bits(), Ordering::Relaxed);
+                println!("{}", metric.snapshot());
             }
 
             last_time = current_time;
@@ -93,9 +256,33 @@ fn start_main_event_loop(
             last_hashrate_display = now;
         }
 
+        if last_cache_log.elapsed() >= cache_log_interval {
+            let vm_memory = &pool.vm_memory_allocator.vm_memory;
+            if vm_memory.mode == CacheMode::FullLazy {
+                info!("dataset cache hit rate {:.2}%", pool.cache_hit_rate() * 100.0);
+            }
+            last_cache_log = Instant::now();
+        }
+
+        //warned at most once per share_timeout
+        let since_last_share = pool.time_since_last_share();
+        if since_last_share > share_timeout
+            && last_share_warning.is_none_or(|warned| warned.elapsed() >= share_timeout)
+        {
+            warn!(
+                "no share submitted for {} seconds, the difficulty may be too high or the pool stopped sending jobs",
+                since_last_share.as_secs()
+            );
+            last_share_warning = Some(Instant::now());
+        }
+
+        //switches to a dataset built by a warm swap as soon as it is ready
+        pool.poll_memory_swap();
+
         // Check if there's any message (with very short timeout)
-        select! {
-            recv(stratum_rcvr) -> stratum_msg => {
+        let timeout = Some(Duration::from_millis(100));
+        match select2(stratum_rcvr, client_err_rcvr, timeout) {
+            Selected2::First(stratum_msg) => {
                 if stratum_msg.is_err() {
                     return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "received error"));
                 }
@@ -107,99 +294,197 @@ fn start_main_event_loop(
                     StratumAction::Error{err} => {
                         println!("Received stratum error: {}", err);
                     },
+                    StratumAction::ShareRejected{err} => {
+                        miner.rejected_shares.fetch_add(1, Ordering::Relaxed);
+                        metric.record_rejected_share();
+                        println!("Share rejected: {}", err);
+                    },
                     StratumAction::Ok => {
+                        miner.accepted_shares.fetch_add(1, Ordering::Relaxed);
+                        metric.record_accepted_share();
                         println!("Received stratum ok");
                     },
                     StratumAction::KeepAliveOk => {
                         println!("Received keep alive ok");
                     }
+                    StratumAction::Unknown{method, ..} => {
+                        println!("Received unknown stratum method {}", method);
+                    }
+                    StratumAction::StateChange(state) => {
+                        println!("Stratum connection {:?}", state);
+                    }
                 }
             },
-            recv(client_err_rcvr) -> client_err_msg => {
+            Selected2::Second(client_err_msg) => {
                 return Err(io::Error::new(io::ErrorKind::Other, format!("error received {:?}", client_err_msg)));
             },
-            default(Duration::from_millis(100)) => {
+            Selected2::Timeout => {
                 // Timeout after 100ms to allow for hashrate display
             }
         }
     }
 }
 
-fn miner_thread_func(_config_path: &str, running: Arc<AtomicBool>) {
-    // Use hardcoded configuration
-    let pool_conf = stratum::stratum_data::PoolConfig {
-        pool_address: "xmrpool.eu:3333".to_string(),
-        wallet_address: "48y3RCT5SzSS4jumHm9rRL91eWWzd6xcVGSCF1KUZGWYJ6npqwFxHee4xkLLNUqY4NjiswdJhxFALeRqzncHoToeJMg2bhL".to_string(),
-        pool_password: "x".to_string(),
-    };
+/// Installs the seccomp filter on the miner thread (the workers it starts inherit it,
+/// the already running connection threads of the login don't), false if that failed
+#[cfg(target_os = "linux")]
+fn apply_sandbox() -> bool {
+    match sandbox::apply_seccomp_filter() {
+        Ok(()) => true,
+        Err(err) => {
+            println!("Enabling the seccomp sandbox failed {:?}", err);
+            false
+        }
+    }
+}
 
-    // Hardcoded worker config with 1 thread
-    let worker_conf = worker::worker_pool::WorkerConfig {
-        num_threads: 1,
-        auto_tune: false,
-        auto_tune_interval_minutes: 0,
-        auto_tune_log: "".to_string(),
-    };
+#[cfg(not(target_os = "linux"))]
+fn apply_sandbox() -> bool {
+    println!("The seccomp sandbox is only supported on linux");
+    false
+}
 
-    // Minimal metric config
-    let metric_conf = metric::MetricConfig {
-        enabled: true,
-        resolution: 100,
-        sample_interval_seconds: 60,
-        report_file: "/dev/null".to_string(),
-    };
+fn miner_thread_func(_config_path: &str, miner: &MinerState) {
+    // Use hardcoded configuration with 1 thread and minimal metrics
+    let config = MithrilConfigBuilder::new()
+        .pool_address("xmrpool.eu:3333")
+        .wallet("48y3RCT5SzSS4jumHm9rRL91eWWzd6xcVGSCF1KUZGWYJ6npqwFxHee4xkLLNUqY4NjiswdJhxFALeRqzncHoToeJMg2bhL")
+        .pool_password("x")
+        .num_threads(1)
+        .metric_enabled(true)
+        .metric_resolution(100)
+        .metric_sample_interval_seconds(60)
+        .metric_max_file_size_bytes(u64::MAX)
+        .build()
+        .expect("hardcoded miner config");
+    run_miner(config, miner);
+}
+
+fn run_miner(config: MithrilConfig, miner: &MinerState) {
+    let sandbox = config.sandbox;
+    let pool_conf = config.pool_conf;
+    let worker_conf = config.worker_conf;
+    let metric_conf = config.metric_conf;
 
+    let miner_start_time = Instant::now();
     let mut vm_memory_allocator = VmMemoryAllocator::initial();
 
-    while running.load(Ordering::Relaxed) {
-        // Stratum start
-        let (stratum_sndr, stratum_rcvr) = unbounded();
-        let (client_err_sndr, client_err_rcvr) = unbounded();
-
-        println!("Logging into stratum server: {}", pool_conf.pool_address);
-        let login_result = StratumClient::login(pool_conf.clone(), client_err_sndr, stratum_sndr);
-        if login_result.is_err() {
-            println!("Stratum login failed {:?}", login_result.err());
-            await_timeout();
-            continue;
+    // Stratum start, the channels are kept for all reconnects
+    let (stratum_sndr, stratum_rcvr) = unbounded();
+    let (client_err_sndr, client_err_rcvr) = unbounded();
+
+    println!("Logging into stratum server: {}", pool_conf.pool_address);
+    let mut client = loop {
+        match StratumClient::login(
+            pool_conf.clone(),
+            client_err_sndr.clone(),
+            stratum_sndr.clone(),
+        ) {
+            Ok(client) => break client,
+            Err(err) => {
+                println!("Stratum login failed {:?}", err);
+                miner.status.store(STATUS_RECONNECTING, Ordering::Relaxed);
+                await_timeout();
+                if !miner.is_running() {
+                    return;
+                }
+            }
         }
+    };
+    print_login_response(client.login_response());
+    // after the login, the sandbox allows no new connections
+    if sandbox && !apply_sandbox() {
+        client.stop();
+        stop_itself(miner);
+        return;
+    }
 
-        println!("Completed stratum login!");
-
-        let client = login_result.expect("stratum client");
+    while miner.is_running() {
         let share_sndr = client.new_cmd_channel();
 
-        let (metric_sndr, metric_rcvr) = unbounded();
-        let metric = metric::start(metric_conf.clone(), metric_rcvr);
+        let (metric_sndr, metric_rcvr) = metric::channel(&metric_conf);
+        let metric = metric::start_since(metric_conf.clone(), metric_rcvr, miner_start_time);
 
         // Start worker pool with single thread
-        let mut pool = worker_pool::start(
+        let mut pool = match worker_pool::start(
             worker_conf.num_threads,
             &share_sndr,
             metric_conf.resolution,
             &metric_sndr.clone(),
-            vm_memory_allocator,
-        );
+            vm_memory_allocator.clone(),
+        ) {
+            Ok(pool) => pool,
+            Err(err) => {
+                println!("Starting worker pool failed: {}", err);
+                miner.status.store(STATUS_RECONNECTING, Ordering::Relaxed);
+                metric.stop();
+                metric.join();
+                await_timeout();
+                continue;
+            }
+        };
 
+        pool.set_warm_swap(worker_conf.warm_swap);
+        miner.status.store(STATUS_RUNNING, Ordering::Relaxed);
         let term_result = start_main_event_loop(
             &mut pool,
             &client_err_rcvr,
             &stratum_rcvr,
             &metric,
-            &running,
+            Duration::from_secs(metric_conf.sample_interval_seconds),
+            Duration::from_secs(worker_conf.share_timeout_secs),
+            miner,
         );
 
+        if miner.is_running() {
+            //the restarted pool continues with the dataset of a running warm swap
+            pool.finish_memory_swap();
+        }
         vm_memory_allocator = pool.vm_memory_allocator.clone();
         pool.stop();
-        client.stop();
+        miner.hashrate.store(0, Ordering::Relaxed);
+        miner
+            .total_hashes
+            .fetch_add(metric.hash_count(), Ordering::Relaxed);
 
         match term_result {
+            Err(err) if sandbox => {
+                println!(
+                    "Error received, a sandboxed miner can't reconnect, stopping. Error: {}",
+                    err
+                );
+                stop_itself(miner);
+            }
             Err(err) => {
                 println!(
                     "Error received, restarting connection after 60 seconds. Error: {}",
                     err
                 );
+                miner.status.store(STATUS_RECONNECTING, Ordering::Relaxed);
                 await_timeout();
+                // jobs of the closed connection, the new login dispatches a current one
+                stratum_rcvr.try_iter().for_each(drop);
+                while miner.is_running() {
+                    match client.reconnect() {
+                        Ok(()) => {
+                            // errors of the closed connection
+                            client_err_rcvr.try_iter().for_each(drop);
+                            print_login_response(client.login_response());
+                            let queued = client.retry_share_count();
+                            if queued > 0 {
+                                println!(
+                                    "{} shares of the closed connection are re-submitted",
+                                    queued
+                                );
+                            }
+                            break;
+                        }
+                        Err(err) => {
+                            println!("Stratum login failed {:?}", err);
+                            await_timeout();
+                        }
+                    }
+                }
             }
             Ok(_) => {
                 println!("Main loop exit");
@@ -208,10 +493,23 @@ fn miner_thread_func(_config_path: &str, running: Arc<AtomicBool>) {
                 metric.join();
             }
         }
+    }
 
-        if !running.load(Ordering::Relaxed) {
-            break;
-        }
+    client.stop();
+}
+
+/// Marks the miner as stopped from its own thread, `begin` joins the thread
+fn stop_itself(miner: &MinerState) {
+    miner.running.store(false, Ordering::Relaxed);
+    miner.status.store(STATUS_STOPPED, Ordering::Relaxed);
+}
+
+/// The job of the login response is already queued on the stratum channel, the
+/// (re)started worker pool picks it up first
+fn print_login_response(login_response: &StratumLoginResponse) {
+    println!("Completed stratum login!");
+    if let Some(job) = &login_response.initial_job {
+        println!("Initial job {} (target {})", job.job_id, job.target);
     }
 }
 
@@ -222,9 +520,7 @@ pub extern "C" fn DllMain(_hinst: usize, reason: u32, _reserved: *mut usize) ->
         1 => {
             // DLL_PROCESS_ATTACH
             // Initialize if needed
-            INIT.call_once(|| unsafe {
-                MINER_RUNNING = Some(Arc::new(AtomicBool::new(false)));
-            });
+            default_miner();
         }
         0 => {
             // DLL_PROCESS_DETACH
@@ -241,58 +537,295 @@ pub extern "C" fn DllMain(_hinst: usize, reason: u32, _reserved: *mut usize) ->
 /// Initialize and start the miner
 #[no_mangle]
 pub extern "C" fn start_mining(config_path: *const i8) -> i32 {
-    unsafe {
-        if MINER_RUNNING.is_none() {
-            INIT.call_once(|| {
-                MINER_RUNNING = Some(Arc::new(AtomicBool::new(false)));
-            });
-        }
-
-        let running = MINER_RUNNING.as_ref().unwrap();
+    // Convert C string to Rust string
+    let config_path_str = if config_path.is_null() {
+        String::new()
+    } else {
+        let c_str = unsafe { std::ffi::CStr::from_ptr(config_path) };
+        c_str.to_string_lossy().into_owned()
+    };
 
-        // If already running, return
-        if running.load(Ordering::Relaxed) {
-            return 0;
-        }
+    default_miner().start(move |miner| miner_thread_func(&config_path_str, miner))
+}
 
-        // Set to running
-        running.store(true, Ordering::Relaxed);
-
-        // Convert C string to Rust string
-        let config_path_str = if config_path.is_null() {
-            String::new()
-        } else {
-            let c_str = std::ffi::CStr::from_ptr(config_path);
-            c_str.to_string_lossy().into_owned()
-        };
+/// Starts the miner with the `json_len` bytes of JSON at `json` (the fields of
+/// `MithrilConfigBuilder::from_json`) instead of a config file. Returns 0 (already
+/// running), 1 (success), 2 (the JSON could not be parsed) or 3 (the configuration is
+/// invalid, see `MithrilConfigBuilder::build`).
+///
+/// # Safety
+///
+/// `json` has to be null or point to `json_len` readable bytes, it is not used after
+/// the call returns.
+#[no_mangle]
+pub unsafe extern "C" fn start_mining_with_json_config(json: *const i8, json_len: usize) -> i32 {
+    if json.is_null() {
+        println!("No JSON config passed");
+        return START_PARSE_ERROR;
+    }
+    let bytes = std::slice::from_raw_parts(json as *const u8, json_len);
+    start_with_json_config(default_miner(), bytes)
+}
 
-        // Start miner thread
-        let running_clone = running.clone();
-        let thread = thread::spawn(move || {
-            miner_thread_func(&config_path_str, running_clone);
-        });
+fn start_with_json_config(handle: &MinerHandle, json: &[u8]) -> i32 {
+    let config = match json_config(json) {
+        Ok(config) => config,
+        Err(code) => return code,
+    };
+    handle.start_with_config(config)
+}
 
-        MINER_THREAD = Some(thread);
+/// The error is the return code of `start_mining_with_json_config`
+fn json_config(bytes: &[u8]) -> Result<MithrilConfig, i32> {
+    let json = std::str::from_utf8(bytes).map_err(|err| {
+        println!("JSON config is not valid UTF-8 {:?}", err);
+        START_PARSE_ERROR
+    })?;
+    let builder = MithrilConfigBuilder::from_json(json).map_err(|err| {
+        println!("Parsing JSON config failed: {}", err);
+        START_PARSE_ERROR
+    })?;
+    builder.build().map_err(|err| {
+        println!("Invalid JSON config: {}", err);
+        START_VALIDATION_ERROR
+    })
+}
 
-        1 // Success
+fn default_miner() -> &'static MinerHandle {
+    unsafe {
+        INIT.call_once(|| {
+            DEFAULT_MINER = Some(MinerHandle::new());
+        });
+        DEFAULT_MINER.as_ref().unwrap()
     }
 }
 
 /// Stop the miner
 #[no_mangle]
 pub extern "C" fn stop_mining() -> i32 {
-    unsafe {
-        if let Some(running) = MINER_RUNNING.as_ref() {
-            // Set to stopped
-            running.store(false, Ordering::Relaxed);
+    if !INIT.is_completed() {
+        return 0; // Not running
+    }
+    default_miner().stop();
+    1 // Success
+}
 
-            // Wait for thread to exit
-            if let Some(thread) = MINER_THREAD.take() {
-                let _ = thread.join();
-            }
+/// Current hashrate in hashes per second, 0 if the miner is not running
+#[no_mangle]
+pub extern "C" fn get_hashrate() -> f64 {
+    f64::from_bits(default_miner().state.hashrate.load(Ordering::Relaxed))
+}
 
-            return 1; // Success
+/// Shares accepted by the pool since the last `start_mining`
+#[no_mangle]
+pub extern "C" fn get_accepted_shares() -> u64 {
+    default_miner()
+        .state
+        .accepted_shares
+        .load(Ordering::Relaxed)
+}
+
+/// 0 (stopped), 1 (starting), 2 (running) or 3 (reconnecting)
+#[no_mangle]
+pub extern "C" fn get_miner_status() -> i32 {
+    default_miner().state.status.load(Ordering::Relaxed)
+}
+
+/// A new stopped miner, independent of the `start_mining` miner and all other handles.
+/// It has to be freed with `destroy_miner`.
+#[no_mangle]
+pub extern "C" fn create_miner() -> *mut MinerHandle {
+    Box::into_raw(Box::new(MinerHandle::new()))
+}
+
+/// Starts the miner of `handle` with the null terminated JSON `json_config` (see
+/// `start_mining_with_json_config`). Returns the codes of `start_mining_with_json_config`
+/// or 4 if `handle` is null.
+///
+/// # Safety
+///
+/// `handle` has to be null or returned by `create_miner` and not destroyed yet,
+/// `json_config` has to be null or a null terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn start_miner(handle: *mut MinerHandle, json_config: *const i8) -> i32 {
+    let handle = match handle.as_ref() {
+        Some(handle) => handle,
+        None => return START_INVALID_HANDLE,
+    };
+    if json_config.is_null() {
+        println!("No JSON config passed");
+        return START_PARSE_ERROR;
+    }
+    let json = std::ffi::CStr::from_ptr(json_config);
+    start_with_json_config(handle, json.to_bytes())
+}
+
+/// Stops the miner of `handle` and waits until it has stopped. Returns 1 if it was
+/// started, 0 if not (or `handle` is null).
+///
+/// # Safety
+///
+/// `handle` has to be null or returned by `create_miner` and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn stop_miner(handle: *mut MinerHandle) -> i32 {
+    match handle.as_ref() {
+        Some(handle) if handle.stop() => 1,
+        _ => 0,
+    }
+}
+
+/// Stops the miner of `handle` (if running) and frees it, null is ignored.
+///
+/// # Safety
+///
+/// `handle` has to be null or returned by `create_miner`, it must not be used after
+/// the call.
+#[no_mangle]
+pub unsafe extern "C" fn destroy_miner(handle: *mut MinerHandle) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = Box::from_raw(handle);
+    handle.stop();
+}
+
+/// Result of `mine_until_stopped`
+#[cfg(feature = "async-api")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiningReport {
+    pub total_hashes: u64,
+    pub accepted_shares: u64,
+    pub rejected_shares: u64,
+    pub uptime: Duration,
+}
+
+/// Error of `mine_until_stopped`
+#[cfg(feature = "async-api")]
+#[derive(Debug)]
+pub enum MithrilError {
+    /// the mining thread could not be started
+    Spawn(io::Error),
+    /// the mining thread panicked
+    Panicked,
+}
+
+#[cfg(feature = "async-api")]
+impl fmt::Display for MithrilError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MithrilError::Spawn(err) => write!(f, "starting the mining thread failed: {}", err),
+            MithrilError::Panicked => write!(f, "the mining thread panicked"),
+        }
+    }
+}
+
+#[cfg(feature = "async-api")]
+impl std::error::Error for MithrilError {}
+
+/// Runs the miner (including the reconnects) with `config` on a thread of its own and
+/// resolves when it is stopped with the `StopHandle` of the future. The miner is
+/// independent of `start_mining` and the FFI handles, it starts on the first poll and
+/// is stopped when the future is dropped.
+///
+/// The thread is not shared with the runtime (unlike tokio's blocking thread pool), so
+/// with `sandbox` the seccomp filter only restricts the miner.
+#[cfg(feature = "async-api")]
+pub fn mine_until_stopped(config: MithrilConfig) -> MineUntilStopped {
+    MineUntilStopped {
+        state: Arc::new(MinerState::new()),
+        config: Some(config),
+        report: None,
+    }
+}
+
+/// Future returned by `mine_until_stopped`
+#[cfg(feature = "async-api")]
+pub struct MineUntilStopped {
+    state: Arc<MinerState>,
+    /// taken by the first poll, which starts the miner
+    config: Option<MithrilConfig>,
+    /// the report of the mining thread, dropped without a report if the thread panicked
+    report: Option<oneshot::Receiver<MiningReport>>,
+}
+
+#[cfg(feature = "async-api")]
+impl MineUntilStopped {
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    fn spawn(&mut self, config: MithrilConfig) -> io::Result<oneshot::Receiver<MiningReport>> {
+        self.state.running.store(true, Ordering::Relaxed);
+        self.state.status.store(STATUS_STARTING, Ordering::Relaxed);
+        let state = self.state.clone();
+        let (report_sndr, report_rcvr) = oneshot::channel();
+        thread::Builder::new()
+            .name("mithril miner".to_string())
+            .spawn(move || {
+                let started = Instant::now();
+                run_miner(config, &state);
+                state.status.store(STATUS_STOPPED, Ordering::Relaxed);
+                state.hashrate.store(0, Ordering::Relaxed);
+                // the future was dropped if the receiver is gone
+                let _ = report_sndr.send(MiningReport {
+                    total_hashes: state.total_hashes.load(Ordering::Relaxed),
+                    accepted_shares: state.accepted_shares.load(Ordering::Relaxed),
+                    rejected_shares: state.rejected_shares.load(Ordering::Relaxed),
+                    uptime: started.elapsed(),
+                });
+            })?;
+        Ok(report_rcvr)
+    }
+}
+
+#[cfg(feature = "async-api")]
+impl Future for MineUntilStopped {
+    type Output = Result<MiningReport, MithrilError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(config) = self.config.take() {
+            match self.spawn(config) {
+                Ok(report) => self.report = Some(report),
+                Err(err) => {
+                    self.state.running.store(false, Ordering::Relaxed);
+                    self.state.status.store(STATUS_STOPPED, Ordering::Relaxed);
+                    return Poll::Ready(Err(MithrilError::Spawn(err)));
+                }
+            }
         }
+        let report = self.report.as_mut().expect("mining thread report");
+        Pin::new(report)
+            .poll(cx)
+            .map(|result| result.map_err(|_| MithrilError::Panicked))
+    }
+}
+
+/// Stops the miner, the mining thread keeps running until the main loop notices
+#[cfg(feature = "async-api")]
+impl Drop for MineUntilStopped {
+    fn drop(&mut self) {
+        self.state.running.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Stops the miner of a `mine_until_stopped` future from any thread
+#[cfg(feature = "async-api")]
+#[derive(Clone)]
+pub struct StopHandle {
+    state: Arc<MinerState>,
+}
+
+#[cfg(feature = "async-api")]
+impl StopHandle {
+    /// The future resolves once the miner thread has stopped
+    pub fn stop(&self) {
+        self.state.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Shares accepted by the pool so far, e.g. to stop after a number of shares
+    pub fn accepted_shares(&self) -> u64 {
+        self.state.accepted_shares.load(Ordering::Relaxed)
     }
-    0 // Not running
 }
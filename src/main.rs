@@ -2,19 +2,27 @@
 extern crate log;
 
 extern crate bandit;
-extern crate crossbeam_channel;
 extern crate env_logger;
 extern crate mithril;
 
-use self::crossbeam_channel::{select, unbounded, Receiver};
 use mithril::bandit_tools;
+use mithril::build_info;
+use mithril::byte_string;
+use mithril::channel::{select3, unbounded, Receiver, Selected3, Sender};
 use mithril::metric;
+use mithril::metric::statsd::{StatsdConfig, StatsdReporter};
 use mithril::mithril_config;
-use mithril::randomx::memory::VmMemoryAllocator;
-use mithril::stratum::{StratumAction, StratumClient};
+use mithril::randomx::m128::m128i;
+use mithril::randomx::memory::{CacheMode, VmMemoryAllocator};
+use mithril::randomx::program::Program;
+#[cfg(target_os = "linux")]
+use mithril::sandbox;
+use mithril::stratum;
+use mithril::stratum::{StratumAction, StratumClient, StratumJob};
 use mithril::timer;
 use mithril::worker::worker_pool;
 use mithril::worker::worker_pool::WorkerPool;
+use std::env;
 use std::io;
 use std::io::Error;
 use std::path::Path;
@@ -25,6 +33,9 @@ use std::time::SystemTime;
 
 use bandit::MultiArmedBandit;
 
+/// how often a login rejected by the pool is retried before reconnecting
+const LOGIN_MAX_RETRIES: u32 = 3;
+
 #[derive(Debug, PartialEq)]
 enum MainLoopExit {
     DrawNewBanditArm,
@@ -33,12 +44,37 @@ enum MainLoopExit {
 
 #[allow(clippy::unnecessary_unwrap)]
 fn main() {
+    let process_start_time = Instant::now();
     env_logger::init();
+    build_info::print_build_info();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() == 3 && args[1] == "--disassemble-program" {
+        disassemble_program(&args[2]);
+        return;
+    }
+    if (args.len() == 2 || args.len() == 3) && args[1] == "--generate-config" {
+        let path = args
+            .get(2)
+            .map(|p| p.as_str())
+            .unwrap_or(mithril_config::CONFIG_FILE_NAME);
+        generate_config(Path::new(path));
+        return;
+    }
+    //prints the state of every worker thread with the cache log
+    let verbose = args.iter().any(|arg| arg == "--verbose");
 
     //Read config
     let cwd_path = &format!("{}{}", "./", mithril_config::CONFIG_FILE_NAME);
     let config =
-        mithril_config::read_config(Path::new(cwd_path), mithril_config::CONFIG_FILE_NAME).unwrap();
+        match mithril_config::read_config(Path::new(cwd_path), mithril_config::CONFIG_FILE_NAME) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        };
+    validate_config(&config);
 
     if config.donation_conf.percentage > 0.0 {
         print_donation_hint(config.donation_conf.percentage);
@@ -47,35 +83,31 @@ fn main() {
     let mut bandit = if config.worker_conf.auto_tune {
         Some(bandit_tools::setup_bandit(
             config.worker_conf.auto_tune_log.clone(),
+            config.worker_conf.bandit_strategy,
         ))
     } else {
         None
     };
-    let timer_rcvr = timer::setup(&config.worker_conf, &config.donation_conf);
-    let mut donation_hashing = false;
+    let (clock_sndr, timer_rcvr) = unbounded();
+    //before the first thread is started, all threads inherit the blocked SIGHUP
+    #[cfg(target_os = "linux")]
+    reload_on_sighup(&config, clock_sndr.clone());
+    timer::setup_with_sender(&config.worker_conf, &config.donation_conf, clock_sndr);
     let mut vm_memory_allocator = VmMemoryAllocator::initial();
 
-    loop {
-        //Stratum start
-        let (stratum_sndr, stratum_rcvr) = unbounded();
-        let (client_err_sndr, client_err_rcvr) = unbounded();
-
-        let conf = if donation_hashing {
-            mithril_config::donation_conf()
-        } else {
-            config.pool_conf.clone()
-        };
-        info!("logging into stratum server: {}", conf.pool_address);
-        let login_result = StratumClient::login(conf, client_err_sndr, stratum_sndr);
-        if login_result.is_err() {
-            error!("stratum login failed {:?}", login_result.err());
-            await_timeout();
-            continue;
-        }
-
-        info!("Completed stratum login!");
+    //Stratum start, the channels are kept for all reconnects
+    let (stratum_sndr, stratum_rcvr) = unbounded();
+    let (client_err_sndr, client_err_rcvr) = unbounded();
+    let mut client = login(&config, &client_err_sndr, &stratum_sndr);
+    if config.sandbox {
+        //after the login, the sandbox allows no new connections
+        apply_sandbox();
+    }
+    //the job the next worker pool starts with, after a reconnect the job of the
+    //new login is queued on the stratum channel instead
+    let mut current_job = client.initial_job().cloned();
 
-        let client = login_result.expect("stratum client");
+    loop {
         let share_sndr = client.new_cmd_channel();
         let (arm, num_threads) = if bandit.is_some() {
             let selected_arm = bandit.as_ref().unwrap().select_arm();
@@ -85,89 +117,373 @@ fn main() {
             (None, config.worker_conf.num_threads)
         };
 
-        let (metric_sndr, metric_rcvr) = unbounded();
-        let metric = metric::start(config.metric_conf.clone(), metric_rcvr);
+        let (metric_sndr, metric_rcvr) = metric::channel(&config.metric_conf);
+        let metric = metric::start_since(
+            config.metric_conf.clone(),
+            metric_rcvr,
+            process_start_time,
+        );
+        let statsd = start_statsd_reporter(&config.statsd_conf, &metric);
 
         //worker pool start
-        let mut pool = worker_pool::start(
+        let mut pool = match worker_pool::start(
             num_threads,
             &share_sndr,
             config.metric_conf.resolution,
             &metric_sndr.clone(),
-            vm_memory_allocator,
-        );
+            vm_memory_allocator.clone(),
+        ) {
+            Ok(pool) => pool,
+            Err(err) => {
+                error!("starting worker pool failed: {}", err);
+                stop_statsd_reporter(statsd);
+                metric.stop();
+                metric.join();
+                await_timeout();
+                continue;
+            }
+        };
 
-        let term_result = start_main_event_loop(
-            &mut pool,
-            &client_err_rcvr,
-            &stratum_rcvr,
-            &timer_rcvr,
-            &metric,
-        );
+        pool.set_warm_swap(config.worker_conf.warm_swap);
+        if let Some(StratumJob {
+            miner_id,
+            seed_hash,
+            blob,
+            job_id,
+            target,
+        }) = &current_job
+        {
+            pool.job_change(miner_id, seed_hash, blob, job_id, target);
+        }
+
+        let cache_log_interval = Duration::from_secs(config.metric_conf.sample_interval_seconds);
+        //hashes for the donation pool, not part of the reward of the arm
+        let mut donation_hashes = 0;
+        let term_result = loop {
+            let result = start_main_event_loop(
+                &mut pool,
+                &client_err_rcvr,
+                &stratum_rcvr,
+                &timer_rcvr,
+                &metric,
+                cache_log_interval,
+                Duration::from_secs(config.worker_conf.share_timeout_secs),
+                verbose,
+            );
+            if let Ok(MainLoopExit::DonationHashing) = result {
+                let hashes = metric.hash_count();
+                let donation_exit = hot_swap_donation(
+                    &config,
+                    &client,
+                    &mut pool,
+                    &timer_rcvr,
+                    &metric,
+                    cache_log_interval,
+                    verbose,
+                );
+                donation_hashes += metric.hash_count() - hashes;
+                if donation_exit == MainLoopExit::DonationHashing {
+                    continue;
+                }
+                break Ok(donation_exit);
+            }
+            break result;
+        };
+        //the next pool starts with the dataset of a running warm swap
+        pool.finish_memory_swap();
+        //newer jobs of the pool are still queued on the stratum channel
+        current_job = pool.current_job();
 
         vm_memory_allocator = pool.vm_memory_allocator.clone();
         pool.stop();
-        client.stop();
+        stop_statsd_reporter(statsd);
+        info!(
+            "pool session ended after {}s (total uptime {}s)",
+            metric.session_uptime_secs(),
+            metric.total_uptime_secs()
+        );
+        info!(
+            "hash durations p50 {}µs, p95 {}µs, p99 {}µs",
+            metric.p50_hash_duration_us(),
+            metric.p95_hash_duration_us(),
+            metric.p99_hash_duration_us()
+        );
+        info!(
+            "{} shares submitted for {} hashes (efficacy ratio {:.8})",
+            metric.submitted_hashes(),
+            metric.hash_count(),
+            metric.efficacy_ratio()
+        );
+        if pool.panic_count() > 0 {
+            warn!("{} worker panics (workers were restarted)", pool.panic_count());
+        }
 
         match term_result {
+            Err(err) if config.sandbox => {
+                error!(
+                    "error received, the sandbox does not allow a reconnect, exiting. err was {}",
+                    err
+                );
+                std::process::exit(1);
+            }
             Err(err) => {
                 error!(
                     "error received, restarting connection after 60 seconds. err was {}",
                     err
                 );
                 await_timeout();
+                reconnect(&mut client, &stratum_rcvr, &client_err_rcvr, &config);
+                current_job = None;
             }
             Ok(ex) => {
                 info!("main loop exit, next loop {:?}", ex);
                 pool.join();
 
                 metric.stop();
-                let hashes = metric.hash_count();
+                let hashes = metric.hash_count() - donation_hashes;
                 metric.join();
 
-                if arm.is_some() && bandit.is_some() && !donation_hashing {
-                    //do not save reward for donation hashing, it probably only runs for a short period
+                if arm.is_some() && bandit.is_some() {
                     let bandit_ref = bandit.as_mut().unwrap();
                     let reward = (hashes as f64
                         / (config.worker_conf.auto_tune_interval_minutes as f64 * 60.0))
                         / 1000.0; /*kH/s*/
                     info!("adding reward {:?} for arm {:?}", reward, arm);
                     bandit_ref.update(arm.unwrap(), reward);
-                    save_bandit_state(bandit_ref);
+                    save_bandit_state(bandit_ref.as_ref(), config.worker_conf.bandit_strategy);
                 }
+            }
+        }
+    }
+}
 
-                donation_hashing = ex == MainLoopExit::DonationHashing;
+/// Logs into the pool, retried every 60 seconds until it succeeds.
+/// Exits if the pool rejects the login with a fatal error code.
+fn login(
+    config: &mithril_config::MithrilConfig,
+    client_err_sndr: &Sender<Error>,
+    stratum_sndr: &Sender<StratumAction>,
+) -> StratumClient {
+    let conf = config.active_pool_conf(false);
+    info!("logging into stratum server: {}", conf.pool_address);
+    loop {
+        match StratumClient::login_with_retry(
+            conf.clone(),
+            LOGIN_MAX_RETRIES,
+            client_err_sndr.clone(),
+            stratum_sndr.clone(),
+        ) {
+            Ok(client) => {
+                info!("Completed stratum login!");
+                return client;
+            }
+            Err(err) => {
+                exit_on_fatal_login_error(&config.fatal_error_conf, &err);
+                error!("stratum login failed {:?}", err);
+                await_timeout();
             }
         }
     }
 }
 
+/// Reconnects `client` to the same pool, retried every 60 seconds until it succeeds.
+/// The channels (and with them the share senders) and the queued shares are kept.
+fn reconnect(
+    client: &mut StratumClient,
+    stratum_rcvr: &Receiver<StratumAction>,
+    client_err_rcvr: &Receiver<Error>,
+    config: &mithril_config::MithrilConfig,
+) {
+    //jobs of the closed connection, the new login dispatches a current one
+    stratum_rcvr.try_iter().for_each(drop);
+    loop {
+        match client.reconnect() {
+            Ok(()) => {
+                //errors of the closed connection
+                client_err_rcvr.try_iter().for_each(drop);
+                info!("Completed stratum login!");
+                let queued = client.retry_share_count();
+                if queued > 0 {
+                    info!("{} shares of the closed connection are re-submitted", queued);
+                }
+                return;
+            }
+            Err(err) => {
+                exit_on_fatal_login_error(&config.fatal_error_conf, &err);
+                error!("stratum login failed {:?}", err);
+                await_timeout();
+            }
+        }
+    }
+}
+
+/// Hashes for the donation pool until the next timer tick with the running workers,
+/// only their share sender and job are switched. Afterwards (right away if the login
+/// to the donation pool failed) the workers are switched back to `client` and the job
+/// they hashed before, newer jobs of `client` are still queued on its channel.
+/// Returns the tick that ended the donation, `DonationHashing` after a donation pool error.
+fn hot_swap_donation(
+    config: &mithril_config::MithrilConfig,
+    client: &StratumClient,
+    pool: &mut WorkerPool,
+    timer_rcvr: &Receiver<timer::TickAction>,
+    metric: &metric::Metric,
+    cache_log_interval: Duration,
+    verbose: bool,
+) -> MainLoopExit {
+    let (stratum_sndr, stratum_rcvr) = unbounded();
+    let (client_err_sndr, client_err_rcvr) = unbounded();
+
+    let conf = config.active_pool_conf(true);
+    info!("logging into donation server: {}", conf.pool_address);
+    let donation_client = match StratumClient::login_with_retry(
+        conf,
+        LOGIN_MAX_RETRIES,
+        client_err_sndr,
+        stratum_sndr,
+    ) {
+        Ok(client) => client,
+        Err(err) => {
+            error!("donation login failed {:?}", err);
+            return MainLoopExit::DonationHashing;
+        }
+    };
+
+    let pool_job = pool.current_job();
+    pool.swap_share_sender(donation_client.new_cmd_channel());
+    if let Some(StratumJob {
+        miner_id,
+        seed_hash,
+        blob,
+        job_id,
+        target,
+    }) = donation_client.initial_job()
+    {
+        pool.job_change(miner_id, seed_hash, blob, job_id, target);
+    }
+
+    let result = start_main_event_loop(
+        pool,
+        &client_err_rcvr,
+        &stratum_rcvr,
+        timer_rcvr,
+        metric,
+        cache_log_interval,
+        Duration::from_secs(config.worker_conf.share_timeout_secs),
+        verbose,
+    );
+
+    pool.swap_share_sender(client.new_cmd_channel());
+    if let Some(StratumJob {
+        miner_id,
+        seed_hash,
+        blob,
+        job_id,
+        target,
+    }) = &pool_job
+    {
+        pool.job_change(miner_id, seed_hash, blob, job_id, target);
+    }
+    donation_client.stop();
+    info!("donation hashing finished");
+
+    match result {
+        Ok(exit) => exit,
+        Err(err) => {
+            error!("donation pool error {}, back to the regular pool", err);
+            MainLoopExit::DonationHashing
+        }
+    }
+}
+
+/// Logs all detected contradictions in the config and exits if one of them is fatal
+fn validate_config(config: &mithril_config::MithrilConfig) {
+    let issues = config.validate();
+    for issue in &issues {
+        match issue {
+            mithril_config::ConfigWarning::Warning(msg) => warn!("config: {}", msg),
+            mithril_config::ConfigWarning::Error(msg) => error!("config: {}", msg),
+        }
+    }
+    if issues.iter().any(|i| i.is_error()) {
+        error!("invalid configuration, aborting");
+        std::process::exit(1);
+    }
+}
+
+/// Installs the seccomp filter on all threads (including the connection and clock
+/// threads), exits if that fails
+#[cfg(target_os = "linux")]
+fn apply_sandbox() {
+    if let Err(err) = sandbox::apply_seccomp_filter_to_process() {
+        error!("enabling the seccomp sandbox failed {:?}, aborting", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn apply_sandbox() {
+    error!("the seccomp sandbox (worker.sandbox) is only supported on linux, aborting");
+    std::process::exit(1);
+}
+
+/// None if `[statsd]` is disabled or the reporter could not be started
+fn start_statsd_reporter(conf: &StatsdConfig, metric: &metric::Metric) -> Option<StatsdReporter> {
+    if !conf.enabled {
+        return None;
+    }
+    match StatsdReporter::start(conf, metric) {
+        Ok(reporter) => {
+            info!("sending metrics to statsd server {}", reporter.host);
+            Some(reporter)
+        }
+        Err(err) => {
+            error!("starting statsd reporter failed {:?}", err);
+            None
+        }
+    }
+}
+
+fn stop_statsd_reporter(statsd: Option<StatsdReporter>) {
+    if let Some(reporter) = statsd {
+        reporter.stop();
+        reporter.join();
+    }
+}
+
 fn await_timeout() {
     thread::sleep(Duration::from_secs(60))
 }
 
-fn save_bandit_state(bandit: &mut bandit::softmax::AnnealingSoftmax<bandit_tools::ThreadArm>) {
+fn save_bandit_state(
+    bandit: &dyn MultiArmedBandit<bandit_tools::ThreadArm>,
+    strategy: bandit_tools::BanditStrategy,
+) {
     let res = bandit_tools::ensure_mithril_folder_exists();
     if res.is_err() {
         error!("could not create folder for state file {:?}", res.err());
     }
 
-    let save_result = bandit.save_bandit(&bandit_tools::state_file());
+    let save_result = bandit.save_bandit(&strategy.state_file());
     if save_result.is_err() {
         error!("error saving bandit state {:?}", save_result.err());
     }
 }
 
 /// This function terminates if a non-recoverable error was detected (i.e. connection lost)
+#[allow(clippy::too_many_arguments)]
 fn start_main_event_loop(
     pool: &mut WorkerPool,
     client_err_rcvr: &Receiver<Error>,
     stratum_rcvr: &Receiver<StratumAction>,
     timer_rcvr: &Receiver<timer::TickAction>,
     metric: &metric::Metric,
+    cache_log_interval: Duration,
+    share_timeout: Duration,
+    verbose: bool,
 ) -> io::Result<MainLoopExit> {
-    let mut last_time = Instant::now();
-    let mut last_hash_count = 0;
+    let mut last_cache_log = Instant::now();
+    let mut last_share_warning: Option<Instant> = None;
     let mut last_hashrate_display = SystemTime::now();
     let hashrate_display_interval = Duration::from_millis(1000);
 
@@ -175,27 +491,40 @@ fn start_main_event_loop(
         // Check if it's time to display hashrate
         let now = SystemTime::now();
         if now.duration_since(last_hashrate_display).unwrap_or(Duration::from_secs(0)) >= hashrate_display_interval {
-            let current_time = Instant::now();
-            let current_hash_count = metric.hash_count();
-            let hash_diff = current_hash_count - last_hash_count;
-            let elapsed_secs = current_time.duration_since(last_time).as_secs_f64();
-            
-            if elapsed_secs > 0.0 {
-                // Convert to kilo-hashes per second
-                let khs = (hash_diff as f64 / elapsed_secs) / 1000.0;
-                
-                println!("Hashrate: {:.2} kH/s ({} hashes in {:.1}s)", 
-                    khs, hash_diff, elapsed_secs);
-            }
-            
-            last_time = current_time;
-            last_hash_count = current_hash_count;
+            println!("{}", metric.snapshot());
             last_hashrate_display = now;
         }
+
+        if last_cache_log.elapsed() >= cache_log_interval {
+            let vm_memory = &pool.vm_memory_allocator.vm_memory;
+            if vm_memory.mode == CacheMode::FullLazy {
+                info!("dataset cache hit rate {:.2}%", pool.cache_hit_rate() * 100.0);
+            }
+            if verbose {
+                println!("{}", worker_pool::thread_stats_table(&pool.thread_stats()));
+            }
+            last_cache_log = Instant::now();
+        }
+
+        //warned at most once per share_timeout
+        let since_last_share = pool.time_since_last_share();
+        if since_last_share > share_timeout
+            && last_share_warning.is_none_or(|warned| warned.elapsed() >= share_timeout)
+        {
+            warn!(
+                "no share submitted for {} seconds, the difficulty may be too high or the pool stopped sending jobs",
+                since_last_share.as_secs()
+            );
+            last_share_warning = Some(Instant::now());
+        }
         
+        //switches to a dataset built by a warm swap as soon as it is ready
+        pool.poll_memory_swap();
+
         // Check if there's any message (with very short timeout)
-        select! {
-            recv(stratum_rcvr) -> stratum_msg => {
+        let timeout = Some(Duration::from_millis(100));
+        match select3(stratum_rcvr, timer_rcvr, client_err_rcvr, timeout) {
+            Selected3::First(stratum_msg) => {
                 if stratum_msg.is_err() {
                     return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "received error"));
                 }
@@ -207,40 +536,132 @@ fn start_main_event_loop(
                     StratumAction::Error{err} => {
                         error!("Received stratum error: {}", err);
                     },
+                    StratumAction::ShareRejected{err} => {
+                        metric.record_rejected_share();
+                        error!("Share rejected: {}", err);
+                    },
                     StratumAction::Ok => {
+                        metric.record_accepted_share();
                         info!("Received stratum ok");
                     },
                     StratumAction::KeepAliveOk => {
                         info!("Received keep alive ok");
                     }
+                    StratumAction::Unknown{method, ..} => {
+                        debug!("Received unknown stratum method {}", method);
+                    }
+                    StratumAction::StateChange(_) => {} //logged by the client
                 }
             },
-            recv(timer_rcvr) -> timer_msg => {
+            Selected3::Second(timer_msg) => {
                 if timer_msg.is_err() {
                     return Err(io::Error::new(io::ErrorKind::Other, format!("error received {:?}", timer_msg)));
                 } else {
                     let tick_action = timer_msg.expect("tickAction");
-                    match tick_action {
-                        timer::TickAction::ArmChange => {
-                            info!("bandit clock signal received - time for new arm");
+                    match timer::handle_tick(tick_action, pool) {
+                        timer::TickOutcome::DrawNewBanditArm => {
                             return Ok(MainLoopExit::DrawNewBanditArm)
                         },
-                        timer::TickAction::DonationHashing => {
+                        timer::TickOutcome::SwitchPool => {
                             return Ok(MainLoopExit::DonationHashing)
                         }
+                        timer::TickOutcome::Continue => {}
                     }
                 }
             },
-            recv(client_err_rcvr) -> client_err_msg => {
+            Selected3::Third(client_err_msg) => {
                 return Err(io::Error::new(io::ErrorKind::Other, format!("error received {:?}", client_err_msg)));
             },
-            default(Duration::from_millis(100)) => {
+            Selected3::Timeout => {
                 // Timeout after 100ms to allow for hashrate display
             }
         }
     }
 }
 
+/// A SIGHUP reloads the config file and resizes the running pool to its `num_threads`.
+/// With auto_tune the bandit selects the thread count, a resize would mix the hashrates
+/// of two thread counts in the reward of the current arm, so the reload is ignored.
+/// Not available in the sandbox, it allows neither waiting for the signal nor reading
+/// the config file.
+#[cfg(target_os = "linux")]
+fn reload_on_sighup(config: &mithril_config::MithrilConfig, clock_sndr: Sender<timer::TickAction>) {
+    if config.sandbox {
+        return;
+    }
+    let auto_tune = config.worker_conf.auto_tune;
+    let result = timer::reconfigure_on_sighup(clock_sndr, move || {
+        if auto_tune {
+            warn!("auto_tune selects the thread count, the config is not reloaded");
+            return None;
+        }
+        let cwd_path = format!("./{}", mithril_config::CONFIG_FILE_NAME);
+        match mithril_config::read_config(Path::new(&cwd_path), mithril_config::CONFIG_FILE_NAME) {
+            Ok(config) => Some(config.worker_conf),
+            Err(err) => {
+                error!("reloading the config failed: {}", err);
+                None
+            }
+        }
+    });
+    if let Err(err) = result {
+        error!("reloading the config on SIGHUP is not available: {}", err);
+    }
+}
+
+/// Writes an example config to `path` (the config file name if no path was given)
+fn generate_config(path: &Path) {
+    if let Err(err) = mithril_config::write_default_config(path) {
+        eprintln!("generating config failed: {}", err);
+        std::process::exit(1);
+    }
+    println!(
+        "example config written to {}, set at least your wallet_address",
+        path.display()
+    );
+}
+
+/// Prints the program generated for a 64 byte program seed (128 hex chars),
+/// i.e. the seed passed to `Vm::run`.
+fn disassemble_program(seed_hex: &str) {
+    let bytes = match byte_string::hex_to_bytes(seed_hex) {
+        Ok(bytes) if bytes.len() == 64 => bytes,
+        Ok(_) => {
+            eprintln!("program seed has to be 128 hex chars, got {:?}", seed_hex);
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("invalid program seed {:?}: {}", seed_hex, err);
+            std::process::exit(1);
+        }
+    };
+    let seed = [
+        m128i::from_u8(&bytes[0..16]),
+        m128i::from_u8(&bytes[16..32]),
+        m128i::from_u8(&bytes[32..48]),
+        m128i::from_u8(&bytes[48..64]),
+    ];
+    for (i, line) in Program::from_seed(&seed).disassemble().iter().enumerate() {
+        println!("{:3}: {}", i, line);
+    }
+}
+
+/// Exits the process if the pool rejected the login with a fatal error code
+/// (e.g. a banned IP), reconnecting every 60 seconds would keep the ban active.
+fn exit_on_fatal_login_error(fatal_error_conf: &mithril_config::FatalErrorConfig, err: &Error) {
+    if let Some(code) = stratum::login_rejected_code(err) {
+        if fatal_error_conf.is_fatal(code) {
+            error!(
+                "{}, this is a fatal error code (the pool may have banned this IP or wallet). \
+                 Check the pool settings and wait before restarting, or set \
+                 exit_on_fatal_error = false to keep reconnecting.",
+                err
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
 fn print_donation_hint(percentage: f64) {
     println!("-------------------------------------------------------------------");
     println!("Donation Hashing enabled with {}%.", percentage);
@@ -1,12 +1,185 @@
-extern crate crossbeam_channel;
+pub mod statsd;
 
-use self::crossbeam_channel::{select, unbounded, Receiver, RecvTimeoutError, Sender};
+use super::channel::{
+    bounded, select2, unbounded, Receiver, RecvTimeoutError, Selected2, Sender, TrySendError,
+};
+use std::fmt;
+use std::fs;
 use std::fs::OpenOptions;
+use std::io;
 use std::io::Write;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time;
+use std::time::Instant;
+
+/// 50 MiB
+pub const DEFAULT_MAX_FILE_SIZE_BYTES: u64 = 50 * 1024 * 1024;
+
+/// capacity of the hash count channel, see `MetricConfig::channel_capacity`
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 65536;
+
+/// default of `MetricConfig::ema_alpha`
+pub const EMA_ALPHA: f64 = 0.2;
+
+/// the sample thread adds the hashrate to the EMA at this interval
+pub const EMA_SAMPLE_INTERVAL_MS: u64 = 1000;
+
+pub const HASH_LATENCY_BUCKETS: usize = 20;
+
+/// Histogram with power-of-two buckets: bucket `i` counts durations below `2^(i+1)` µs
+/// (bucket 0 everything below 2 µs), the last bucket everything from 2^19 µs (~0.5 s) on.
+pub struct HashLatencyHistogram {
+    buckets: [AtomicU64; HASH_LATENCY_BUCKETS],
+}
+
+impl Default for HashLatencyHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HashLatencyHistogram {
+    pub const fn new() -> HashLatencyHistogram {
+        HashLatencyHistogram {
+            buckets: [const { AtomicU64::new(0) }; HASH_LATENCY_BUCKETS],
+        }
+    }
+
+    pub fn record(&self, duration: time::Duration) {
+        let micros = duration.as_micros().max(1) as u64;
+        let bucket = (63 - micros.leading_zeros() as usize).min(HASH_LATENCY_BUCKETS - 1);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).sum()
+    }
+
+    /// Upper bound in µs of the bucket containing the `percentile` (0.0 - 1.0), 0 if empty
+    pub fn percentile_us(&self, percentile: f64) -> u64 {
+        let counts: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|b| b.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((total as f64 * percentile).ceil() as u64).max(1);
+        let mut cumulative = 0;
+        for (i, count) in counts.iter().enumerate() {
+            cumulative += count;
+            if cumulative >= rank {
+                return 1 << (i + 1);
+            }
+        }
+        1 << HASH_LATENCY_BUCKETS
+    }
+
+    pub fn reset(&self) {
+        for bucket in &self.buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `submitted / total`, 0 before the first hash. With a correct target this is
+/// about `1 / difficulty` of the pool jobs.
+pub fn efficacy_ratio(submitted: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    submitted as f64 / total as f64
+}
+
+/// Values the workers record for one `Metric`, shared through its `MetricSender`
+#[derive(Default)]
+struct Counters {
+    /// per-hash durations of all workers
+    hash_latency: HashLatencyHistogram,
+    /// all hashes since `start`
+    total_hashes: AtomicU64,
+    /// hashes since the last line of the report file
+    sample_hashes: AtomicU64,
+    /// hash counts the workers could not send because the channel was full
+    dropped_metric_events: AtomicU64,
+    /// hashes below the job target that were submitted as shares
+    submitted_hashes: AtomicU64,
+    /// pool responses to submitted shares
+    accepted_shares: AtomicU64,
+    rejected_shares: AtomicU64,
+}
+
+impl Counters {
+    fn add_hashes(&self, hash_count: u64) {
+        self.sample_hashes.fetch_add(hash_count, Ordering::SeqCst);
+        self.total_hashes.fetch_add(hash_count, Ordering::SeqCst);
+    }
+}
+
+/// The workers' end of a metric, sends the hash counts and records the other worker
+/// values of the `Metric` started with the `MetricReceiver` of the same `channel`
+#[derive(Clone)]
+pub struct MetricSender {
+    hash_cnt_sndr: Sender<u64>,
+    counters: Arc<Counters>,
+}
+
+impl MetricSender {
+    /// Sends without blocking the worker. If the channel is full the count is added
+    /// to the hash counts directly (the bandit and the hashrate rely on every hash)
+    /// and counted as a dropped event.
+    pub fn send_hash_count(&self, hash_count: u64) {
+        match self.hash_cnt_sndr.try_send(hash_count) {
+            Ok(()) => {}
+            Err(TrySendError::Full(hash_count)) => {
+                self.counters.add_hashes(hash_count);
+                self.counters
+                    .dropped_metric_events
+                    .fetch_add(1, Ordering::Relaxed);
+            }
+            Err(err @ TrySendError::Disconnected(_)) => error!("metric submit failed {:?}", err),
+        }
+    }
+
+    /// Called by the workers after every hash
+    pub fn record_hash_duration(&self, duration: time::Duration) {
+        self.counters.hash_latency.record(duration);
+    }
+
+    /// Called by the workers for every share they submit, accepted by the pool or not
+    pub fn record_submitted_hash(&self) {
+        self.counters
+            .submitted_hashes
+            .fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// The end of a `channel` passed to `start`
+pub struct MetricReceiver {
+    hash_cnt_rcvr: Receiver<u64>,
+    counters: Arc<Counters>,
+}
+
+/// Channel from the workers to `start`, up to the configured capacity of hash counts
+/// are buffered
+pub fn channel(conf: &MetricConfig) -> (MetricSender, MetricReceiver) {
+    let (hash_cnt_sndr, hash_cnt_rcvr) = bounded(conf.channel_capacity);
+    let counters = Arc::new(Counters::default());
+    (
+        MetricSender {
+            hash_cnt_sndr,
+            counters: counters.clone(),
+        },
+        MetricReceiver {
+            hash_cnt_rcvr,
+            counters,
+        },
+    )
+}
 
 #[derive(Clone)]
 pub struct MetricConfig {
@@ -14,51 +187,111 @@ pub struct MetricConfig {
     pub resolution: u64,
     pub sample_interval_seconds: u64,
     pub report_file: String,
+    /// the report file is moved to `<report_file>.1` when it reaches this size
+    pub max_file_size_bytes: u64,
+    /// capacity of the hash count channel, while it is full the workers add their
+    /// counts to the metric directly
+    pub channel_capacity: usize,
+    /// smoothing factor of the EMA hashrate (0.1 - 0.3 is a good range, higher follows
+    /// changes faster, 1.0 is the raw hashrate)
+    pub ema_alpha: f64,
+}
+
+/// Values of a `Metric` read at the same time, see `Metric::snapshot`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    /// see `Metric::raw_hashrate_khs`
+    pub raw_hashrate_khs: f64,
+    /// see `Metric::ema_hashrate_khs`
+    pub hashrate_khs: f64,
+    pub hash_count: u64,
+    pub accepted_shares: u64,
+    pub rejected_shares: u64,
+    /// seconds since the metric was started
+    pub uptime_secs: u64,
+}
+
+/// e.g. `kH/s: 43.0 (EMA 42.3) | accepted: 15 | rejected: 0 | uptime: 1h23m`
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "kH/s: {:.1} (EMA {:.1}) | accepted: {} | rejected: {} | uptime: {}h{:02}m",
+            self.raw_hashrate_khs,
+            self.hashrate_khs,
+            self.accepted_shares,
+            self.rejected_shares,
+            self.uptime_secs / 3600,
+            self.uptime_secs % 3600 / 60
+        )
+    }
 }
 
 pub struct Metric {
-    /// This is the total hash count since the construction of the
-    /// metric struct.
-    total_hashes: Arc<AtomicU64>,
+    counters: Arc<Counters>,
+    /// set on `start`, a metric is started for every pool connection
+    session_start_time: Instant,
+    miner_start_time: Instant,
+    /// EMA hashrate in milli-kH/s (fixed-point), updated by the sample thread
+    ema_milli_khs: Arc<AtomicU64>,
+    /// hashrate of the last sample in milli-kH/s
+    raw_milli_khs: Arc<AtomicU64>,
     cnt_hnd: thread::JoinHandle<()>,
     tick_hnd: thread::JoinHandle<()>,
     stop_tick_sndr: Sender<()>,
     stop_cnt_sndr: Sender<()>,
 }
 
-pub fn start(conf: MetricConfig, hash_cnt_rcvr: Receiver<u64>) -> Metric {
-    let log_count = Arc::new(AtomicU64::new(0));
-    let total_count = Arc::new(AtomicU64::new(0));
+/// Starts a metric whose total uptime is the session uptime, see `start_since`
+pub fn start(conf: MetricConfig, rcvr: MetricReceiver) -> Metric {
+    start_since(conf, rcvr, Instant::now())
+}
 
-    let thread_log_count = log_count.clone();
-    let thread_total_count = total_count.clone();
+/// Starts the metric of a pool connection of the miner started at `miner_start_time`,
+/// which `total_uptime_secs` counts from across reconnects
+pub fn start_since(conf: MetricConfig, rcvr: MetricReceiver, miner_start_time: Instant) -> Metric {
+    let MetricReceiver {
+        hash_cnt_rcvr,
+        counters,
+    } = rcvr;
+    let session_start_time = Instant::now();
+    let ema_milli_khs = Arc::new(AtomicU64::new(0));
+    let raw_milli_khs = Arc::new(AtomicU64::new(0));
+
+    let thread_counters = counters.clone();
     let (stop_cnt_sndr, stop_cnt_rcvr) = unbounded();
 
     let cnt_hnd = thread::Builder::new()
         .name("metric counting thread".to_string())
         .spawn(move || 'select_loop: loop {
-            select! {
-                recv(stop_cnt_rcvr) -> _ => {
+            match select2(&stop_cnt_rcvr, &hash_cnt_rcvr, None) {
+                Selected2::First(_) | Selected2::Timeout => {
                     info!("stopping metric counting thread");
                     break 'select_loop;
-                },
-                recv(hash_cnt_rcvr) -> cnt_msg => {
+                }
+                Selected2::Second(cnt_msg) => {
                     let cnt = cnt_msg.expect("Hash channel unexpectedly closed");
-                    thread_log_count.fetch_add(cnt, Ordering::SeqCst);
-                    thread_total_count.fetch_add(cnt, Ordering::SeqCst);
+                    thread_counters.add_hashes(cnt);
                 }
             }
         })
         .expect("metric counting thread handle");
 
     let (stop_tick_sndr, stop_tick_rcvr) = unbounded();
+    let mut reported_dropped = 0;
+    let thread_counters = counters.clone();
+    let thread_ema_milli_khs = ema_milli_khs.clone();
+    let thread_raw_milli_khs = raw_milli_khs.clone();
+    let mut ema = Ema::new(session_start_time, conf.ema_alpha);
+    let mut last_write = session_start_time;
+    let write_interval = time::Duration::from_secs(conf.sample_interval_seconds);
 
     let tick_hnd = thread::Builder::new()
         .name("metric sample thread".to_string())
         .spawn(move || {
             loop {
                 let recv_result = stop_tick_rcvr
-                    .recv_timeout(time::Duration::from_secs(conf.sample_interval_seconds));
+                    .recv_timeout(time::Duration::from_millis(EMA_SAMPLE_INTERVAL_MS));
                 match recv_result {
                     Ok(()) | Err(RecvTimeoutError::Disconnected) => {
                         info!("metric sample thread stopped");
@@ -67,7 +300,28 @@ pub fn start(conf: MetricConfig, hash_cnt_rcvr: Receiver<u64>) -> Metric {
                     Err(RecvTimeoutError::Timeout) => {} //continue with next loop
                 }
 
-                let sample_cnt = log_count.swap(0, Ordering::SeqCst);
+                let (raw_khs, ema_khs) = ema.sample(
+                    Instant::now(),
+                    thread_counters.total_hashes.load(Ordering::SeqCst),
+                );
+                thread_raw_milli_khs.store((raw_khs * 1000.0).round() as u64, Ordering::SeqCst);
+                thread_ema_milli_khs.store((ema_khs * 1000.0).round() as u64, Ordering::SeqCst);
+
+                let dropped = thread_counters.dropped_metric_events.load(Ordering::SeqCst);
+                if dropped > reported_dropped {
+                    warn!(
+                        "metric channel full, dropped {} hash count events ({} total), their hashes are still counted",
+                        dropped - reported_dropped,
+                        dropped
+                    );
+                    reported_dropped = dropped;
+                }
+
+                if last_write.elapsed() < write_interval {
+                    continue;
+                }
+                last_write = Instant::now();
+                let sample_cnt = thread_counters.sample_hashes.swap(0, Ordering::SeqCst);
 
                 let timestamp_result = time::SystemTime::now().duration_since(time::UNIX_EPOCH);
                 if timestamp_result.is_err() {
@@ -77,6 +331,11 @@ pub fn start(conf: MetricConfig, hash_cnt_rcvr: Receiver<u64>) -> Metric {
                 let timestamp = timestamp_result.unwrap();
                 let millis = timestamp.as_secs() * 1_000 + u64::from(timestamp.subsec_millis());
 
+                let rotate_result = rotate_if_needed(&conf.report_file, conf.max_file_size_bytes);
+                if rotate_result.is_err() {
+                    error!("could not rotate metric file {:?}", rotate_result);
+                }
+
                 let file_result = OpenOptions::new()
                     .create(true)
                     .append(true)
@@ -97,7 +356,11 @@ pub fn start(conf: MetricConfig, hash_cnt_rcvr: Receiver<u64>) -> Metric {
         .expect("metric sample thread handle");
 
     Metric {
-        total_hashes: total_count,
+        counters,
+        session_start_time,
+        miner_start_time,
+        ema_milli_khs,
+        raw_milli_khs,
         cnt_hnd,
         tick_hnd,
         stop_tick_sndr,
@@ -105,9 +368,133 @@ pub fn start(conf: MetricConfig, hash_cnt_rcvr: Receiver<u64>) -> Metric {
     }
 }
 
+/// Renames `report_file` to `<report_file>.1` (replacing an older one) if it
+/// has at least `max_size` bytes, the next write then starts a fresh file.
+/// Returns true if the file was rotated.
+pub fn rotate_if_needed(report_file: &str, max_size: u64) -> io::Result<bool> {
+    match fs::metadata(report_file) {
+        Ok(meta) if meta.is_file() && meta.len() >= max_size => {
+            fs::rename(report_file, format!("{}.1", report_file))?;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Exponential moving average of the hashrate with smoothing factor `alpha`
+struct Ema {
+    alpha: f64,
+    khs: Option<f64>,
+    /// time and hash count of the previous sample
+    last_sample: (Instant, u64),
+}
+
+impl Ema {
+    fn new(start: Instant, alpha: f64) -> Ema {
+        Ema {
+            alpha,
+            khs: None,
+            last_sample: (start, 0),
+        }
+    }
+
+    /// Adds the hashrate since the previous sample, returns it and the average in kH/s.
+    /// The first sample starts the average at the raw hashrate.
+    fn sample(&mut self, now: Instant, hash_count: u64) -> (f64, f64) {
+        let elapsed_secs = now.duration_since(self.last_sample.0).as_secs_f64();
+        if elapsed_secs <= 0.0 {
+            return (0.0, self.khs.unwrap_or(0.0));
+        }
+        let raw_khs = (hash_count - self.last_sample.1) as f64 / elapsed_secs / 1000.0;
+        self.last_sample = (now, hash_count);
+        let ema_khs = match self.khs {
+            None => raw_khs,
+            Some(prev) => self.alpha * raw_khs + (1.0 - self.alpha) * prev,
+        };
+        self.khs = Some(ema_khs);
+        (raw_khs, ema_khs)
+    }
+}
+
 impl Metric {
     pub fn hash_count(&self) -> u64 {
-        self.total_hashes.load(Ordering::SeqCst)
+        self.counters.total_hashes.load(Ordering::SeqCst)
+    }
+
+    /// EMA hashrate in kH/s (see `MetricConfig::ema_alpha`), updated every
+    /// `EMA_SAMPLE_INTERVAL_MS` by the sample thread. 0 before the first sample.
+    pub fn ema_hashrate_khs(&self) -> f64 {
+        self.ema_milli_khs.load(Ordering::SeqCst) as f64 / 1000.0
+    }
+
+    /// Hashrate in kH/s of the last `EMA_SAMPLE_INTERVAL_MS`, 0 before the first sample
+    pub fn raw_hashrate_khs(&self) -> f64 {
+        self.raw_milli_khs.load(Ordering::SeqCst) as f64 / 1000.0
+    }
+
+    /// Hash duration percentiles (bucket upper bounds) of all workers since `start`
+    pub fn p50_hash_duration_us(&self) -> u64 {
+        self.counters.hash_latency.percentile_us(0.50)
+    }
+
+    pub fn p95_hash_duration_us(&self) -> u64 {
+        self.counters.hash_latency.percentile_us(0.95)
+    }
+
+    pub fn p99_hash_duration_us(&self) -> u64 {
+        self.counters.hash_latency.percentile_us(0.99)
+    }
+
+    /// Hash count events dropped since `start` because the metric channel was full,
+    /// their hashes are included in `hash_count`
+    pub fn dropped_metric_events(&self) -> u64 {
+        self.counters.dropped_metric_events.load(Ordering::SeqCst)
+    }
+
+    /// Shares submitted by the workers since `start`
+    pub fn submitted_hashes(&self) -> u64 {
+        self.counters.submitted_hashes.load(Ordering::SeqCst)
+    }
+
+    /// Called by the main loop for every share the pool accepted
+    pub fn record_accepted_share(&self) {
+        self.counters
+            .accepted_shares
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Called by the main loop for every share submission the pool answered with an error
+    pub fn record_rejected_share(&self) {
+        self.counters
+            .rejected_shares
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Submitted shares per computed hash since `start`, see `efficacy_ratio`
+    pub fn efficacy_ratio(&self) -> f64 {
+        efficacy_ratio(self.submitted_hashes(), self.hash_count())
+    }
+
+    /// Current values for printing
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            raw_hashrate_khs: self.raw_hashrate_khs(),
+            hashrate_khs: self.ema_hashrate_khs(),
+            hash_count: self.hash_count(),
+            accepted_shares: self.counters.accepted_shares.load(Ordering::SeqCst),
+            rejected_shares: self.counters.rejected_shares.load(Ordering::SeqCst),
+            uptime_secs: self.session_uptime_secs(),
+        }
+    }
+
+    /// Seconds since the current pool connection (this metric) was started
+    pub fn session_uptime_secs(&self) -> u64 {
+        self.session_start_time.elapsed().as_secs()
+    }
+
+    /// Seconds since the miner start passed to `start_since`, not reset on reconnects
+    pub fn total_uptime_secs(&self) -> u64 {
+        self.miner_start_time.elapsed().as_secs()
     }
 
     pub fn stop(&self) {
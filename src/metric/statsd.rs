@@ -0,0 +1,152 @@
+//! Pushes the hashrate and share counts to a StatsD server (e.g. for Grafana or InfluxDB).
+
+use super::super::channel::{unbounded, RecvTimeoutError, Sender};
+use super::{Counters, Metric};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The `[statsd]` section of the config, disabled if missing
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct StatsdConfig {
+    pub enabled: bool,
+    /// `host:port` of the StatsD server
+    pub host: String,
+    /// prepended to the metric names, e.g. `mithril.hashrate_khs`
+    pub prefix: String,
+    pub sample_interval_seconds: u64,
+}
+
+impl Default for StatsdConfig {
+    fn default() -> Self {
+        StatsdConfig {
+            enabled: false,
+            host: "127.0.0.1:8125".to_string(),
+            prefix: "mithril".to_string(),
+            sample_interval_seconds: 10,
+        }
+    }
+}
+
+/// Sends the values of a `Metric` to a StatsD server every `sample_interval_seconds`
+pub struct StatsdReporter {
+    pub host: SocketAddr,
+    pub prefix: String,
+    hnd: thread::JoinHandle<()>,
+    stop_sndr: Sender<()>,
+}
+
+impl StatsdReporter {
+    /// Resolves `config.host` and starts the reporting thread. Every interval one
+    /// datagram per value is sent, the hashrate is the average over the interval.
+    pub fn start(config: &StatsdConfig, metric: &Metric) -> io::Result<StatsdReporter> {
+        let host = config.host.to_socket_addrs()?.next().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("statsd host {} not found", config.host),
+            )
+        })?;
+        if config.sample_interval_seconds == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "statsd sample interval must be positive",
+            ));
+        }
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        let prefix = config.prefix.clone();
+        let interval = Duration::from_secs(config.sample_interval_seconds);
+        let counters = metric.counters.clone();
+        let (stop_sndr, stop_rcvr) = unbounded();
+
+        let thread_prefix = prefix.clone();
+        let mut counts = ReportedCounts::new(&counters);
+        let hnd = thread::Builder::new()
+            .name("statsd thread".to_string())
+            .spawn(move || {
+                loop {
+                    match stop_rcvr.recv_timeout(interval) {
+                        Ok(()) | Err(RecvTimeoutError::Disconnected) => break,
+                        Err(RecvTimeoutError::Timeout) => {}
+                    }
+                    let (hashrate_khs, accepted, rejected) = counts.update(&counters);
+                    for datagram in
+                        statsd_datagrams(&thread_prefix, hashrate_khs, accepted, rejected)
+                    {
+                        if let Err(err) = socket.send_to(datagram.as_bytes(), host) {
+                            warn!("sending to statsd {} failed {:?}", host, err);
+                        }
+                    }
+                }
+                info!("statsd thread stopped");
+            })?;
+
+        Ok(StatsdReporter {
+            host,
+            prefix,
+            hnd,
+            stop_sndr,
+        })
+    }
+
+    pub fn stop(&self) {
+        if self.stop_sndr.send(()).is_err() {
+            info!("statsd thread already stopped");
+        }
+    }
+
+    pub fn join(self) {
+        let _ = self.hnd.join();
+    }
+}
+
+/// Counts at the previous report, for the values since then
+struct ReportedCounts {
+    at: Instant,
+    hashes: u64,
+    accepted: u64,
+    rejected: u64,
+}
+
+impl ReportedCounts {
+    fn new(counters: &Counters) -> ReportedCounts {
+        ReportedCounts {
+            at: Instant::now(),
+            hashes: counters.total_hashes.load(Ordering::SeqCst),
+            accepted: counters.accepted_shares.load(Ordering::SeqCst),
+            rejected: counters.rejected_shares.load(Ordering::SeqCst),
+        }
+    }
+
+    /// kH/s, accepted and rejected shares since the previous call
+    fn update(&mut self, counters: &Counters) -> (f64, u64, u64) {
+        let now = ReportedCounts::new(counters);
+        let secs = now.at.duration_since(self.at).as_secs_f64();
+        let hashrate_khs = if secs > 0.0 {
+            now.hashes.saturating_sub(self.hashes) as f64 / secs / 1000.0
+        } else {
+            0.0
+        };
+        let accepted = now.accepted - self.accepted;
+        let rejected = now.rejected - self.rejected;
+        *self = now;
+        (hashrate_khs, accepted, rejected)
+    }
+}
+
+/// `<prefix>.hashrate_khs:<value>|g`, `<prefix>.accepted:<delta>|c` and
+/// `<prefix>.rejected:<delta>|c`
+pub fn statsd_datagrams(
+    prefix: &str,
+    hashrate_khs: f64,
+    accepted: u64,
+    rejected: u64,
+) -> Vec<String> {
+    vec![
+        format!("{}.hashrate_khs:{:.3}|g", prefix, hashrate_khs),
+        format!("{}.accepted:{}|c", prefix, accepted),
+        format!("{}.rejected:{}|c", prefix, rejected),
+    ]
+}
@@ -1,14 +1,95 @@
 extern crate config;
+extern crate serde_json;
 
-use metric::MetricConfig;
+use bandit_tools::BanditStrategy;
+use metric::statsd::StatsdConfig;
+use metric::{MetricConfig, DEFAULT_CHANNEL_CAPACITY, DEFAULT_MAX_FILE_SIZE_BYTES, EMA_ALPHA};
 use stratum::stratum_data::PoolConfig;
-use worker::worker_pool::WorkerConfig;
+use worker::worker_pool::{WorkerConfig, DEFAULT_SHARE_TIMEOUT_SECS};
 
-use self::config::{Config, ConfigError, File};
+use self::config::{Config, File};
 use std;
+use std::error::Error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
 use std::path::Path;
 
-pub const CONFIG_FILE_NAME: &str = "default_config.toml";
+/// Name of the config file, `default_config.toml` unless `MITHRIL_CONFIG_FILE_NAME`
+/// was set when compiling (see `build.rs`)
+pub const CONFIG_FILE_NAME: &str = env!("MITHRIL_CONFIG_FILE_NAME_DEFAULT");
+
+/// Written by `write_default_config`, every option with a placeholder or its default value
+pub const EXAMPLE_CONFIG: &str = r#"# Mithril config, see the README for details on the options
+
+[pool]
+# stratum pool as host:port (or ws://host:port/path with the websocket feature)
+pool_address = "pool.example.com:3333"
+# your Monero address the pool pays the reward to
+wallet_address = "YOUR_MONERO_WALLET_ADDRESS"
+pool_password = "x"
+# login error codes after which mithril exits instead of reconnecting every 60 seconds
+# (24 and 25 are used by some pools for banned IPs), set exit_on_fatal_error = false to keep retrying
+exit_on_fatal_error = true
+fatal_error_codes = [24, 25]
+
+[worker]
+# number of mining threads, ignored if auto_tune is enabled
+num_threads = 4
+# finds the best thread count for the hardware via a multi-armed bandit algorithm
+auto_tune = false
+# minutes an arm is evaluated before a new arm is drawn
+auto_tune_interval_minutes = 15
+# log of every bandit step (for the Bandit-Tools)
+auto_tune_log = "./bandit.log"
+# bandit algorithm of auto_tune, "annealing_softmax" or "ucb1"
+bandit_strategy = "annealing_softmax"
+# a warning is logged if no share was submitted for this long (the difficulty may be too
+# high for the hashrate, or the pool stopped sending jobs)
+share_timeout_secs = 1800
+# builds the dataset of a new seed_hash in the background while the workers keep hashing
+# the current job (needs the RAM of a second dataset, about 2 GiB, during the switch)
+warm_swap = false
+# restricts mithril to the syscalls it needs with a seccomp filter (linux only)
+sandbox = false
+
+[metric]
+# writes the hash counts to report_file
+enabled = false
+# determines how often a hash result is reported
+resolution = 1000
+sample_interval_seconds = 5
+report_file = "metric.csv"
+# report_file is moved to report_file.1 at this size
+max_file_size_bytes = 52428800
+# hash count events bypass the channel (with a warning) while it is full
+channel_capacity = 65536
+# smoothing factor of the EMA hashrate printed next to the raw one (0 < ema_alpha <= 1,
+# higher follows changes faster), also used if the metric is disabled
+ema_alpha = 0.2
+
+[donation]
+# percentage of the time mining for the project, set to 0 to disable
+percentage = 0
+# optional, donate to another pool/address instead of the built-in one:
+# pool_address = "xmrpool.eu:3333"
+# wallet_address = "..."
+# pool_password = "x"
+
+[statsd]
+# sends the hashrate and share counts to a StatsD server (e.g. for Grafana or InfluxDB)
+enabled = false
+host = "127.0.0.1:8125"
+prefix = "mithril"
+sample_interval_seconds = 10
+"#;
+
+/// `ConfigError::InvalidField` reason of counts and intervals that are 0 or negative
+const MUST_BE_POSITIVE: &str = "must be positive";
+
+/// `ConfigError::InvalidField` reason of a smoothing factor outside of (0, 1]
+const EMA_ALPHA_RANGE: &str = "has to be greater than 0 and at most 1";
 
 /// contains all configurations for mithril
 #[derive(Clone)]
@@ -17,32 +98,574 @@ pub struct MithrilConfig {
     pub worker_conf: WorkerConfig,
     pub metric_conf: MetricConfig,
     pub donation_conf: DonationConfig,
+    pub fatal_error_conf: FatalErrorConfig,
+    /// the `[statsd]` section, disabled if it is missing
+    pub statsd_conf: StatsdConfig,
+    /// install `sandbox::apply_seccomp_filter` after the login (linux only, `worker.sandbox`)
+    pub sandbox: bool,
+}
+
+/// Login error codes after which reconnecting does not help (e.g. a banned IP)
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FatalErrorConfig {
+    /// if false, mithril keeps reconnecting on fatal errors (for embedding applications)
+    pub exit_on_fatal_error: bool,
+    pub fatal_error_codes: Vec<i64>,
+}
+
+impl Default for FatalErrorConfig {
+    fn default() -> Self {
+        FatalErrorConfig {
+            exit_on_fatal_error: true,
+            fatal_error_codes: vec![24, 25],
+        }
+    }
+}
+
+impl FatalErrorConfig {
+    /// True if mithril should exit after the pool rejected the login with `code`
+    pub fn is_fatal(&self, code: i64) -> bool {
+        self.exit_on_fatal_error && self.fatal_error_codes.contains(&code)
+    }
 }
 
 #[derive(Clone)]
 pub struct DonationConfig {
     pub percentage: f64,
+    /// pool used for donation hashing instead of the built-in `donation_conf()`
+    pub custom_pool: Option<PoolConfig>,
+}
+
+/// A contradiction between options detected by `MithrilConfig::validate`
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigWarning {
+    /// non-fatal, mithril runs but an option does not do what it suggests
+    Warning(String),
+    /// fatal, mithril must not start with this configuration
+    Error(String),
+}
+
+impl ConfigWarning {
+    pub fn is_error(&self) -> bool {
+        match self {
+            ConfigWarning::Warning(_) => false,
+            ConfigWarning::Error(_) => true,
+        }
+    }
+}
+
+impl MithrilConfig {
+    /// The pool to connect to next, the donation pool while donation hashing
+    pub fn active_pool_conf(&self, donation_hashing: bool) -> PoolConfig {
+        if donation_hashing {
+            self.donation_conf
+                .custom_pool
+                .clone()
+                .unwrap_or_else(donation_conf)
+        } else {
+            self.pool_conf.clone()
+        }
+    }
+
+    /// Checks for options that are valid on their own but contradict each other.
+    pub fn validate(&self) -> Vec<ConfigWarning> {
+        let mut result = Vec::new();
+        let auto_tune = self.worker_conf.auto_tune;
+        let percentage = self.donation_conf.percentage;
+
+        if auto_tune && self.worker_conf.num_threads > 1 {
+            result.push(ConfigWarning::Warning(format!(
+                "num_threads = {} is ignored, auto_tune selects the thread count",
+                self.worker_conf.num_threads
+            )));
+        }
+        if !(0.0..=100.0).contains(&percentage) {
+            result.push(ConfigWarning::Error(format!(
+                "donation percentage {} has to be between 0 and 100",
+                percentage
+            )));
+        } else if auto_tune && percentage >= 100.0 {
+            result.push(ConfigWarning::Error(
+                "auto_tune cannot evaluate thread counts with a donation percentage of 100"
+                    .to_string(),
+            ));
+        }
+        //the sandbox is installed after the login, afterwards no sockets or folders can be created
+        if self.sandbox && auto_tune {
+            result.push(ConfigWarning::Warning(
+                "the sandbox does not allow auto_tune to save the bandit state".to_string(),
+            ));
+        }
+        if self.sandbox && percentage > 0.0 {
+            result.push(ConfigWarning::Warning(
+                "the sandbox does not allow connecting to the donation pool, donation hashing is skipped"
+                    .to_string(),
+            ));
+        }
+        if self.sandbox && self.statsd_conf.enabled {
+            result.push(ConfigWarning::Warning(
+                "the sandbox does not allow sending metrics to statsd".to_string(),
+            ));
+        }
+        result
+    }
+}
+
+/// Builds a `MithrilConfig` from code instead of a config file, all options
+/// not set keep the defaults of `default_config.toml` (with metrics disabled).
+///
+/// ```
+/// use mithril::mithril_config::MithrilConfigBuilder;
+///
+/// let config = MithrilConfigBuilder::new()
+///     .pool_address("xmrpool.eu:3333")
+///     .wallet("48y3RCT5SzSS4jumHm9rRL91eWWzd6xcVGSCF1KUZGWYJ6npqwFxHee4xkLLNUqY4NjiswdJhxFALeRqzncHoToeJMg2bhL")
+///     .num_threads(1)
+///     .build()
+///     .unwrap();
+/// assert_eq!(config.worker_conf.num_threads, 1);
+/// ```
+#[derive(Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MithrilConfigBuilder {
+    pool_address: String,
+    wallet_address: String,
+    pool_password: String,
+    num_threads: usize,
+    auto_tune: bool,
+    auto_tune_interval_minutes: u64,
+    auto_tune_log: String,
+    bandit_strategy: BanditStrategy,
+    share_timeout_secs: u64,
+    warm_swap: bool,
+    metric_enabled: bool,
+    metric_resolution: u64,
+    metric_sample_interval_seconds: u64,
+    metric_report_file: String,
+    metric_max_file_size_bytes: u64,
+    metric_channel_capacity: usize,
+    metric_ema_alpha: f64,
+    donation_percentage: f64,
+    donation_pool: Option<PoolConfig>,
+    fatal_error_conf: FatalErrorConfig,
+    statsd_conf: StatsdConfig,
+    sandbox: bool,
+}
+
+impl Default for MithrilConfigBuilder {
+    fn default() -> Self {
+        MithrilConfigBuilder::new()
+    }
 }
 
-pub fn read_config(conf_file: &Path, filename: &str) -> Result<MithrilConfig, config::ConfigError> {
+impl MithrilConfigBuilder {
+    pub fn new() -> MithrilConfigBuilder {
+        MithrilConfigBuilder {
+            pool_address: String::new(),
+            wallet_address: String::new(),
+            pool_password: String::new(),
+            num_threads: 4,
+            auto_tune: false,
+            auto_tune_interval_minutes: 15,
+            auto_tune_log: "./bandit.log".to_string(),
+            bandit_strategy: BanditStrategy::default(),
+            share_timeout_secs: DEFAULT_SHARE_TIMEOUT_SECS,
+            warm_swap: false,
+            metric_enabled: false,
+            metric_resolution: 1000,
+            metric_sample_interval_seconds: 5,
+            metric_report_file: "/dev/null".to_string(),
+            metric_max_file_size_bytes: DEFAULT_MAX_FILE_SIZE_BYTES,
+            metric_channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            metric_ema_alpha: EMA_ALPHA,
+            donation_percentage: 0.0,
+            donation_pool: None,
+            fatal_error_conf: FatalErrorConfig::default(),
+            statsd_conf: StatsdConfig::default(),
+            sandbox: false,
+        }
+    }
+
+    /// Reads the options from a JSON object with the field names of the builder, e.g.
+    /// `{"pool_address": "xmrpool.eu:3333", "wallet_address": "...", "num_threads": 2}`.
+    /// Missing fields keep their defaults, unknown fields are an error.
+    pub fn from_json(json: &str) -> Result<MithrilConfigBuilder, ConfigError> {
+        serde_json::from_str(json).map_err(ConfigError::Json)
+    }
+
+    pub fn pool_address(&mut self, addr: &str) -> &mut Self {
+        self.pool_address = addr.to_string();
+        self
+    }
+
+    pub fn wallet(&mut self, addr: &str) -> &mut Self {
+        self.wallet_address = addr.to_string();
+        self
+    }
+
+    pub fn pool_password(&mut self, password: &str) -> &mut Self {
+        self.pool_password = password.to_string();
+        self
+    }
+
+    pub fn num_threads(&mut self, n: usize) -> &mut Self {
+        self.num_threads = n;
+        self
+    }
+
+    pub fn auto_tune(&mut self, enabled: bool) -> &mut Self {
+        self.auto_tune = enabled;
+        self
+    }
+
+    pub fn auto_tune_interval_minutes(&mut self, minutes: u64) -> &mut Self {
+        self.auto_tune_interval_minutes = minutes;
+        self
+    }
+
+    pub fn auto_tune_log(&mut self, log_file: &str) -> &mut Self {
+        self.auto_tune_log = log_file.to_string();
+        self
+    }
+
+    pub fn bandit_strategy(&mut self, strategy: BanditStrategy) -> &mut Self {
+        self.bandit_strategy = strategy;
+        self
+    }
+
+    /// seconds without a submitted share after which a warning is logged
+    pub fn share_timeout_secs(&mut self, secs: u64) -> &mut Self {
+        self.share_timeout_secs = secs;
+        self
+    }
+
+    /// see `WorkerPool::set_warm_swap`
+    pub fn warm_swap(&mut self, enabled: bool) -> &mut Self {
+        self.warm_swap = enabled;
+        self
+    }
+
+    pub fn metric_enabled(&mut self, enabled: bool) -> &mut Self {
+        self.metric_enabled = enabled;
+        self
+    }
+
+    /// number of hashes after which a worker reports its hash count
+    pub fn metric_resolution(&mut self, resolution: u64) -> &mut Self {
+        self.metric_resolution = resolution;
+        self
+    }
+
+    pub fn metric_sample_interval_seconds(&mut self, seconds: u64) -> &mut Self {
+        self.metric_sample_interval_seconds = seconds;
+        self
+    }
+
+    pub fn metric_report_file(&mut self, report_file: &str) -> &mut Self {
+        self.metric_report_file = report_file.to_string();
+        self
+    }
+
+    pub fn metric_max_file_size_bytes(&mut self, max_size: u64) -> &mut Self {
+        self.metric_max_file_size_bytes = max_size;
+        self
+    }
+
+    pub fn metric_channel_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.metric_channel_capacity = capacity;
+        self
+    }
+
+    /// smoothing factor of the EMA hashrate, see `MetricConfig::ema_alpha`
+    pub fn metric_ema_alpha(&mut self, alpha: f64) -> &mut Self {
+        self.metric_ema_alpha = alpha;
+        self
+    }
+
+    pub fn donation_percentage(&mut self, percentage: f64) -> &mut Self {
+        self.donation_percentage = percentage;
+        self
+    }
+
+    pub fn donation_pool(&mut self, pool: PoolConfig) -> &mut Self {
+        self.donation_pool = Some(pool);
+        self
+    }
+
+    pub fn exit_on_fatal_error(&mut self, exit: bool) -> &mut Self {
+        self.fatal_error_conf.exit_on_fatal_error = exit;
+        self
+    }
+
+    pub fn fatal_error_codes(&mut self, codes: Vec<i64>) -> &mut Self {
+        self.fatal_error_conf.fatal_error_codes = codes;
+        self
+    }
+
+    /// Enables sending the metrics to the StatsD server at `host` (`host:port`)
+    pub fn statsd(&mut self, host: &str, prefix: &str) -> &mut Self {
+        self.statsd_conf.enabled = true;
+        self.statsd_conf.host = host.to_string();
+        self.statsd_conf.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn sandbox(&mut self, enabled: bool) -> &mut Self {
+        self.sandbox = enabled;
+        self
+    }
+
+    /// Checks the options the same way `read_config` does and fails on the
+    /// combinations `MithrilConfig::validate` reports as fatal.
+    pub fn build(&self) -> Result<MithrilConfig, ConfigError> {
+        if self.pool_address.is_empty() {
+            return Err(ConfigError::invalid_field("pool_address", "", "must be set"));
+        }
+        if self.wallet_address.is_empty() {
+            return Err(ConfigError::invalid_field("wallet_address", "", "must be set"));
+        }
+        if self.num_threads == 0 {
+            return Err(ConfigError::invalid_field("num_threads", 0, MUST_BE_POSITIVE));
+        }
+        if self.auto_tune_interval_minutes == 0 {
+            return Err(ConfigError::invalid_field(
+                "auto_tune_interval_minutes",
+                0,
+                MUST_BE_POSITIVE,
+            ));
+        }
+        if self.share_timeout_secs == 0 {
+            return Err(ConfigError::invalid_field(
+                "share_timeout_secs",
+                0,
+                MUST_BE_POSITIVE,
+            ));
+        }
+        if !(0.0..=100.0).contains(&self.donation_percentage) {
+            return Err(ConfigError::invalid_field(
+                "donation_percentage",
+                self.donation_percentage,
+                "has to be between 0 and 100",
+            ));
+        }
+        if !(self.metric_ema_alpha > 0.0 && self.metric_ema_alpha <= 1.0) {
+            return Err(ConfigError::invalid_field(
+                "metric_ema_alpha",
+                self.metric_ema_alpha,
+                EMA_ALPHA_RANGE,
+            ));
+        }
+        if self.auto_tune && self.donation_percentage >= 100.0 {
+            return Err(ConfigError::invalid_field(
+                "donation_percentage",
+                self.donation_percentage,
+                "auto_tune cannot evaluate thread counts with a donation percentage of 100",
+            ));
+        }
+
+        let metric_conf = if self.metric_enabled {
+            for (field, value) in &[
+                ("metric_resolution", self.metric_resolution),
+                (
+                    "metric_sample_interval_seconds",
+                    self.metric_sample_interval_seconds,
+                ),
+                (
+                    "metric_max_file_size_bytes",
+                    self.metric_max_file_size_bytes,
+                ),
+                (
+                    "metric_channel_capacity",
+                    self.metric_channel_capacity as u64,
+                ),
+            ] {
+                if *value == 0 {
+                    return Err(ConfigError::invalid_field(field, value, MUST_BE_POSITIVE));
+                }
+            }
+            MetricConfig {
+                enabled: true,
+                resolution: self.metric_resolution,
+                sample_interval_seconds: self.metric_sample_interval_seconds,
+                report_file: self.metric_report_file.clone(),
+                max_file_size_bytes: self.metric_max_file_size_bytes,
+                channel_capacity: self.metric_channel_capacity,
+                ema_alpha: self.metric_ema_alpha,
+            }
+        } else {
+            disabled_metric_config(self.metric_ema_alpha)
+        };
+
+        Ok(MithrilConfig {
+            pool_conf: PoolConfig {
+                pool_address: self.pool_address.clone(),
+                wallet_address: self.wallet_address.clone(),
+                pool_password: self.pool_password.clone(),
+            },
+            worker_conf: WorkerConfig {
+                num_threads: self.num_threads as u64,
+                auto_tune: self.auto_tune,
+                auto_tune_interval_minutes: self.auto_tune_interval_minutes,
+                auto_tune_log: self.auto_tune_log.clone(),
+                bandit_strategy: self.bandit_strategy,
+                share_timeout_secs: self.share_timeout_secs,
+                warm_swap: self.warm_swap,
+            },
+            metric_conf,
+            donation_conf: DonationConfig {
+                percentage: self.donation_percentage,
+                custom_pool: self.donation_pool.clone(),
+            },
+            fatal_error_conf: self.fatal_error_conf.clone(),
+            statsd_conf: self.statsd_conf.clone(),
+            sandbox: self.sandbox,
+        })
+    }
+}
+
+/// Error of `read_config`, `MithrilConfigBuilder::from_json` and `MithrilConfigBuilder::build`
+#[derive(Debug)]
+pub enum ConfigError {
+    /// the file could not be read, or a field is missing or has the wrong type
+    Config(config::ConfigError),
+    /// invalid JSON passed to `MithrilConfigBuilder::from_json`
+    Json(serde_json::Error),
+    /// a field has a value outside of its valid range
+    InvalidField {
+        field: &'static str,
+        value: String,
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    fn invalid_field<V: fmt::Display>(field: &'static str, value: V, reason: &str) -> ConfigError {
+        ConfigError::InvalidField {
+            field,
+            value: value.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Config(err) => write!(f, "config error: {}", err),
+            ConfigError::Json(err) => write!(f, "config error: {}", err),
+            ConfigError::InvalidField {
+                field,
+                value,
+                reason,
+            } => write!(
+                f,
+                "config error: field '{}' has invalid value '{}': {}",
+                field, value, reason
+            ),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<config::ConfigError> for ConfigError {
+    fn from(err: config::ConfigError) -> ConfigError {
+        ConfigError::Config(err)
+    }
+}
+
+pub fn read_config(conf_file: &Path, filename: &str) -> Result<MithrilConfig, ConfigError> {
     let config = parse_conf(conf_file, filename)?;
 
     let pool_conf = pool_config(&config)?;
     let worker_conf = worker_config(&config)?;
     let metric_conf = metric_config(&config)?;
     let donation_conf = donation_config(&config)?;
+    let fatal_error_conf = fatal_error_config(&config)?;
+    let statsd_conf = statsd_config(&config)?;
+    //optional, off by default
+    let sandbox = match config.get_bool("worker.sandbox") {
+        Err(config::ConfigError::NotFound(_)) => false,
+        sandbox => sandbox?,
+    };
 
     Ok(MithrilConfig {
         pool_conf,
         worker_conf,
         metric_conf,
         donation_conf,
+        fatal_error_conf,
+        statsd_conf,
+        sandbox,
     })
 }
 
+/// The `[statsd]` section and all of its fields are optional
+fn statsd_config(conf: &Config) -> Result<StatsdConfig, ConfigError> {
+    let mut statsd_conf = StatsdConfig::default();
+    match conf.get_bool("statsd.enabled") {
+        Err(config::ConfigError::NotFound(_)) => {}
+        enabled => statsd_conf.enabled = enabled?,
+    }
+    match conf.get_string("statsd.host") {
+        Err(config::ConfigError::NotFound(_)) => {}
+        host => statsd_conf.host = host?,
+    }
+    match conf.get_string("statsd.prefix") {
+        Err(config::ConfigError::NotFound(_)) => {}
+        prefix => statsd_conf.prefix = prefix?,
+    }
+    match conf.get_int("statsd.sample_interval_seconds") {
+        Err(config::ConfigError::NotFound(_)) => {}
+        _ => {
+            statsd_conf.sample_interval_seconds =
+                get_u64_no_zero(conf, "statsd.sample_interval_seconds")?
+        }
+    }
+    Ok(statsd_conf)
+}
+
+/// `pool.exit_on_fatal_error` and `pool.fatal_error_codes` are optional
+fn fatal_error_config(conf: &Config) -> Result<FatalErrorConfig, ConfigError> {
+    let mut fatal_error_conf = FatalErrorConfig::default();
+    match conf.get_bool("pool.exit_on_fatal_error") {
+        Err(config::ConfigError::NotFound(_)) => {}
+        exit => fatal_error_conf.exit_on_fatal_error = exit?,
+    }
+    match conf.get_array("pool.fatal_error_codes") {
+        Err(config::ConfigError::NotFound(_)) => {}
+        codes => {
+            fatal_error_conf.fatal_error_codes = codes?
+                .into_iter()
+                .map(|code| code.into_int())
+                .collect::<Result<_, _>>()?
+        }
+    }
+    Ok(fatal_error_conf)
+}
+
 fn donation_config(conf: &Config) -> Result<DonationConfig, ConfigError> {
     let percentage = conf.get_float("donation.percentage")?;
-    Ok(DonationConfig { percentage })
+    if !(0.0..=100.0).contains(&percentage) {
+        return Err(ConfigError::invalid_field(
+            "donation.percentage",
+            percentage,
+            "has to be between 0 and 100",
+        ));
+    }
+    let custom_pool = match conf.get_string("donation.pool_address") {
+        Err(config::ConfigError::NotFound(_)) => None,
+        pool_address => Some(PoolConfig {
+            pool_address: pool_address?,
+            wallet_address: conf.get_string("donation.wallet_address")?,
+            pool_password: conf.get_string("donation.pool_password")?,
+        }),
+    };
+    Ok(DonationConfig {
+        percentage,
+        custom_pool,
+    })
 }
 
 fn pool_config(conf: &Config) -> Result<PoolConfig, ConfigError> {
@@ -59,8 +682,10 @@ fn pool_config(conf: &Config) -> Result<PoolConfig, ConfigError> {
 fn worker_config(conf: &Config) -> Result<WorkerConfig, ConfigError> {
     let num_threads = conf.get_int("worker.num_threads")?;
     if num_threads <= 0 {
-        return Err(ConfigError::Message(
-            "num_threads has to be > 0".to_string(),
+        return Err(ConfigError::invalid_field(
+            "worker.num_threads",
+            num_threads,
+            MUST_BE_POSITIVE,
         ));
     }
 
@@ -68,47 +693,110 @@ fn worker_config(conf: &Config) -> Result<WorkerConfig, ConfigError> {
 
     let auto_tune_interval_minutes = conf.get_int("worker.auto_tune_interval_minutes")?;
     if auto_tune_interval_minutes <= 0 {
-        return Err(ConfigError::Message(
-            "auto_tune_interval_minutes has to be > 0".to_string(),
+        return Err(ConfigError::invalid_field(
+            "worker.auto_tune_interval_minutes",
+            auto_tune_interval_minutes,
+            MUST_BE_POSITIVE,
         ));
     }
 
     let auto_tune_log = conf.get_string("worker.auto_tune_log")?;
 
+    let bandit_strategy = match conf.get_string("worker.bandit_strategy") {
+        Err(config::ConfigError::NotFound(_)) => BanditStrategy::default(),
+        name => {
+            let name = name?;
+            match BanditStrategy::from_name(&name) {
+                Some(strategy) => strategy,
+                None => {
+                    return Err(ConfigError::invalid_field(
+                        "worker.bandit_strategy",
+                        name,
+                        "must be \"annealing_softmax\" or \"ucb1\"",
+                    ))
+                }
+            }
+        }
+    };
+
+    let share_timeout_secs = match conf.get_int("worker.share_timeout_secs") {
+        Err(config::ConfigError::NotFound(_)) => DEFAULT_SHARE_TIMEOUT_SECS,
+        _ => get_u64_no_zero(conf, "worker.share_timeout_secs")?,
+    };
+
+    //optional, off by default
+    let warm_swap = match conf.get_bool("worker.warm_swap") {
+        Err(config::ConfigError::NotFound(_)) => false,
+        warm_swap => warm_swap?,
+    };
+
     Ok(WorkerConfig {
         num_threads: num_threads as u64,
         auto_tune,
         auto_tune_interval_minutes: auto_tune_interval_minutes as u64,
         auto_tune_log,
+        bandit_strategy,
+        share_timeout_secs,
+        warm_swap,
     })
 }
 
 fn metric_config(conf: &Config) -> Result<MetricConfig, ConfigError> {
     let enabled = conf.get_bool("metric.enabled")?;
+    let ema_alpha = match conf.get_float("metric.ema_alpha") {
+        Err(config::ConfigError::NotFound(_)) => EMA_ALPHA,
+        ema_alpha => ema_alpha?,
+    };
+    if !(ema_alpha > 0.0 && ema_alpha <= 1.0) {
+        return Err(ConfigError::invalid_field(
+            "metric.ema_alpha",
+            ema_alpha,
+            EMA_ALPHA_RANGE,
+        ));
+    }
     if enabled {
         let resolution = get_u64_no_zero(conf, "metric.resolution")?;
         let sample_interval_seconds = get_u64_no_zero(conf, "metric.sample_interval_seconds")?;
         let report_file = conf.get_string("metric.report_file")?;
+        let max_file_size_bytes = match conf.get_int("metric.max_file_size_bytes") {
+            Err(config::ConfigError::NotFound(_)) => DEFAULT_MAX_FILE_SIZE_BYTES,
+            _ => get_u64_no_zero(conf, "metric.max_file_size_bytes")?,
+        };
+        let channel_capacity = match conf.get_int("metric.channel_capacity") {
+            Err(config::ConfigError::NotFound(_)) => DEFAULT_CHANNEL_CAPACITY,
+            _ => get_u64_no_zero(conf, "metric.channel_capacity")? as usize,
+        };
         Ok(MetricConfig {
             enabled,
             resolution,
             sample_interval_seconds,
             report_file,
+            max_file_size_bytes,
+            channel_capacity,
+            ema_alpha,
         })
     } else {
-        Ok(MetricConfig {
-            enabled: false,
-            resolution: std::u32::MAX as u64,
-            sample_interval_seconds: std::u32::MAX as u64,
-            report_file: "/dev/null".to_string(),
-        })
+        Ok(disabled_metric_config(ema_alpha))
     }
 }
 
-fn get_u64_no_zero(conf: &Config, field: &str) -> Result<u64, ConfigError> {
+/// Only the EMA hashrate is computed, for printing it
+fn disabled_metric_config(ema_alpha: f64) -> MetricConfig {
+    MetricConfig {
+        enabled: false,
+        resolution: std::u32::MAX as u64,
+        sample_interval_seconds: std::u32::MAX as u64,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+        ema_alpha,
+    }
+}
+
+fn get_u64_no_zero(conf: &Config, field: &'static str) -> Result<u64, ConfigError> {
     let val = conf.get_int(field)?;
     if val <= 0 {
-        return Err(ConfigError::Message(format!("{} has to be > 0", field)));
+        return Err(ConfigError::invalid_field(field, val, MUST_BE_POSITIVE));
     }
     Ok(val as u64)
 }
@@ -119,9 +807,30 @@ fn parse_conf(conf_file: &Path, filename: &str) -> Result<Config, ConfigError> {
         conf.merge(File::with_name(filename))?;
         return Ok(conf);
     }
-    Err(ConfigError::Message("config file not found".to_string()))
+    Err(config::ConfigError::Message("config file not found".to_string()).into())
+}
+
+/// Writes `EXAMPLE_CONFIG` to `path`. An existing file is not overwritten,
+/// that is an `AlreadyExists` error.
+pub fn write_default_config(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(|e| {
+            if e.kind() == io::ErrorKind::AlreadyExists {
+                io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists, not overwriting it", path.display()),
+                )
+            } else {
+                e
+            }
+        })?;
+    file.write_all(EXAMPLE_CONFIG.as_bytes())
 }
 
+/// The built-in donation pool, hashing to it supports the project.
 pub fn donation_conf() -> PoolConfig {
     PoolConfig {
         pool_address: "xmrpool.eu:3333".to_string(),
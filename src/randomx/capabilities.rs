@@ -0,0 +1,39 @@
+//! CPU features detected once at runtime.
+
+use std::cell::Cell;
+use std::sync::OnceLock;
+
+static HAS_AES: OnceLock<bool> = OnceLock::new();
+
+thread_local! {
+    /// set while a `SoftAesGuard` of the thread is alive
+    static FORCE_SOFT_AES: Cell<bool> = const { Cell::new(false) };
+}
+
+/// True if the CPU supports AES-NI and the calling thread does not force the software
+/// rounds, otherwise the AES functions of `hash` (and `m128i::aesenc`/`aesdec`) use the
+/// (much slower) rounds of `m128i_soft`. The functions check it once per call.
+pub fn has_aes() -> bool {
+    !FORCE_SOFT_AES.with(Cell::get) && cpu_has_aes()
+}
+
+/// True if the CPU supports AES-NI
+pub fn cpu_has_aes() -> bool {
+    *HAS_AES.get_or_init(|| is_x86_feature_detected!("aes"))
+}
+
+/// Makes `has_aes` return false on the calling thread until dropped
+pub struct SoftAesGuard(bool);
+
+impl SoftAesGuard {
+    pub fn force() -> SoftAesGuard {
+        SoftAesGuard(FORCE_SOFT_AES.with(|force| force.replace(true)))
+    }
+}
+
+impl Drop for SoftAesGuard {
+    fn drop(&mut self) {
+        let previous = self.0;
+        FORCE_SOFT_AES.with(|force| force.set(previous));
+    }
+}
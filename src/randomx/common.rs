@@ -1,6 +1,15 @@
 const P_2EXP63: u64 = 1 << 63;
 const INT32_MAX: u32 = i32::MAX as u32;
 
+/// Size in bytes of the scratchpad part L1 memory operands access (mod 2 instructions),
+/// sized to fit the L1 data cache of a CPU core
+pub const RANDOMX_SCRATCHPAD_L1: usize = 16384;
+/// Size in bytes of the scratchpad part L2 memory operands access, sized to fit the L2 cache
+pub const RANDOMX_SCRATCHPAD_L2: usize = 262144;
+/// Size in bytes of the whole scratchpad, L3 operands and the per-iteration register
+/// loads and stores can access all of it
+pub const RANDOMX_SCRATCHPAD_L3: usize = 2097152;
+
 pub fn u64_from_i32_imm(imm: i32) -> u64 {
     let x = imm as u32;
     if x > INT32_MAX {
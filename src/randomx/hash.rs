@@ -1,4 +1,12 @@
-use super::m128::m128i;
+use super::capabilities::has_aes;
+use super::m128::{m128i, AesRounds, HardAes, SoftAes};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+    __m128i, __m512i, _mm512_aesdec_epi128, _mm512_aesenc_epi128, _mm512_broadcast_i32x4,
+    _mm512_loadu_si512, _mm512_mask_blend_epi64,
+};
+#[cfg(target_arch = "x86_64")]
+use std::mem;
 
 #[allow(overflowing_literals)]
 fn keys_1rx4() -> (m128i, m128i, m128i, m128i) {
@@ -10,55 +18,215 @@ fn keys_1rx4() -> (m128i, m128i, m128i, m128i) {
     )
 }
 
-#[allow(overflowing_literals)]
+/// Hashes the scratchpad, using the AVX-512 variant when the CPU supports it
+/// (and the software AES rounds are not forced, see `SoftAesGuard`).
 pub fn hash_aes_1rx4(input: &[u64]) -> [m128i; 4] {
-    debug_assert!(input.len() % 64 == 0);
+    hash_aes_nrx4::<4>(input)
+}
 
-    // Initialize state with constants
-    let mut state0 = m128i::from_i32(0xd7983aad, 0xcc82db47, 0x9fa856de, 0x92b52c0d);
-    let mut state1 = m128i::from_i32(0xace78057, 0xf59e125a, 0x15c7b798, 0x338d996e);
-    let mut state2 = m128i::from_i32(0xe8a07ce4, 0x5079506b, 0xae62c7d0, 0x6a770017);
-    let mut state3 = m128i::from_i32(0x7e994948, 0x79a10005, 0x07ad828d, 0x630a240c);
+/// `hash_aes_1rx4` with `N` parallel states, every block of the input is `N` 128 bit
+/// values (one per state). 4 is the only supported value so far (checked at compile
+/// time), a wider variant needs its own initial states.
+pub fn hash_aes_nrx4<const N: usize>(input: &[u64]) -> [m128i; N] {
+    const { assert!(N == 4, "hash_aes_nrx4 only supports 4 states") };
 
-    // Pre-calculate the chunks size for better prediction
-    let chunks = input.len() / 8;
-    
-    // Process input in chunks of 8 u64 values
-    for chunk in 0..chunks {
-        let base_idx = chunk * 8;
-        
-        // Load input values for this chunk
-        let in0 = m128i::from_u64(input[base_idx + 1], input[base_idx]);
-        let in1 = m128i::from_u64(input[base_idx + 3], input[base_idx + 2]);
-        let in2 = m128i::from_u64(input[base_idx + 5], input[base_idx + 4]);
-        let in3 = m128i::from_u64(input[base_idx + 7], input[base_idx + 6]);
+    #[cfg(target_arch = "x86_64")]
+    {
+        if has_aes() && is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("vaes") {
+            let state = unsafe { hash_aes_1rx4_avx512(input) };
+            return std::array::from_fn(|i| state[i]);
+        }
+    }
+    if has_aes() {
+        hash_aes_nrx4_with::<HardAes, N>(input)
+    } else {
+        hash_aes_nrx4_with::<SoftAes, N>(input)
+    }
+}
 
-        // Update states with AES operations
-        state0 = state0.aesenc(in0);
-        state1 = state1.aesdec(in1);
-        state2 = state2.aesenc(in2);
-        state3 = state3.aesdec(in3);
+fn hash_aes_nrx4_with<A: AesRounds, const N: usize>(input: &[u64]) -> [m128i; N] {
+    let mut state = initial_state_nrx4::<N>();
+    aes_rounds_nrx4::<A, N>(&mut state, input);
+    final_rounds_nrx4::<A, N>(&mut state);
+    state
+}
+
+pub fn hash_aes_1rx4_scalar(input: &[u64]) -> [m128i; 4] {
+    debug_assert!(
+        input.len() % 64 == 0,
+        "hash_aes_1rx4 input length {} is not a multiple of 64",
+        input.len()
+    );
+
+    let mut hasher = HashAes1rx4Hasher::new();
+    hasher.update(input);
+    hasher.finalize()
+}
+
+#[allow(overflowing_literals)]
+fn initial_state_nrx4<const N: usize>() -> [m128i; N] {
+    let state = [
+        m128i::from_i32(0xd7983aad, 0xcc82db47, 0x9fa856de, 0x92b52c0d),
+        m128i::from_i32(0xace78057, 0xf59e125a, 0x15c7b798, 0x338d996e),
+        m128i::from_i32(0xe8a07ce4, 0x5079506b, 0xae62c7d0, 0x6a770017),
+        m128i::from_i32(0x7e994948, 0x79a10005, 0x07ad828d, 0x630a240c),
+    ];
+    std::array::from_fn(|i| state[i])
+}
+
+/// One AES round per block, even states encrypt and odd states decrypt
+fn aes_rounds_nrx4<A: AesRounds, const N: usize>(state: &mut [m128i; N], input: &[u64]) {
+    for block in input.chunks_exact(2 * N) {
+        for (i, s) in state.iter_mut().enumerate() {
+            let key = m128i::from_u64(block[2 * i + 1], block[2 * i]);
+            *s = if i % 2 == 0 {
+                A::aesenc(*s, key)
+            } else {
+                A::aesdec(*s, key)
+            };
+        }
     }
+}
 
-    // Final mixing with constant keys
+/// Final mixing with constant keys
+#[allow(overflowing_literals)]
+fn final_rounds_nrx4<A: AesRounds, const N: usize>(state: &mut [m128i; N]) {
     let x_key_0 = m128i::from_i32(0x06890201, 0x90dc56bf, 0x8b24949f, 0xf6fa8389);
     let x_key_1 = m128i::from_i32(0xed18f99b, 0xee1043c6, 0x51f4e03c, 0x61b263d1);
 
-    // Apply final rounds of AES encryption/decryption
-    state0 = state0.aesenc(x_key_0);
-    state1 = state1.aesdec(x_key_0);
-    state2 = state2.aesenc(x_key_0);
-    state3 = state3.aesdec(x_key_0);
+    for x_key in &[x_key_0, x_key_1] {
+        for (i, s) in state.iter_mut().enumerate() {
+            *s = if i % 2 == 0 {
+                A::aesenc(*s, *x_key)
+            } else {
+                A::aesdec(*s, *x_key)
+            };
+        }
+    }
+}
 
-    state0 = state0.aesenc(x_key_1);
-    state1 = state1.aesdec(x_key_1);
-    state2 = state2.aesenc(x_key_1);
-    state3 = state3.aesdec(x_key_1);
+/// Streaming version of `hash_aes_1rx4_scalar`, the input can be fed in chunks
+/// (e.g. 512 bytes of the scratchpad at a time). Every chunk must be a multiple of
+/// 8 u64 values, the result is the same as hashing the concatenated chunks at once.
+pub struct HashAes1rx4Hasher {
+    state: [m128i; 4],
+    /// `has_aes` when the hasher was created
+    hard_aes: bool,
+}
 
-    [state0, state1, state2, state3]
+impl Default for HashAes1rx4Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
+impl HashAes1rx4Hasher {
+    pub fn new() -> HashAes1rx4Hasher {
+        HashAes1rx4Hasher {
+            state: initial_state_nrx4(),
+            hard_aes: has_aes(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u64]) {
+        debug_assert!(
+            chunk.len().is_multiple_of(8),
+            "hash_aes_1rx4 chunk length {} is not a multiple of 8",
+            chunk.len()
+        );
+        if self.hard_aes {
+            aes_rounds_nrx4::<HardAes, 4>(&mut self.state, chunk);
+        } else {
+            aes_rounds_nrx4::<SoftAes, 4>(&mut self.state, chunk);
+        }
+    }
+
+    pub fn finalize(mut self) -> [m128i; 4] {
+        if self.hard_aes {
+            final_rounds_nrx4::<HardAes, 4>(&mut self.state);
+        } else {
+            final_rounds_nrx4::<SoftAes, 4>(&mut self.state);
+        }
+        self.state
+    }
+}
+
+/// Same result as `hash_aes_1rx4_scalar`, but all four states live in one 512 bit
+/// register. Every round computes aesenc and aesdec over the whole register and
+/// blends the lanes 0 and 2 (enc) with the lanes 1 and 3 (dec).
+///
+/// # Safety
+///
+/// The CPU must support `avx512f` and `vaes`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,vaes")]
+#[allow(overflowing_literals)]
+pub unsafe fn hash_aes_1rx4_avx512(input: &[u64]) -> [m128i; 4] {
+    debug_assert!(
+        input.len() % 64 == 0,
+        "hash_aes_1rx4 input length {} is not a multiple of 64",
+        input.len()
+    );
+
+    // 64 bit elements of the lanes 0 and 2 (the aesenc states)
+    const ENC_MASK: u8 = 0b0011_0011;
+
+    let mut state: __m512i = mem::transmute::<[__m128i; 4], __m512i>([
+        m128i::from_i32(0xd7983aad, 0xcc82db47, 0x9fa856de, 0x92b52c0d).0,
+        m128i::from_i32(0xace78057, 0xf59e125a, 0x15c7b798, 0x338d996e).0,
+        m128i::from_i32(0xe8a07ce4, 0x5079506b, 0xae62c7d0, 0x6a770017).0,
+        m128i::from_i32(0x7e994948, 0x79a10005, 0x07ad828d, 0x630a240c).0,
+    ]);
+
+    // One chunk of 8 u64 values is exactly in0..in3
+    for chunk in input.chunks_exact(8) {
+        let key = _mm512_loadu_si512(chunk.as_ptr() as *const _);
+        state = _mm512_mask_blend_epi64(
+            ENC_MASK,
+            _mm512_aesdec_epi128(state, key),
+            _mm512_aesenc_epi128(state, key),
+        );
+    }
+
+    let x_key_0 = m128i::from_i32(0x06890201, 0x90dc56bf, 0x8b24949f, 0xf6fa8389);
+    let x_key_1 = m128i::from_i32(0xed18f99b, 0xee1043c6, 0x51f4e03c, 0x61b263d1);
+
+    for x_key in &[x_key_0, x_key_1] {
+        let key = _mm512_broadcast_i32x4(x_key.0);
+        state = _mm512_mask_blend_epi64(
+            ENC_MASK,
+            _mm512_aesdec_epi128(state, key),
+            _mm512_aesenc_epi128(state, key),
+        );
+    }
+
+    let lanes = mem::transmute::<__m512i, [__m128i; 4]>(state);
+    [
+        m128i(lanes[0]),
+        m128i(lanes[1]),
+        m128i(lanes[2]),
+        m128i(lanes[3]),
+    ]
+}
+
+/// Fills `into` with AES output generated from the `input` states and returns the
+/// final states. `into` is not resized: its length has to be a non-zero multiple
+/// of 8 (one chunk of the four 128 bit states), a remainder is left untouched.
+/// See `fill_aes_1rx4_u64_alloc` for a version allocating the output.
 pub fn fill_aes_1rx4_u64(input: &[m128i; 4], into: &mut Vec<u64>) -> [m128i; 4] {
+    debug_assert!(
+        into.len().is_multiple_of(8) && !into.is_empty(),
+        "fill_aes_1rx4 output length {} is not a non-zero multiple of 8",
+        into.len()
+    );
+    if has_aes() {
+        fill_aes_1rx4_u64_with::<HardAes>(input, into)
+    } else {
+        fill_aes_1rx4_u64_with::<SoftAes>(input, into)
+    }
+}
+
+fn fill_aes_1rx4_u64_with<A: AesRounds>(input: &[m128i; 4], into: &mut [u64]) -> [m128i; 4] {
     // Get the AES keys once
     let (key0, key1, key2, key3) = keys_1rx4();
     
@@ -77,10 +245,10 @@ pub fn fill_aes_1rx4_u64(input: &[m128i; 4], into: &mut Vec<u64>) -> [m128i; 4]
         let out_ix = chunk * 8;
         
         // Apply AES operations to states
-        state0 = state0.aesdec(key0);
-        state1 = state1.aesenc(key1);
-        state2 = state2.aesdec(key2);
-        state3 = state3.aesenc(key3);
+        state0 = A::aesdec(state0, key0);
+        state1 = A::aesenc(state1, key1);
+        state2 = A::aesdec(state2, key2);
+        state3 = A::aesenc(state3, key3);
         
         // Extract results from states
         let (s0_1, s0_0) = state0.as_i64();
@@ -103,7 +271,22 @@ pub fn fill_aes_1rx4_u64(input: &[m128i; 4], into: &mut Vec<u64>) -> [m128i; 4]
     [state0, state1, state2, state3]
 }
 
-fn fill_aes_1rx4_m128i(input: &[m128i; 4], into: &mut Vec<m128i>) -> [m128i; 4] {
+/// `fill_aes_1rx4_u64` into a newly allocated `Vec` of `len` values (a non-zero multiple of 8)
+pub fn fill_aes_1rx4_u64_alloc(input: &[m128i; 4], len: usize) -> Vec<u64> {
+    let mut into = vec![0; len];
+    fill_aes_1rx4_u64(input, &mut into);
+    into
+}
+
+fn fill_aes_1rx4_m128i(input: &[m128i; 4], into: &mut [m128i]) -> [m128i; 4] {
+    if has_aes() {
+        fill_aes_1rx4_m128i_with::<HardAes>(input, into)
+    } else {
+        fill_aes_1rx4_m128i_with::<SoftAes>(input, into)
+    }
+}
+
+fn fill_aes_1rx4_m128i_with<A: AesRounds>(input: &[m128i; 4], into: &mut [m128i]) -> [m128i; 4] {
     // Get AES keys
     let (key0, key1, key2, key3) = keys_1rx4();
     
@@ -121,10 +304,10 @@ fn fill_aes_1rx4_m128i(input: &[m128i; 4], into: &mut Vec<m128i>) -> [m128i; 4]
         let out_ix = chunk * 4;
         
         // Apply AES operations to states
-        state0 = state0.aesdec(key0);
-        state1 = state1.aesenc(key1);
-        state2 = state2.aesdec(key2);
-        state3 = state3.aesenc(key3);
+        state0 = A::aesdec(state0, key0);
+        state1 = A::aesenc(state1, key1);
+        state2 = A::aesdec(state2, key2);
+        state3 = A::aesenc(state3, key3);
         
         // Store results directly
         into[out_ix] = state0;
@@ -138,7 +321,12 @@ fn fill_aes_1rx4_m128i(input: &[m128i; 4], into: &mut Vec<m128i>) -> [m128i; 4]
 }
 
 pub fn gen_program_aes_1rx4(input: &[m128i; 4], output_size: usize) -> (Vec<m128i>, [m128i; 4]) {
-    debug_assert!(output_size % 4 == 0);
+    debug_assert!(
+        output_size % 4 == 0,
+        "output_size {} is not a multiple of 4 (seed {:?})",
+        output_size,
+        input
+    );
 
     // Preallocate the result vector with proper capacity
     let mut result: Vec<m128i> = vec![m128i::zero(); output_size];
@@ -149,10 +337,26 @@ pub fn gen_program_aes_1rx4(input: &[m128i; 4], output_size: usize) -> (Vec<m128
     (result, new_seed)
 }
 
+/// Generates `output_size` values, `output_size` has to be a multiple of 4
+/// (otherwise an error is logged and the result is empty).
 #[allow(overflowing_literals)]
 pub fn gen_program_aes_4rx4(input: &[m128i; 4], output_size: usize) -> Vec<m128i> {
-    debug_assert!(output_size % 4 == 0);
-    
+    if !output_size.is_multiple_of(4) {
+        error!(
+            "output_size {} is not a multiple of 4 (seed {:?})",
+            output_size, input
+        );
+        return Vec::with_capacity(0);
+    }
+    if has_aes() {
+        gen_program_aes_4rx4_with::<HardAes>(input, output_size)
+    } else {
+        gen_program_aes_4rx4_with::<SoftAes>(input, output_size)
+    }
+}
+
+#[allow(overflowing_literals)]
+fn gen_program_aes_4rx4_with<A: AesRounds>(input: &[m128i; 4], output_size: usize) -> Vec<m128i> {
     // Preallocate with exact capacity to avoid reallocations
     let mut result = Vec::with_capacity(output_size);
     
@@ -181,28 +385,28 @@ pub fn gen_program_aes_4rx4(input: &[m128i; 4], output_size: usize) -> Vec<m128i
     // Process each chunk
     for _ in 0..iterations {
         // First round of AES operations
-        state0 = state0.aesdec(key0);
-        state1 = state1.aesenc(key0);
-        state2 = state2.aesdec(key4);
-        state3 = state3.aesenc(key4);
+        state0 = A::aesdec(state0, key0);
+        state1 = A::aesenc(state1, key0);
+        state2 = A::aesdec(state2, key4);
+        state3 = A::aesenc(state3, key4);
         
         // Second round of AES operations
-        state0 = state0.aesdec(key1);
-        state1 = state1.aesenc(key1);
-        state2 = state2.aesdec(key5);
-        state3 = state3.aesenc(key5);
+        state0 = A::aesdec(state0, key1);
+        state1 = A::aesenc(state1, key1);
+        state2 = A::aesdec(state2, key5);
+        state3 = A::aesenc(state3, key5);
 
         // Third round of AES operations
-        state0 = state0.aesdec(key2);
-        state1 = state1.aesenc(key2);
-        state2 = state2.aesdec(key6);
-        state3 = state3.aesenc(key6);
+        state0 = A::aesdec(state0, key2);
+        state1 = A::aesenc(state1, key2);
+        state2 = A::aesdec(state2, key6);
+        state3 = A::aesenc(state3, key6);
         
         // Fourth round of AES operations
-        state0 = state0.aesdec(key3);
-        state1 = state1.aesenc(key3);
-        state2 = state2.aesdec(key7);
-        state3 = state3.aesenc(key7);
+        state0 = A::aesdec(state0, key3);
+        state1 = A::aesenc(state1, key3);
+        state2 = A::aesdec(state2, key7);
+        state3 = A::aesenc(state3, key7);
 
         // Store results
         result.push(state0);
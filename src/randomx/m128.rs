@@ -1,15 +1,18 @@
+use super::capabilities::has_aes;
+use super::m128i_soft;
 #[cfg(all(
     any(target_arch = "x86", target_arch = "x86_64"),
     target_feature = "sse2"
 ))]
 use std::arch::x86_64::{
-    __m128d, __m128i, _mm_add_pd, _mm_aesdec_si128, _mm_aesenc_si128, _mm_and_pd, _mm_cmpeq_epi32,
-    _mm_cmpeq_pd, _mm_cvtepi32_pd, _mm_div_pd, _mm_extract_epi64, _mm_movemask_epi8,
+    __m128d, __m128i, _mm_add_pd, _mm_aesdec_si128, _mm_aesenc_si128, _mm_and_pd, _mm_cmpeq_pd,
+    _mm_cvtepi32_pd, _mm_div_pd, _mm_extract_epi64,
     _mm_movemask_pd, _mm_mul_pd, _mm_or_pd, _mm_set_epi32, _mm_set_epi64x, _mm_set_pd,
     _mm_shuffle_pd, _mm_sqrt_pd, _mm_store_sd, _mm_storeh_pd, _mm_sub_pd, _mm_xor_pd,
 };
 use std::convert::TryInto;
 use std::fmt;
+use std::hash::{Hash, Hasher};
 
 #[allow(nonstandard_style)]
 #[derive(Copy, Clone)]
@@ -33,11 +36,22 @@ impl m128i {
     pub fn from_u64(u1: u64, u0: u64) -> m128i {
         unsafe { m128i(_mm_set_epi64x(u1 as i64, u0 as i64)) }
     }
+    /// Checks `has_aes` on every call, loops over many rounds pick the `AesRounds`
+    /// once instead
     pub fn aesdec(&self, key: m128i) -> m128i {
-        unsafe { m128i(_mm_aesdec_si128(self.0, key.0)) }
+        if has_aes() {
+            HardAes::aesdec(*self, key)
+        } else {
+            SoftAes::aesdec(*self, key)
+        }
     }
+    /// see `aesdec`
     pub fn aesenc(&self, key: m128i) -> m128i {
-        unsafe { m128i(_mm_aesenc_si128(self.0, key.0)) }
+        if has_aes() {
+            HardAes::aesenc(*self, key)
+        } else {
+            SoftAes::aesenc(*self, key)
+        }
     }
     pub fn as_i64(&self) -> (i64, i64) {
         unsafe {
@@ -58,31 +72,92 @@ impl m128i {
     }
 }
 
+/// One implementation of the AES rounds, the hash functions pick it once per call
+/// (with `has_aes`) instead of on every round
+pub(crate) trait AesRounds {
+    fn aesenc(state: m128i, key: m128i) -> m128i;
+    fn aesdec(state: m128i, key: m128i) -> m128i;
+}
+
+/// AES-NI, only picked if `has_aes` is true
+pub(crate) struct HardAes;
+
+/// The rounds of `m128i_soft`
+pub(crate) struct SoftAes;
+
+impl AesRounds for HardAes {
+    #[inline(always)]
+    fn aesenc(state: m128i, key: m128i) -> m128i {
+        unsafe { m128i(_mm_aesenc_si128(state.0, key.0)) }
+    }
+    #[inline(always)]
+    fn aesdec(state: m128i, key: m128i) -> m128i {
+        unsafe { m128i(_mm_aesdec_si128(state.0, key.0)) }
+    }
+}
+
+impl AesRounds for SoftAes {
+    fn aesenc(state: m128i, key: m128i) -> m128i {
+        m128i::from(m128i_soft::aesenc(u128::from(state), u128::from(key)))
+    }
+    fn aesdec(state: m128i, key: m128i) -> m128i {
+        m128i::from(m128i_soft::aesdec(u128::from(state), u128::from(key)))
+    }
+}
+
+impl From<m128i> for u128 {
+    fn from(m: m128i) -> u128 {
+        let (high, low) = m.as_i64();
+        (u128::from(high as u64) << 64) | u128::from(low as u64)
+    }
+}
+
+impl From<u128> for m128i {
+    fn from(u: u128) -> m128i {
+        m128i::from_u64((u >> 64) as u64, u as u64)
+    }
+}
+
 impl PartialEq for m128i {
     fn eq(&self, other: &Self) -> bool {
-        unsafe {
-            let test = _mm_cmpeq_epi32(self.0, other.0);
-            _mm_movemask_epi8(test) == 0xffff
-        }
+        u128::from(*self) == u128::from(*other)
     }
 }
 
 impl Eq for m128i {}
 
-fn format_m128i(m: &m128i, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    let (low, high) = m.as_i64();
-    f.write_fmt(format_args!("({:x},{:x})", high, low))
+impl Hash for m128i {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        u128::from(*self).hash(state);
+    }
 }
 
+/// Formats the 128 bits as a 32 char hex string, most significant lane first.
 impl fmt::LowerHex for m128i {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        format_m128i(self, f)
+        let (high, low) = self.as_i64();
+        f.write_fmt(format_args!("{:016x}{:016x}", high, low))
+    }
+}
+
+impl fmt::Display for m128i {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::LowerHex::fmt(self, f)
     }
 }
 
+/// Shows the four i32 lanes in the same order as `m128i::from_i32` takes them,
+/// so the output can be pasted back into a test.
 impl fmt::Debug for m128i {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        format_m128i(self, f)
+        let (high, low) = self.as_i64();
+        f.write_fmt(format_args!(
+            "m128i({:#010x}, {:#010x}, {:#010x}, {:#010x})",
+            (high >> 32) as i32,
+            high as i32,
+            (low >> 32) as i32,
+            low as i32
+        ))
     }
 }
 
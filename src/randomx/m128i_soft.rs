@@ -0,0 +1,96 @@
+//! Software AES rounds for CPUs without AES-NI, same results as `_mm_aesenc_si128` and
+//! `_mm_aesdec_si128`. The state is the 16 bytes of an `m128i` in memory order
+//! (byte `4 * c + r` is row `r` of column `c`).
+
+static SBOX: [u8; 256] = sbox();
+static INV_SBOX: [u8; 256] = inv_sbox(&SBOX);
+
+/// One AES encryption round: ShiftRows, SubBytes, MixColumns, then XOR with `key`
+pub fn aesenc(state: u128, key: u128) -> u128 {
+    let s = state.to_le_bytes();
+    let mut out = [0u8; 16];
+    for c in 0..4 {
+        let mut col = [0u8; 4];
+        for (r, b) in col.iter_mut().enumerate() {
+            *b = SBOX[s[4 * ((c + r) % 4) + r] as usize];
+        }
+        out[4 * c] = gf_mul(col[0], 2) ^ gf_mul(col[1], 3) ^ col[2] ^ col[3];
+        out[4 * c + 1] = col[0] ^ gf_mul(col[1], 2) ^ gf_mul(col[2], 3) ^ col[3];
+        out[4 * c + 2] = col[0] ^ col[1] ^ gf_mul(col[2], 2) ^ gf_mul(col[3], 3);
+        out[4 * c + 3] = gf_mul(col[0], 3) ^ col[1] ^ col[2] ^ gf_mul(col[3], 2);
+    }
+    u128::from_le_bytes(out) ^ key
+}
+
+/// One AES decryption round: InvShiftRows, InvSubBytes, InvMixColumns, then XOR with `key`
+pub fn aesdec(state: u128, key: u128) -> u128 {
+    let s = state.to_le_bytes();
+    let mut out = [0u8; 16];
+    for c in 0..4 {
+        let mut col = [0u8; 4];
+        for (r, b) in col.iter_mut().enumerate() {
+            *b = INV_SBOX[s[4 * ((c + 4 - r) % 4) + r] as usize];
+        }
+        for r in 0..4 {
+            out[4 * c + r] = gf_mul(col[r], 14)
+                ^ gf_mul(col[(r + 1) % 4], 11)
+                ^ gf_mul(col[(r + 2) % 4], 13)
+                ^ gf_mul(col[(r + 3) % 4], 9);
+        }
+    }
+    u128::from_le_bytes(out) ^ key
+}
+
+/// Multiplication in GF(2^8) with the AES polynomial x^8 + x^4 + x^3 + x + 1
+const fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut p = 0;
+    while b != 0 {
+        if b & 1 != 0 {
+            p ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    p
+}
+
+/// Multiplicative inverse as a^254, 0 for 0
+const fn gf_inv(a: u8) -> u8 {
+    let mut result = 1;
+    let mut base = a;
+    let mut exp = 254;
+    while exp != 0 {
+        if exp & 1 != 0 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exp >>= 1;
+    }
+    result
+}
+
+const fn sbox() -> [u8; 256] {
+    let mut sbox = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let x = gf_inv(i as u8);
+        sbox[i] =
+            x ^ x.rotate_left(1) ^ x.rotate_left(2) ^ x.rotate_left(3) ^ x.rotate_left(4) ^ 0x63;
+        i += 1;
+    }
+    sbox
+}
+
+const fn inv_sbox(sbox: &[u8; 256]) -> [u8; 256] {
+    let mut inv = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        inv[sbox[i] as usize] = i as u8;
+        i += 1;
+    }
+    inv
+}
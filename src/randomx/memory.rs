@@ -1,12 +1,22 @@
 extern crate argon2;
 
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__m256i, _mm256_set_epi64x, _mm256_xor_si256};
 use std::arch::x86_64::{_mm_prefetch, _MM_HINT_NTA};
+use std::fmt;
+#[cfg(target_arch = "x86_64")]
+use std::mem;
 use std::sync::{Arc, RwLock};
+use std::thread;
 use std::time::Instant;
 
 use argon2::Block;
+#[cfg(feature = "async-support")]
+use tokio::task::{spawn_blocking, JoinHandle};
 
 use super::super::byte_string;
+#[cfg(all(target_os = "linux", feature = "numa"))]
+use super::numa;
 use super::superscalar::{Blake2Generator, ScProgram};
 
 const RANDOMX_ARGON_LANES: u32 = 1;
@@ -30,6 +40,33 @@ const SUPERSCALAR_ADD_5: u64 = 10536153434571861004;
 const SUPERSCALAR_ADD_6: u64 = 3398623926847679864;
 const SUPERSCALAR_ADD_7: u64 = 9549104520008361294;
 
+/// Seed memory with an unexpected size, e.g. from an argon2 version that fills a
+/// different number of blocks
+#[derive(Debug, PartialEq)]
+pub enum MemoryError {
+    BlockCount { expected: usize, actual: usize },
+    ProgramCount { expected: usize, actual: usize },
+}
+
+impl fmt::Display for MemoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryError::BlockCount { expected, actual } => write!(
+                f,
+                "seed memory has {} argon2 blocks, expected {}",
+                actual, expected
+            ),
+            MemoryError::ProgramCount { expected, actual } => write!(
+                f,
+                "seed memory has {} superscalar programs, expected {}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MemoryError {}
+
 //256MiB, always used, named randomx_cache in the reference implementation
 pub struct SeedMemory {
     pub blocks: Box<[Block]>,
@@ -44,24 +81,61 @@ impl SeedMemory {
         }
     }
 
-    /// Creates a new initialised seed memory.
+    /// Creates a new initialised seed memory, panics if it has the wrong size
+    /// (see `new_initialised_checked`).
     pub fn new_initialised(key: &[u8]) -> SeedMemory {
+        match SeedMemory::new_initialised_checked(key) {
+            Ok(seed_mem) => seed_mem,
+            Err(e) => panic!("seed memory initialisation failed: {}", e),
+        }
+    }
+
+    /// Creates a new initialised seed memory and checks that argon2 filled
+    /// `RANDOMX_ARGON_MEMORY` blocks and that all superscalar programs were generated.
+    pub fn new_initialised_checked(key: &[u8]) -> Result<SeedMemory, MemoryError> {
         let mut mem = argon2::Memory::new(RANDOMX_ARGON_LANES, RANDOMX_ARGON_MEMORY);
         let context = &create_argon_context(key);
         argon2::initialize(context, &mut mem);
         argon2::fill_memory_blocks(context, &mut mem);
 
-        let mut programs = Vec::with_capacity(RANDOMX_CACHE_ACCESSES);
-        let mut gen = Blake2Generator::new(key, 0);
-        for _ in 0..RANDOMX_CACHE_ACCESSES {
-            programs.push(ScProgram::generate(&mut gen));
-        }
-
-        SeedMemory {
+        let seed_mem = SeedMemory {
             blocks: mem.blocks,
-            programs,
+            programs: superscalar_programs(key),
+        };
+        seed_mem.check_size()?;
+        Ok(seed_mem)
+    }
+
+    fn check_size(&self) -> Result<(), MemoryError> {
+        if self.blocks.len() != RANDOMX_ARGON_MEMORY as usize {
+            return Err(MemoryError::BlockCount {
+                expected: RANDOMX_ARGON_MEMORY as usize,
+                actual: self.blocks.len(),
+            });
         }
+        if self.programs.len() != RANDOMX_CACHE_ACCESSES {
+            return Err(MemoryError::ProgramCount {
+                expected: RANDOMX_CACHE_ACCESSES,
+                actual: self.programs.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Generates the superscalar programs of the seed memory.
+///
+/// Note: this can not be done in parallel. The spec uses one `Blake2Generator`
+/// (nonce 0) for all programs, program `i` starts with the generator state
+/// program `i - 1` left behind. Generators created per program index would give
+/// different programs and with that a different dataset.
+pub fn superscalar_programs(key: &[u8]) -> Vec<ScProgram<'static>> {
+    let mut programs = Vec::with_capacity(RANDOMX_CACHE_ACCESSES);
+    let mut gen = Blake2Generator::new(key, 0);
+    for _ in 0..RANDOMX_CACHE_ACCESSES {
+        programs.push(ScProgram::generate(&mut gen));
     }
+    programs
 }
 
 fn create_argon_context(key: &[u8]) -> argon2::Context {
@@ -96,10 +170,8 @@ fn mix_block_value(seed_mem: &SeedMemory, reg_value: u64, r: usize) -> u64 {
     seed_mem.blocks[block_ix as usize][block_v_ix as usize]
 }
 
-pub fn init_dataset_item(seed_mem: &SeedMemory, item_num: u64) -> [u64; 8] {
+fn initial_registers(item_num: u64) -> [u64; 8] {
     let mut ds = [0; 8];
-
-    let mut reg_value = item_num;
     ds[0] = (item_num + 1).wrapping_mul(SUPERSCALAR_MUL_0);
     ds[1] = ds[0] ^ SUPERSCALAR_ADD_1;
     ds[2] = ds[0] ^ SUPERSCALAR_ADD_2;
@@ -108,7 +180,13 @@ pub fn init_dataset_item(seed_mem: &SeedMemory, item_num: u64) -> [u64; 8] {
     ds[5] = ds[0] ^ SUPERSCALAR_ADD_5;
     ds[6] = ds[0] ^ SUPERSCALAR_ADD_6;
     ds[7] = ds[0] ^ SUPERSCALAR_ADD_7;
+    ds
+}
 
+pub fn init_dataset_item(seed_mem: &SeedMemory, item_num: u64) -> [u64; 8] {
+    let mut ds = initial_registers(item_num);
+
+    let mut reg_value = item_num;
     for prog in &seed_mem.programs {
         prog.execute(&mut ds);
 
@@ -121,10 +199,72 @@ pub fn init_dataset_item(seed_mem: &SeedMemory, item_num: u64) -> [u64; 8] {
     ds
 }
 
+/// Calculates the dataset items `item_num` and `item_num + 1`, with the
+/// superscalar programs of both items interleaved in AVX2 registers if the
+/// CPU supports it.
+pub fn init_dataset_item_pair(seed_mem: &SeedMemory, item_num: u64) -> [[u64; 8]; 2] {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { init_dataset_item_pair_avx2(seed_mem, item_num) };
+        }
+    }
+    [
+        init_dataset_item(seed_mem, item_num),
+        init_dataset_item(seed_mem, item_num + 1),
+    ]
+}
+
+/// AVX2 variant of `init_dataset_item_pair`, lane 0 of the registers holds
+/// the first item, lane 1 the second (lanes 2 and 3 are unused).
+///
+/// # Safety
+///
+/// The CPU must support `avx2`.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+pub unsafe fn init_dataset_item_pair_avx2(seed_mem: &SeedMemory, item_num: u64) -> [[u64; 8]; 2] {
+    let first = initial_registers(item_num);
+    let second = initial_registers(item_num + 1);
+    let mut ds: [__m256i; 8] = mem::zeroed();
+    for (r, v) in ds.iter_mut().enumerate() {
+        *v = _mm256_set_epi64x(0, 0, second[r] as i64, first[r] as i64);
+    }
+
+    let mut reg_values = [item_num, item_num + 1];
+    for prog in &seed_mem.programs {
+        prog.execute_avx2(&mut ds);
+
+        for (r, v) in ds.iter_mut().enumerate() {
+            let mix_value = _mm256_set_epi64x(
+                0,
+                0,
+                mix_block_value(seed_mem, reg_values[1], r) as i64,
+                mix_block_value(seed_mem, reg_values[0], r) as i64,
+            );
+            *v = _mm256_xor_si256(*v, mix_value);
+        }
+        let address: [u64; 4] = mem::transmute(ds[prog.address_reg]);
+        reg_values = [address[0], address[1]];
+    }
+
+    let mut items = [[0; 8]; 2];
+    for (r, v) in ds.iter().enumerate() {
+        let lanes: [u64; 4] = mem::transmute(*v);
+        items[0][r] = lanes[0];
+        items[1][r] = lanes[1];
+    }
+    items
+}
+
 #[derive(Clone)]
 pub struct VmMemoryAllocator {
     pub vm_memory_seed: String,
     pub vm_memory: Arc<VmMemory>,
+    /// one dataset per NUMA node (`vm_memory` is the one of node 0),
+    /// empty on machines with a single node
+    #[cfg(all(target_os = "linux", feature = "numa"))]
+    pub numa_memory: Vec<Arc<VmMemory>>,
 }
 
 impl VmMemoryAllocator {
@@ -132,13 +272,33 @@ impl VmMemoryAllocator {
         VmMemoryAllocator {
             vm_memory_seed: "".to_string(),
             vm_memory: Arc::new(VmMemory::no_memory()),
+            #[cfg(all(target_os = "linux", feature = "numa"))]
+            numa_memory: Vec::new(),
+        }
+    }
+
+    /// Allocator for memory that was already initialised with the key `seed`,
+    /// `reallocate` with the same seed keeps it
+    pub fn preallocated(seed: String, vm_memory: Arc<VmMemory>) -> VmMemoryAllocator {
+        VmMemoryAllocator {
+            vm_memory_seed: seed,
+            vm_memory,
+            #[cfg(all(target_os = "linux", feature = "numa"))]
+            numa_memory: Vec::new(),
         }
     }
 
     pub fn reallocate(&mut self, seed: String) -> bool {
         if seed != self.vm_memory_seed {
+            let seed_bytes = match byte_string::hex_to_bytes(&seed) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    error!("keeping the memory, invalid seed_hash {}: {}", seed, err);
+                    return false;
+                }
+            };
             let mem_init_start = Instant::now();
-            self.vm_memory = Arc::new(VmMemory::full(&byte_string::string_to_u8_array(&seed)));
+            self.allocate(&seed_bytes);
             self.vm_memory_seed = seed;
             info!(
                 "memory init took {}ms with seed_hash: {}",
@@ -149,17 +309,115 @@ impl VmMemoryAllocator {
         }
         false // No reallocation needed
     }
+
+    /// Runs `reallocate` on tokio's blocking thread pool, so a full dataset init (10-30s)
+    /// does not stall the async executor. Resolves to the updated allocator and the `reallocate` result.
+    #[cfg(feature = "async-support")]
+    pub fn reallocate_async(mut self, seed: String) -> JoinHandle<(VmMemoryAllocator, bool)> {
+        spawn_blocking(move || {
+            let reallocated = self.reallocate(seed);
+            (self, reallocated)
+        })
+    }
     
     // Add get_memory method to retrieve the current memory Arc
     pub fn get_memory(&self) -> Arc<VmMemory> {
         self.vm_memory.clone()
     }
+
+    #[cfg(not(all(target_os = "linux", feature = "numa")))]
+    fn allocate(&mut self, key: &[u8]) {
+        self.vm_memory = Arc::new(VmMemory::full(key));
+    }
+
+    /// Allocates a dataset on every NUMA node (in parallel) if there is more than one
+    #[cfg(all(target_os = "linux", feature = "numa"))]
+    fn allocate(&mut self, key: &[u8]) {
+        let nodes = numa::node_count();
+        if nodes < 2 {
+            self.numa_memory.clear();
+            self.vm_memory = Arc::new(VmMemory::full(key));
+            return;
+        }
+        //drop the old datasets first, they do not fit next to the new ones
+        self.numa_memory.clear();
+        self.vm_memory = Arc::new(VmMemory::no_memory());
+        self.numa_memory = thread::scope(|scope| {
+            let handles: Vec<_> = (0..nodes)
+                .map(|node| scope.spawn(move || Arc::new(VmMemory::numa_local(key, node))))
+                .collect();
+            handles
+                .into_iter()
+                .map(|hnd| hnd.join().expect("NUMA local memory init"))
+                .collect()
+        });
+        self.vm_memory = self.numa_memory[0].clone();
+        info!("allocated one dataset on each of the {} NUMA nodes", nodes);
+    }
+
+    /// The memory worker `worker` uses, see `worker_numa_node`
+    #[cfg(not(all(target_os = "linux", feature = "numa")))]
+    pub fn worker_memory(&self, _worker: usize) -> Arc<VmMemory> {
+        self.vm_memory.clone()
+    }
+
+    /// The memory worker `worker` uses, see `worker_numa_node`
+    #[cfg(all(target_os = "linux", feature = "numa"))]
+    pub fn worker_memory(&self, worker: usize) -> Arc<VmMemory> {
+        match self.numa_memory.get(worker_numa_node(worker)) {
+            Some(memory) => memory.clone(),
+            None => self.vm_memory.clone(),
+        }
+    }
+}
+
+/// NUMA node worker `worker` is bound to, workers are distributed round robin
+#[cfg(all(target_os = "linux", feature = "numa"))]
+pub fn worker_numa_node(worker: usize) -> usize {
+    worker % numa::node_count()
+}
+
+/// How `VmMemory` answers dataset reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// every read initialises the item from the seed memory, no dataset is allocated
+    Light,
+    /// items are initialised on their first read (or prefetch) and kept
+    FullLazy,
+    /// all items are initialised before the VM starts, a missing item is a bug
+    FullPreloaded,
+}
+
+/// Dataset reads of one VM, see `VmMemory::dataset_read`. Every VM counts its own
+/// reads, `WorkerPool::cache_hit_rate` adds up the counts of the workers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// reads answered from the cache (only counted in `CacheMode::FullLazy`)
+    pub hits: u64,
+    /// items the VM had to initialise, on a read or ahead of it in `dataset_prefetch`
+    pub misses: u64,
+}
+
+impl CacheStats {
+    /// Share of the reads served from the cache, 0.0 before the first read. An item
+    /// initialised by `dataset_prefetch` counts as a miss, its read as a hit.
+    pub fn hit_rate(&self) -> f64 {
+        if self.hits + self.misses == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / (self.hits + self.misses) as f64
+    }
+
+    pub fn add(&mut self, other: CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+    }
 }
 
 pub struct VmMemory {
     pub seed_memory: SeedMemory,
     pub dataset_memory: RwLock<Vec<Option<[u64; 8]>>>,
-    pub cache: bool,
+    pub mode: CacheMode,
 }
 
 impl VmMemory {
@@ -167,7 +425,7 @@ impl VmMemory {
     pub fn no_memory() -> VmMemory {
         VmMemory {
             seed_memory: SeedMemory::no_memory(),
-            cache: false,
+            mode: CacheMode::Light,
             dataset_memory: RwLock::new(Vec::with_capacity(0)),
         }
     }
@@ -175,7 +433,7 @@ impl VmMemory {
     pub fn light(key: &[u8]) -> VmMemory {
         VmMemory {
             seed_memory: SeedMemory::new_initialised(key),
-            cache: false,
+            mode: CacheMode::Light,
             dataset_memory: RwLock::new(Vec::with_capacity(0)),
         }
     }
@@ -184,53 +442,125 @@ impl VmMemory {
         let mem = vec![None; DATASET_ITEM_COUNT];
         VmMemory {
             seed_memory: seed_mem,
-            cache: true,
+            mode: CacheMode::FullLazy,
             dataset_memory: RwLock::new(mem),
         }
     }
 
-    pub fn dataset_prefetch(&self, offset: u64) {
-        if !self.cache {
+    /// Like `full`, but every dataset item is initialised up front, split over `threads`
+    /// threads. Takes a while, the dataset has `DATASET_ITEM_COUNT` items.
+    pub fn full_parallel(key: &[u8], threads: usize) -> VmMemory {
+        let seed_mem = SeedMemory::new_initialised(key);
+        let mut mem = vec![None; DATASET_ITEM_COUNT];
+        let chunk_size = DATASET_ITEM_COUNT.div_ceil(threads.max(1));
+        thread::scope(|scope| {
+            for (chunk_ix, chunk) in mem.chunks_mut(chunk_size).enumerate() {
+                let seed_mem = &seed_mem;
+                scope.spawn(move || {
+                    let first = (chunk_ix * chunk_size) as u64;
+                    for (i, item) in chunk.iter_mut().enumerate() {
+                        *item = Some(init_dataset_item(seed_mem, first + i as u64));
+                    }
+                });
+            }
+        });
+        VmMemory {
+            seed_memory: seed_mem,
+            mode: CacheMode::FullPreloaded,
+            dataset_memory: RwLock::new(mem),
+        }
+    }
+
+    /// Like `full`, but the memory is placed on NUMA `node`. It is initialised by a
+    /// thread bound to `node`, so every page is first touched (and allocated) there.
+    /// If binding fails the memory is allocated like `full`.
+    #[cfg(all(target_os = "linux", feature = "numa"))]
+    pub fn numa_local(key: &[u8], node: usize) -> VmMemory {
+        let key = key.to_vec();
+        thread::spawn(move || {
+            let bound = numa::bind_current_thread(node);
+            if bound.is_err() {
+                warn!(
+                    "binding to NUMA node {} failed {:?}, memory is not node local",
+                    node, bound
+                );
+            }
+            VmMemory::full(&key)
+        })
+        .join()
+        .expect("NUMA local memory init")
+    }
+
+    /// Initialises the dataset item at `offset` and the `lookahead - 1` items after it
+    /// (nothing for a `lookahead` of 0). Only the item at `offset` is known to be read
+    /// next, the addresses after it depend on the registers of the following program runs.
+    /// The neighbours are filled speculatively, every item of a full dataset is read
+    /// eventually. Items already in the cache are only prefetched into the CPU cache.
+    /// The initialised items are counted as misses in `stats`.
+    pub fn dataset_prefetch(&self, offset: u64, lookahead: usize, stats: &mut CacheStats) {
+        if self.mode == CacheMode::Light {
             return; // Skip prefetching for non-cached memory
         }
 
-        let item_num = offset / CACHE_LINE_SIZE;
+        let first = offset / CACHE_LINE_SIZE;
 
-        // Quick read lock to check if the item is cached
-        let need_init = {
+        // Quick read lock to find the items that are not cached yet
+        let missing: Vec<u64> = {
             let mem = self.dataset_memory.read().unwrap();
-            let rl_cached = &mem[item_num as usize];
-
-            if let Some(rl) = rl_cached {
-                // Item exists in cache, prefetch it
-                unsafe {
-                    let raw: *const i8 = std::mem::transmute(rl);
-                    _mm_prefetch(raw, _MM_HINT_NTA);
-                }
-                false
-            } else {
-                // Item doesn't exist in cache
-                true
-            }
+            let last = (first + lookahead as u64).min(mem.len() as u64);
+            (first..last)
+                .filter(|&item_num| match &mem[item_num as usize] {
+                    Some(rl) => {
+                        // Item exists in cache, prefetch it
+                        unsafe {
+                            let raw: *const i8 = std::mem::transmute(rl);
+                            _mm_prefetch(raw, _MM_HINT_NTA);
+                        }
+                        false
+                    }
+                    None => true,
+                })
+                .collect()
         };
+        if missing.is_empty() {
+            return;
+        }
 
-        // prefetch the next few items as well (spatial locality)
-        if need_init && item_num + 1 < DATASET_ITEM_COUNT as u64 {
-            // Precompute the next item asynchronously if it's not in cache
-            // We don't actually need to do anything here as the next read will initialize it
-            // This is just a hint to the code that we might need it soon
+        // Initialise without holding the lock, the items are cheap to store afterwards
+        let items: Vec<(u64, [u64; 8])> = missing
+            .into_iter()
+            .map(|item_num| (item_num, init_dataset_item(&self.seed_memory, item_num)))
+            .collect();
+        stats.misses += items.len() as u64;
+        let mut mem_mut = self.dataset_memory.write().unwrap();
+        for (item_num, rl) in items {
+            mem_mut[item_num as usize] = Some(rl);
         }
     }
 
-    pub fn dataset_read(&self, offset: u64, reg: &mut [u64; 8]) {
+    /// XORs the dataset item at `offset` into `reg`, cache hits and misses are counted
+    /// in `stats`
+    pub fn dataset_read(&self, offset: u64, reg: &mut [u64; 8], stats: &mut CacheStats) {
         let item_num = offset / CACHE_LINE_SIZE;
 
-        if self.cache {
+        if self.mode == CacheMode::FullPreloaded {
+            let mem = self.dataset_memory.read().unwrap();
+            let rl = mem[item_num as usize].expect("preloaded dataset item");
+            reg[0] ^= rl[0];
+            reg[1] ^= rl[1];
+            reg[2] ^= rl[2];
+            reg[3] ^= rl[3];
+            reg[4] ^= rl[4];
+            reg[5] ^= rl[5];
+            reg[6] ^= rl[6];
+            reg[7] ^= rl[7];
+        } else if self.mode == CacheMode::FullLazy {
             // Use a scope for the read lock to ensure it's dropped quickly
             let rl_opt: std::option::Option<[u64; 8]> = {
                 let mem = self.dataset_memory.read().unwrap();
                 let rl_cached = &mem[item_num as usize];
                 if let Some(rl) = rl_cached {
+                    stats.hits += 1;
                     // If cached, apply XOR directly and return
                     reg[0] ^= rl[0];
                     reg[1] ^= rl[1];
@@ -247,6 +577,7 @@ impl VmMemory {
 
             // If we get here, we need to initialize the item
             if rl_opt.is_none() {
+                stats.misses += 1;
                 let rl = init_dataset_item(&self.seed_memory, item_num);
 
                 // Apply XOR
@@ -1,7 +1,11 @@
+pub mod capabilities;
 pub mod common;
 pub mod hash;
 pub mod m128;
+pub mod m128i_soft;
 pub mod memory;
+#[cfg(all(target_os = "linux", feature = "numa"))]
+pub mod numa;
 pub mod program;
 pub mod superscalar;
 pub mod vm;
@@ -0,0 +1,60 @@
+//! NUMA node queries and thread placement through libnuma (`numa` feature, linux only).
+
+extern crate libc;
+
+use std::io;
+
+use self::libc::c_int;
+
+#[link(name = "numa")]
+extern "C" {
+    fn numa_available() -> c_int;
+    fn numa_max_node() -> c_int;
+    fn numa_node_of_cpu(cpu: c_int) -> c_int;
+    fn numa_run_on_node(node: c_int) -> c_int;
+    fn numa_set_preferred(node: c_int);
+}
+
+/// false if the kernel or machine has no NUMA support
+pub fn available() -> bool {
+    unsafe { numa_available() >= 0 }
+}
+
+/// Number of NUMA nodes, 1 if NUMA is not available
+pub fn node_count() -> usize {
+    if !available() {
+        return 1;
+    }
+    unsafe { numa_max_node().max(0) as usize + 1 }
+}
+
+/// Node of the CPU the calling thread currently runs on
+pub fn current_node() -> Option<usize> {
+    if !available() {
+        return None;
+    }
+    let node = unsafe { numa_node_of_cpu(libc::sched_getcpu()) };
+    if node < 0 {
+        None
+    } else {
+        Some(node as usize)
+    }
+}
+
+/// Restricts the calling thread to the CPUs of `node` and prefers the memory of
+/// `node` for its allocations
+pub fn bind_current_thread(node: usize) -> io::Result<()> {
+    if !available() {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "NUMA is not available",
+        ));
+    }
+    unsafe {
+        if numa_run_on_node(node as c_int) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        numa_set_preferred(node as c_int);
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+use super::hash::gen_program_aes_4rx4;
 use super::m128::m128i;
 use super::vm::{is_zero_or_power_of_2, Vm, SCRATCHPAD_L3_MASK};
 use std::fmt;
@@ -7,6 +8,8 @@ pub const MAX_FLOAT_REG: usize = 4;
 pub const MAX_REG: usize = 8;
 pub const REG_NEEDS_DISPLACEMENT_IX: usize = 5;
 pub const REG_NEEDS_DISPLACEMENT: Store = Store::R(REG_NEEDS_DISPLACEMENT_IX);
+/// How often a CBRANCH can jump back in a row, see `Program::max_execution_cycles`
+pub const MAX_CBRANCH_REPEATS: u64 = 2;
 const STORE_L3_CONDITION: u8 = 14;
 
 #[allow(nonstandard_style)]
@@ -44,7 +47,7 @@ pub enum Opcode {
     ISTORE = 0x100,
 }
 
-#[derive(Display, PartialEq)]
+#[derive(Display, Debug, PartialEq)]
 pub enum Store {
     NONE,
     //registers
@@ -60,7 +63,7 @@ pub enum Store {
     L3(Box<Store>),
 }
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq)]
 pub enum Mode {
     None,
     Cond(u8),
@@ -77,6 +80,7 @@ impl fmt::Display for Mode {
     }
 }
 
+#[derive(Debug)]
 pub struct Instr {
     pub op: Opcode,
     pub src: Store,
@@ -179,6 +183,19 @@ impl Instr {
     }
 }
 
+/// `effect` is not compared, it follows from `op`
+impl PartialEq for Instr {
+    fn eq(&self, other: &Instr) -> bool {
+        self.op == other.op
+            && self.src == other.src
+            && self.dst == other.dst
+            && self.imm == other.imm
+            && self.unsigned_imm == other.unsigned_imm
+            && self.mode == other.mode
+            && self.target == other.target
+    }
+}
+
 impl fmt::Display for Instr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} ", self.op)?;
@@ -242,6 +259,92 @@ fn write_l_access(
     }
 }
 
+/// An instruction field that `Program::validate` found out of range
+#[derive(Debug, PartialEq)]
+pub enum ProgramError {
+    /// register index >= 8 (r) or >= 4 (f, e, a), `instr` is the instruction index
+    InvalidRegister { instr: usize, register: String },
+    /// a memory operand that is not addressed by an r register or an immediate
+    InvalidMemoryOperand { instr: usize },
+    /// branch target outside of the program
+    InvalidBranchTarget { instr: usize, target: i32 },
+    /// entropy has to be 16 values (128 bytes)
+    InvalidEntropyLength(usize),
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProgramError::InvalidRegister { instr, register } => {
+                write!(
+                    f,
+                    "instruction {}: register {} out of range",
+                    instr, register
+                )
+            }
+            ProgramError::InvalidMemoryOperand { instr } => {
+                write!(f, "instruction {}: invalid memory operand", instr)
+            }
+            ProgramError::InvalidBranchTarget { instr, target } => {
+                write!(
+                    f,
+                    "instruction {}: branch target {} out of range",
+                    instr, target
+                )
+            }
+            ProgramError::InvalidEntropyLength(len) => {
+                write!(f, "program entropy has {} values, expected 16", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+/// Bytes that `Program::decode` can't turn into a program
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// the first 8 values are the entropy, `len` is the number of values given
+    MissingEntropy { len: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::MissingEntropy { len } => {
+                write!(f, "program has {} values, expected at least 8", len)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+fn validate_store(store: &Store, instr: usize) -> Result<(), ProgramError> {
+    let (name, ix, max) = match store {
+        Store::R(ix) => ("r", *ix, MAX_REG),
+        Store::F(ix) => ("f", *ix, MAX_FLOAT_REG),
+        Store::E(ix) => ("e", *ix, MAX_FLOAT_REG),
+        Store::A(ix) => ("a", *ix, MAX_FLOAT_REG),
+        Store::L1(reg) | Store::L2(reg) | Store::L3(reg) => {
+            return match **reg {
+                Store::R(_) => validate_store(reg, instr),
+                Store::Imm => Ok(()),
+                _ => Err(ProgramError::InvalidMemoryOperand { instr }),
+            };
+        }
+        Store::NONE | Store::Imm => return Ok(()),
+    };
+    if ix >= max {
+        return Err(ProgramError::InvalidRegister {
+            instr,
+            register: format!("{}{}", name, ix),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Program {
     pub entropy: Vec<u64>,
     pub program: Vec<Instr>,
@@ -274,6 +377,81 @@ impl Program {
             register_usage,
         }
     }
+
+    /// Like `from_bytes`, but fails instead of panicking if the entropy is missing
+    pub fn decode(bytes: &[m128i]) -> Result<Program, DecodeError> {
+        if bytes.len() < 8 {
+            return Err(DecodeError::MissingEntropy { len: bytes.len() });
+        }
+        Ok(Program::from_bytes(bytes.to_vec()))
+    }
+
+    /// The inverse of `decode`: the entropy followed by two instructions per value.
+    /// Several bytes decode to the same instruction, so these are not necessarily the
+    /// bytes the program was decoded from. A program with an odd number of
+    /// instructions gets a trailing NOP.
+    pub fn encode(&self) -> Vec<m128i> {
+        let mut bytes = Vec::with_capacity(8 + self.program.len().div_ceil(2));
+        for e in self.entropy.chunks(2) {
+            bytes.push(m128i::from_u64(e.get(1).cloned().unwrap_or(0), e[0]));
+        }
+        for instrs in self.program.chunks(2) {
+            let op1 = encode_instruction(&instrs[0]);
+            let op2 = instrs
+                .get(1)
+                .map(encode_instruction)
+                .unwrap_or(NOP_ENCODING);
+            bytes.push(m128i::from_u64(op2 as u64, op1 as u64));
+        }
+        bytes
+    }
+
+    /// The program `Vm::run` executes for `seed`
+    pub fn from_seed(seed: &[m128i; 4]) -> Program {
+        Program::from_bytes(gen_program_aes_4rx4(seed, 136))
+    }
+
+    /// Checks that all registers and branch targets are in range, so the
+    /// program can be executed by the `Vm` without out-of-bounds access.
+    pub fn validate(&self) -> Result<(), ProgramError> {
+        if self.entropy.len() != 16 {
+            return Err(ProgramError::InvalidEntropyLength(self.entropy.len()));
+        }
+        for (i, instr) in self.program.iter().enumerate() {
+            validate_store(&instr.dst, i)?;
+            validate_store(&instr.src, i)?;
+            if let Some(target) = instr.target {
+                //-1 branches to the program start (pc is incremented after the jump)
+                if target < -1 || target >= self.program.len() as i32 {
+                    return Err(ProgramError::InvalidBranchTarget { instr: i, target });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Upper bound for the instructions executed in one pass over the program.
+    ///
+    /// The instructions between a CBRANCH target and the CBRANCH never write the
+    /// branch register and contain no other CBRANCH, so every repetition only adds
+    /// the immediate to the register. The bit below the condition window is cleared
+    /// in the immediate, so the window can't get a carry twice in a row and the
+    /// jump is taken at most `MAX_CBRANCH_REPEATS` times in a row.
+    pub fn max_execution_cycles(&self) -> u64 {
+        let mut cycles = self.program.len() as u64;
+        for (i, instr) in self.program.iter().enumerate() {
+            if let Some(target) = instr.target {
+                let body_len = (i as i64 - i64::from(target)).max(0) as u64;
+                cycles += MAX_CBRANCH_REPEATS * body_len;
+            }
+        }
+        cycles
+    }
+
+    /// One line per instruction, e.g. `IADD_RS r3, r1, SHFT 2`
+    pub fn disassemble(&self) -> Vec<String> {
+        self.program.iter().map(|instr| instr.to_string()).collect()
+    }
 }
 
 impl fmt::Display for Program {
@@ -395,7 +573,8 @@ pub fn decode_instruction(bytes: i64, i: i32, register_usage: &mut [i32; MAX_REG
         );
     }
     if op < Opcode::IMUL_RCP as i64 {
-        if !is_zero_or_power_of_2(imm as u64) {
+        //the imm32 is unsigned, i32::MIN (2^31) is a power of 2
+        if !is_zero_or_power_of_2(imm as u32 as u64) {
             register_usage[dst % MAX_REG] = i;
         }
         let mut instr = new_imm_instr(
@@ -600,6 +779,92 @@ pub fn decode_instruction(bytes: i64, i: i32, register_usage: &mut [i32; MAX_REG
     new_instr(Opcode::NOP, Store::NONE, Store::NONE, imm, Mode::None, nop)
 }
 
+/// ISWAP_R with src == dst, the only encoding `decode_instruction` turns into a NOP
+const NOP_ENCODING: i64 = Opcode::IROL_R as i64;
+
+/// Bytes that `decode_instruction` decodes to `instr` (for the same register usage)
+pub fn encode_instruction(instr: &Instr) -> i64 {
+    //the decoder maps the op range [previous opcode, opcode) to the opcode
+    let op = match instr.op {
+        Opcode::NOP => return encode_fields(NOP_ENCODING, 0, 0, 0, instr.imm.unwrap_or(0)),
+        Opcode::IADD_RS => 0,
+        Opcode::IADD_M => Opcode::IADD_RS as i64,
+        Opcode::ISUB_R => Opcode::IADD_M as i64,
+        Opcode::ISUB_M => Opcode::ISUB_R as i64,
+        Opcode::IMUL_R => Opcode::ISUB_M as i64,
+        Opcode::IMUL_M => Opcode::IMUL_R as i64,
+        Opcode::IMULH_R => Opcode::IMUL_M as i64,
+        Opcode::IMULH_M => Opcode::IMULH_R as i64,
+        Opcode::ISMULH_R => Opcode::IMULH_M as i64,
+        Opcode::ISMULH_M => Opcode::ISMULH_R as i64,
+        Opcode::IMUL_RCP => Opcode::ISMULH_M as i64,
+        Opcode::INEG_R => Opcode::IMUL_RCP as i64,
+        Opcode::IXOR_R => Opcode::INEG_R as i64,
+        Opcode::IXOR_M => Opcode::IXOR_R as i64,
+        Opcode::IROR_R => Opcode::IXOR_M as i64,
+        Opcode::IROL_R => Opcode::IROR_R as i64,
+        Opcode::ISWAP_R => Opcode::IROL_R as i64,
+        Opcode::FSWAP_R => Opcode::ISWAP_R as i64,
+        Opcode::FADD_R => Opcode::FSWAP_R as i64,
+        Opcode::FADD_M => Opcode::FADD_R as i64,
+        Opcode::FSUB_R => Opcode::FADD_M as i64,
+        Opcode::FSUB_M => Opcode::FSUB_R as i64,
+        Opcode::FSCAL_R => Opcode::FSUB_M as i64,
+        Opcode::FMUL_R => Opcode::FSCAL_R as i64,
+        Opcode::FDIV_M => Opcode::FMUL_R as i64,
+        Opcode::FSQRT_R => Opcode::FDIV_M as i64,
+        Opcode::CBRANCH => Opcode::FSQRT_R as i64,
+        Opcode::CFROUND => Opcode::CBRANCH as i64,
+        Opcode::ISTORE => Opcode::CFROUND as i64,
+    };
+    let dst = match instr.dst {
+        //FSWAP_R selects the e registers with the upper half of the register index
+        Store::E(ix) if instr.op == Opcode::FSWAP_R => ix + MAX_FLOAT_REG,
+        _ => store_ix(&instr.dst),
+    };
+    //no src register (or the L3 immediate address) means src == dst
+    let src = match instr.src {
+        Store::NONE => dst,
+        _ if is_l3_imm(&instr.src) => dst,
+        _ => store_ix(&instr.src),
+    };
+    let modi = match instr.mode {
+        Mode::Shft(shft) => shft << 2,
+        Mode::Cond(cond) => cond << 4,
+        Mode::None => mod_mem_encoding(&instr.dst) | mod_mem_encoding(&instr.src),
+    };
+    encode_fields(op, dst, src, modi, instr.imm.unwrap_or(0))
+}
+
+fn encode_fields(op: i64, dst: usize, src: usize, modi: u8, imm: i32) -> i64 {
+    i64::from(imm) << 32
+        | i64::from(modi) << 24
+        | (src as i64 & 0xFF) << 16
+        | (dst as i64 & 0xFF) << 8
+        | op
+}
+
+fn store_ix(store: &Store) -> usize {
+    match store {
+        Store::R(ix) | Store::F(ix) | Store::E(ix) | Store::A(ix) => *ix,
+        Store::L1(reg) | Store::L2(reg) | Store::L3(reg) => store_ix(reg),
+        Store::NONE | Store::Imm => 0,
+    }
+}
+
+fn is_l3_imm(store: &Store) -> bool {
+    matches!(store, Store::L3(reg) if **reg == Store::Imm)
+}
+
+/// The modifier bits `l_cache` and `l12_cache` decode to `store`
+fn mod_mem_encoding(store: &Store) -> u8 {
+    match store {
+        Store::L1(_) => 1,
+        Store::L3(_) => STORE_L3_CONDITION << 4,
+        _ => 0,
+    }
+}
+
 pub fn r_reg(dst: usize) -> Store {
     match dst % MAX_REG {
         0 => Store::R(0),
@@ -672,7 +937,7 @@ fn l12_cache(src: usize, modi: u8) -> Store {
     Store::L1(Box::new(reg))
 }
 
-fn is_l_cache(store: &Store) -> bool {
+pub fn is_l_cache(store: &Store) -> bool {
     matches!(store, Store::L1(_) | Store::L2(_) | Store::L3(_))
 }
 
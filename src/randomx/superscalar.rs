@@ -1,8 +1,16 @@
 extern crate blake2b_simd;
 
 use self::blake2b_simd::Params;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+	__m256i, _mm256_add_epi64, _mm256_mul_epu32, _mm256_or_si256, _mm256_set1_epi64x,
+	_mm256_sll_epi64, _mm256_slli_epi64, _mm256_srl_epi64, _mm256_srli_epi64, _mm256_sub_epi64,
+	_mm256_xor_si256, _mm_cvtsi64_si128,
+};
 use std::convert::TryInto;
 use std::fmt;
+#[cfg(target_arch = "x86_64")]
+use std::mem;
 use strum::Display;
 
 use super::common::{mulh, randomx_reciprocal, smulh, u64_from_u32_imm};
@@ -93,6 +101,16 @@ impl ScInstr<'_> {
 		((self.mod_v >> 2) % 4) as u64
 	}
 
+	/// Cycles from reading the operands to the result on the reference CPU (the
+	/// latency of the result macro-op). For IMUL_RCP that excludes loading the
+	/// reciprocal, which does not depend on a register.
+	pub fn latency_cycles(&self) -> u8 {
+		if self.info.size() == 0 {
+			return 0;
+		}
+		self.info.macro_op(self.info.result_op).latency as u8
+	}
+
 	fn select_destination(
 		&mut self,
 		cycle: usize,
@@ -523,11 +541,24 @@ pub struct Blake2Generator {
 	gen_params: Params,
 }
 
+/// Snapshot of a `Blake2Generator`, see `Blake2Generator::checkpoint`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Blake2GeneratorState {
+	pub index: usize,
+	/// the current 64 byte hash output
+	pub data: Vec<u8>,
+}
+
+fn gen_params() -> Params {
+	let mut params = Params::new();
+	params.hash_length(BLAKE_GEN_DATA_LEN);
+	params
+}
+
 impl Blake2Generator {
 	pub fn new(seed: &[u8], nonce: u32) -> Blake2Generator {
 		debug_assert!(seed.len() <= BLAKE_GEN_DATA_LEN - 4);
-		let mut params = Params::new();
-		params.hash_length(BLAKE_GEN_DATA_LEN);
+		let params = gen_params();
 
 		let mut key: [u8; 60] = [0; 60];
 		key[..seed.len()].copy_from_slice(seed);
@@ -543,6 +574,26 @@ impl Blake2Generator {
 		}
 	}
 
+	pub fn checkpoint(&self) -> Blake2GeneratorState {
+		Blake2GeneratorState {
+			index: self.index,
+			data: self.data.to_vec(),
+		}
+	}
+
+	/// Creates a generator that continues exactly where the checkpointed one was.
+	/// Panics if `state.data` is not 64 bytes long.
+	pub fn restore(state: Blake2GeneratorState) -> Blake2Generator {
+		assert!(state.index <= BLAKE_GEN_DATA_LEN);
+		let mut data = [0; BLAKE_GEN_DATA_LEN];
+		data.copy_from_slice(&state.data);
+		Blake2Generator {
+			index: state.index,
+			data,
+			gen_params: gen_params(),
+		}
+	}
+
 	pub fn get_byte(&mut self) -> u8 {
 		self.check_data(1);
 		let v = self.data[self.index];
@@ -648,6 +699,20 @@ pub struct ScProgram<'a> {
 	pub mul_count: usize,
 }
 
+/// `ScProgram::new` was given an `address_reg` outside of the 8 dataset registers
+#[derive(Debug, PartialEq)]
+pub struct InvalidProgram {
+	pub address_reg: usize,
+}
+
+impl fmt::Display for InvalidProgram {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "address register r{} does not exist (r0-r7)", self.address_reg)
+	}
+}
+
+impl std::error::Error for InvalidProgram {}
+
 impl fmt::Display for ScProgram<'_> {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		for instr in &self.prog {
@@ -662,7 +727,32 @@ impl fmt::Display for ScProgram<'_> {
 	}
 }
 
-impl ScProgram<'_> {
+impl<'a> ScProgram<'a> {
+	/// Program from `prog` that mixes the dataset item with register `address_reg`
+	/// (`init_dataset_item` indexes the 8 registers with it). The statistics
+	/// (latencies, ipc, sizes) are only known for generated programs and stay 0.
+	pub fn new(
+		prog: Vec<ScInstr<'a>>,
+		address_reg: usize,
+	) -> Result<ScProgram<'a>, InvalidProgram> {
+		if address_reg >= 8 {
+			return Err(InvalidProgram { address_reg });
+		}
+		Ok(ScProgram {
+			prog,
+			asic_latencies: vec![0; 8],
+			cpu_latencies: vec![0; 8],
+			address_reg,
+			ipc: 0.0,
+			code_size: 0,
+			macro_ops: 0,
+			decode_cycles: 0,
+			cpu_latency: 0,
+			asic_latency: 0,
+			mul_count: 0,
+		})
+	}
+
 	pub fn generate(gen: &mut Blake2Generator) -> ScProgram<'static> {
 		let mut prog = Vec::with_capacity(SUPERSCALAR_MAX_SIZE);
 
@@ -819,7 +909,7 @@ impl ScProgram<'_> {
 			cpu_latencies[i] = registers[i].latency;
 		}
 
-		ScProgram {
+		let program = ScProgram {
 			prog,
 			asic_latencies,
 			cpu_latencies,
@@ -831,7 +921,74 @@ impl ScProgram<'_> {
 			code_size,
 			macro_ops: macro_op_count,
 			decode_cycles: decode_cycle,
+		};
+		//the schedule on the reference CPU can not be shorter than the dependency chains
+		debug_assert!(
+			program.critical_path_latency() as usize
+				<= program.cpu_latencies.iter().copied().max().unwrap_or(0),
+			"critical path of {} cycles exceeds the scheduled latency {:?}",
+			program.critical_path_latency(),
+			program.cpu_latencies
+		);
+		program
+	}
+
+	/// Length in cycles (see `ScInstr::latency_cycles`) of the longest chain of
+	/// instructions that depend on the result of the previous one, i.e. the
+	/// latency with unlimited execution ports.
+	pub fn critical_path_latency(&self) -> u32 {
+		let mut ready = [0u32; 8];
+		for instr in &self.prog {
+			let dst = instr.dst as usize;
+			let start = if instr.src < 0 {
+				ready[dst]
+			} else {
+				ready[dst].max(ready[instr.src as usize])
+			};
+			ready[dst] = start + u32::from(instr.latency_cycles());
+		}
+		ready.iter().copied().max().unwrap_or(0)
+	}
+
+	/// Instructions per cycle on an in-order pipeline that issues up to `pipeline_width`
+	/// instructions per cycle. An instruction waits until its registers are ready (see
+	/// `ScInstr::latency_cycles`) and no later instruction issues before it, so unlike
+	/// `ipc` (the reference CPU with its execution ports) this only measures how well
+	/// the register dependencies allow parallel execution. 0 for an empty program.
+	///
+	/// Panics if `pipeline_width` is 0.
+	pub fn estimated_ipc(&self, pipeline_width: usize) -> f64 {
+		assert!(pipeline_width > 0, "pipeline width has to be at least 1");
+		if self.prog.is_empty() {
+			return 0.0;
+		}
+		let mut ready = [0u64; 8];
+		let mut cycle = 0u64;
+		let mut issued_in_cycle = 0;
+		for instr in &self.prog {
+			let dst = instr.dst as usize;
+			let operands_ready = if instr.src < 0 {
+				ready[dst]
+			} else {
+				ready[dst].max(ready[instr.src as usize])
+			};
+			if issued_in_cycle == pipeline_width {
+				cycle += 1;
+				issued_in_cycle = 0;
+			}
+			if operands_ready > cycle {
+				cycle = operands_ready;
+				issued_in_cycle = 0;
+			}
+			issued_in_cycle += 1;
+			ready[dst] = cycle + u64::from(instr.latency_cycles());
 		}
+		self.prog.len() as f64 / (cycle + 1) as f64
+	}
+
+	/// Number of instructions, RandomX programs average about 450
+	pub fn instruction_count(&self) -> usize {
+		self.prog.len()
 	}
 
 	pub fn execute(&self, ds: &mut [u64; 8]) {
@@ -862,6 +1019,71 @@ impl ScProgram<'_> {
 			}
 		}
 	}
+
+	/// Same as `execute`, but for several register sets at once. Every 64 bit
+	/// lane of the `__m256i` registers is an independent register set.
+	///
+	/// # Safety
+	///
+	/// The CPU must support `avx2`.
+	#[cfg(target_arch = "x86_64")]
+	#[target_feature(enable = "avx2")]
+	pub unsafe fn execute_avx2(&self, ds: &mut [__m256i; 8]) {
+		for instr in &self.prog {
+			let dst = instr.dst as usize;
+			let src = instr.src as usize;
+			match instr.info.op {
+				ScOpcode::ISUB_R => ds[dst] = _mm256_sub_epi64(ds[dst], ds[src]),
+				ScOpcode::IXOR_R => ds[dst] = _mm256_xor_si256(ds[dst], ds[src]),
+				ScOpcode::IADD_RS => {
+					let shift = _mm_cvtsi64_si128(instr.mod_shift() as i64);
+					ds[dst] = _mm256_add_epi64(ds[dst], _mm256_sll_epi64(ds[src], shift));
+				}
+				ScOpcode::IMUL_R => ds[dst] = mullo_epi64(ds[dst], ds[src]),
+				ScOpcode::IROR_C => {
+					let rot = u64::from(instr.imm32 % 64);
+					let right = _mm256_srl_epi64(ds[dst], _mm_cvtsi64_si128(rot as i64));
+					let left = _mm256_sll_epi64(ds[dst], _mm_cvtsi64_si128((64 - rot) as i64));
+					ds[dst] = _mm256_or_si256(right, left);
+				}
+				ScOpcode::IADD_C7 | ScOpcode::IADD_C8 | ScOpcode::IADD_C9 => {
+					let imm = _mm256_set1_epi64x(u64_from_u32_imm(instr.imm32) as i64);
+					ds[dst] = _mm256_add_epi64(ds[dst], imm);
+				}
+				ScOpcode::IXOR_C7 | ScOpcode::IXOR_C8 | ScOpcode::IXOR_C9 => {
+					let imm = _mm256_set1_epi64x(u64_from_u32_imm(instr.imm32) as i64);
+					ds[dst] = _mm256_xor_si256(ds[dst], imm);
+				}
+				//AVX2 has no 64x64->128 bit multiplication, done per lane
+				ScOpcode::IMULH_R => ds[dst] = lanewise(ds[dst], ds[src], mulh),
+				ScOpcode::ISMULH_R => ds[dst] = lanewise(ds[dst], ds[src], smulh),
+				ScOpcode::IMUL_RCP => {
+					let rcp = _mm256_set1_epi64x(randomx_reciprocal(instr.imm32 as u64) as i64);
+					ds[dst] = mullo_epi64(ds[dst], rcp);
+				}
+				ScOpcode::COUNT => panic!("COUNT execution tried"),
+				ScOpcode::INVALID => panic!("INVALLID execution tried"),
+			}
+		}
+	}
+}
+
+/// Lower 64 bit of the 64x64 bit products, composed of 32x32 bit multiplications.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn mullo_epi64(a: __m256i, b: __m256i) -> __m256i {
+	let lo = _mm256_mul_epu32(a, b);
+	let a_hi_b = _mm256_mul_epu32(_mm256_srli_epi64(a, 32), b);
+	let a_b_hi = _mm256_mul_epu32(a, _mm256_srli_epi64(b, 32));
+	_mm256_add_epi64(lo, _mm256_slli_epi64(_mm256_add_epi64(a_hi_b, a_b_hi), 32))
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn lanewise(a: __m256i, b: __m256i, op: fn(u64, u64) -> u64) -> __m256i {
+	let a: [u64; 4] = mem::transmute(a);
+	let b: [u64; 4] = mem::transmute(b);
+	mem::transmute([op(a[0], b[0]), op(a[1], b[1]), op(a[2], b[2]), op(a[3], b[3])])
 }
 
 #[allow(clippy::unnecessary_unwrap)]
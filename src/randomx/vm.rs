@@ -1,21 +1,34 @@
 extern crate blake2b_simd;
 
 use self::blake2b_simd::{blake2b, Hash, Params};
-use super::common::{mulh, randomx_reciprocal, smulh, u64_from_i32_imm};
-use super::hash::{fill_aes_1rx4_u64, gen_program_aes_4rx4, hash_aes_1rx4};
+use super::capabilities::{cpu_has_aes, SoftAesGuard};
+use super::common::{
+    mulh, randomx_reciprocal, smulh, u64_from_i32_imm, RANDOMX_SCRATCHPAD_L1,
+    RANDOMX_SCRATCHPAD_L2, RANDOMX_SCRATCHPAD_L3,
+};
+use super::hash::{fill_aes_1rx4_u64, hash_aes_nrx4};
 use super::m128::{m128d, m128i};
-use super::memory::{VmMemory, CACHE_LINE_SIZE};
-use super::program::{Instr, Mode, Program, Store, MAX_FLOAT_REG, MAX_REG};
+use super::memory::{CacheStats, VmMemory, CACHE_LINE_SIZE};
+use super::program::{
+    is_l_cache, Instr, Mode, Program, Store, MAX_CBRANCH_REPEATS, MAX_FLOAT_REG, MAX_REG,
+};
 use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+use std::collections::TryReserveError;
 use std::convert::TryInto;
+use std::fmt;
+use std::io::Write;
 use std::sync::Arc;
 
-pub const SCRATCHPAD_L1_MASK: u64 = 0x3ff8;
-pub const SCRATCHPAD_L2_MASK: u64 = 0x3fff8;
-pub const SCRATCHPAD_L3_MASK: u64 = 0x1ffff8;
-const SCRATCHPAD_L3_MASK_U32: u32 = 0x1fffc0;
+//byte offsets of 8 byte aligned memory operands
+pub const SCRATCHPAD_L1_MASK: u64 = (RANDOMX_SCRATCHPAD_L1 as u64 - 1) & !7; //0x3ff8
+pub const SCRATCHPAD_L2_MASK: u64 = (RANDOMX_SCRATCHPAD_L2 as u64 - 1) & !7; //0x3fff8
+pub const SCRATCHPAD_L3_MASK: u64 = (RANDOMX_SCRATCHPAD_L3 as u64 - 1) & !7; //0x1ffff8
+//byte offset of the cache line aligned register loads and stores
+const SCRATCHPAD_L3_MASK_U32: u32 =
+    ((RANDOMX_SCRATCHPAD_L3 as u64 - 1) & !(CACHE_LINE_SIZE - 1)) as u32; //0x1fffc0
 
-const SCRATCHPAD_SIZE: usize = 262144;
+/// scratchpad length in u64 words
+pub const SCRATCHPAD_SIZE: usize = RANDOMX_SCRATCHPAD_L3 / 8;
 const MXCSR_DEFAULT: u32 = 0x9FC0;
 const CONDITION_OFFSET: u64 = 8;
 const CONDITION_MASK: u64 = (1 << CONDITION_OFFSET) - 1;
@@ -23,6 +36,13 @@ const CONDITION_MASK: u64 = (1 << CONDITION_OFFSET) - 1;
 const RANDOMX_PROGRAM_COUNT: usize = 8;
 const RANDOMX_PROGRAM_SIZE: i32 = 256;
 const RANDOMX_PROGRAM_ITERATIONS: usize = 2048;
+/// Dataset items initialised ahead by `VmMemory::dataset_prefetch`, only the item read
+/// next is known. Filling its neighbours too needs a bench showing it pays off
+/// (`cargo bench --bench dataset`).
+pub const DATASET_PREFETCH_LOOKAHEAD: usize = 1;
+/// Limit for `Program::max_execution_cycles`. CBRANCH loops of decoded programs never
+/// overlap, so all loop bodies together are at most one program long.
+const MAX_EXECUTION_CYCLES: u64 = RANDOMX_PROGRAM_SIZE as u64 * (1 + MAX_CBRANCH_REPEATS);
 const RANDOMX_DATASET_BASE_SIZE: usize = 2147483648;
 const RANDOMX_DATASET_ITEM_SIZE: usize = 64;
 const RANDOMX_DATASET_EXTRA_SIZE: usize = 33554368;
@@ -121,9 +141,116 @@ pub struct Vm {
     pub config: VmConfig,
     pub mem: Arc<VmMemory>,
     pub dataset_offset: u64,
+    /// dataset reads of this VM, see `WorkerPool::cache_hit_rate`
+    pub cache_stats: CacheStats,
+    /// if set, every executed instruction is written as CSV line (see `enable_trace`)
+    trace: Option<Box<dyn Write + Send>>,
+    trace_step: u64,
+    /// see `VmBuilder::sanitize_floats`
+    sanitize_floats: bool,
+    /// see `VmBuilder::soft_aes`
+    soft_aes: bool,
+}
+
+/// Register and memory values of an instruction before it is executed
+struct TraceBefore {
+    dst: String,
+    src: String,
+    memory_address: String,
+}
+
+/// Saved MXCSR value (SSE rounding mode and flags), written back on drop
+struct MxcsrGuard(u32);
+
+impl MxcsrGuard {
+    #[allow(deprecated)]
+    fn save() -> MxcsrGuard {
+        MxcsrGuard(unsafe { _mm_getcsr() })
+    }
+}
+
+impl Drop for MxcsrGuard {
+    #[allow(deprecated)]
+    fn drop(&mut self) {
+        unsafe { _mm_setcsr(self.0) }
+    }
 }
 
 impl Vm {
+    /// Writes a CSV line per executed instruction to `output`:
+    /// `step,opcode,dst_reg_before,src_reg_before,dst_reg_after,memory_address`.
+    /// Values are hex, scratchpad operands show the scratchpad value and the
+    /// memory address is the scratchpad byte offset. Tracing is meant for
+    /// debugging only, it slows hashing down a lot.
+    pub fn enable_trace(&mut self, mut output: Box<dyn Write + Send>) {
+        let header = writeln!(
+            output,
+            "step,opcode,dst_reg_before,src_reg_before,dst_reg_after,memory_address"
+        );
+        if header.is_err() {
+            error!("writing trace header failed {:?}", header);
+        }
+        self.trace = Some(output);
+        self.trace_step = 0;
+    }
+
+    pub fn disable_trace(&mut self) {
+        if let Some(mut output) = self.trace.take() {
+            let _ = output.flush();
+        }
+    }
+
+    fn trace_before(&self, instr: &Instr) -> TraceBefore {
+        let memory_address = if is_l_cache(&instr.src) {
+            format!("{:x}", self.scratchpad_src_ix(instr) * 8)
+        } else if is_l_cache(&instr.dst) {
+            format!("{:x}", self.scratchpad_dst_ix(instr) * 8)
+        } else {
+            String::new()
+        };
+        TraceBefore {
+            dst: self.trace_value(instr, &instr.dst, false),
+            src: self.trace_value(instr, &instr.src, true),
+            memory_address,
+        }
+    }
+
+    fn trace_value(&self, instr: &Instr, store: &Store, is_src: bool) -> String {
+        match store {
+            Store::R(i) => format!("{:016x}", self.reg.r[*i]),
+            Store::F(_) | Store::E(_) | Store::A(_) => {
+                let (h, l) = self.read_float_reg(store).as_u64();
+                format!("{:016x}{:016x}", h, l)
+            }
+            Store::L1(_) | Store::L2(_) | Store::L3(_) => {
+                let ix = if is_src {
+                    self.scratchpad_src_ix(instr)
+                } else {
+                    self.scratchpad_dst_ix(instr)
+                };
+                format!("{:016x}", self.scratchpad[ix])
+            }
+            Store::NONE | Store::Imm => String::new(),
+        }
+    }
+
+    fn write_trace(&mut self, instr: &Instr, before: TraceBefore) {
+        let dst_after = self.trace_value(instr, &instr.dst, false);
+        let step = self.trace_step;
+        self.trace_step += 1;
+        if let Some(output) = self.trace.as_mut() {
+            let result = writeln!(
+                output,
+                "{},{},{},{},{},{}",
+                step, instr.op, before.dst, before.src, dst_after, before.memory_address
+            );
+            if result.is_err() {
+                error!("writing trace failed, disabling trace {:?}", result);
+                self.trace = None;
+            }
+        }
+    }
+
     pub fn init_vm(&mut self, prog: &Program) {
         self.reg.a[0] = m128d::from_u64(
             small_positive_float_bit(prog.entropy[1]),
@@ -166,10 +293,34 @@ impl Vm {
     }
 
     pub fn init_scratchpad(&mut self, seed: &[m128i; 4]) -> [m128i; 4] {
+        debug_assert_eq!(
+            self.scratchpad.len(),
+            SCRATCHPAD_SIZE,
+            "scratchpad has wrong size for seed {:?}",
+            seed
+        );
         fill_aes_1rx4_u64(seed, &mut self.scratchpad)
     }
 
+    /// Panics if the hash is aborted, see `try_calculate_hash`
     pub fn calculate_hash(&mut self, input: &[u8]) -> Hash {
+        self.try_calculate_hash(input).expect("hash aborted")
+    }
+
+    /// None if the hash was aborted: a program over the cycle limit (see `run`)
+    /// or a float register that is not finite (see `VmBuilder::sanitize_floats`).
+    /// An aborted hash has no result, it must not be submitted.
+    #[cfg_attr(
+        feature = "tracing-support",
+        ::tracing::instrument(level = "trace", skip(self, input))
+    )]
+    pub fn try_calculate_hash(&mut self, input: &[u8]) -> Option<Hash> {
+        let _soft_aes = if self.soft_aes {
+            Some(SoftAesGuard::force())
+        } else {
+            None
+        };
+
         // Initial Blake2b hash of input data
         let hash = blake2b(input);
         
@@ -179,7 +330,9 @@ impl Vm {
         // Initialize the scratchpad with the seed
         let mut tmp_hash = self.init_scratchpad(&seed);
         
-        // Reset the CPU rounding mode for consistent floating-point operations
+        // Reset the CPU rounding mode for consistent floating-point operations,
+        // the rounding mode of the calling thread is restored when the guard is dropped
+        let _mxcsr = MxcsrGuard::save();
         self.reset_rounding_mode();
 
         // Run multiple iterations of the RandomX program
@@ -187,7 +340,9 @@ impl Vm {
         const ITERATIONS: usize = RANDOMX_PROGRAM_COUNT - 1;
         for _ in 0..ITERATIONS {
             // Run the RandomX program with the current hash
-            self.run(&tmp_hash);
+            if !self.run(&tmp_hash) || !self.floats_sane() {
+                return None;
+            }
             
             // Generate a new hash from the VM register state
             let reg_bytes = self.reg.to_bytes();
@@ -198,10 +353,12 @@ impl Vm {
         }
 
         // Run the final iteration of the RandomX program
-        self.run(&tmp_hash);
+        if !self.run(&tmp_hash) || !self.floats_sane() {
+            return None;
+        }
         
         // Generate the final hash using AES operations on the scratchpad
-        let final_hash = hash_aes_1rx4(&self.scratchpad);
+        let final_hash = hash_aes_nrx4::<4>(&self.scratchpad);
         
         // Store the final hash in the VM's register state
         self.reg.a[0] = final_hash[0].as_m128d();
@@ -212,13 +369,43 @@ impl Vm {
         // Create a Blake2b hash of the VM's final register state
         let mut params = Params::new();
         params.hash_length(RANDOMX_HASH_SIZE);
-        params.hash(&self.reg.to_bytes())
+        Some(params.hash(&self.reg.to_bytes()))
     }
 
-    /// Runs one round
-    pub fn run(&mut self, seed: &[m128i; 4]) {
+    /// False if `sanitize_floats` is set and a float register is NaN or infinite,
+    /// which the RandomX float operations never produce
+    fn floats_sane(&self) -> bool {
+        if !self.sanitize_floats {
+            return true;
+        }
+        let sane = self.reg.f.iter().chain(self.reg.e.iter()).all(|v| {
+            let (h, l) = v.as_f64();
+            h.is_finite() && l.is_finite()
+        });
+        if !sane {
+            warn!("float register is not finite, hash aborted");
+        }
+        sane
+    }
+
+    /// Runs one round, returns `false` without running it if the program could
+    /// execute more than `MAX_EXECUTION_CYCLES` instructions per iteration
+    pub fn run(&mut self, seed: &[m128i; 4]) -> bool {
         // Generate program from seed
-        let prog = Program::from_bytes(gen_program_aes_4rx4(seed, 136));
+        let prog = Program::from_seed(seed);
+        // decoding maps every field into range, so this only guards against decoder bugs
+        // and is not worth the time per hash in release builds
+        debug_assert_eq!(prog.validate(), Ok(()));
+
+        // CBRANCH loops are bounded, this only guards against decoder bugs as well
+        let max_cycles = prog.max_execution_cycles();
+        if max_cycles > MAX_EXECUTION_CYCLES {
+            error!(
+                "program may execute {} instructions per iteration (limit {}), aborting hash",
+                max_cycles, MAX_EXECUTION_CYCLES
+            );
+            return false;
+        }
 
         // Initialize VM state with the program
         self.init_vm(&prog);
@@ -272,7 +459,13 @@ impl Vm {
             self.pc = 0;
             while self.pc < RANDOMX_PROGRAM_SIZE {
                 let instr = &prog.program[self.pc as usize];
-                instr.execute(self);
+                if self.trace.is_some() {
+                    let before = self.trace_before(instr);
+                    instr.execute(self);
+                    self.write_trace(instr, before);
+                } else {
+                    instr.execute(self);
+                }
                 self.pc += 1;
             }
 
@@ -282,11 +475,15 @@ impl Vm {
             self.mem_reg.mx &= CACHE_LINE_ALIGN_MASK as usize;
             
             // Prefetch dataset for next iteration
-            self.mem.dataset_prefetch(self.mem_reg.mx as u64);
+            self.mem.dataset_prefetch(
+                self.dataset_offset + self.mem_reg.mx as u64,
+                DATASET_PREFETCH_LOOKAHEAD,
+                &mut self.cache_stats,
+            );
             
             // Read from dataset into registers
             let read_offset = self.dataset_offset + self.mem_reg.ma as u64;
-            self.mem.dataset_read(read_offset, &mut self.reg.r);
+            self.mem.dataset_read(read_offset, &mut self.reg.r, &mut self.cache_stats);
 
             // Swap memory registers for next iteration
             std::mem::swap(&mut self.mem_reg.mx, &mut self.mem_reg.ma);
@@ -313,8 +510,11 @@ impl Vm {
             sp_addr_0 = 0;
             sp_addr_1 = 0;
         }
+        true
     }
 
+    // CFROUND (`exec_cfround`) changes the rounding mode in MXCSR, `calculate_hash`
+    // restores the value of the caller with `MxcsrGuard`
     pub fn reset_rounding_mode(&mut self) {
         unsafe {
             _mm_setcsr(MXCSR_DEFAULT);
@@ -429,8 +629,8 @@ impl Vm {
     }
 
     pub fn exec_imul_rcp(&mut self, instr: &Instr) {
-        if !is_zero_or_power_of_2(instr.imm.unwrap() as u64) {
-            let v = randomx_reciprocal((instr.imm.unwrap() as u64) & 0x00000000FFFFFFFF);
+        let v = generate_imul_rcp(instr.imm.unwrap() as u32 as u64);
+        if v != 0 {
             self.write_r(&instr.dst, self.read_r(&instr.dst).wrapping_mul(v));
         } //else: nop
     }
@@ -653,6 +853,16 @@ pub fn is_zero_or_power_of_2(imm: u64) -> bool {
     imm & imm.wrapping_sub(1) == 0
 }
 
+/// Reciprocal of the unsigned imm32 of IMUL_RCP, 0 (the instruction is a nop) if
+/// `divisor` is 0 or a power of 2. A real reciprocal is always >= 2^63.
+pub fn generate_imul_rcp(divisor: u64) -> u64 {
+    if is_zero_or_power_of_2(divisor) {
+        0
+    } else {
+        randomx_reciprocal(divisor)
+    }
+}
+
 fn small_positive_float_bit(entropy: u64) -> u64 {
     let mut exponent = entropy >> 59; //0..31
     let mantissa = entropy & MANTISSA_MASK;
@@ -673,11 +883,113 @@ fn static_exponent(entropy: u64) -> u64 {
     exponent << MANTISSA_SIZE
 }
 
+/// The scratchpad of a new VM could not be allocated
+#[derive(Debug)]
+pub struct AllocationError(pub TryReserveError);
+
+impl fmt::Display for AllocationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "allocating the VM scratchpad failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for AllocationError {}
+
+/// A `VmBuilder` could not create the VM
+#[derive(Debug)]
+pub enum VmError {
+    Allocation(AllocationError),
+    /// `soft_aes(false)` on a CPU without AES-NI
+    AesNotSupported,
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::Allocation(err) => write!(f, "{}", err),
+            VmError::AesNotSupported => write!(f, "the CPU does not support AES-NI"),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
+impl From<AllocationError> for VmError {
+    fn from(err: AllocationError) -> VmError {
+        VmError::Allocation(err)
+    }
+}
+
+/// Creates a `Vm` with optional features, all of them are off by default
+/// (like a VM of `try_new_vm`)
+pub struct VmBuilder {
+    memory: Arc<VmMemory>,
+    trace: Option<Box<dyn Write + Send>>,
+    sanitize_floats: bool,
+    soft_aes: Option<bool>,
+}
+
+impl VmBuilder {
+    pub fn new(memory: Arc<VmMemory>) -> VmBuilder {
+        VmBuilder {
+            memory,
+            trace: None,
+            sanitize_floats: false,
+            soft_aes: None,
+        }
+    }
+
+    /// Traces every executed instruction to `writer`, see `Vm::enable_trace`
+    pub fn trace(mut self, writer: Box<dyn Write + Send>) -> VmBuilder {
+        self.trace = Some(writer);
+        self
+    }
+
+    /// Checks the float registers after every program. A hash with a NaN or infinite
+    /// register is aborted, `Vm::try_calculate_hash` returns None for it and the worker
+    /// skips its nonce. Meant for debugging the float operations.
+    pub fn sanitize_floats(mut self, sanitize: bool) -> VmBuilder {
+        self.sanitize_floats = sanitize;
+        self
+    }
+
+    /// `true` uses the software AES rounds (`m128i_soft`) even if the CPU supports
+    /// AES-NI, `false` requires AES-NI. Without this the rounds are picked by the CPU.
+    pub fn soft_aes(mut self, soft_aes: bool) -> VmBuilder {
+        self.soft_aes = Some(soft_aes);
+        self
+    }
+
+    pub fn build(self) -> Result<Vm, VmError> {
+        if self.soft_aes == Some(false) && !cpu_has_aes() {
+            return Err(VmError::AesNotSupported);
+        }
+        let mut vm = try_new_vm(self.memory)?;
+        vm.sanitize_floats = self.sanitize_floats;
+        vm.soft_aes = self.soft_aes == Some(true);
+        if let Some(writer) = self.trace {
+            vm.enable_trace(writer);
+        }
+        Ok(vm)
+    }
+}
+
+/// Panics if the scratchpad cannot be allocated, see `try_new_vm`
 pub fn new_vm(mem: Arc<VmMemory>) -> Vm {
-    Vm {
+    try_new_vm(mem).expect("vm scratchpad allocation")
+}
+
+pub fn try_new_vm(mem: Arc<VmMemory>) -> Result<Vm, AllocationError> {
+    let mut scratchpad = Vec::new();
+    scratchpad
+        .try_reserve_exact(SCRATCHPAD_SIZE)
+        .map_err(AllocationError)?;
+    scratchpad.resize(SCRATCHPAD_SIZE, 0);
+
+    Ok(Vm {
         mem_reg: MemoryRegister { mx: 0, ma: 0 },
         reg: new_register(),
-        scratchpad: vec![0; SCRATCHPAD_SIZE],
+        scratchpad,
         pc: 0,
         config: VmConfig {
             e_mask: [0; 2],
@@ -685,5 +997,10 @@ pub fn new_vm(mem: Arc<VmMemory>) -> Vm {
         },
         mem,
         dataset_offset: 0,
-    }
+        cache_stats: CacheStats::default(),
+        trace: None,
+        trace_step: 0,
+        sanitize_floats: false,
+        soft_aes: false,
+    })
 }
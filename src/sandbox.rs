@@ -0,0 +1,219 @@
+//! seccomp-BPF filter that restricts the miner to the syscalls it needs (linux only).
+//!
+//! The filter allows neither new sockets nor opening files for reading, so it has to be
+//! installed after the login to the pool (the name resolution and the connection are done
+//! by then). A sandboxed miner can not reconnect.
+
+extern crate libc;
+
+use std::io;
+
+use self::libc::{c_long, c_ulong, sock_filter, sock_fprog};
+
+/// `AUDIT_ARCH_X86_64` from `linux/audit.h`, syscall numbers are only valid for this arch
+const AUDIT_ARCH_X86_64: u32 = 0xC000_003E;
+
+/// offsets in `struct seccomp_data`
+const SECCOMP_DATA_NR_OFFSET: u32 = 0;
+const SECCOMP_DATA_ARCH_OFFSET: u32 = 4;
+/// lower 32 bits of `args[0]`, the next arguments follow every 8 bytes
+const SECCOMP_DATA_ARGS_OFFSET: u32 = 16;
+
+/// classic BPF opcodes from `linux/filter.h` (`BPF_LD | BPF_W | BPF_ABS` etc.)
+const BPF_LD_W_ABS: u16 = 0x20;
+const BPF_JMP_JEQ_K: u16 = 0x15;
+const BPF_JMP_JSET_K: u16 = 0x45;
+const BPF_RET_K: u16 = 0x06;
+
+const SECCOMP_SET_MODE_FILTER: c_ulong = 1;
+const SECCOMP_FILTER_FLAG_TSYNC: c_ulong = 1;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+
+/// Mining itself needs `read`, `write`, `recvfrom`, `sendto`, `futex`, `clock_nanosleep`,
+/// `mmap`, `munmap` and `exit_group`. The rest is used to start and end threads (the
+/// workers are restarted for every bandit arm), to pin and prioritize the workers and
+/// to close the pool connection. `ARG_RULES` adds a few syscalls with checked arguments.
+const ALLOWED_SYSCALLS: &[c_long] = &[
+    libc::SYS_read,
+    libc::SYS_write,
+    libc::SYS_recvfrom,
+    libc::SYS_sendto,
+    libc::SYS_futex,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_exit_group,
+    //threads and memory
+    libc::SYS_exit,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_mremap,
+    libc::SYS_brk,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_sigaltstack,
+    libc::SYS_sched_yield,
+    libc::SYS_clock_gettime,
+    libc::SYS_sched_getaffinity,
+    libc::SYS_sched_setaffinity,
+    libc::SYS_setpriority,
+    libc::SYS_gettid,
+    libc::SYS_getrandom,
+    //closing the pool connection and the metric report file
+    libc::SYS_shutdown,
+    libc::SYS_close,
+    //round-trip time of the pool connection (`TCP_INFO`)
+    libc::SYS_getsockopt,
+    //size of the metric report file (`fs::metadata`)
+    libc::SYS_statx,
+    //rotating the metric report file (`fs::rename`), depending on the libc
+    libc::SYS_rename,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+];
+
+/// `O_WRONLY | O_CREAT | O_APPEND | O_CLOEXEC`, how the metric sample thread opens
+/// the report file
+const METRIC_REPORT_OPEN_FLAGS: u32 =
+    (libc::O_WRONLY | libc::O_CREAT | libc::O_APPEND | libc::O_CLOEXEC) as u32;
+
+/// A syscall that is only allowed if one of its arguments passes a check
+struct ArgRule {
+    nr: c_long,
+    /// index in `seccomp_data.args`, only the lower 32 bits are checked
+    arg: u32,
+    check: ArgCheck,
+}
+
+enum ArgCheck {
+    Equals(u32),
+    HasBit(u32),
+}
+
+const ARG_RULES: &[ArgRule] = &[
+    //appending to the metric report, no other file can be opened
+    ArgRule {
+        nr: libc::SYS_openat,
+        arg: 2,
+        check: ArgCheck::Equals(METRIC_REPORT_OPEN_FLAGS),
+    },
+    //naming new threads
+    ArgRule {
+        nr: libc::SYS_prctl,
+        arg: 0,
+        check: ArgCheck::Equals(libc::PR_SET_NAME as u32),
+    },
+    //new threads but no new processes
+    ArgRule {
+        nr: libc::SYS_clone,
+        arg: 0,
+        check: ArgCheck::HasBit(libc::CLONE_THREAD as u32),
+    },
+];
+
+/// Installs a seccomp filter on the calling thread, all threads it starts afterwards
+/// inherit it. Syscalls outside of `ALLOWED_SYSCALLS` and `ARG_RULES` fail with `EPERM`
+/// (so e.g. a reconnect fails with an error instead of killing the process). The filter
+/// can not be removed again.
+pub fn apply_seccomp_filter() -> io::Result<()> {
+    install_seccomp_filter(0)
+}
+
+/// Like `apply_seccomp_filter`, but the filter is installed on all threads of the process
+/// at once, including the ones already running (e.g. the threads of a stratum client
+/// that is logged in). Only for processes that do nothing but mining.
+pub fn apply_seccomp_filter_to_process() -> io::Result<()> {
+    install_seccomp_filter(SECCOMP_FILTER_FLAG_TSYNC)
+}
+
+fn install_seccomp_filter(flags: c_ulong) -> io::Result<()> {
+    let mut filter = seccomp_filter();
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    unsafe {
+        //required to install a filter without CAP_SYS_ADMIN, the unused arguments have to be 0
+        let no_new_privs: c_ulong = 1;
+        let unused: c_ulong = 0;
+        if libc::prctl(
+            libc::PR_SET_NO_NEW_PRIVS,
+            no_new_privs,
+            unused,
+            unused,
+            unused,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        let result = libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            flags,
+            &prog as *const sock_fprog,
+        );
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if result > 0 {
+            //with TSYNC: the id of a thread the filter could not be installed on
+            return Err(io::Error::other(format!(
+                "thread {} could not be synchronized",
+                result
+            )));
+        }
+    }
+    info!("seccomp sandbox enabled");
+    Ok(())
+}
+
+fn seccomp_filter() -> Vec<sock_filter> {
+    let mut filter = vec![
+        bpf_stmt(BPF_LD_W_ABS, SECCOMP_DATA_ARCH_OFFSET),
+        bpf_jump(BPF_JMP_JEQ_K, AUDIT_ARCH_X86_64, 1, 0),
+        bpf_stmt(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        bpf_stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR_OFFSET),
+    ];
+    for nr in ALLOWED_SYSCALLS {
+        filter.push(bpf_jump(BPF_JMP_JEQ_K, *nr as u32, 0, 1));
+        filter.push(bpf_stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+    }
+    //glibc falls back to clone (checked below) if clone3 is not implemented,
+    //the clone3 flags are behind a pointer and can't be checked
+    filter.push(bpf_jump(BPF_JMP_JEQ_K, libc::SYS_clone3 as u32, 0, 1));
+    filter.push(bpf_stmt(BPF_RET_K, SECCOMP_RET_ERRNO | libc::ENOSYS as u32));
+    for rule in ARG_RULES {
+        //reloads the syscall number, the previous rule loaded an argument
+        filter.push(bpf_stmt(BPF_LD_W_ABS, SECCOMP_DATA_NR_OFFSET));
+        filter.push(bpf_jump(BPF_JMP_JEQ_K, rule.nr as u32, 0, 4));
+        filter.push(bpf_stmt(
+            BPF_LD_W_ABS,
+            SECCOMP_DATA_ARGS_OFFSET + 8 * rule.arg,
+        ));
+        filter.push(match rule.check {
+            ArgCheck::Equals(value) => bpf_jump(BPF_JMP_JEQ_K, value, 0, 1),
+            ArgCheck::HasBit(bit) => bpf_jump(BPF_JMP_JSET_K, bit, 0, 1),
+        });
+        filter.push(bpf_stmt(BPF_RET_K, SECCOMP_RET_ALLOW));
+        filter.push(bpf_stmt(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32));
+    }
+    filter.push(bpf_stmt(BPF_RET_K, SECCOMP_RET_ERRNO | libc::EPERM as u32));
+    filter
+}
+
+fn bpf_stmt(code: u16, k: u32) -> sock_filter {
+    sock_filter {
+        code,
+        jt: 0,
+        jf: 0,
+        k,
+    }
+}
+
+fn bpf_jump(code: u16, k: u32, jt: u8, jf: u8) -> sock_filter {
+    sock_filter { code, jt, jf, k }
+}
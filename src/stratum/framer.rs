@@ -0,0 +1,81 @@
+//! Splits the byte stream from the pool into newline-delimited JSON-RPC messages.
+
+use std::io;
+use std::io::{ErrorKind, Read};
+use std::net::TcpStream;
+
+/// longest message (without the newline) accepted from a pool, a pool sending
+/// more without a newline is treated as broken instead of buffering it all
+pub const MAX_STRATUM_MESSAGE_BYTES: usize = 65536;
+
+const READ_CHUNK_BYTES: usize = 4096;
+
+/// Reads from a plain `TcpStream` or anything else that returns the lines of the pool
+/// (e.g. the read half of a WebSocket connection)
+pub struct StratumFramer<R: Read = TcpStream> {
+    stream: R,
+    /// bytes read but not yet returned, at most one partial message after `next_message`
+    buffer: Vec<u8>,
+}
+
+impl<R: Read> StratumFramer<R> {
+    pub fn new(stream: R) -> StratumFramer<R> {
+        StratumFramer {
+            stream,
+            buffer: Vec::with_capacity(READ_CHUNK_BYTES),
+        }
+    }
+
+    /// Reads until a complete message is buffered and returns it without the trailing
+    /// `\n` (or `\r\n`). A message split over several reads is put together, a read
+    /// that got more than one message keeps the rest for the next call.
+    /// Fails with `UnexpectedEof` when the pool closed the connection and with
+    /// `InvalidData` for messages longer than `MAX_STRATUM_MESSAGE_BYTES`.
+    /// On read errors (e.g. a read timeout) the buffered bytes are kept, so calling
+    /// it again continues the same message.
+    pub fn next_message(&mut self) -> io::Result<String> {
+        let mut searched = 0;
+        loop {
+            if let Some(pos) = self.buffer[searched..].iter().position(|b| *b == b'\n') {
+                let end = searched + pos;
+                if end > MAX_STRATUM_MESSAGE_BYTES {
+                    return Err(too_long());
+                }
+                let mut message: Vec<u8> = self.buffer.drain(..=end).collect();
+                message.pop();
+                if message.last() == Some(&b'\r') {
+                    message.pop();
+                }
+                return String::from_utf8(message)
+                    .map_err(|e| io::Error::new(ErrorKind::InvalidData, e));
+            }
+            searched = self.buffer.len();
+            if searched > MAX_STRATUM_MESSAGE_BYTES {
+                return Err(too_long());
+            }
+
+            let mut chunk = [0; READ_CHUNK_BYTES];
+            match self.stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "connection terminated",
+                    ))
+                }
+                Ok(n) => self.buffer.extend_from_slice(&chunk[..n]),
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn too_long() -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "stratum message longer than {} bytes",
+            MAX_STRATUM_MESSAGE_BYTES
+        ),
+    )
+}
@@ -1,22 +1,35 @@
+pub mod framer;
+pub mod rtt;
 pub mod stratum_data;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+pub use self::framer::{StratumFramer, MAX_STRATUM_MESSAGE_BYTES};
+pub use self::rtt::RttStats;
+
+use super::byte_string;
 
-extern crate crossbeam_channel;
 extern crate serde;
 extern crate serde_json;
 
-use self::crossbeam_channel::{unbounded, Receiver, SendError, Sender};
+use super::channel::{unbounded, Receiver, SendError, Sender};
+use std::collections::HashMap;
+use std::fmt;
 use std::io;
-use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::io::{BufWriter, Error, ErrorKind, Read, Write};
+use std::mem;
 use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 /// command send to the stratum server
 #[derive(Debug)]
 pub enum StratumCmd {
     Login {},
     SubmitShare { share: stratum_data::Share },
+    RetryShare { retry: RetryShare, miner_id: String },
     KeepAlive { miner_id: String },
     Shutdown {},
 }
@@ -34,87 +47,466 @@ pub enum StratumAction {
     Error {
         err: String,
     },
+    /// an error response to a share submission, see `share_response`
+    ShareRejected {
+        err: String,
+    },
     Ok,
     KeepAliveOk,
+    /// a method this client does not handle (e.g. `mining.set_extranonce`
+    /// or pool specific extensions), passed on as received
+    Unknown {
+        method: String,
+        params: serde_json::Value,
+    },
+    /// the `StratumClient` changed its `ConnectionState`
+    StateChange(ConnectionState),
+}
+
+/// Connection state of a `StratumClient`, see `StratumClient::state`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ConnectionState {
+    /// connecting and logging in for the first time
+    Connecting,
+    LoggedIn,
+    /// `reconnect` closed the connection and is logging in again
+    Reconnecting,
+    Stopped,
+}
+
+impl ConnectionState {
+    fn from_u8(value: u8) -> ConnectionState {
+        match value {
+            0 => ConnectionState::Connecting,
+            1 => ConnectionState::LoggedIn,
+            2 => ConnectionState::Reconnecting,
+            _ => ConnectionState::Stopped,
+        }
+    }
 }
 
 pub enum StratumError {}
 
+/// A job sent by the pool
+#[derive(Debug, Clone, PartialEq)]
+pub struct StratumJob {
+    pub miner_id: String,
+    pub seed_hash: String,
+    pub blob: String,
+    pub job_id: String,
+    pub target: String,
+}
+
+impl From<StratumJob> for StratumAction {
+    fn from(job: StratumJob) -> StratumAction {
+        StratumAction::Job {
+            miner_id: job.miner_id,
+            seed_hash: job.seed_hash,
+            blob: job.blob,
+            job_id: job.job_id,
+            target: job.target,
+        }
+    }
+}
+
+/// What the pool answered to the login request of the current connection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StratumLoginResponse {
+    /// the job sent with the login result, pools send the next one only with a new block
+    pub initial_job: Option<StratumJob>,
+}
+
+/// how long the login waits for the pool to answer a login request
+const LOGIN_RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+/// pause before re-issuing a rejected login request
+const LOGIN_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// default of `StratumClient::set_max_retry_attempts`
+pub const DEFAULT_MAX_RETRY_ATTEMPTS: u32 = 3;
+/// keep alives are sent at this interval, but only if the pool sent nothing for this long
+pub const KEEP_ALIVE_INTERVAL_MS: u64 = 60_000;
+/// how often `keep_alive_due` is checked, the pool is silent for at most
+/// `KEEP_ALIVE_INTERVAL_MS` plus this before a keep alive is sent
+const KEEP_ALIVE_CHECK_INTERVAL_MS: u64 = KEEP_ALIVE_INTERVAL_MS / 4;
+
+/// A share submission that was sent to the pool and is still waiting for its response
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub submitted_at: Instant,
+    pub nonce: u32,
+    /// 0 for the first submission of the share, see `RetryShare::attempts`
+    pub retry_attempt: u32,
+}
+
+pub type PendingRequests = Arc<Mutex<HashMap<u64, PendingRequest>>>;
+
+/// A share that could not be sent because the connection broke, it is
+/// re-submitted after the next `reconnect` logged in
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryShare {
+    pub nonce: u32,
+    pub job_id: String,
+    /// the hash of the share
+    pub result: Vec<u8>,
+    /// re-submissions so far
+    pub attempts: u32,
+}
+
+impl RetryShare {
+    /// Fails if the hash of the share is not valid hex, the pool would reject it anyway
+    pub fn from_share(share: &stratum_data::Share) -> Result<RetryShare, byte_string::HexError> {
+        Ok(RetryShare {
+            nonce: u32::from_str_radix(&share.nonce, 16).unwrap_or(0),
+            job_id: share.job_id.clone(),
+            result: byte_string::hex_to_bytes(&share.hash)?,
+            attempts: 0,
+        })
+    }
+
+    /// The share for the (new) login `miner_id`
+    pub fn to_share(&self, miner_id: &str) -> stratum_data::Share {
+        stratum_data::Share {
+            miner_id: miner_id.to_string(),
+            job_id: self.job_id.clone(),
+            nonce: format!("{:08x}", self.nonce),
+            hash: byte_string::u8_array_to_string(&self.result),
+        }
+    }
+}
+
+pub type RetryShares = Arc<Mutex<Vec<RetryShare>>>;
+
+/// the pool messages of a plain TCP or a WebSocket connection
+type PoolReader = StratumFramer<Box<dyn Read + Send>>;
+type PoolWriter = BufWriter<Box<dyn Write + Send>>;
+
 pub struct StratumClient {
     command_sender: Sender<StratumCmd>,
+    /// kept so the command channel survives a `reconnect`
+    command_receiver: Receiver<StratumCmd>,
+    pool_conf: stratum_data::PoolConfig,
+    err_receiver: Sender<Error>,
+    action_rcv: Sender<StratumAction>,
     send_thread: thread::JoinHandle<()>,
     rcv_thread: thread::JoinHandle<()>,
     keep_alive_thread: thread::JoinHandle<()>,
+    rtt_thread: thread::JoinHandle<()>,
     tcp_stream_hnd: TcpStream,
     tick_tx: Sender<()>,
+    rtt_tick_tx: Sender<()>,
+    rtt_stats: Arc<Mutex<RttStats>>,
+    /// JSON-RPC id of the next request
+    next_id: Arc<AtomicU64>,
+    /// submitted shares by JSON-RPC id, removed when the pool answered
+    pending_requests: PendingRequests,
+    login_response: StratumLoginResponse,
+    /// shares waiting for re-submission, kept on `reconnect`
+    retry_shares: RetryShares,
+    max_retry_attempts: u32,
+    /// `ConnectionState` as u8, kept on `reconnect`
+    state: Arc<AtomicU8>,
 }
 
 /// All operation in the client are async
 impl StratumClient {
+    /// Logs in (like `login_with_retry` without retries) and dispatches the job of the
+    /// login response on `action_rcv`, so the workers start on it without waiting for
+    /// the next job of the pool.
     pub fn login(
         pool_conf: stratum_data::PoolConfig,
         err_receiver: Sender<Error>,
         action_rcv: Sender<StratumAction>,
+    ) -> io::Result<StratumClient> {
+        let client = StratumClient::login_with_retry(pool_conf, 0, err_receiver, action_rcv)?;
+        client.dispatch_initial_job();
+        Ok(client)
+    }
+
+    /// Logs in before returning. If the pool rejects the login with an auth error
+    /// (see `is_auth_error`) the request is re-issued on the same connection up to
+    /// `max_retries` times. Network errors are returned immediately.
+    /// Unlike `login` the job of the login response is not dispatched, see `initial_job`.
+    pub fn login_with_retry(
+        pool_conf: stratum_data::PoolConfig,
+        max_retries: u32,
+        err_receiver: Sender<Error>,
+        action_rcv: Sender<StratumAction>,
     ) -> io::Result<StratumClient> {
         info!("connecting to address: {}", pool_conf.pool_address);
+        send_state_change(&action_rcv, ConnectionState::Connecting);
+
+        let (tcp_stream_hnd, reader, writer, login_response, next_id) =
+            StratumClient::connect_and_login(&pool_conf, max_retries)?;
+
+        let client = StratumClient::start(
+            tcp_stream_hnd,
+            reader,
+            writer,
+            pool_conf,
+            err_receiver,
+            action_rcv,
+            Some(login_response),
+            next_id,
+            unbounded(),
+            Arc::new(Mutex::new(Vec::new())),
+        )?;
+        client.set_state(ConnectionState::LoggedIn);
+        Ok(client)
+    }
+
+    /// Closes the connection and logs in again with the same pool config (like `login`,
+    /// so the job of the new login response is dispatched on the action channel).
+    /// Commands, actions and errors keep using the existing channels, so senders from
+    /// `new_cmd_channel` stay valid. Commands not yet sent on the old connection are dropped.
+    /// The end of the old connection is reported on the error channel before this returns,
+    /// drain it afterwards.
+    /// Shares the old connection could not send are re-submitted after the login,
+    /// each share at most `max_retry_attempts` times (see `set_max_retry_attempts`).
+    /// If the login fails the old connection stays closed, call `reconnect` again.
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        info!("reconnecting to address: {}", self.pool_conf.pool_address);
+        self.set_state(ConnectionState::Reconnecting);
+
+        self.shutdown();
+        while !(self.send_thread.is_finished()
+            && self.rcv_thread.is_finished()
+            && self.keep_alive_thread.is_finished()
+            && self.rtt_thread.is_finished())
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        //e.g. the Shutdown if the send thread had already failed
+        let mut dropped = 0;
+        {
+            let mut retry_shares = self.retry_shares.lock().expect("retry shares lock");
+            for cmd in self.command_receiver.try_iter() {
+                match cmd {
+                    StratumCmd::SubmitShare { share } => match RetryShare::from_share(&share) {
+                        Ok(retry) => retry_shares.push(retry),
+                        Err(err) => drop_invalid_share(&share, &err),
+                    },
+                    StratumCmd::RetryShare { retry, .. } => retry_shares.push(retry),
+                    _ => dropped += 1,
+                }
+            }
+            let max_attempts = self.max_retry_attempts;
+            retry_shares.retain(|retry| {
+                if retry.attempts >= max_attempts {
+                    warn!(
+                        "share with nonce {:08x} lost, not sent after {} attempts",
+                        retry.nonce, retry.attempts
+                    );
+                }
+                retry.attempts < max_attempts
+            });
+        }
+        if dropped > 0 {
+            info!("dropped {} commands of the closed connection", dropped);
+        }
 
-        let (tcp_stream_hnd, reader, writer) = StratumClient::connect_tcp(&pool_conf.pool_address)?;
+        let (tcp_stream_hnd, reader, writer, login_response, next_id) =
+            StratumClient::connect_and_login(&self.pool_conf, 0)?;
+        let mut client = StratumClient::start(
+            tcp_stream_hnd,
+            reader,
+            writer,
+            self.pool_conf.clone(),
+            self.err_receiver.clone(),
+            self.action_rcv.clone(),
+            Some(login_response),
+            next_id,
+            (self.command_sender.clone(), self.command_receiver.clone()),
+            self.retry_shares.clone(),
+        )?;
+        client.max_retry_attempts = self.max_retry_attempts;
+        client.state = self.state.clone();
+        if let Some(job) = &client.login_response.initial_job {
+            //the queued shares don't have to wait for the next message of the pool
+            queue_retry_shares(&job.miner_id, &client.retry_shares, &client.command_sender);
+        }
+        mem::replace(self, client).join();
+        self.set_state(ConnectionState::LoggedIn);
+        self.dispatch_initial_job();
+        Ok(())
+    }
 
+    /// Sends the job of the login response as `StratumAction::Job`
+    fn dispatch_initial_job(&self) {
+        if let Some(job) = &self.login_response.initial_job {
+            if self.action_rcv.send(job.clone().into()).is_err() {
+                info!("sending initial job failed, receiver already terminated");
+            }
+        }
+    }
+
+    /// Starts the send, receive, keep alive and round-trip time threads on an established
+    /// connection.
+    /// `login_response` is dispatched before the receive thread starts.
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        tcp_stream_hnd: TcpStream,
+        reader: PoolReader,
+        writer: PoolWriter,
+        pool_conf: stratum_data::PoolConfig,
+        err_receiver: Sender<Error>,
+        action_rcv: Sender<StratumAction>,
+        login_response: Option<String>,
+        first_id: u64,
+        (command_sender, command_receiver): (Sender<StratumCmd>, Receiver<StratumCmd>),
+        retry_shares: RetryShares,
+    ) -> io::Result<StratumClient> {
         let miner_id = Arc::new(Mutex::new(Option::None));
-        let (command_sender, command_receiver) = unbounded();
+        let mut login = StratumLoginResponse::default();
+        if let Some(line) = login_response {
+            match parse_line(&line, &miner_id) {
+                StratumAction::Job {
+                    miner_id,
+                    seed_hash,
+                    blob,
+                    job_id,
+                    target,
+                } => {
+                    login.initial_job = Some(StratumJob {
+                        miner_id,
+                        seed_hash,
+                        blob,
+                        job_id,
+                        target,
+                    })
+                }
+                action => {
+                    if action_rcv.send(action).is_err() {
+                        info!("sending login action failed, receiver already terminated");
+                    }
+                }
+            }
+        }
+        if let Some(StratumJob { job_id, target, .. }) = &login.initial_job {
+            match target_difficulty(target) {
+                Ok(difficulty) => info!(
+                    "initial job {} from login, difficulty {} (target {})",
+                    job_id, difficulty, target
+                ),
+                Err(err) => warn!(
+                    "initial job {} from login has an invalid target {}: {}",
+                    job_id, target, err
+                ),
+            }
+        }
+
+        let next_id = Arc::new(AtomicU64::new(first_id));
+        let pending_requests = Arc::new(Mutex::new(HashMap::new()));
+        //the login response was just received
+        let last_message_at = Arc::new(AtomicU64::new(epoch_millis()));
 
         let send_thread = StratumClient::start_send_thread(
             writer,
-            command_receiver,
-            pool_conf,
+            command_receiver.clone(),
+            pool_conf.clone(),
             err_receiver.clone(),
+            next_id.clone(),
+            pending_requests.clone(),
+            retry_shares.clone(),
         )?;
         let rcv_thread = StratumClient::start_receive_thread(
             reader,
-            action_rcv,
+            action_rcv.clone(),
             miner_id.clone(),
-            err_receiver,
+            err_receiver.clone(),
+            pending_requests.clone(),
+            (retry_shares.clone(), command_sender.clone()),
+            last_message_at.clone(),
         )?;
-        let (keep_alive_thread, tick_tx) =
-            StratumClient::start_keep_alive_thread(command_sender.clone(), miner_id)?;
-
-        command_sender
-            .send(StratumCmd::Login {})
-            .expect("login command send");
+        let (keep_alive_thread, tick_tx) = StratumClient::start_keep_alive_thread(
+            command_sender.clone(),
+            miner_id,
+            last_message_at,
+        )?;
+        let rtt_stats = Arc::new(Mutex::new(RttStats::new()));
+        let (rtt_thread, rtt_tick_tx) =
+            StratumClient::start_rtt_thread(tcp_stream_hnd.try_clone()?, rtt_stats.clone())?;
 
         Ok(StratumClient {
             command_sender,
+            command_receiver,
+            pool_conf,
+            err_receiver,
+            action_rcv,
             send_thread,
             rcv_thread,
             keep_alive_thread,
+            rtt_thread,
             tcp_stream_hnd,
             tick_tx,
+            rtt_tick_tx,
+            rtt_stats,
+            next_id,
+            pending_requests,
+            login_response: login,
+            retry_shares,
+            max_retry_attempts: DEFAULT_MAX_RETRY_ATTEMPTS,
+            state: Arc::new(AtomicU8::new(ConnectionState::Connecting as u8)),
         })
     }
 
-    fn connect_tcp(
-        pool_address: &str,
-    ) -> io::Result<(TcpStream, BufReader<TcpStream>, BufWriter<TcpStream>)> {
-        let stream = TcpStream::connect(pool_address)?;
+    /// Connects and does the login handshake, returns the login response line and
+    /// the next JSON-RPC id
+    #[allow(clippy::type_complexity)]
+    fn connect_and_login(
+        pool_conf: &stratum_data::PoolConfig,
+        max_retries: u32,
+    ) -> io::Result<(TcpStream, PoolReader, PoolWriter, String, u64)> {
+        let (tcp_stream_hnd, mut reader, mut writer) =
+            StratumClient::connect(&pool_conf.pool_address)?;
+
+        tcp_stream_hnd.set_read_timeout(Some(LOGIN_RESPONSE_TIMEOUT))?;
+        let (login_response, next_id) =
+            login_handshake(&mut reader, &mut writer, pool_conf, max_retries)?;
+        tcp_stream_hnd.set_read_timeout(None)?;
+
+        Ok((tcp_stream_hnd, reader, writer, login_response, next_id))
+    }
+
+    fn connect(pool_address: &str) -> io::Result<(TcpStream, PoolReader, PoolWriter)> {
+        let (stream, read_half, write_half) =
+            match stratum_data::StratumTransport::from_url(pool_address)? {
+                stratum_data::StratumTransport::Tcp(address) => {
+                    let stream = TcpStream::connect(address)?;
+                    let read_half: Box<dyn Read + Send> = Box::new(stream.try_clone()?);
+                    let write_half: Box<dyn Write + Send> = Box::new(stream.try_clone()?);
+                    (stream, read_half, write_half)
+                }
+                stratum_data::StratumTransport::WebSocket(url) => connect_websocket(&url)?,
+            };
         stream.set_read_timeout(None)?;
         stream.set_write_timeout(Some(Duration::from_secs(10)))?;
 
-        let reader = BufReader::new(stream.try_clone()?);
-        let writer = BufWriter::new(stream.try_clone()?);
+        let reader = StratumFramer::new(read_half);
+        let writer = BufWriter::new(write_half);
 
         Ok((stream, reader, writer))
     }
 
     fn start_send_thread(
-        writer: BufWriter<TcpStream>,
+        writer: PoolWriter,
         command_rcv: Receiver<StratumCmd>,
         pool_conf: stratum_data::PoolConfig,
         err_receiver: Sender<Error>,
+        next_id: Arc<AtomicU64>,
+        pending_requests: PendingRequests,
+        retry_shares: RetryShares,
     ) -> io::Result<thread::JoinHandle<()>> {
         thread::Builder::new()
             .name("Stratum send thread".to_string())
             .spawn(move || {
-                let result = handle_stratum_send(&command_rcv, writer, &pool_conf);
+                let result = handle_stratum_send(
+                    &command_rcv,
+                    writer,
+                    &pool_conf,
+                    &next_id,
+                    &pending_requests,
+                    &retry_shares,
+                );
                 if result.is_err() {
                     err_receiver
                         .send(result.err().expect("result error send thread"))
@@ -125,15 +517,25 @@ impl StratumClient {
     }
 
     fn start_receive_thread(
-        reader: BufReader<TcpStream>,
+        reader: PoolReader,
         action_rcv: Sender<StratumAction>,
         miner_id: Arc<Mutex<Option<String>>>,
         err_receiver: Sender<Error>,
+        pending_requests: PendingRequests,
+        retry: (RetryShares, Sender<StratumCmd>),
+        last_message_at: Arc<AtomicU64>,
     ) -> io::Result<thread::JoinHandle<()>> {
         thread::Builder::new()
             .name("Stratum receive thread".to_string())
             .spawn(move || {
-                let result = handle_stratum_receive(reader, &action_rcv, &miner_id);
+                let result = handle_stratum_receive(
+                    reader,
+                    &action_rcv,
+                    &miner_id,
+                    &pending_requests,
+                    &retry,
+                    &last_message_at,
+                );
                 if result.is_err() {
                     err_receiver
                         .send(result.err().expect("result error recv thread"))
@@ -143,13 +545,19 @@ impl StratumClient {
             })
     }
 
+    /// Sends a keep alive once the pool sent nothing for `KEEP_ALIVE_INTERVAL_MS`
+    /// (see `keep_alive_due`), checked every `KEEP_ALIVE_CHECK_INTERVAL_MS`
     fn start_keep_alive_thread(
         cmd_alive: Sender<StratumCmd>,
         alive_miner_id: Arc<Mutex<Option<String>>>,
+        last_message_at: Arc<AtomicU64>,
     ) -> io::Result<(thread::JoinHandle<()>, Sender<()>)> {
         let (stop_sndr, stop_rcvr) = unbounded();
 
-        let (tick_rcv, _) = start_tick_thread(Duration::from_secs(60), stop_rcvr);
+        let (tick_rcv, _) = start_tick_thread(
+            Duration::from_millis(KEEP_ALIVE_CHECK_INTERVAL_MS),
+            stop_rcvr,
+        );
         Ok((
             thread::Builder::new()
                 .name("keep alive thread".to_string())
@@ -160,6 +568,13 @@ impl StratumClient {
                             break;
                         } //else: normal tick, loop around
 
+                        if !keep_alive_due(
+                            last_message_at.load(Ordering::SeqCst),
+                            epoch_millis(),
+                            KEEP_ALIVE_INTERVAL_MS,
+                        ) {
+                            continue;
+                        }
                         let miner_id_guard = &*alive_miner_id.lock().expect("miner_id lock");
                         if miner_id_guard.is_some() {
                             let miner_id = miner_id_guard.clone().expect("miner_id clone");
@@ -174,15 +589,118 @@ impl StratumClient {
         ))
     }
 
-    /// Returns a new channel for sending commands to the stratum client
+    /// Takes a round-trip time sample every `RTT_SAMPLE_INTERVAL_MS` (see `rtt::socket_rtt`),
+    /// independent of the keep alives. Logs a warning when the average gets above
+    /// `RTT_WARN_THRESHOLD_MS`.
+    fn start_rtt_thread(
+        stream: TcpStream,
+        rtt_stats: Arc<Mutex<RttStats>>,
+    ) -> io::Result<(thread::JoinHandle<()>, Sender<()>)> {
+        let (stop_sndr, stop_rcvr) = unbounded();
+
+        let (tick_rcv, _) = start_tick_thread(
+            Duration::from_millis(rtt::RTT_SAMPLE_INTERVAL_MS),
+            stop_rcvr,
+        );
+        Ok((
+            thread::Builder::new()
+                .name("rtt thread".to_string())
+                .spawn(move || {
+                    loop {
+                        let tick_result = tick_rcv.recv();
+                        if tick_result.is_err() || tick_result.expect("tick result") == Tick::Stop {
+                            break;
+                        } //else: normal tick, loop around
+
+                        let rtt = match rtt::socket_rtt(&stream) {
+                            Ok(rtt) => rtt,
+                            Err(err) => {
+                                debug!("measuring round-trip time failed, {:?}", err);
+                                continue;
+                            }
+                        };
+                        if rtt.is_zero() {
+                            continue; //nothing acknowledged yet
+                        }
+                        let mut stats = rtt_stats.lock().expect("rtt stats lock");
+                        let was_slow = stats.is_slow();
+                        stats.add_sample(rtt);
+                        if stats.is_slow() && !was_slow {
+                            warn!(
+                                "average round-trip time to the pool is {:.1}ms (last {} samples)",
+                                stats.avg_rtt_ms().expect("rtt average"),
+                                stats.sample_count()
+                            );
+                        }
+                    }
+                    info!("rtt thread ended");
+                })?,
+            stop_sndr,
+        ))
+    }
+
+    /// Returns a new channel for sending commands to the stratum client, it stays valid across `reconnect`
     pub fn new_cmd_channel(&self) -> Sender<StratumCmd> {
         self.command_sender.clone()
     }
 
+    /// The login response of the current connection
+    pub fn login_response(&self) -> &StratumLoginResponse {
+        &self.login_response
+    }
+
+    /// The job of the login response. It is only sent as `StratumAction` by `login` and
+    /// `reconnect`, with `login_with_retry` the workers can start on it right away.
+    pub fn initial_job(&self) -> Option<&StratumJob> {
+        self.login_response.initial_job.as_ref()
+    }
+
+    /// Round-trip times of the current connection, a `reconnect` starts over
+    pub fn rtt_stats(&self) -> RttStats {
+        self.rtt_stats.lock().expect("rtt stats lock").clone()
+    }
+
+    /// Number of submitted shares the pool has not answered yet
+    pub fn pending_request_count(&self) -> usize {
+        self.pending_requests.lock().expect("pending requests lock").len()
+    }
+
+    /// How often a share the connection could not send is re-submitted after a
+    /// `reconnect`, `DEFAULT_MAX_RETRY_ATTEMPTS` by default
+    pub fn set_max_retry_attempts(&mut self, attempts: u32) {
+        self.max_retry_attempts = attempts;
+    }
+
+    /// Shares waiting for re-submission
+    pub fn retry_share_count(&self) -> usize {
+        self.retry_shares.lock().expect("retry shares lock").len()
+    }
+
+    /// The JSON-RPC id that will be used for the next request
+    pub fn next_request_id(&self) -> u64 {
+        self.next_id.load(Ordering::SeqCst)
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        ConnectionState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    /// Sends a `StratumAction::StateChange` if `state` is not the current state
+    fn set_state(&self, state: ConnectionState) {
+        if self.state.swap(state as u8, Ordering::SeqCst) != state as u8 {
+            send_state_change(&self.action_rcv, state);
+        }
+    }
+
     /// Stops the StratumClient, ending all communication with the server end.
     pub fn stop(self) {
         info!("stopping stratum client");
+        self.set_state(ConnectionState::Stopped);
+        self.shutdown();
+        self.join();
+    }
 
+    fn shutdown(&self) {
         //stop send thread
         self.command_sender
             .send(StratumCmd::Shutdown {})
@@ -197,10 +715,26 @@ impl StratumClient {
         }
 
         //stop keep alive thread (via stopping tick thread)
-        self.tick_tx.send(()).expect("ending tick thread");
+        if self.tick_tx.send(()).is_err() {
+            info!("tick thread already ended");
+        }
+        if self.rtt_tick_tx.send(()).is_err() {
+            info!("rtt tick thread already ended");
+        }
+    }
+
+    fn join(self) {
         self.send_thread.join().expect("join send thread");
         self.rcv_thread.join().expect("join rcv thread");
         self.keep_alive_thread.join().expect("keep alive thread");
+        self.rtt_thread.join().expect("rtt thread");
+    }
+}
+
+fn send_state_change(action_rcv: &Sender<StratumAction>, state: ConnectionState) {
+    info!("stratum connection state {:?}", state);
+    if action_rcv.send(StratumAction::StateChange(state)).is_err() {
+        info!("sending state change failed, receiver already terminated");
     }
 }
 
@@ -210,6 +744,23 @@ pub enum Tick {
     Stop,
 }
 
+/// the `TcpStream` and the read and write half of a pool connection
+type PoolConnection = (TcpStream, Box<dyn Read + Send>, Box<dyn Write + Send>);
+
+#[cfg(feature = "websocket")]
+fn connect_websocket(url: &str) -> io::Result<PoolConnection> {
+    let (stream, reader, writer) = websocket::connect(url)?;
+    Ok((stream, Box::new(reader), Box::new(writer)))
+}
+
+#[cfg(not(feature = "websocket"))]
+fn connect_websocket(url: &str) -> io::Result<PoolConnection> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        format!("{} needs mithril built with the websocket feature", url),
+    ))
+}
+
 pub fn start_tick_thread(
     interval: Duration,
     stop_rcv: Receiver<()>,
@@ -250,14 +801,50 @@ pub fn submit_share(
 
 fn handle_stratum_send(
     rx: &Receiver<StratumCmd>,
-    mut writer: BufWriter<TcpStream>,
+    mut writer: PoolWriter,
     pool_conf: &stratum_data::PoolConfig,
+    next_id: &AtomicU64,
+    pending_requests: &PendingRequests,
+    retry_shares: &RetryShares,
 ) -> Result<(), Error> {
     loop {
-        match rx.recv().expect("stratum receiver") {
-            StratumCmd::Login {} => do_stratum_login(&mut writer, pool_conf)?,
-            StratumCmd::SubmitShare { share } => do_stratum_submit_share(&mut writer, share)?,
-            StratumCmd::KeepAlive { miner_id } => do_stratum_keep_alive(&mut writer, miner_id)?,
+        let cmd = rx.recv().expect("stratum receiver");
+        let id = next_id.fetch_add(1, Ordering::SeqCst);
+        match cmd {
+            StratumCmd::Login {} => do_stratum_login(&mut writer, id, pool_conf)?,
+            StratumCmd::SubmitShare { share } => match RetryShare::from_share(&share) {
+                Ok(retry) => submit_share_or_queue(
+                    &mut writer,
+                    id,
+                    share,
+                    retry,
+                    pending_requests,
+                    retry_shares,
+                )?,
+                Err(err) => drop_invalid_share(&share, &err),
+            },
+            StratumCmd::RetryShare {
+                mut retry,
+                miner_id,
+            } => {
+                retry.attempts += 1;
+                info!(
+                    "re-submitting share with nonce {:08x}, attempt {}",
+                    retry.nonce, retry.attempts
+                );
+                let share = retry.to_share(&miner_id);
+                submit_share_or_queue(
+                    &mut writer,
+                    id,
+                    share,
+                    retry,
+                    pending_requests,
+                    retry_shares,
+                )?
+            }
+            StratumCmd::KeepAlive { miner_id } => {
+                do_stratum_keep_alive(&mut writer, id, miner_id)?
+            }
             StratumCmd::Shutdown {} => {
                 info!("stopping stratum send thread");
                 break;
@@ -267,9 +854,9 @@ fn handle_stratum_send(
     Ok(())
 }
 
-fn do_stratum_keep_alive(writer: &mut BufWriter<TcpStream>, miner_id: String) -> Result<(), Error> {
+fn do_stratum_keep_alive<W: Write>(writer: &mut W, id: u64, miner_id: String) -> Result<(), Error> {
     let keep_alive_req = stratum_data::KeepAliveRequest {
-        id: 1,
+        id,
         method: "keepalived".to_string(),
         params: stratum_data::KeepAliveParams { id: miner_id },
     };
@@ -280,12 +867,53 @@ fn do_stratum_keep_alive(writer: &mut BufWriter<TcpStream>, miner_id: String) ->
     Ok(())
 }
 
-fn do_stratum_submit_share(
-    writer: &mut BufWriter<TcpStream>,
+fn drop_invalid_share(share: &stratum_data::Share, err: &byte_string::HexError) {
+    error!(
+        "dropping share with nonce {} of job {}, invalid hash {}: {}",
+        share.nonce, share.job_id, share.hash, err
+    );
+}
+
+/// Sends the share, if that fails it is queued as `retry` for the next connection
+fn submit_share_or_queue<W: Write>(
+    writer: &mut W,
+    id: u64,
+    share: stratum_data::Share,
+    retry: RetryShare,
+    pending_requests: &PendingRequests,
+    retry_shares: &RetryShares,
+) -> Result<(), Error> {
+    let pending = PendingRequest {
+        submitted_at: Instant::now(),
+        nonce: retry.nonce,
+        retry_attempt: retry.attempts,
+    };
+    pending_requests
+        .lock()
+        .expect("pending requests lock")
+        .insert(id, pending);
+    let result = do_stratum_submit_share(writer, id, share);
+    if result.is_err() {
+        pending_requests
+            .lock()
+            .expect("pending requests lock")
+            .remove(&id);
+        warn!(
+            "sending share with nonce {:08x} failed, re-submitting it after reconnect",
+            retry.nonce
+        );
+        retry_shares.lock().expect("retry shares lock").push(retry);
+    }
+    result
+}
+
+fn do_stratum_submit_share<W: Write>(
+    writer: &mut W,
+    id: u64,
     share: stratum_data::Share,
 ) -> Result<(), Error> {
     let submit_req = stratum_data::SubmitRequest {
-        id: 1,
+        id,
         method: "submit".to_string(),
         params: stratum_data::SubmitParams {
             id: share.miner_id,
@@ -300,12 +928,13 @@ fn do_stratum_submit_share(
     Ok(())
 }
 
-fn do_stratum_login(
-    writer: &mut BufWriter<TcpStream>,
+fn do_stratum_login<W: Write>(
+    writer: &mut W,
+    id: u64,
     pool_conf: &stratum_data::PoolConfig,
 ) -> Result<(), Error> {
     let login_req = stratum_data::LoginRequest {
-        id: 1,
+        id,
         method: "login".to_string(),
         params: stratum_data::LoginParams {
             login: pool_conf.wallet_address.clone(),
@@ -318,30 +947,264 @@ fn do_stratum_login(
     Ok(())
 }
 
+/// Error payload of the `PermissionDenied` error `login_with_retry` returns when
+/// the pool rejected the login, see `login_rejected_code`
+#[derive(Debug)]
+pub struct LoginRejected {
+    pub code: i64,
+    pub message: String,
+}
+
+impl fmt::Display for LoginRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "login rejected: {} (code {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for LoginRejected {}
+
+/// The pool error code if `err` is a rejected login
+pub fn login_rejected_code(err: &Error) -> Option<i64> {
+    err.get_ref()?
+        .downcast_ref::<LoginRejected>()
+        .map(|rejected| rejected.code)
+}
+
+/// Sends login requests until the pool accepts one, returns the accepted response
+/// and the next free request id.
+fn login_handshake<R: Read, W: Write>(
+    reader: &mut StratumFramer<R>,
+    writer: &mut W,
+    pool_conf: &stratum_data::PoolConfig,
+    max_retries: u32,
+) -> Result<(String, u64), Error> {
+    let mut id = 1;
+    let mut retries = 0;
+    loop {
+        do_stratum_login(writer, id, pool_conf)?;
+        id += 1;
+
+        let line = reader.next_message()?;
+
+        let error: Result<stratum_data::ErrorResult, serde_json::Error> =
+            serde_json::from_str(&line);
+        let err_details = match error {
+            Ok(stratum_data::ErrorResult { error: err_details }) => err_details,
+            Err(_) => return Ok((line, id)),
+        };
+
+        if !is_auth_error(err_details.code) || retries >= max_retries {
+            return Err(Error::new(
+                ErrorKind::PermissionDenied,
+                LoginRejected {
+                    code: err_details.code,
+                    message: err_details.message,
+                },
+            ));
+        }
+        retries += 1;
+        warn!(
+            "login rejected: {} (code {}), retry {}/{}",
+            err_details.message, err_details.code, retries, max_retries
+        );
+        thread::sleep(LOGIN_RETRY_DELAY);
+    }
+}
+
+/// JSON-RPC reserves -32700 to -32600 for malformed requests, retrying those
+/// does not help. Neither does retrying an unauthorized worker or a worker that is not
+/// subscribed (24 and 25, the codes pools ban a wallet with, see `fatal_error_codes`).
+/// Every other code is the pool rejecting the login itself.
+pub fn is_auth_error(code: i64) -> bool {
+    match StratumErrorCode::from_code(code) {
+        StratumErrorCode::UnauthorizedWorker | StratumErrorCode::NotSubscribed => false,
+        _ => !(-32700..=-32600).contains(&code),
+    }
+}
+
+/// Error codes of pool error responses
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StratumErrorCode {
+    /// -1, node-cryptonote-pool and nodejs-pool (MoneroOcean) answer every
+    /// rejection with this code, the reason is only in the message
+    PoolRejected,
+    Other,
+    LowDifficulty,
+    DuplicateShare,
+    InvalidNonce,
+    UnauthorizedWorker,
+    NotSubscribed,
+    /// JSON-RPC codes, mostly from node based pools (e.g. XMR-node)
+    ParseError,
+    InvalidRequest,
+    MethodNotFound,
+    InvalidParams,
+    InternalError,
+    Unknown(i64),
+}
+
+impl StratumErrorCode {
+    pub fn from_code(code: i64) -> StratumErrorCode {
+        match code {
+            -1 => StratumErrorCode::PoolRejected,
+            20 => StratumErrorCode::Other,
+            21 => StratumErrorCode::LowDifficulty,
+            22 => StratumErrorCode::DuplicateShare,
+            23 => StratumErrorCode::InvalidNonce,
+            24 => StratumErrorCode::UnauthorizedWorker,
+            25 => StratumErrorCode::NotSubscribed,
+            -32700 => StratumErrorCode::ParseError,
+            -32600 => StratumErrorCode::InvalidRequest,
+            -32601 => StratumErrorCode::MethodNotFound,
+            -32602 => StratumErrorCode::InvalidParams,
+            -32603 => StratumErrorCode::InternalError,
+            _ => StratumErrorCode::Unknown(code),
+        }
+    }
+
+    pub fn description(&self) -> &str {
+        match self {
+            StratumErrorCode::PoolRejected => "request rejected by pool",
+            StratumErrorCode::Other => "other/unknown error",
+            StratumErrorCode::LowDifficulty => "low difficulty share",
+            StratumErrorCode::DuplicateShare => "duplicate share",
+            StratumErrorCode::InvalidNonce => "invalid nonce",
+            StratumErrorCode::UnauthorizedWorker => "unauthorized worker",
+            StratumErrorCode::NotSubscribed => "not subscribed",
+            StratumErrorCode::ParseError => "invalid JSON (parse error)",
+            StratumErrorCode::InvalidRequest => "invalid request",
+            StratumErrorCode::MethodNotFound => "method not found",
+            StratumErrorCode::InvalidParams => "invalid method parameters",
+            StratumErrorCode::InternalError => "internal pool error",
+            StratumErrorCode::Unknown(_) => "unknown error code",
+        }
+    }
+}
+
+/// Logs the error of a pool error response in `line` with the description of its code.
+/// Returns the code, None if `line` is not an error response.
+pub fn warn_pool_error(line: &str) -> Option<StratumErrorCode> {
+    let stratum_data::ErrorResult { error } = serde_json::from_str(line).ok()?;
+    let code = StratumErrorCode::from_code(error.code);
+    warn!(
+        "pool error {} ({}): {}",
+        error.code,
+        code.description(),
+        error.message
+    );
+    Some(code)
+}
+
 fn handle_stratum_receive(
-    mut reader: BufReader<TcpStream>,
+    mut reader: PoolReader,
     rcv: &Sender<StratumAction>,
     miner_id: &Arc<Mutex<Option<String>>>,
+    pending_requests: &PendingRequests,
+    (retry_shares, cmd): &(RetryShares, Sender<StratumCmd>),
+    last_message_at: &AtomicU64,
 ) -> Result<(), Error> {
     loop {
-        let mut line = String::new();
-        match reader.read_line(&mut line) {
-            Ok(n) => {
-                if n == 0 {
-                    //that means EOF in the TCPStream was reached
-                    return Err(Error::new(ErrorKind::Other, "connection terminated"));
+        match reader.next_message() {
+            Ok(line) => {
+                last_message_at.store(epoch_millis(), Ordering::SeqCst);
+                let answered = complete_pending_request(&line, pending_requests);
+                let answers_share = answered.is_some();
+                let pool_error = warn_pool_error(&line);
+                if let Some(pending) = answered.filter(|p| p.retry_attempt > 0) {
+                    if pool_error.is_none() {
+                        info!(
+                            "re-submitted share with nonce {:08x} accepted",
+                            pending.nonce
+                        );
+                    } else {
+                        warn!(
+                            "re-submitted share with nonce {:08x} rejected",
+                            pending.nonce
+                        );
+                    }
+                }
+                let mut action = parse_line(&line, miner_id);
+                if answers_share {
+                    action = share_response(action);
                 }
-                parse_line_dispatch_result(&line, rcv, miner_id);
+                dispatch_action(action, rcv);
+                resubmit_retry_shares(miner_id, retry_shares, cmd);
             }
             Err(e) => {
-                //read_line fails (maybe connection lost, dispatch err to channel)
-                //=> Terminate loop
+                //connection terminated or lost, or the pool sent garbage
+                //(dispatch err to channel) => Terminate loop
                 return Err(e);
             }
         };
     }
 }
 
+/// True if nothing was received from the pool for at least `interval_ms` (epoch ms),
+/// a keep alive is not needed while the pool sends jobs or share responses
+pub fn keep_alive_due(last_message_at_ms: u64, now_ms: u64, interval_ms: u64) -> bool {
+    now_ms.saturating_sub(last_message_at_ms) >= interval_ms
+}
+
+/// Milliseconds since the unix epoch, 0 if the clock is before it
+pub fn epoch_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Hands the queued shares to the send thread as soon as the login gave a miner id
+fn resubmit_retry_shares(
+    miner_id: &Arc<Mutex<Option<String>>>,
+    retry_shares: &RetryShares,
+    cmd: &Sender<StratumCmd>,
+) {
+    let miner_id = match miner_id.lock().expect("miner id lock").clone() {
+        Some(miner_id) => miner_id,
+        None => return,
+    };
+    queue_retry_shares(&miner_id, retry_shares, cmd);
+}
+
+/// Sends the queued shares to the send thread, re-submitted with `miner_id`
+fn queue_retry_shares(miner_id: &str, retry_shares: &RetryShares, cmd: &Sender<StratumCmd>) {
+    let retries: Vec<RetryShare> = retry_shares
+        .lock()
+        .expect("retry shares lock")
+        .drain(..)
+        .collect();
+    for retry in retries {
+        let send_result = cmd.send(StratumCmd::RetryShare {
+            retry,
+            miner_id: miner_id.to_string(),
+        });
+        if send_result.is_err() {
+            error!("queueing share re-submission failed {:?}", send_result);
+        }
+    }
+}
+
+/// Removes the pending share submission the response in `line` answers (if any)
+/// and returns it.
+pub fn complete_pending_request(
+    line: &str,
+    pending_requests: &PendingRequests,
+) -> Option<PendingRequest> {
+    let response: stratum_data::ResponseId = serde_json::from_str(line).ok()?;
+    let id = response.id?;
+    let pending = pending_requests
+        .lock()
+        .expect("pending requests lock")
+        .remove(&id)?;
+    info!(
+        "pool answered share with nonce {:08x} (request id {}) after {}ms",
+        pending.nonce,
+        id,
+        pending.submitted_at.elapsed().as_millis()
+    );
+    Some(pending)
+}
+
 fn is_known_ok(
     result: Result<stratum_data::OkResponse, serde_json::Error>,
 ) -> Option<StratumAction> {
@@ -356,12 +1219,32 @@ fn is_known_ok(
     None
 }
 
-//TODO Refactor this method (it is very ugly) - its probably better to use generic value parsing and not using struct for every case
 pub fn parse_line_dispatch_result(
     line: &str,
     rcv: &Sender<StratumAction>,
     miner_id_mutx: &Arc<Mutex<Option<String>>>,
 ) {
+    dispatch_action(parse_line(line, miner_id_mutx), rcv);
+}
+
+/// The action for a response that answers a share submission: errors are
+/// `ShareRejected`, unlike errors answering the login or a keep alive
+pub fn share_response(action: StratumAction) -> StratumAction {
+    match action {
+        StratumAction::Error { err } => StratumAction::ShareRejected { err },
+        action => action,
+    }
+}
+
+fn dispatch_action(action: StratumAction, rcv: &Sender<StratumAction>) {
+    let send_result = rcv.send(action);
+    if send_result.is_err() {
+        info!("sending action to receiver failed (receiver probably already terminated), trying next receiver");
+    }
+}
+
+//TODO Refactor this method (it is very ugly) - its probably better to use generic value parsing and not using struct for every case
+pub fn parse_line(line: &str, miner_id_mutx: &Arc<Mutex<Option<String>>>) -> StratumAction {
     let action;
 
     let error: Result<stratum_data::ErrorResult, serde_json::Error> = serde_json::from_str(line);
@@ -383,13 +1266,12 @@ pub fn parse_line_dispatch_result(
             let result: Result<stratum_data::Method, serde_json::Error> =
                 serde_json::from_str(line);
             if result.is_ok() {
-                let stratum_data::Method { method } = result.expect("result unwrap");
+                let stratum_data::Method { method, params } = result.expect("result unwrap");
                 match method.as_ref() {
                     "job" => action = parse_job(line, miner_id_mutx),
                     _ => {
-                        action = StratumAction::Error {
-                            err: format!("unknown method received: {}", method),
-                        }
+                        debug!("unknown method received: {}", method);
+                        action = StratumAction::Unknown { method, params }
                     }
                 };
             } else {
@@ -437,11 +1319,25 @@ pub fn parse_line_dispatch_result(
             }
         }
     }
+    action
+}
 
-    let send_result = rcv.send(action);
-    if send_result.is_err() {
-        info!("sending action to receiver failed (receiver probably already terminated), trying next receiver");
+/// Difficulty of a job target, i.e. the expected number of hashes per share.
+/// Only the 8 hex char (32 bit, little-endian) targets used by Monero pools are supported,
+/// shorter or non hex targets are an error.
+pub fn target_difficulty(target: &str) -> Result<u64, byte_string::HexError> {
+    let bytes = byte_string::hex_to_bytes(target)?;
+    if bytes.len() < 4 {
+        return Err(byte_string::HexError::TooShort {
+            len: target.len(),
+            min: 8,
+        });
+    }
+    let t = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if t == 0 {
+        return Ok(0);
     }
+    Ok(u64::from(u32::MAX) / u64::from(t))
 }
 
 fn parse_job(line: &str, miner_id_mutx: &Arc<Mutex<Option<String>>>) -> StratumAction {
@@ -0,0 +1,95 @@
+//! Round-trip time to the pool, as measured by the TCP stack of the kernel (linux only).
+
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+use std::collections::VecDeque;
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// samples kept by `RttStats`
+pub const RTT_SAMPLE_COUNT: usize = 10;
+/// `StratumClient` takes a sample at this interval
+pub const RTT_SAMPLE_INTERVAL_MS: u64 = 10_000;
+/// a warning is logged when the average of the samples gets above this
+pub const RTT_WARN_THRESHOLD_MS: f64 = 200.0;
+
+/// The last `RTT_SAMPLE_COUNT` round-trip times of a connection
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RttStats {
+    samples: VecDeque<f64>,
+}
+
+impl RttStats {
+    pub fn new() -> RttStats {
+        RttStats::default()
+    }
+
+    /// Adds a sample, the oldest one is dropped if there are already `RTT_SAMPLE_COUNT`
+    pub fn add_sample(&mut self, rtt: Duration) {
+        if self.samples.len() == RTT_SAMPLE_COUNT {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(rtt.as_secs_f64() * 1000.0);
+    }
+
+    pub fn sample_count(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// None without samples (same for `avg_rtt_ms` and `max_rtt_ms`)
+    pub fn min_rtt_ms(&self) -> Option<f64> {
+        self.samples.iter().cloned().reduce(f64::min)
+    }
+
+    pub fn avg_rtt_ms(&self) -> Option<f64> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<f64>() / self.samples.len() as f64)
+    }
+
+    pub fn max_rtt_ms(&self) -> Option<f64> {
+        self.samples.iter().cloned().reduce(f64::max)
+    }
+
+    /// True if the average is above `RTT_WARN_THRESHOLD_MS`
+    pub fn is_slow(&self) -> bool {
+        self.avg_rtt_ms()
+            .is_some_and(|avg| avg > RTT_WARN_THRESHOLD_MS)
+    }
+}
+
+/// Smoothed round-trip time of the connection (`tcpi_rtt` of `TCP_INFO`). The kernel
+/// measures it from the acknowledgements of the data sent, so no extra packets are
+/// needed. Zero until the first acknowledgement was received.
+#[cfg(target_os = "linux")]
+pub fn socket_rtt(stream: &TcpStream) -> io::Result<Duration> {
+    use std::mem;
+    use std::os::unix::io::AsRawFd;
+
+    let mut info: libc::tcp_info = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Duration::from_micros(u64::from(info.tcpi_rtt)))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn socket_rtt(_stream: &TcpStream) -> io::Result<Duration> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "round-trip time is only available on linux",
+    ))
+}
@@ -1,10 +1,24 @@
 extern crate serde;
 extern crate serde_json;
 
+use std::fmt;
+use std::io;
+
+#[cfg(feature = "zeroize")]
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
 /// For checking the method in the json content and parsing further
 #[derive(Deserialize, Debug)]
 pub struct Method {
     pub method: String,
+    #[serde(default)]
+    pub params: serde_json::Value,
+}
+
+/// For correlating a response with the request it answers
+#[derive(Deserialize, Debug)]
+pub struct ResponseId {
+    pub id: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -47,7 +61,7 @@ pub struct OkResult {
 
 #[derive(Deserialize, Clone)]
 pub struct OkResponse {
-    pub id: u32,
+    pub id: u64,
     pub result: OkResult,
 }
 
@@ -64,7 +78,7 @@ pub struct LoginParams {
 
 #[derive(Serialize)]
 pub struct LoginRequest {
-    pub id: u32,
+    pub id: u64,
     pub method: String,
     pub params: LoginParams,
 }
@@ -76,7 +90,7 @@ pub struct KeepAliveParams {
 
 #[derive(Serialize)]
 pub struct KeepAliveRequest {
-    pub id: u32,
+    pub id: u64,
     pub method: String,
     pub params: KeepAliveParams,
 }
@@ -91,7 +105,7 @@ pub struct SubmitParams {
 
 #[derive(Serialize)]
 pub struct SubmitRequest {
-    pub id: u32,
+    pub id: u64,
     pub method: String,
     pub params: SubmitParams,
 }
@@ -104,9 +118,74 @@ pub struct Share {
     pub hash: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq, Hash)]
 pub struct PoolConfig {
     pub pool_address: String,
     pub wallet_address: String,
     pub pool_password: String,
 }
+
+impl PoolConfig {
+    pub fn transport(&self) -> io::Result<StratumTransport> {
+        StratumTransport::from_url(&self.pool_address)
+    }
+}
+
+/// Number of wallet address characters shown by `Display`
+const WALLET_DISPLAY_LEN: usize = 10;
+
+/// `host:port (wallet...)`, the wallet address is truncated for logging
+impl fmt::Display for PoolConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut wallet = self.wallet_address.chars();
+        let prefix: String = wallet.by_ref().take(WALLET_DISPLAY_LEN).collect();
+        let ellipsis = if wallet.next().is_some() { "..." } else { "" };
+        write!(f, "{} ({}{})", self.pool_address, prefix, ellipsis)
+    }
+}
+
+/// Overwrites the address, wallet and password (all copies made with `clone` are
+/// zeroized separately when they are dropped)
+#[cfg(feature = "zeroize")]
+impl Zeroize for PoolConfig {
+    fn zeroize(&mut self) {
+        self.pool_address.zeroize();
+        self.wallet_address.zeroize();
+        self.pool_password.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for PoolConfig {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl ZeroizeOnDrop for PoolConfig {}
+
+/// How the pool is reached, selected by the scheme of the pool address
+#[derive(Debug, Clone, PartialEq)]
+pub enum StratumTransport {
+    /// `host:port`, one JSON-RPC message per line
+    Tcp(String),
+    /// `ws://host:port/path`, one JSON-RPC message per text frame
+    WebSocket(String),
+}
+
+impl StratumTransport {
+    /// Fails with `InvalidInput` for `wss://` urls, TLS is not supported
+    pub fn from_url(url: &str) -> io::Result<StratumTransport> {
+        if url.starts_with("wss://") {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{}: wss:// (TLS) is not supported", url),
+            ))
+        } else if url.starts_with("ws://") {
+            Ok(StratumTransport::WebSocket(url.to_string()))
+        } else {
+            Ok(StratumTransport::Tcp(url.to_string()))
+        }
+    }
+}
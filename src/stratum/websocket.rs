@@ -0,0 +1,161 @@
+//! Stratum over WebSocket (`ws://` pool addresses). The pool connection is split into
+//! a `Read` half that returns every text frame as a JSON-RPC line and a `Write` half
+//! that sends every line as a text frame, so the stratum client reads and writes lines
+//! the same way as on a plain TCP connection.
+
+use std::io;
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+
+use tungstenite::client::client;
+use tungstenite::protocol::Role;
+use tungstenite::{Error, Message, WebSocket};
+
+/// Connects to the pool at `url` and does the WebSocket handshake. Returns the
+/// `TcpStream` (for timeouts and the shutdown) and the two halves of the connection.
+pub fn connect(url: &str) -> io::Result<(TcpStream, WebSocketReader, WebSocketWriter)> {
+    let stream = TcpStream::connect(host_port(url)?)?;
+    let write_lock = Arc::new(Mutex::new(()));
+    let reader_stream = SharedStream {
+        stream: stream.try_clone()?,
+        write_lock: write_lock.clone(),
+    };
+    //the handshake may have read frames sent right after it, they stay in this instance
+    let (ws, _) = client(url, reader_stream).map_err(|e| {
+        io::Error::new(
+            ErrorKind::ConnectionRefused,
+            format!("websocket handshake with {} failed: {}", url, e),
+        )
+    })?;
+    let writer_stream = SharedStream {
+        stream: stream.try_clone()?,
+        write_lock,
+    };
+    let writer = WebSocketWriter {
+        ws: WebSocket::from_raw_socket(writer_stream, Role::Client, None),
+        line: Vec::new(),
+    };
+    let reader = WebSocketReader {
+        ws,
+        pending: Vec::new(),
+    };
+    Ok((stream, reader, writer))
+}
+
+/// `host:port` of a `ws://` url, port 80 if the url has none
+pub fn host_port(url: &str) -> io::Result<String> {
+    let authority = url
+        .strip_prefix("ws://")
+        .and_then(|rest| rest.split('/').next())
+        .filter(|authority| !authority.is_empty())
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a ws:// url", url),
+            )
+        })?;
+    let has_port = match authority.rfind(':') {
+        Some(ix) => !authority[ix..].contains(']'), //the colons of an IPv6 address
+        None => false,
+    };
+    if has_port {
+        Ok(authority.to_string())
+    } else {
+        Ok(format!("{}:80", authority))
+    }
+}
+
+/// Both halves write to the same socket: the reader answers pings and close frames,
+/// the writer sends the requests. Every write of a WebSocket is one or more complete
+/// frames, writing it under the lock keeps the frames of the two halves apart.
+struct SharedStream {
+    stream: TcpStream,
+    write_lock: Arc<Mutex<()>>,
+}
+
+impl Read for SharedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for SharedStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let _guard = self.write_lock.lock().expect("websocket write lock");
+        self.stream.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Returns the text frames from the pool, each one followed by a `\n`
+pub struct WebSocketReader {
+    ws: WebSocket<SharedStream>,
+    /// the part of the last text frame not returned by `read` yet
+    pending: Vec<u8>,
+}
+
+impl Read for WebSocketReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.ws.read() {
+                Ok(Message::Text(text)) => {
+                    self.pending.extend_from_slice(text.trim_end().as_bytes());
+                    self.pending.push(b'\n');
+                }
+                Ok(Message::Close(_)) => return Ok(0),
+                Ok(_) => {} //pings are answered by tungstenite, binary frames are not stratum
+                Err(Error::ConnectionClosed) | Err(Error::AlreadyClosed) => return Ok(0),
+                Err(e) => return Err(ws_error(e)),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Sends every complete line as a text frame, a partial line waits for its `\n`
+pub struct WebSocketWriter {
+    ws: WebSocket<SharedStream>,
+    line: Vec<u8>,
+}
+
+impl Write for WebSocketWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        while let Some(pos) = self.line.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.line.drain(..=pos).collect();
+            let text = String::from_utf8_lossy(&line).trim_end().to_string();
+            self.ws.write(Message::Text(text)).map_err(ws_error)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.ws.flush().map_err(ws_error)
+    }
+}
+
+/// Closes the WebSocket when the stratum client stops sending
+impl Drop for WebSocketWriter {
+    fn drop(&mut self) {
+        let _ = self.ws.close(None);
+        let _ = self.ws.flush();
+    }
+}
+
+fn ws_error(err: Error) -> io::Error {
+    match err {
+        Error::Io(e) => e,
+        Error::ConnectionClosed | Error::AlreadyClosed => {
+            io::Error::new(ErrorKind::ConnectionAborted, "websocket closed")
+        }
+        e => io::Error::other(e.to_string()),
+    }
+}
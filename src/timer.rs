@@ -1,9 +1,10 @@
-extern crate crossbeam_channel;
+#[cfg(target_os = "linux")]
+extern crate libc;
 
 use mithril_config::DonationConfig;
-use worker::worker_pool::WorkerConfig;
+use worker::worker_pool::{WorkerConfig, WorkerPool};
 
-use self::crossbeam_channel::{unbounded, Receiver};
+use channel::{unbounded, Receiver, Sender};
 use std;
 use std::thread;
 use std::time::Duration;
@@ -14,6 +15,41 @@ const DONATION_THRESHOLD: f64 = 1.0 / 10.0;
 pub enum TickAction {
     ArmChange,
     DonationHashing,
+    /// never sent by the clock, injected through the sender of `setup_with_sender`
+    /// to change the worker config of the running pool from outside the main loop
+    /// (e.g. by `reconfigure_on_sighup`)
+    Reconfigure {
+        new_config: WorkerConfig,
+    },
+}
+
+/// What the event loop does after `handle_tick`
+#[derive(Debug, PartialEq)]
+pub enum TickOutcome {
+    /// keep hashing with the running pool
+    Continue,
+    /// end the pool session, the next one tries a new bandit arm
+    DrawNewBanditArm,
+    /// switch the workers to the donation pool (or back to the regular pool)
+    SwitchPool,
+}
+
+/// Applies `tick` to the running `pool` (a `Reconfigure` resizes it right away) and
+/// tells the event loop how to continue
+pub fn handle_tick(tick: TickAction, pool: &mut WorkerPool) -> TickOutcome {
+    match tick {
+        TickAction::ArmChange => {
+            info!("bandit clock signal received - time for new arm");
+            TickOutcome::DrawNewBanditArm
+        }
+        TickAction::DonationHashing => TickOutcome::SwitchPool,
+        TickAction::Reconfigure { new_config } => {
+            if let Err(err) = pool.resize(new_config.num_threads) {
+                error!("reconfiguring workers failed: {}", err);
+            }
+            TickOutcome::Continue
+        }
+    }
 }
 
 pub fn interval_mod_setup(
@@ -46,7 +82,17 @@ pub fn interval_mod_setup(
 /// clock for bandit arm change and donation
 pub fn setup(worker_conf: &WorkerConfig, donation_conf: &DonationConfig) -> Receiver<TickAction> {
     let (clock_sndr, clock_rcvr) = unbounded();
+    setup_with_sender(worker_conf, donation_conf, clock_sndr);
+    clock_rcvr
+}
 
+/// Same as `setup` but the clock signals are sent to `clock_sndr`, other clones
+/// of the sender can inject ticks (e.g. in tests).
+pub fn setup_with_sender(
+    worker_conf: &WorkerConfig,
+    donation_conf: &DonationConfig,
+    clock_sndr: Sender<TickAction>,
+) {
     let (reg_interval, donation_mod) = interval_mod_setup(worker_conf, donation_conf);
     let mut interval = reg_interval;
 
@@ -81,6 +127,46 @@ pub fn setup(worker_conf: &WorkerConfig, donation_conf: &DonationConfig) -> Rece
             }
         })
         .expect("clock signal thread handle");
+}
 
-    clock_rcvr
+/// Sends a `Reconfigure` with the worker config returned by `reload` every time the
+/// process receives a SIGHUP, `reload` returns None to skip it. SIGHUP is blocked for
+/// the calling thread and the threads it starts afterwards, so this has to be called
+/// before any other thread is started.
+#[cfg(target_os = "linux")]
+pub fn reconfigure_on_sighup<F>(clock_sndr: Sender<TickAction>, reload: F) -> std::io::Result<()>
+where
+    F: Fn() -> Option<WorkerConfig> + Send + 'static,
+{
+    let mut signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGHUP);
+        let result = libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut());
+        if result != 0 {
+            return Err(std::io::Error::from_raw_os_error(result));
+        }
+    }
+    thread::Builder::new()
+        .name("reload signal thread".to_string())
+        .spawn(move || loop {
+            let mut signal = 0;
+            let result = unsafe { libc::sigwait(&signals, &mut signal) };
+            if result != 0 {
+                error!(
+                    "waiting for SIGHUP failed {:?}",
+                    std::io::Error::from_raw_os_error(result)
+                );
+                return;
+            }
+            info!("SIGHUP received, reloading the worker config");
+            if let Some(new_config) = reload() {
+                //the main loop is gone
+                let reconfigure = TickAction::Reconfigure { new_config };
+                if clock_sndr.send(reconfigure).is_err() {
+                    return;
+                }
+            }
+        })?;
+    Ok(())
 }
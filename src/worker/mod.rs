@@ -1 +1,3 @@
 pub mod worker_pool;
+pub mod priority;
+pub mod thread_info;
@@ -0,0 +1,69 @@
+#[cfg(target_os = "linux")]
+extern crate libc;
+#[cfg(windows)]
+extern crate thread_priority;
+
+use std::io;
+
+/// OS scheduling priority of the mining threads
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ThreadPriority {
+    BelowNormal,
+    #[default]
+    Normal,
+    AboveNormal,
+}
+
+/// Changes the priority of the calling thread. Has to be called from within
+/// the thread that should be changed.
+pub fn set_current_thread_priority(priority: ThreadPriority) {
+    let result = apply_priority(priority);
+    if result.is_err() {
+        error!("setting thread priority {:?} failed {:?}", priority, result);
+    } else {
+        info!("thread priority set to {:?}", priority);
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn apply_priority(priority: ThreadPriority) -> io::Result<()> {
+    //nice values, on linux PRIO_PROCESS with a thread id only affects that thread
+    let nice = match priority {
+        ThreadPriority::BelowNormal => 5,
+        ThreadPriority::Normal => 0,
+        ThreadPriority::AboveNormal => -5,
+    };
+    unsafe {
+        let tid = libc::syscall(libc::SYS_gettid) as libc::id_t;
+        if libc::setpriority(libc::PRIO_PROCESS, tid, nice) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn apply_priority(priority: ThreadPriority) -> io::Result<()> {
+    use self::thread_priority::windows::WinAPIThreadPriority;
+
+    let win_priority = match priority {
+        ThreadPriority::BelowNormal => WinAPIThreadPriority::BelowNormal,
+        ThreadPriority::Normal => WinAPIThreadPriority::Normal,
+        ThreadPriority::AboveNormal => WinAPIThreadPriority::AboveNormal,
+    };
+    thread_priority::set_current_thread_priority(thread_priority::ThreadPriority::Os(
+        win_priority.into(),
+    ))
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("{:?}", e)))
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn apply_priority(priority: ThreadPriority) -> io::Result<()> {
+    if priority == ThreadPriority::Normal {
+        return Ok(());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "thread priorities are not supported on this platform",
+    ))
+}
@@ -0,0 +1,73 @@
+//! CPU affinity and stack size of the calling thread, for logging the worker setup
+
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+#[cfg(target_os = "linux")]
+use std::mem;
+
+/// CPUs the calling thread may run on (e.g. `0-3,8`), None if unknown
+#[cfg(target_os = "linux")]
+pub fn current_thread_cpus() -> Option<String> {
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    let size = mem::size_of::<libc::cpu_set_t>();
+    //pid 0 is the calling thread
+    if unsafe { libc::sched_getaffinity(0, size, &mut set) } != 0 {
+        return None;
+    }
+    let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+        .collect();
+    Some(cpu_list(&cpus))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_thread_cpus() -> Option<String> {
+    None
+}
+
+/// Stack size of the calling thread in bytes as set up by the thread library, None if unknown
+#[cfg(target_os = "linux")]
+pub fn current_thread_stack_size() -> Option<usize> {
+    unsafe {
+        let mut attr: libc::pthread_attr_t = mem::zeroed();
+        if libc::pthread_getattr_np(libc::pthread_self(), &mut attr) != 0 {
+            return None;
+        }
+        let mut size = 0;
+        let result = libc::pthread_attr_getstacksize(&attr, &mut size);
+        libc::pthread_attr_destroy(&mut attr);
+        if result == 0 {
+            Some(size)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn current_thread_stack_size() -> Option<usize> {
+    None
+}
+
+/// Sorted CPU numbers as ranges, `[0, 1, 2, 3, 8]` is `0-3,8`
+pub fn cpu_list(cpus: &[usize]) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &cpu in cpus {
+        match ranges.last_mut() {
+            Some(last) if last.1 + 1 == cpu => last.1 = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
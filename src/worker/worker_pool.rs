@@ -1,28 +1,277 @@
-extern crate crossbeam_channel;
-
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use self::crossbeam_channel::{unbounded, Receiver, Sender};
+use super::super::channel::{unbounded, Receiver, Sender};
+use super::priority::{set_current_thread_priority, ThreadPriority};
+use super::thread_info::{current_thread_cpus, current_thread_stack_size};
+use super::super::bandit_tools::BanditStrategy;
 use super::super::byte_string;
-use super::super::randomx::memory::{VmMemory, VmMemoryAllocator};
-use super::super::randomx::vm::new_vm;
+use super::super::metric;
+#[cfg(all(target_os = "linux", feature = "numa"))]
+use super::super::randomx::memory::worker_numa_node;
+use super::super::randomx::memory::{CacheMode, CacheStats, VmMemory, VmMemoryAllocator};
+#[cfg(all(target_os = "linux", feature = "numa"))]
+use super::super::randomx::numa;
+use super::super::randomx::vm::{try_new_vm, AllocationError, Vm};
 use super::super::stratum;
 use super::super::stratum::stratum_data;
 
+pub type ShareSender = Sender<stratum::StratumCmd>;
+
+/// Highest nonce the workers hash, the nonce range is shared by all threads
+const MAX_NONCE: u32 = 65535;
+
+/// 2 MiB scratchpad allocated by every worker VM
+const SCRATCHPAD_BYTES: u64 = 2 * 1024 * 1024;
+/// full dataset shared by all workers (base + extra size)
+const DATASET_BYTES: u64 = 2147483648 + 33554368;
+/// RAM kept free for the OS and other processes
+const OS_OVERHEAD_BYTES: u64 = 512 * 1024 * 1024;
+/// how long dropping a pool waits for each worker thread to stop
+const DROP_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+/// offset of the 4 nonce bytes in a job blob
+const NONCE_OFFSET_BYTES: usize = 39;
+
+/// seed (and job id) of the fixed job of a `start_headless` pool
+const HEADLESS_SEED: &str = "headless";
+/// about one in 2^32 hashes is a share, sharing is not what a headless pool is for
+const HEADLESS_TARGET: &str = "01000000";
+const HEADLESS_METRIC_RESOLUTION: u64 = 1000;
+
+/// default of `WorkerConfig::share_timeout_secs`
+pub const DEFAULT_SHARE_TIMEOUT_SECS: u64 = 30 * 60;
+
+#[derive(Debug)]
+pub enum PoolError {
+    ZeroThreads,
+    ThreadSpawnFailed(io::Error),
+    /// more threads than nonces in the nonce range
+    InvalidNonceRange,
+    /// the VM scratchpad of a worker could not be allocated
+    VmAllocationFailed(AllocationError),
+    /// the blob has no room for the nonce
+    BlobTooShort(usize),
+    /// `resize` of a pool started by `start_heterogeneous`
+    NotResizable,
+}
+
+impl fmt::Display for PoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolError::ZeroThreads => write!(f, "worker pool needs at least one thread"),
+            PoolError::ThreadSpawnFailed(e) => write!(f, "spawning worker thread failed: {}", e),
+            PoolError::InvalidNonceRange => write!(
+                f,
+                "more worker threads than nonces (max {})",
+                u64::from(MAX_NONCE) + 1
+            ),
+            PoolError::VmAllocationFailed(e) => write!(f, "{}", e),
+            PoolError::BlobTooShort(len) => write!(
+                f,
+                "blob of {} bytes is too short, the nonce ends at byte {}",
+                len,
+                NONCE_OFFSET_BYTES + 4
+            ),
+            PoolError::NotResizable => write!(
+                f,
+                "a worker pool started with a config per thread can not be resized"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PoolError {}
+
 pub struct WorkerPool {
     thread_chan: Vec<Sender<WorkerCmd>>,
     thread_hnd: Vec<thread::JoinHandle<()>>,
+    /// where the workers submit shares, for workers started by `resize`
+    share_sndr: ShareSender,
     pub vm_memory_allocator: VmMemoryAllocator,
+    /// If set, a `ShareFoundEvent` is sent for every share found by the workers
+    pub share_found_sndr: Option<Sender<ShareFoundEvent>>,
+    job_start_time: Instant,
+    job_hashes_at_start: u64,
+    /// Hashes calculated by all workers since the pool was started
+    hash_count: Arc<AtomicU64>,
+    /// epoch millis of the last share submitted by a worker (pool start before the first)
+    last_share_at: Arc<AtomicU64>,
+    priority: ThreadPriority,
+    metric_resolution: u64,
+    metric_sndr: metric::MetricSender,
+    current_job: Option<CurrentJob>,
+    pause: PauseControl,
+    /// worker panics caught (and restarted) since the pool was started
+    panic_count: Arc<AtomicU64>,
+    /// updated by the workers after every hash, by worker index
+    thread_stats: Vec<Arc<RwLock<ThreadStat>>>,
+    /// incremented by every `job_change`, shared with forked pools
+    job_sequence: Arc<AtomicU64>,
+    /// by worker index, empty unless the pool was started by `start_heterogeneous`
+    thread_configs: Vec<ThreadConfig>,
+    /// see `set_warm_swap`
+    warm_swap: bool,
+    /// builds the datasets of `warm_swap`, see `set_shadow_memory_builder`
+    shadow_memory_builder: MemoryBuilder,
+    /// the dataset of the next seed while it is built
+    shadow_memory: Option<ShadowMemory>,
+}
+
+/// Builds the memory for a seed, see `WorkerPool::set_shadow_memory_builder`
+pub type MemoryBuilder = Arc<dyn Fn(String) -> VmMemoryAllocator + Send + Sync>;
+
+/// The default `MemoryBuilder`, a full dataset like `VmMemoryAllocator::reallocate`
+pub fn build_full_memory(seed: String) -> VmMemoryAllocator {
+    let mut allocator = VmMemoryAllocator::initial();
+    allocator.reallocate(seed);
+    allocator
+}
+
+/// Dataset for a new seed built by a background thread while the workers continue on
+/// the current job. Only one dataset is built at a time.
+struct ShadowMemory {
+    seed: String,
+    build: thread::JoinHandle<VmMemoryAllocator>,
+    /// the newest job waiting for a new dataset, sent to the workers once it is ready.
+    /// A job for another seed than `seed` waits for the next build, None if the workers
+    /// went back to the seed of the current dataset.
+    pending_job: Option<CurrentJob>,
+}
+
+/// Memory, share sender and nonces of one worker of a `start_heterogeneous` pool
+#[derive(Clone)]
+pub struct ThreadConfig {
+    /// kept for every job, the seed hash of the jobs is ignored
+    pub vm_memory: Arc<VmMemory>,
+    pub share_sndr: ShareSender,
+    /// the worker hashes `nonce_start`, `nonce_start + nonce_stride`, ... up to `MAX_NONCE`
+    pub nonce_start: u32,
+    pub nonce_stride: u32,
+}
+
+/// Nonces a worker hashes in each job
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum NonceSequence {
+    /// the next unhashed nonce of the counter shared by all workers (`JobData::nonce`)
+    Shared,
+    Stride { start: u32, stride: u32 },
+}
+
+impl NonceSequence {
+    fn first(&self, job: &JobData) -> u32 {
+        match *self {
+            NonceSequence::Shared => job.nonce.fetch_add(1, Ordering::Relaxed),
+            NonceSequence::Stride { start, .. } => start,
+        }
+    }
+
+    /// greater than `MAX_NONCE` if the worker has no nonces left
+    fn next(&self, job: &JobData, nonce: u32) -> u32 {
+        match *self {
+            NonceSequence::Shared => job.nonce.fetch_add(1, Ordering::Relaxed),
+            NonceSequence::Stride { stride, .. } => nonce.saturating_add(stride),
+        }
+    }
+
+    /// first and last nonce the worker can hash, for a stride worker only every
+    /// `stride`th nonce in between
+    fn range(&self) -> (u32, u32) {
+        match *self {
+            NonceSequence::Shared => (0, MAX_NONCE),
+            NonceSequence::Stride { start, stride } => {
+                (start, start + (MAX_NONCE - start) / stride * stride)
+            }
+        }
+    }
+
+    /// `shared` if the nonces come from the counter shared by all workers
+    fn step(&self) -> String {
+        match *self {
+            NonceSequence::Shared => "shared".to_string(),
+            NonceSequence::Stride { stride, .. } => stride.to_string(),
+        }
+    }
+}
+
+/// State of one worker thread, see `WorkerPool::thread_stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadStat {
+    pub thread_id: usize,
+    /// `JobData::sequence` of the current job, 0 before the first job
+    pub job_sequence: u64,
+    /// hashes of the current job
+    pub hashes_since_last_job: u64,
+    pub last_hash_at: Option<Instant>,
+    /// first and last nonce the worker hashed in the current job, the nonces in
+    /// between are shared with the other workers (see `work_job`)
+    pub nonce_range: (u32, u32),
+    /// dataset reads of the worker's VM, written with the other values
+    pub dataset_cache: CacheStats,
+    /// panics caught, the worker was restarted after each one
+    pub panics: u64,
+}
+
+impl ThreadStat {
+    fn new(thread_id: usize) -> ThreadStat {
+        ThreadStat {
+            thread_id,
+            job_sequence: 0,
+            hashes_since_last_job: 0,
+            last_hash_at: None,
+            nonce_range: (0, 0),
+            dataset_cache: CacheStats::default(),
+            panics: 0,
+        }
+    }
 }
 
+/// Shared between the pool and its workers, see `WorkerPool::pause`
+#[derive(Default)]
+struct PauseState {
+    paused: bool,
+    /// number of workers currently blocked on the condvar
+    waiting: usize,
+}
+
+type PauseControl = Arc<(Mutex<PauseState>, Condvar)>;
+
+/// The job the workers currently work on, kept for handing it to a forked pool
 #[derive(Clone)]
+struct CurrentJob {
+    miner_id: String,
+    seed_hash: String,
+    blob: String,
+    job_id: String,
+    target: String,
+    nonce: Arc<AtomicU32>,
+    sequence: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
 pub struct WorkerConfig {
     pub num_threads: u64,
     pub auto_tune: bool,
     pub auto_tune_interval_minutes: u64,
     pub auto_tune_log: String,
+    #[serde(default)]
+    pub bandit_strategy: BanditStrategy,
+    /// a warning is logged if no share was submitted for this long
+    #[serde(default = "default_share_timeout_secs")]
+    pub share_timeout_secs: u64,
+    /// see `WorkerPool::set_warm_swap`
+    #[serde(default)]
+    pub warm_swap: bool,
+}
+
+fn default_share_timeout_secs() -> u64 {
+    DEFAULT_SHARE_TIMEOUT_SECS
 }
 
 pub struct JobData {
@@ -33,16 +282,55 @@ pub struct JobData {
     pub job_id: String,
     pub target: String,
     pub nonce: Arc<AtomicU32>,
+    /// `job_change` count of the pool when the job was sent, a worker discards
+    /// jobs that are not newer than the one it has
+    pub sequence: u64,
+    pub start_time: Instant,
+    pub hashes_at_start: u64,
+    pub hash_count: Arc<AtomicU64>,
+    /// set to the epoch millis when a share is submitted
+    pub last_share_at: Arc<AtomicU64>,
+    pub share_found_sndr: Option<Sender<ShareFoundEvent>>,
+}
+
+/// Statistics for the job a share was found in, measured from the `job_change`
+/// until the share was found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareFoundEvent {
+    pub job_id: String,
+    pub hashes_attempted: u64,
+    pub solution_time_ms: u64,
+    pub hashrate_khs: f64,
+}
+
+impl ShareFoundEvent {
+    pub fn new(job_id: &str, hashes_attempted: u64, solution_time: Duration) -> ShareFoundEvent {
+        let secs = solution_time.as_secs_f64();
+        let hashrate_khs = if secs > 0.0 {
+            (hashes_attempted as f64 / secs) / 1000.0
+        } else {
+            0.0
+        };
+        ShareFoundEvent {
+            job_id: job_id.to_string(),
+            hashes_attempted,
+            solution_time_ms: solution_time.as_millis() as u64,
+            hashrate_khs,
+        }
+    }
 }
 
 pub enum WorkerCmd {
     NewJob { job_data: JobData },
+    SetPriority { priority: ThreadPriority },
+    SwapShareSender { share_sndr: ShareSender },
+    Pause,
     Stop,
 }
 
 enum WorkerExit {
     NonceSpaceExhausted,
-    NewJob { job_data: JobData },
+    NewJob { job_data: Box<JobData> },
     Stopped,
 }
 
@@ -50,38 +338,201 @@ pub fn start(
     num_threads: u64,
     share_sndr: &Sender<stratum::StratumCmd>,
     metric_resolution: u64,
-    metric_sndr: &Sender<u64>,
+    metric_sndr: &metric::MetricSender,
     vm_memory_allocator: VmMemoryAllocator,
-) -> WorkerPool {
-    let mut thread_chan: Vec<Sender<WorkerCmd>> = Vec::with_capacity(num_threads as usize);
-    let mut thread_hnd: Vec<thread::JoinHandle<()>> = Vec::with_capacity(num_threads as usize);
-    for i in 0..num_threads {
-        let (sndr, rcvr) = unbounded();
-        let share_sndr_thread = share_sndr.clone();
-        let metric_sndr_thread = metric_sndr.clone();
-
-        let hnd = thread::Builder::new()
-            .name(format!("worker thread {}", i))
-            .spawn(move || {
-                work(
-                    &rcvr,
-                    &share_sndr_thread,
-                    metric_resolution,
-                    &metric_sndr_thread,
-                )
-            })
-            .expect("worker thread handle");
-        thread_chan.push(sndr);
-        thread_hnd.push(hnd);
+) -> Result<WorkerPool, PoolError> {
+    if num_threads == 0 {
+        return Err(PoolError::ZeroThreads);
+    }
+    if num_threads > u64::from(MAX_NONCE) + 1 {
+        return Err(PoolError::InvalidNonceRange);
     }
-    WorkerPool {
-        thread_chan,
-        thread_hnd,
+
+    let num_threads = threads_fitting_in_ram(num_threads, 0, &vm_memory_allocator);
+
+    let mut pool = WorkerPool {
+        thread_chan: Vec::with_capacity(num_threads as usize),
+        thread_hnd: Vec::with_capacity(num_threads as usize),
+        share_sndr: share_sndr.clone(),
         vm_memory_allocator,
+        share_found_sndr: None,
+        job_start_time: Instant::now(),
+        job_hashes_at_start: 0,
+        hash_count: Arc::new(AtomicU64::new(0)),
+        last_share_at: Arc::new(AtomicU64::new(stratum::epoch_millis())),
+        priority: ThreadPriority::default(),
+        metric_resolution,
+        metric_sndr: metric_sndr.clone(),
+        current_job: None,
+        pause: Arc::new((Mutex::new(PauseState::default()), Condvar::new())),
+        panic_count: Arc::new(AtomicU64::new(0)),
+        thread_stats: Vec::with_capacity(num_threads as usize),
+        job_sequence: Arc::new(AtomicU64::new(0)),
+        thread_configs: Vec::new(),
+        warm_swap: false,
+        shadow_memory_builder: Arc::new(build_full_memory),
+        shadow_memory: None,
+    };
+    for _ in 0..num_threads {
+        if let Err(e) = pool.spawn_worker() {
+            pool.stop();
+            pool.join();
+            return Err(e);
+        }
     }
+    Ok(pool)
+}
+
+/// Starts a worker per config, each with its own memory (e.g. for hashing with different
+/// keys at the same time), share sender and nonces. The memory is not changed by
+/// `job_change`, `swap_share_sender` switches the share sender of all workers.
+pub fn start_heterogeneous(
+    configs: Vec<ThreadConfig>,
+    metric_resolution: u64,
+    metric_sndr: &metric::MetricSender,
+) -> Result<WorkerPool, PoolError> {
+    let first = match configs.first() {
+        Some(first) => first,
+        None => return Err(PoolError::ZeroThreads),
+    };
+    if configs
+        .iter()
+        .any(|conf| conf.nonce_stride == 0 || conf.nonce_start > MAX_NONCE)
+    {
+        return Err(PoolError::InvalidNonceRange);
+    }
+
+    let mut pool = WorkerPool {
+        thread_chan: Vec::with_capacity(configs.len()),
+        thread_hnd: Vec::with_capacity(configs.len()),
+        share_sndr: first.share_sndr.clone(),
+        vm_memory_allocator: VmMemoryAllocator::initial(),
+        share_found_sndr: None,
+        job_start_time: Instant::now(),
+        job_hashes_at_start: 0,
+        hash_count: Arc::new(AtomicU64::new(0)),
+        last_share_at: Arc::new(AtomicU64::new(stratum::epoch_millis())),
+        priority: ThreadPriority::default(),
+        metric_resolution,
+        metric_sndr: metric_sndr.clone(),
+        current_job: None,
+        pause: Arc::new((Mutex::new(PauseState::default()), Condvar::new())),
+        panic_count: Arc::new(AtomicU64::new(0)),
+        thread_stats: Vec::with_capacity(configs.len()),
+        job_sequence: Arc::new(AtomicU64::new(0)),
+        thread_configs: configs,
+        warm_swap: false,
+        shadow_memory_builder: Arc::new(build_full_memory),
+        shadow_memory: None,
+    };
+    for i in 0..pool.thread_configs.len() {
+        let conf = pool.thread_configs[i].clone();
+        let nonces = NonceSequence::Stride {
+            start: conf.nonce_start,
+            stride: conf.nonce_stride,
+        };
+        if let Err(e) = pool.spawn_worker_with(conf.vm_memory, conf.share_sndr, nonces) {
+            pool.stop();
+            pool.join();
+            return Err(e);
+        }
+    }
+    Ok(pool)
+}
+
+/// `num_threads` or less, so that the scratchpads of all workers fit in the available RAM.
+/// The scratchpads of `running_threads` are already allocated.
+fn threads_fitting_in_ram(
+    num_threads: u64,
+    running_threads: u64,
+    vm_memory_allocator: &VmMemoryAllocator,
+) -> u64 {
+    let available_ram = match available_ram_bytes() {
+        Some(available_ram) => available_ram + running_threads * SCRATCHPAD_BYTES,
+        None => return num_threads,
+    };
+    let max_threads = WorkerPool::max_threads_for(available_ram, vm_memory_allocator).max(1) as u64;
+    if num_threads > max_threads {
+        warn!(
+            "{} threads do not fit in {} MiB available RAM, using {} threads",
+            num_threads,
+            available_ram / (1024 * 1024),
+            max_threads
+        );
+        return max_threads;
+    }
+    num_threads
+}
+
+/// Number of scratchpads that fit in `available_ram_bytes` next to `reserved_bytes`
+/// and some RAM for the OS
+fn max_threads_next_to(available_ram_bytes: u64, reserved_bytes: u64) -> usize {
+    let free = available_ram_bytes.saturating_sub(reserved_bytes + OS_OVERHEAD_BYTES);
+    (free / SCRATCHPAD_BYTES) as usize
+}
+
+/// Starts a pool that is not connected to a stratum pool, for benchmarks and tests.
+/// The workers hash `null_blob` (with their nonces) on `vm_memory` until the pool is
+/// stopped, found shares are discarded.
+pub fn start_headless(
+    num_threads: u64,
+    vm_memory: Arc<VmMemory>,
+    metric_sndr: &metric::MetricSender,
+    null_blob: &[u8],
+) -> Result<WorkerPool, PoolError> {
+    if null_blob.len() < NONCE_OFFSET_BYTES + 4 {
+        return Err(PoolError::BlobTooShort(null_blob.len()));
+    }
+    let (share_sndr, share_rcvr) = unbounded();
+    thread::Builder::new()
+        .name("headless share sink".to_string())
+        .spawn(move || for _ in share_rcvr.iter() {})
+        .map_err(PoolError::ThreadSpawnFailed)?;
+
+    let allocator = VmMemoryAllocator::preallocated(HEADLESS_SEED.to_string(), vm_memory);
+    let mut pool = start(
+        num_threads,
+        &share_sndr,
+        HEADLESS_METRIC_RESOLUTION,
+        metric_sndr,
+        allocator,
+    )?;
+    pool.job_change(
+        HEADLESS_SEED,
+        HEADLESS_SEED,
+        &byte_string::u8_array_to_string(null_blob),
+        HEADLESS_SEED,
+        HEADLESS_TARGET,
+    );
+    Ok(pool)
 }
 
 impl WorkerPool {
+    /// Maximum number of worker threads whose scratchpads fit in `available_ram_bytes`
+    /// next to the dataset and some RAM for the OS, 0 if not even the dataset fits.
+    pub fn max_safe_threads(available_ram_bytes: u64) -> usize {
+        max_threads_next_to(available_ram_bytes, DATASET_BYTES)
+    }
+
+    /// Like `max_safe_threads`, but the dataset is only reserved if `vm_memory_allocator`
+    /// has yet to allocate it. In light mode (so also before the first job, the allocator
+    /// starts without memory) there is no dataset, an allocated one is not part of the
+    /// available RAM anymore.
+    pub fn max_threads_for(
+        available_ram_bytes: u64,
+        vm_memory_allocator: &VmMemoryAllocator,
+    ) -> usize {
+        let vm_memory = &vm_memory_allocator.vm_memory;
+        let dataset_bytes = if vm_memory.mode == CacheMode::Light
+            || !vm_memory_allocator.vm_memory_seed.is_empty()
+        {
+            0
+        } else {
+            DATASET_BYTES
+        };
+        max_threads_next_to(available_ram_bytes, dataset_bytes)
+    }
+
     pub fn job_change(
         &mut self,
         miner_id: &str,
@@ -91,23 +542,448 @@ impl WorkerPool {
         target: &str,
     ) {
         info!("job change, blob {}", blob);
-        self.vm_memory_allocator.reallocate(seed_hash.to_string());
-        let nonce = Arc::new(AtomicU32::new(0));
+        self.poll_memory_swap();
+        let job = CurrentJob {
+            miner_id: miner_id.to_string(),
+            seed_hash: seed_hash.to_string(),
+            blob: blob.to_string(),
+            job_id: job_id.to_string(),
+            target: target.to_string(),
+            nonce: Arc::new(AtomicU32::new(0)),
+            sequence: self.job_sequence.fetch_add(1, Ordering::SeqCst) + 1,
+        };
+        if self.thread_configs.is_empty() {
+            let new_seed = seed_hash != self.vm_memory_allocator.vm_memory_seed;
+            if new_seed && self.warm_swap && self.current_job.is_some() {
+                self.build_shadow_memory(job);
+                return;
+            }
+            if let Some(shadow) = &mut self.shadow_memory {
+                //back to the current seed, the job waiting for the shadow memory is
+                //outdated. The build can't be cancelled, its dataset is dropped when done.
+                shadow.pending_job = None;
+            }
+            self.vm_memory_allocator.reallocate(seed_hash.to_string());
+        }
+        self.send_job(job);
+    }
+
+    /// If enabled, `job_change` builds the dataset for a new seed on a background
+    /// thread while the workers keep hashing the current job, instead of stopping all
+    /// workers until the dataset is built (10-30s). The job of the new seed is sent once
+    /// the dataset is ready (see `poll_memory_swap`). Needs the RAM of a second dataset
+    /// during the transition. Has no effect on `start_heterogeneous` pools.
+    pub fn set_warm_swap(&mut self, enabled: bool) {
+        self.warm_swap = enabled;
+    }
+
+    /// Replaces `build_full_memory` for the datasets of `set_warm_swap`, e.g. with light
+    /// memory in tests
+    pub fn set_shadow_memory_builder(&mut self, builder: MemoryBuilder) {
+        self.shadow_memory_builder = builder;
+    }
+
+    /// Seed of the dataset that is built in the background, if any
+    pub fn shadow_memory_seed(&self) -> Option<&str> {
+        self.shadow_memory
+            .as_ref()
+            .map(|shadow| shadow.seed.as_str())
+    }
+
+    /// The newest job passed to `job_change` (it may still wait for its dataset),
+    /// None before the first job. A restarted pool can continue with it.
+    pub fn current_job(&self) -> Option<stratum::StratumJob> {
+        let job = match &self.shadow_memory {
+            Some(ShadowMemory {
+                pending_job: Some(job),
+                ..
+            }) => Some(job),
+            _ => self.current_job.as_ref(),
+        };
+        job.map(|job| stratum::StratumJob {
+            miner_id: job.miner_id.clone(),
+            seed_hash: job.seed_hash.clone(),
+            blob: job.blob.clone(),
+            job_id: job.job_id.clone(),
+            target: job.target.clone(),
+        })
+    }
 
-        for (_, tx) in self.thread_chan.iter().enumerate() {
+    fn build_shadow_memory(&mut self, job: CurrentJob) {
+        if let Some(shadow) = &mut self.shadow_memory {
+            //one build at a time, a job for another seed waits until it is done
+            shadow.pending_job = Some(job);
+            return;
+        }
+        self.start_shadow_memory_build(job);
+    }
+
+    fn start_shadow_memory_build(&mut self, job: CurrentJob) {
+        let seed = job.seed_hash.clone();
+        let build_seed = seed.clone();
+        let builder = self.shadow_memory_builder.clone();
+        let spawn_result = thread::Builder::new()
+            .name("shadow memory".to_string())
+            .spawn(move || builder(build_seed));
+        match spawn_result {
+            Ok(build) => {
+                info!("building dataset for seed_hash {} in the background", seed);
+                self.shadow_memory = Some(ShadowMemory {
+                    seed,
+                    build,
+                    pending_job: Some(job),
+                });
+            }
+            Err(err) => {
+                error!("starting the shadow memory build failed {:?}", err);
+                self.vm_memory_allocator.reallocate(seed);
+                self.send_job(job);
+            }
+        }
+    }
+
+    /// Switches to the dataset built in the background and sends the job that waited
+    /// for it. The workers drop the old dataset when they start the job. A job that
+    /// waits for another seed starts the next build. Called by every `job_change`, call
+    /// it periodically to switch as soon as the dataset is ready.
+    /// Returns true if the workers were switched to a new dataset.
+    pub fn poll_memory_swap(&mut self) -> bool {
+        match &self.shadow_memory {
+            Some(shadow) if shadow.build.is_finished() => {}
+            _ => return false,
+        }
+        let shadow = self.shadow_memory.take().expect("finished shadow memory");
+        let built = shadow.build.join();
+        if built.is_err() {
+            error!(
+                "building the shadow memory for seed_hash {} panicked",
+                shadow.seed
+            );
+        }
+        match shadow.pending_job {
+            None => {
+                info!(
+                    "dropping the dataset for seed_hash {}, it is outdated",
+                    shadow.seed
+                );
+                false
+            }
+            Some(job) if job.seed_hash != shadow.seed => {
+                //the outdated dataset is dropped before the next one is built
+                drop(built);
+                self.start_shadow_memory_build(job);
+                false
+            }
+            Some(job) => {
+                match built {
+                    Ok(allocator) => self.vm_memory_allocator = allocator,
+                    Err(_) => {
+                        self.vm_memory_allocator.reallocate(shadow.seed);
+                    }
+                }
+                self.send_job(job);
+                true
+            }
+        }
+    }
+
+    /// Waits for the dataset built in the background and switches to it (or drops it),
+    /// e.g. before the pool is stopped and its allocator reused
+    pub fn finish_memory_swap(&mut self) {
+        while let Some(shadow) = &self.shadow_memory {
+            while !shadow.build.is_finished() {
+                thread::sleep(Duration::from_millis(100));
+            }
+            self.poll_memory_swap();
+        }
+    }
+
+    fn send_job(&mut self, job: CurrentJob) {
+        self.job_start_time = Instant::now();
+        self.job_hashes_at_start = self.hash_count.load(Ordering::Relaxed);
+
+        for (i, tx) in self.thread_chan.iter().enumerate() {
             tx.send(WorkerCmd::NewJob {
-                job_data: JobData {
-                    miner_id: miner_id.to_string(),
-                    seed_hash: seed_hash.to_string(),
-                    memory: self.vm_memory_allocator.vm_memory.clone(),
-                    blob: blob.to_string(),
-                    job_id: job_id.to_string(),
-                    target: target.to_string(),
-                    nonce: nonce.clone(),
-                },
+                job_data: self.job_data(&job, i),
             })
             .expect("sending new job command");
         }
+        self.current_job = Some(job);
+    }
+
+    fn job_data(&self, job: &CurrentJob, worker: usize) -> JobData {
+        JobData {
+            miner_id: job.miner_id.clone(),
+            seed_hash: job.seed_hash.clone(),
+            memory: match self.thread_configs.get(worker) {
+                Some(conf) => conf.vm_memory.clone(),
+                None => self.vm_memory_allocator.worker_memory(worker),
+            },
+            blob: job.blob.clone(),
+            job_id: job.job_id.clone(),
+            target: job.target.clone(),
+            nonce: job.nonce.clone(),
+            sequence: job.sequence,
+            start_time: self.job_start_time,
+            hashes_at_start: self.job_hashes_at_start,
+            hash_count: self.hash_count.clone(),
+            last_share_at: self.last_share_at.clone(),
+            share_found_sndr: self.share_found_sndr.clone(),
+        }
+    }
+
+    /// Starts one more worker with the next worker index
+    fn spawn_worker(&mut self) -> Result<(), PoolError> {
+        let vm_memory = self.vm_memory_allocator.vm_memory.clone();
+        let share_sndr = self.share_sndr.clone();
+        self.spawn_worker_with(vm_memory, share_sndr, NonceSequence::Shared)
+    }
+
+    fn spawn_worker_with(
+        &mut self,
+        vm_memory: Arc<VmMemory>,
+        share_sndr: ShareSender,
+        nonces: NonceSequence,
+    ) -> Result<(), PoolError> {
+        let i = self.thread_chan.len() as u64;
+        //allocated here so running out of memory is an error instead of a panic in the thread
+        let vm = try_new_vm(vm_memory).map_err(PoolError::VmAllocationFailed)?;
+        let (sndr, rcvr) = unbounded();
+        let share_sndr_thread = share_sndr;
+        let metric_resolution = self.metric_resolution;
+        let metric_sndr_thread = self.metric_sndr.clone();
+        let pause_thread = self.pause.clone();
+        let panic_count_thread = self.panic_count.clone();
+        let thread_stat = Arc::new(RwLock::new(ThreadStat::new(i as usize)));
+        let thread_stat_thread = thread_stat.clone();
+
+        let hnd = thread::Builder::new()
+            .name(format!("worker thread {}", i))
+            .spawn(move || {
+                bind_worker_to_numa_node(i);
+                log_worker_setup(i, nonces);
+                work_with_recovery(
+                    i,
+                    &rcvr,
+                    vm,
+                    share_sndr_thread,
+                    metric_resolution,
+                    &metric_sndr_thread,
+                    &pause_thread,
+                    &panic_count_thread,
+                    &thread_stat_thread,
+                    nonces,
+                )
+            })
+            .map_err(PoolError::ThreadSpawnFailed)?;
+        self.thread_chan.push(sndr);
+        self.thread_hnd.push(hnd);
+        self.thread_stats.push(thread_stat);
+        Ok(())
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.thread_chan.len()
+    }
+
+    /// Starts or stops workers until `num_threads` are running. New workers get the
+    /// priority of the pool and continue on the current job (with the shared nonce
+    /// counter), removed workers finish their current batch of hashes and exit.
+    /// Like `start` the thread count is limited by the available RAM.
+    pub fn resize(&mut self, num_threads: u64) -> Result<(), PoolError> {
+        if !self.thread_configs.is_empty() {
+            return Err(PoolError::NotResizable);
+        }
+        if num_threads == 0 {
+            return Err(PoolError::ZeroThreads);
+        }
+        if num_threads > u64::from(MAX_NONCE) + 1 {
+            return Err(PoolError::InvalidNonceRange);
+        }
+        let running_threads = self.thread_count() as u64;
+        let num_threads =
+            threads_fitting_in_ram(num_threads, running_threads, &self.vm_memory_allocator)
+                as usize;
+        info!(
+            "resizing worker pool from {} to {} threads",
+            self.thread_count(),
+            num_threads
+        );
+
+        while self.thread_count() > num_threads {
+            if let Some(tx) = self.thread_chan.pop() {
+                let _ = tx.send(WorkerCmd::Stop);
+            }
+            self.thread_stats.pop();
+            let hnd = self.thread_hnd.pop().expect("worker handle");
+            //a paused worker only sees the stop after `resume` (or `stop`), it is not waited for
+            if !self.is_paused() {
+                let join_result = hnd.join();
+                if join_result.is_err() {
+                    error!("thread join failed {:?}", join_result)
+                }
+            }
+        }
+
+        while self.thread_count() < num_threads {
+            self.spawn_worker()?;
+            let i = self.thread_count() - 1;
+            if self.priority != ThreadPriority::default() {
+                let priority = self.priority;
+                let _ = self.thread_chan[i].send(WorkerCmd::SetPriority { priority });
+            }
+            if let Some(job) = &self.current_job {
+                let job_data = self.job_data(job, i);
+                let _ = self.thread_chan[i].send(WorkerCmd::NewJob { job_data });
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a new pool with the same number of threads, memory and priority that
+    /// immediately continues on the current job. Both pools draw their nonces from
+    /// the same counter, so they never hash the same nonce (for a `start_heterogeneous`
+    /// pool the forked workers hash the same nonces as the workers of this pool).
+    /// The forked pool is independent of this pool, both have to be stopped
+    /// and joined explicitly.
+    pub fn fork(&self, new_share_sndr: &ShareSender) -> Result<WorkerPool, PoolError> {
+        let mut forked = if self.thread_configs.is_empty() {
+            start(
+                self.thread_chan.len() as u64,
+                new_share_sndr,
+                self.metric_resolution,
+                &self.metric_sndr,
+                self.vm_memory_allocator.clone(),
+            )?
+        } else {
+            let configs = self
+                .thread_configs
+                .iter()
+                .map(|conf| ThreadConfig {
+                    share_sndr: new_share_sndr.clone(),
+                    ..conf.clone()
+                })
+                .collect();
+            start_heterogeneous(configs, self.metric_resolution, &self.metric_sndr)?
+        };
+        forked.share_found_sndr = self.share_found_sndr.clone();
+        forked.job_sequence = self.job_sequence.clone();
+        forked.warm_swap = self.warm_swap;
+        forked.shadow_memory_builder = self.shadow_memory_builder.clone();
+        if self.priority != ThreadPriority::default() {
+            forked.set_priority(self.priority);
+        }
+        if let Some(job) = &self.current_job {
+            forked.send_job(job.clone());
+        }
+        Ok(forked)
+    }
+
+    /// Changes the OS priority of all worker threads. The change is applied by
+    /// each thread the next time it checks for commands.
+    pub fn set_priority(&mut self, priority: ThreadPriority) {
+        info!("changing worker priority to {:?}", priority);
+        self.priority = priority;
+
+        for tx in &self.thread_chan {
+            let send_result = tx.send(WorkerCmd::SetPriority { priority });
+            if send_result.is_err() {
+                error!("sending priority change failed {:?}", send_result);
+            }
+        }
+    }
+
+    pub fn priority(&self) -> ThreadPriority {
+        self.priority
+    }
+
+    /// Replaces the channel the workers submit their shares to, without
+    /// stopping them (e.g. for switching to the donation pool). Each worker
+    /// switches when it checks for commands, before any job sent after this
+    /// call, so shares of the new pool's jobs always go to `new_sndr`.
+    pub fn swap_share_sender(&mut self, new_sndr: ShareSender) {
+        info!("swapping share sender of workers");
+        self.share_sndr = new_sndr.clone();
+        for tx in &self.thread_chan {
+            let send_result = tx.send(WorkerCmd::SwapShareSender {
+                share_sndr: new_sndr.clone(),
+            });
+            if send_result.is_err() {
+                error!("sending share sender swap failed {:?}", send_result);
+            }
+        }
+    }
+
+    /// Stops hashing without ending the worker threads, the VMs and the
+    /// memory are kept. Each worker blocks after finishing its current batch
+    /// of hashes until `resume` is called.
+    pub fn pause(&self) {
+        info!("pausing workers");
+        self.set_paused(true);
+
+        for tx in &self.thread_chan {
+            let send_result = tx.send(WorkerCmd::Pause);
+            if send_result.is_err() {
+                error!("sending pause failed {:?}", send_result);
+            }
+        }
+    }
+
+    /// Continues hashing after `pause`
+    pub fn resume(&self) {
+        info!("resuming workers");
+        self.set_paused(false);
+    }
+
+    /// Hashes calculated by all workers since the pool was started
+    pub fn hash_count(&self) -> u64 {
+        self.hash_count.load(Ordering::Relaxed)
+    }
+
+    /// Time since a worker last submitted a share, since the pool was started if none
+    /// was submitted yet.
+    pub fn time_since_last_share(&self) -> Duration {
+        let last_share_at = self.last_share_at.load(Ordering::Relaxed);
+        Duration::from_millis(stratum::epoch_millis().saturating_sub(last_share_at))
+    }
+
+    /// Snapshot of every worker, each read under its own lock (so the workers are
+    /// not stopped all at once)
+    pub fn thread_stats(&self) -> Vec<ThreadStat> {
+        self.thread_stats
+            .iter()
+            .map(|stat| stat.read().expect("thread stat lock").clone())
+            .collect()
+    }
+
+    /// Share of the dataset reads of all workers served from the cache, as of the last
+    /// thread stat writes (see `CacheStats`)
+    pub fn cache_hit_rate(&self) -> f64 {
+        let mut total = CacheStats::default();
+        for stat in self.thread_stats() {
+            total.add(stat.dataset_cache);
+        }
+        total.hit_rate()
+    }
+
+    /// Number of worker panics, a panicked worker is restarted and waits for the next job
+    pub fn panic_count(&self) -> u64 {
+        self.panic_count.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.0.lock().expect("pause lock").paused
+    }
+
+    /// Number of workers that are currently blocked because of `pause`
+    pub fn paused_workers(&self) -> usize {
+        self.pause.0.lock().expect("pause lock").waiting
+    }
+
+    fn set_paused(&self, paused: bool) {
+        let (lock, cvar) = &*self.pause;
+        lock.lock().expect("pause lock").paused = paused;
+        cvar.notify_all();
     }
 
     pub fn stop(&self) {
@@ -116,11 +992,13 @@ impl WorkerPool {
         for tx in &self.thread_chan {
             let _ = tx.send(WorkerCmd::Stop);
         }
+        //paused workers have to wake up to receive the stop command
+        self.set_paused(false);
     }
 
     //Waits for completing of all threads in the pool
-    pub fn join(self) {
-        for hnd in self.thread_hnd {
+    pub fn join(mut self) {
+        for hnd in mem::take(&mut self.thread_hnd) {
             let join_result = hnd.join();
             if join_result.is_err() {
                 error!("thread join failed {:?}, waiting for next", join_result)
@@ -129,44 +1007,168 @@ impl WorkerPool {
     }
 }
 
-fn work(
+impl Drop for WorkerPool {
+    /// Stops the workers of a pool that is dropped without `join`. A worker that does
+    /// not exit within `DROP_JOIN_TIMEOUT` is left running in the background.
+    fn drop(&mut self) {
+        if self.thread_hnd.is_empty() {
+            return;
+        }
+        self.stop();
+        for hnd in self.thread_hnd.drain(..) {
+            let deadline = Instant::now() + DROP_JOIN_TIMEOUT;
+            while !hnd.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(10));
+            }
+            if !hnd.is_finished() {
+                warn!(
+                    "worker thread {:?} did not stop within {:?}",
+                    hnd.thread().name(),
+                    DROP_JOIN_TIMEOUT
+                );
+                continue;
+            }
+            let join_result = hnd.join();
+            if join_result.is_err() {
+                error!("thread join failed {:?}, waiting for next", join_result)
+            }
+        }
+    }
+}
+
+/// Binds worker `index` to the NUMA node whose dataset it gets, see `VmMemoryAllocator::worker_memory`
+#[cfg(all(target_os = "linux", feature = "numa"))]
+fn bind_worker_to_numa_node(index: u64) {
+    if numa::node_count() < 2 {
+        return;
+    }
+    let node = worker_numa_node(index as usize);
+    let result = numa::bind_current_thread(node);
+    if result.is_err() {
+        warn!("binding worker {} to NUMA node {} failed {:?}", index, node, result);
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "numa")))]
+fn bind_worker_to_numa_node(_index: u64) {}
+
+/// Logs the nonces, CPU affinity and stack size of the calling worker thread
+fn log_worker_setup(index: u64, nonces: NonceSequence) {
+    let (first_nonce, last_nonce) = nonces.range();
+    let affinity = current_thread_cpus().unwrap_or_else(|| "unknown".to_string());
+    let stack = match current_thread_stack_size() {
+        Some(size) => format!("{}kB", size / 1024),
+        None => "unknown".to_string(),
+    };
+    info!(
+        "spawned worker thread {}: nonce_range=[{}..{}], nonce_step={}, affinity={}, stack={}",
+        index,
+        first_nonce,
+        last_nonce,
+        nonces.step(),
+        affinity,
+        stack
+    );
+}
+
+/// Runs `work` and runs it again with a fresh VM if it panicked. The job of the
+/// panicked worker is lost, the restarted worker waits for the next job.
+#[allow(clippy::too_many_arguments)]
+fn work_with_recovery(
+    index: u64,
     rcv: &Receiver<WorkerCmd>,
-    share_tx: &Sender<stratum::StratumCmd>,
+    mut vm: Vm,
+    mut share_tx: ShareSender,
     metric_resolution: u64,
-    metric_tx: &Sender<u64>,
+    metric_tx: &metric::MetricSender,
+    pause: &PauseControl,
+    panic_count: &AtomicU64,
+    stat: &RwLock<ThreadStat>,
+    nonces: NonceSequence,
 ) {
-    let first_job = rcv.recv();
-    if first_job.is_err() {
-        error!("job channel was dropped");
-        return;
+    loop {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            work(
+                rcv,
+                vm,
+                &mut share_tx,
+                metric_resolution,
+                metric_tx,
+                pause,
+                stat,
+                nonces,
+            )
+        }));
+        let payload = match result {
+            Ok(()) => return,
+            Err(payload) => payload,
+        };
+        panic_count.fetch_add(1, Ordering::SeqCst);
+        match stat.write() {
+            Ok(mut stat) => stat.panics += 1,
+            Err(poisoned) => poisoned.into_inner().panics += 1,
+        }
+        stat.clear_poison();
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_string());
+        error!("worker thread {} panicked: {}, restarting it", index, msg);
+
+        vm = match try_new_vm(Arc::new(VmMemory::no_memory())) {
+            Ok(vm) => vm,
+            Err(e) => {
+                error!("worker thread {} could not be restarted: {}", index, e);
+                return;
+            }
+        };
     }
-    let mut job = match first_job.unwrap() {
-        WorkerCmd::NewJob { job_data } => job_data,
-        WorkerCmd::Stop => {
+}
+
+#[allow(clippy::too_many_arguments)]
+fn work(
+    rcv: &Receiver<WorkerCmd>,
+    mut vm: Vm,
+    share_tx: &mut ShareSender,
+    metric_resolution: u64,
+    metric_tx: &metric::MetricSender,
+    pause: &PauseControl,
+    stat: &RwLock<ThreadStat>,
+    nonces: NonceSequence,
+) {
+    let mut job = match await_job(rcv, share_tx, pause, 0) {
+        Some(job_data) => job_data,
+        None => {
             info!("Worker immediately stopped");
             return;
         }
     };
 
     loop {
-        let exit_reason = work_job(&job, rcv, share_tx, metric_resolution, metric_tx);
+        let exit_reason = work_job(
+            &job,
+            rcv,
+            &mut vm,
+            share_tx,
+            metric_resolution,
+            metric_tx,
+            pause,
+            stat,
+            nonces,
+        );
         //if work_job returns the nonce space was exhausted or a new job was received.
         //In case the nonce space was exhausted, we have to wait blocking for a new job and "idle".
         match exit_reason {
             WorkerExit::NonceSpaceExhausted => {
                 warn!("nonce space exhausted, thread idle");
-                let job_blocking = rcv.recv();
-                if job_blocking.is_err() {
-                    error!("job channel was dropped");
-                    return;
-                }
-                job = match job_blocking.unwrap() {
-                    WorkerCmd::NewJob { job_data } => job_data,
-                    WorkerCmd::Stop => break, //Terminate thread
+                job = match await_job(rcv, share_tx, pause, job.sequence) {
+                    Some(job_data) => job_data,
+                    None => break, //Terminate thread
                 };
             }
             WorkerExit::NewJob { job_data } => {
-                job = job_data;
+                job = *job_data;
             }
             WorkerExit::Stopped => break, //Terminate thread
         }
@@ -175,18 +1177,87 @@ fn work(
     info!("Worker stopped")
 }
 
+/// Blocks until a job newer than `last_sequence` is received, commands that do not change
+/// the job are applied while waiting. Returns None if the worker has to stop.
+fn await_job(
+    rcv: &Receiver<WorkerCmd>,
+    share_tx: &mut ShareSender,
+    pause: &PauseControl,
+    last_sequence: u64,
+) -> Option<JobData> {
+    loop {
+        match rcv.recv() {
+            Ok(WorkerCmd::NewJob { job_data }) if job_data.sequence > last_sequence => {
+                return newest_queued_job(job_data, rcv, share_tx, pause);
+            }
+            Ok(WorkerCmd::NewJob { job_data }) => discard_job(&job_data),
+            Ok(WorkerCmd::SetPriority { priority }) => set_current_thread_priority(priority),
+            Ok(WorkerCmd::SwapShareSender { share_sndr }) => *share_tx = share_sndr,
+            Ok(WorkerCmd::Pause) => wait_while_paused(pause),
+            Ok(WorkerCmd::Stop) => return None,
+            Err(_) => {
+                error!("job channel was dropped");
+                return None;
+            }
+        }
+    }
+}
+
+/// Applies the commands queued after `job` and returns the newest of the queued jobs,
+/// so a worker that fell behind skips the jobs in between. None if the worker has to stop.
+fn newest_queued_job(
+    job: JobData,
+    rcv: &Receiver<WorkerCmd>,
+    share_tx: &mut ShareSender,
+    pause: &PauseControl,
+) -> Option<JobData> {
+    let mut job = job;
+    while let Some(cmd_value) = check_command_available(rcv) {
+        match cmd_value {
+            WorkerCmd::NewJob { job_data } if job_data.sequence > job.sequence => job = job_data,
+            WorkerCmd::NewJob { job_data } => discard_job(&job_data),
+            WorkerCmd::SetPriority { priority } => set_current_thread_priority(priority),
+            WorkerCmd::SwapShareSender { share_sndr } => *share_tx = share_sndr,
+            WorkerCmd::Pause => wait_while_paused(pause),
+            WorkerCmd::Stop => return None,
+        }
+    }
+    Some(job)
+}
+
+fn discard_job(job: &JobData) {
+    debug!(
+        "discarding job {} (sequence {}), a newer job was received",
+        job.job_id, job.sequence
+    );
+}
+
+#[allow(clippy::too_many_arguments)]
 fn work_job<'a>(
     job: &'a JobData,
     rcv: &'a Receiver<WorkerCmd>,
-    share_tx: &Sender<stratum::StratumCmd>,
+    vm: &mut Vm,
+    share_tx: &mut ShareSender,
     metric_resolution: u64,
-    metric_tx: &Sender<u64>,
+    metric_tx: &metric::MetricSender,
+    pause: &PauseControl,
+    stat: &RwLock<ThreadStat>,
+    nonces: NonceSequence,
 ) -> WorkerExit {
     let num_target = job_target_value(&job.target);
-    let mut nonce = job.nonce.fetch_add(1, Ordering::Relaxed); // Relaxed ordering is sufficient here
+    // Unless the worker has its own nonces (`NonceSequence::Stride`) every worker claims
+    // the next unhashed nonce from the shared counter, so no worker idles while nonces
+    // are left (Relaxed ordering is sufficient here)
+    let mut nonce = nonces.first(job);
 
     let mut hash_count: u64 = 0;
-    let mut vm = new_vm(job.memory.clone());
+    let mut stat_batch = ThreadStatBatch::default();
+    vm.mem = job.memory.clone();
+    {
+        let mut stat = stat.write().expect("thread stat lock");
+        stat.job_sequence = job.sequence;
+        stat.hashes_since_last_job = 0;
+    }
     
     // Pre-calculate the static part of the blob
     let (blob_prefix, blob_suffix) = job.blob.split_at(78);
@@ -195,68 +1266,215 @@ fn work_job<'a>(
     // Use a larger batch size to improve performance
     const BATCH_SIZE: u32 = 32;
     
-    'outer: while nonce <= 65535 {
+    'outer: while nonce <= MAX_NONCE {
         // Process a batch of nonces before checking for commands
         for _ in 0..BATCH_SIZE {
-            if nonce > 65535 {
+            if nonce > MAX_NONCE {
                 break 'outer;
             }
             
             let nonce_hex = nonce_hex(nonce);
             // Avoid string concatenation by building the hash input more efficiently
             let hash_in = format!("{}{}{}", blob_prefix, nonce_hex, blob_suffix);
-            let bytes_in = byte_string::string_to_u8_array(&hash_in);
+            let bytes_in = match byte_string::hex_to_bytes(&hash_in) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    error!("invalid blob in job {}: {}", job.job_id, err);
+                    break 'outer;
+                }
+            };
 
-            let hash_result = vm.calculate_hash(&bytes_in).to_hex();
-            let hash_val = hash_target_value(&hash_result);
+            #[cfg(feature = "tracing-support")]
+            let _hash_span = ::tracing::span!(
+                ::tracing::Level::TRACE,
+                "rx_hash",
+                nonce,
+                thread_id = ?thread::current().id()
+            )
+            .entered();
 
-            if hash_val < num_target {
-                let share = stratum_data::Share {
-                    miner_id: job.miner_id.clone(),
-                    job_id: job.job_id.clone(),
-                    nonce: nonce_hex,
-                    hash: hash_result.to_string(),
-                };
+            let hash_start = Instant::now();
+            let hash = vm.try_calculate_hash(&bytes_in);
+            metric_tx.record_hash_duration(hash_start.elapsed());
+
+            // an aborted hash has no result, the nonce is skipped
+            if let Some(hash) = hash {
+                // the job target only covers the most significant 8 bytes of the hash
+                let target_bytes = num_target.to_le_bytes();
+                if byte_string::ct_compare_hash(&hash.as_bytes()[24..], &target_bytes) {
+                    report_share_found(job);
 
-                let submit_result = stratum::submit_share(share_tx, share);
-                if submit_result.is_err() {
-                    error!("submitting share failed: {:?}", submit_result);
+                    let share = stratum_data::Share {
+                        miner_id: job.miner_id.clone(),
+                        job_id: job.job_id.clone(),
+                        nonce: nonce_hex,
+                        hash: hash.to_hex().to_string(),
+                    };
+
+                    let submit_result = stratum::submit_share(share_tx, share);
+                    if submit_result.is_err() {
+                        error!("submitting share failed: {:?}", submit_result);
+                    } else {
+                        metric_tx.record_submitted_hash();
+                        job.last_share_at
+                            .store(stratum::epoch_millis(), Ordering::Relaxed);
+                    }
                 }
             }
 
             hash_count += 1;
+            job.hash_count.fetch_add(1, Ordering::Relaxed);
+            stat_batch.record(stat, nonce, vm.cache_stats);
             if hash_count % metric_resolution == 0 {
-                let send_result = metric_tx.send(hash_count);
-                if send_result.is_err() {
-                    error!("metric submit failed {:?}", send_result);
-                }
+                metric_tx.send_hash_count(hash_count);
                 hash_count = 0;
             }
             
-            nonce = job.nonce.fetch_add(1, Ordering::Relaxed);
+            nonce = nonces.next(job, nonce);
         }
         
         // Check for commands after processing a batch
-        let cmd = check_command_available(rcv);
-        if let Some(cmd_value) = cmd {
+        while let Some(cmd_value) = check_command_available(rcv) {
             match cmd_value {
-                WorkerCmd::NewJob { job_data } => {
+                WorkerCmd::NewJob { job_data } if job_data.sequence > job.sequence => {
                     // Send remaining hash count before switching jobs
                     if hash_count > 0 {
-                        let send_result = metric_tx.send(hash_count);
-                        if send_result.is_err() {
-                            error!("metric submit failed {:?}", send_result);
-                        }
+                        metric_tx.send_hash_count(hash_count);
                     }
-                    return WorkerExit::NewJob { job_data };
+                    stat_batch.write(stat, vm.cache_stats);
+                    return match newest_queued_job(job_data, rcv, share_tx, pause) {
+                        Some(job_data) => WorkerExit::NewJob {
+                            job_data: Box::new(job_data),
+                        },
+                        None => WorkerExit::Stopped,
+                    };
+                }
+                WorkerCmd::NewJob { job_data } => discard_job(&job_data),
+                WorkerCmd::SetPriority { priority } => set_current_thread_priority(priority),
+                WorkerCmd::SwapShareSender { share_sndr } => *share_tx = share_sndr,
+                WorkerCmd::Pause => wait_while_paused(pause),
+                WorkerCmd::Stop => {
+                    stat_batch.write(stat, vm.cache_stats);
+                    return WorkerExit::Stopped;
                 }
-                WorkerCmd::Stop => return WorkerExit::Stopped,
             }
         }
     }
+    stat_batch.write(stat, vm.cache_stats);
     WorkerExit::NonceSpaceExhausted
 }
 
+/// Hashes between two `ThreadStat` writes of a worker, so the stat lock and the clock
+/// are not taken for every hash
+const THREAD_STAT_INTERVAL: u64 = 32;
+
+/// The `ThreadStat` changes of the current job a worker has not written yet
+#[derive(Default)]
+struct ThreadStatBatch {
+    hashes: u64,
+    nonce_range: (u32, u32),
+    /// false until the first hash of the job was written
+    job_written: bool,
+}
+
+impl ThreadStatBatch {
+    /// Writes every `THREAD_STAT_INTERVAL` hashes, the first hash of a job at once
+    fn record(&mut self, stat: &RwLock<ThreadStat>, nonce: u32, cache: CacheStats) {
+        if self.hashes == 0 {
+            self.nonce_range = (nonce, nonce);
+        } else {
+            self.nonce_range.1 = nonce;
+        }
+        self.hashes += 1;
+        if self.hashes >= THREAD_STAT_INTERVAL || !self.job_written {
+            self.write(stat, cache);
+        }
+    }
+
+    fn write(&mut self, stat: &RwLock<ThreadStat>, cache: CacheStats) {
+        if self.hashes == 0 {
+            return;
+        }
+        let mut stat = stat.write().expect("thread stat lock");
+        if stat.hashes_since_last_job == 0 {
+            stat.nonce_range = self.nonce_range;
+        } else {
+            stat.nonce_range.1 = self.nonce_range.1;
+        }
+        stat.hashes_since_last_job += self.hashes;
+        stat.last_hash_at = Some(Instant::now());
+        stat.dataset_cache = cache;
+        self.hashes = 0;
+        self.job_written = true;
+    }
+}
+
+/// One line per worker, for logging `WorkerPool::thread_stats`
+pub fn thread_stats_table(stats: &[ThreadStat]) -> String {
+    let mut table = format!(
+        "{:>6} {:>10} {:>10} {:>13} {:>6}",
+        "thread", "hashes", "last hash", "nonces", "panics"
+    );
+    for stat in stats {
+        let last_hash = match stat.last_hash_at {
+            Some(at) => format!("{:.1}s ago", at.elapsed().as_secs_f64()),
+            None => "-".to_string(),
+        };
+        table.push_str(&format!(
+            "\n{:>6} {:>10} {:>10} {:>13} {:>6}",
+            stat.thread_id,
+            stat.hashes_since_last_job,
+            last_hash,
+            format!("{}-{}", stat.nonce_range.0, stat.nonce_range.1),
+            stat.panics
+        ));
+    }
+    table
+}
+
+/// Blocks the worker until the pool is resumed (or stopped)
+fn wait_while_paused(pause: &PauseControl) {
+    let (lock, cvar) = &**pause;
+    let mut state = lock.lock().expect("pause lock");
+    state.waiting += 1;
+    while state.paused {
+        state = cvar.wait(state).expect("pause wait");
+    }
+    state.waiting -= 1;
+}
+
+fn report_share_found(job: &JobData) {
+    let hashes_attempted = job.hash_count.load(Ordering::Relaxed) - job.hashes_at_start + 1;
+    let event = ShareFoundEvent::new(&job.job_id, hashes_attempted, job.start_time.elapsed());
+    info!(
+        "share found for job {} after {} hashes in {}ms ({:.2} kH/s)",
+        event.job_id, event.hashes_attempted, event.solution_time_ms, event.hashrate_khs
+    );
+
+    if let Some(sndr) = &job.share_found_sndr {
+        let send_result = sndr.send(event);
+        if send_result.is_err() {
+            error!("sending share found event failed {:?}", send_result);
+        }
+    }
+}
+
+/// Available RAM as reported by `MemAvailable` in `/proc/meminfo`,
+/// None if it can not be determined (e.g. on non Linux systems)
+pub fn available_ram_bytes() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    parse_mem_available(&meminfo)
+}
+
+/// Parses the `MemAvailable:   1234 kB` line of a `/proc/meminfo` content to bytes
+pub fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemAvailable:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
 pub fn nonce_hex(nonce: u32) -> String {
     format!("{:08x}", nonce)
 }
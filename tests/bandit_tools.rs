@@ -0,0 +1,163 @@
+extern crate bandit;
+extern crate mithril;
+extern crate serde_json;
+
+use bandit::softmax::{AnnealingSoftmax, AnnealingSoftmaxConfig};
+use bandit::{BanditConfig, MultiArmedBandit};
+use mithril::bandit_tools::{BanditStrategy, ThreadArm, UcbBandit};
+use std::env;
+use std::fs;
+
+#[test]
+fn arm_serde_roundtrip() {
+    let arm = ThreadArm { num_threads: 8 };
+
+    let json = serde_json::to_string(&arm).unwrap();
+    let parsed: ThreadArm = serde_json::from_str(&json).unwrap();
+
+    assert_eq!(parsed, arm);
+    assert_eq!(parsed.num_threads, 8);
+}
+
+/// The fixture was written by an earlier version, schema changes to `ThreadArm`
+/// have to keep it loadable (e.g. new fields need `#[serde(default)]`).
+#[test]
+fn arm_fixture_backward_compatible() {
+    let fixture = include_str!("fixtures/thread_arms.json");
+
+    let arms: Vec<ThreadArm> = serde_json::from_str(fixture).unwrap();
+
+    assert_eq!(
+        arms,
+        vec![
+            ThreadArm { num_threads: 1 },
+            ThreadArm { num_threads: 2 },
+            ThreadArm { num_threads: 8 },
+        ]
+    );
+}
+
+#[test]
+fn bandit_strategy_from_name() {
+    assert_eq!(
+        BanditStrategy::from_name("annealing_softmax"),
+        Some(BanditStrategy::AnnealingSoftmax)
+    );
+    assert_eq!(
+        BanditStrategy::from_name("ucb1"),
+        Some(BanditStrategy::Ucb1)
+    );
+    assert_eq!(BanditStrategy::from_name("ucb"), None);
+    assert_eq!(BanditStrategy::default(), BanditStrategy::AnnealingSoftmax);
+}
+
+#[test]
+fn ucb_tries_every_arm_first() {
+    let arms = thread_arms(4);
+    let mut bandit = UcbBandit::new(arms.clone());
+
+    for arm in &arms {
+        let selected = bandit.select_arm();
+        assert_eq!(selected, *arm);
+        bandit.update(selected, 1.0);
+    }
+    assert!(bandit.arm_stats().iter().all(|(_, stats)| stats.pulls == 1));
+}
+
+#[test]
+fn ucb_save_load_roundtrip() {
+    let path = env::temp_dir().join(format!("mithril_ucb_state_{}.json", std::process::id()));
+    let mut bandit = UcbBandit::new(thread_arms(3));
+    bandit.update(ThreadArm { num_threads: 2 }, 4.5);
+    bandit.update(ThreadArm { num_threads: 2 }, 5.5);
+    bandit.save_bandit(&path).unwrap();
+
+    //arms that were added since the state was saved start untried
+    let loaded = UcbBandit::load_bandit(thread_arms(4), &path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let stats = loaded.arm_stats();
+    assert_eq!(stats.len(), 4);
+    assert_eq!(stats[1].1.pulls, 2);
+    assert_eq!(stats[1].1.reward_sum, 10.0);
+    assert_eq!(stats[3].1.pulls, 0);
+    assert_eq!(loaded.select_arm(), ThreadArm { num_threads: 1 });
+}
+
+/// Expected hashrate in kH/s of the synthetic reward: peaks at 6 threads and falls
+/// off when the threads compete for the cache
+fn expected_reward(arm: &ThreadArm) -> f64 {
+    let threads = arm.num_threads as f64;
+    (BEST_REWARD - 0.25 * (threads - 6.0) * (threads - 6.0)).max(0.5)
+}
+
+const BEST_REWARD: f64 = 10.0;
+
+/// `expected_reward` with +-10% deterministic (xorshift) noise
+fn synthetic_reward(arm: &ThreadArm, rng: &mut u64) -> f64 {
+    *rng ^= *rng << 13;
+    *rng ^= *rng >> 7;
+    *rng ^= *rng << 17;
+    let noise = (*rng % 2001) as f64 / 10_000.0 - 0.1;
+    expected_reward(arm) * (1.0 + noise)
+}
+
+/// Cumulative regret (expected reward of the best arm minus the expected reward of
+/// the selected arm) after `rounds` auto tune intervals
+fn simulate_regret(bandit: &mut dyn MultiArmedBandit<ThreadArm>, rounds: usize) -> f64 {
+    let mut rng = 0x9E37_79B9_7F4A_7C15;
+    let mut regret = 0.0;
+    for _ in 0..rounds {
+        let arm = bandit.select_arm();
+        regret += BEST_REWARD - expected_reward(&arm);
+        let reward = synthetic_reward(&arm, &mut rng);
+        bandit.update(arm, reward);
+    }
+    regret
+}
+
+#[test]
+fn ucb_and_softmax_regret() {
+    let rounds = 2000;
+    let arms = thread_arms(12);
+    let uniform_regret = arms
+        .iter()
+        .map(|arm| BEST_REWARD - expected_reward(arm))
+        .sum::<f64>()
+        / arms.len() as f64
+        * rounds as f64;
+
+    //UCB1 is deterministic for the seeded rewards
+    let mut ucb = UcbBandit::new(arms.clone());
+    let ucb_regret = simulate_regret(&mut ucb, rounds);
+
+    //softmax draws its arms from an unseeded rng, the average of several runs is
+    //compared so a single unlucky run does not fail the test
+    let runs = 10;
+    let softmax_regret = (0..runs)
+        .map(|_| {
+            let mut softmax = AnnealingSoftmax::new(
+                arms.clone(),
+                BanditConfig { log_file: None },
+                AnnealingSoftmaxConfig {
+                    cooldown_factor: 0.7,
+                },
+            );
+            simulate_regret(&mut softmax, rounds)
+        })
+        .sum::<f64>()
+        / runs as f64;
+
+    println!(
+        "regret after {} rounds: ucb1 {:.1}, annealing softmax {:.1} (average), uniform {:.1}",
+        rounds, ucb_regret, softmax_regret, uniform_regret
+    );
+    assert!(ucb_regret < uniform_regret / 2.0);
+    assert!(softmax_regret < uniform_regret / 2.0);
+}
+
+fn thread_arms(count: u64) -> Vec<ThreadArm> {
+    (1..=count)
+        .map(|num_threads| ThreadArm { num_threads })
+        .collect()
+}
@@ -0,0 +1,16 @@
+extern crate mithril;
+
+use mithril::build_info;
+
+#[test]
+fn test_build_info() {
+    let info = build_info::build_info();
+    assert!(info.starts_with(&format!("mithril v{} (", env!("CARGO_PKG_VERSION"))));
+    assert!(info.contains(&format!("target={}", build_info::TARGET)));
+    assert!(!build_info::GIT_HASH.is_empty());
+
+    let date: Vec<&str> = build_info::BUILD_DATE.split('-').collect();
+    assert_eq!(date.len(), 3);
+    assert_eq!(date[0].len(), 4);
+    assert!(date.iter().all(|part| part.parse::<u32>().is_ok()));
+}
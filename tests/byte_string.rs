@@ -3,7 +3,10 @@
 
 extern crate mithril;
 
-use mithril::byte_string::{hex2_u32_le, hex2_u64_be, string_to_u8_array, u8_array_to_string};
+use mithril::byte_string::{
+    ct_compare_hash, hex2_u32_le, hex2_u64_be, hex_to_bytes, string_to_u8_array,
+    u8_array_to_string, HexError,
+};
 
 #[test]
 fn test_hex2_u32_le() {
@@ -26,3 +29,59 @@ fn test_conv_back_and_forth() {
     let str_out = u8_array_to_string(&a);
     assert_eq!(str_in, str_out);
 }
+
+#[test]
+fn test_hex_round_trip_random() {
+    //xorshift, fixed seed for reproducible runs
+    let mut x: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    for _ in 0..500 {
+        let len = (next() % 100) as usize;
+        let bytes: Vec<u8> = (0..len).map(|_| next() as u8).collect();
+        let hex = u8_array_to_string(&bytes);
+        assert_eq!(hex.len(), 2 * len);
+        assert_eq!(hex_to_bytes(&hex), Ok(bytes.clone()));
+        assert_eq!(string_to_u8_array(&hex), bytes);
+
+        //inverse direction, upper case input comes back lower case
+        let hex_in: String = (0..2 * len)
+            .map(|_| b"0123456789abcdefABCDEF"[(next() % 22) as usize] as char)
+            .collect();
+        let decoded = hex_to_bytes(&hex_in).unwrap();
+        assert_eq!(u8_array_to_string(&decoded), hex_in.to_lowercase());
+    }
+}
+
+#[test]
+fn test_hex_to_bytes_invalid() {
+    assert_eq!(hex_to_bytes(""), Ok(vec![]));
+    assert_eq!(hex_to_bytes("0a1"), Err(HexError::OddLength(3)));
+    assert_eq!(
+        hex_to_bytes("0a1g"),
+        Err(HexError::InvalidChar { index: 3, c: 'g' })
+    );
+    assert_eq!(
+        hex_to_bytes("+a"),
+        Err(HexError::InvalidChar { index: 0, c: '+' })
+    );
+}
+
+#[test]
+fn test_ct_compare_hash() {
+    let target =
+        string_to_u8_array("00000000000000000000000000000000000000000000000000000000169f0200");
+    let below =
+        string_to_u8_array("ffffffffffffffffffffffffffffffffffffffffffffffffffffffff169f0100");
+    let above =
+        string_to_u8_array("0000000000000000000000000000000000000000000000000000000000000300");
+
+    assert!(ct_compare_hash(&below, &target));
+    assert!(!ct_compare_hash(&target, &target));
+    assert!(!ct_compare_hash(&above, &target));
+    assert!(!ct_compare_hash(&below[..31], &target));
+}
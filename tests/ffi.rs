@@ -0,0 +1,229 @@
+extern crate mithril;
+#[cfg(feature = "async-api")]
+extern crate tokio;
+
+use mithril::mithril_config::MithrilConfigBuilder;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_stats_before_start() {
+    assert_eq!(mithril::get_miner_status(), 0);
+    assert_eq!(mithril::get_hashrate(), 0.0);
+    assert_eq!(mithril::get_accepted_shares(), 0);
+}
+
+#[test]
+fn test_start_mining_with_invalid_json_config() {
+    let json = "{\"pool_address\": \"localhost:3333\",";
+    let code =
+        unsafe { mithril::start_mining_with_json_config(json.as_ptr() as *const i8, json.len()) };
+    assert_eq!(code, 2);
+
+    //unknown fields are rejected instead of ignored
+    let json = "{\"pool_address\": \"localhost:3333\", \"threads\": 2}";
+    let code =
+        unsafe { mithril::start_mining_with_json_config(json.as_ptr() as *const i8, json.len()) };
+    assert_eq!(code, 2);
+
+    let code = unsafe { mithril::start_mining_with_json_config(std::ptr::null(), 0) };
+    assert_eq!(code, 2);
+
+    //the wallet address is missing
+    let json = "{\"pool_address\": \"localhost:3333\", \"num_threads\": 2}";
+    let code =
+        unsafe { mithril::start_mining_with_json_config(json.as_ptr() as *const i8, json.len()) };
+    assert_eq!(code, 3);
+
+    assert_eq!(mithril::get_miner_status(), 0);
+}
+
+#[test]
+fn test_miner_handles() {
+    let first = mithril::create_miner();
+    let second = mithril::create_miner();
+    assert!(!first.is_null() && first != second);
+
+    let invalid = "{\"pool_address\": \"localhost:3333\",\0";
+    let code = unsafe { mithril::start_miner(first, invalid.as_ptr() as *const i8) };
+    assert_eq!(code, 2);
+    let code = unsafe { mithril::start_miner(second, std::ptr::null()) };
+    assert_eq!(code, 2);
+    let code = unsafe { mithril::start_miner(std::ptr::null_mut(), invalid.as_ptr() as *const i8) };
+    assert_eq!(code, 4);
+
+    //never started
+    assert_eq!(unsafe { mithril::stop_miner(first) }, 0);
+    assert_eq!(unsafe { mithril::stop_miner(std::ptr::null_mut()) }, 0);
+
+    unsafe {
+        mithril::destroy_miner(first);
+        mithril::destroy_miner(second);
+        mithril::destroy_miner(std::ptr::null_mut());
+    }
+}
+
+/// Answers the login with a job the workers cannot hash (the blob is not hex), then
+/// accepts `accepted` shares and waits for the miner to disconnect. The receiver gets a
+/// message when the miner connected.
+fn start_mock_pool(accepted: usize) -> (String, mpsc::Receiver<()>, thread::JoinHandle<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let (connected_sndr, connected_rcvr) = mpsc::channel();
+    let hnd = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        connected_sndr.send(()).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut login = String::new();
+        reader.read_line(&mut login).unwrap();
+        assert!(login.contains("login"), "{}", login);
+        let blob = "zz".repeat(76);
+        writeln!(writer, r#"{{"id":1,"jsonrpc":"2.0","error":null,"result":{{"id":"miner","job":{{"blob":"{}","job_id":"job1","target":"ffffffff","seed_hash":""}},"status":"OK"}}}}"#, blob).unwrap();
+        for id in 0..accepted {
+            writeln!(
+                writer,
+                r#"{{"id":{},"jsonrpc":"2.0","error":null,"result":{{"status":"OK"}}}}"#,
+                100 + id
+            )
+            .unwrap();
+        }
+        let mut rest = String::new();
+        while reader.read_line(&mut rest).unwrap_or(0) > 0 {
+            rest.clear();
+        }
+    });
+    (address, connected_rcvr, hnd)
+}
+
+fn mock_pool_config(address: &str) -> mithril::mithril_config::MithrilConfig {
+    MithrilConfigBuilder::new()
+        .pool_address(address)
+        .wallet("wallet")
+        .num_threads(1)
+        .build()
+        .unwrap()
+}
+
+fn wait_until<F: Fn() -> bool>(condition: F) {
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !condition() {
+        assert!(Instant::now() < deadline, "condition not met within 30s");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_miner_handles_count_separately() {
+    let handles = [mithril::create_miner(), mithril::create_miner()];
+    let mut pools = Vec::new();
+    for (i, handle) in handles.iter().enumerate() {
+        let (address, connected, pool) = start_mock_pool(i + 1);
+        pools.push((connected, pool));
+        let handle = unsafe { &**handle };
+        assert_eq!(handle.start_with_config(mock_pool_config(&address)), 1);
+    }
+
+    let first = unsafe { &*handles[0] };
+    let second = unsafe { &*handles[1] };
+    wait_until(|| first.accepted_shares() == 1 && second.accepted_shares() == 2);
+    assert_eq!(first.status(), 2);
+    assert_eq!(second.status(), 2);
+    //the default miner is not affected
+    assert_eq!(mithril::get_accepted_shares(), 0);
+
+    assert_eq!(unsafe { mithril::stop_miner(handles[0]) }, 1);
+    assert_eq!(first.status(), 0);
+    assert_eq!(second.status(), 2);
+    assert_eq!(second.accepted_shares(), 2);
+    unsafe {
+        mithril::destroy_miner(handles[0]);
+        mithril::destroy_miner(handles[1]);
+    }
+    for (_connected, pool) in pools {
+        pool.join().unwrap();
+    }
+}
+
+#[test]
+#[cfg(feature = "async-api")]
+fn test_mine_until_stopped() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let (address, connected, pool) = start_mock_pool(2);
+    let mining = mithril::mine_until_stopped(mock_pool_config(&address));
+    let stop = mining.stop_handle();
+    //nothing runs before the first poll
+    assert!(connected.recv_timeout(Duration::from_millis(200)).is_err());
+
+    //stops on timeouts too, the report assert below fails then
+    let stopper = thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while stop.accepted_shares() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        stop.stop();
+    });
+    let report = runtime.block_on(mining).unwrap();
+    stopper.join().unwrap();
+    assert_eq!(report.accepted_shares, 2);
+    assert_eq!(report.rejected_shares, 0);
+    //the miner closed the connection
+    pool.join().unwrap();
+    //the default miner is not affected
+    assert_eq!(mithril::get_miner_status(), 0);
+}
+
+#[test]
+#[cfg(feature = "async-api")]
+fn test_mine_until_stopped_drop() {
+    use std::future::{poll_fn, Future};
+    use std::pin::Pin;
+    use std::task::Poll;
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let (address, connected, pool) = start_mock_pool(0);
+    let mut mining = mithril::mine_until_stopped(mock_pool_config(&address));
+    runtime.block_on(poll_fn(|cx| {
+        assert!(Pin::new(&mut mining).poll(cx).is_pending());
+        Poll::Ready(())
+    }));
+    connected.recv_timeout(Duration::from_secs(30)).unwrap();
+
+    //dropping the future stops the miner, which closes the connection
+    drop(mining);
+    pool.join().unwrap();
+}
+
+#[test]
+#[cfg(all(feature = "async-api", target_os = "linux"))]
+fn test_mine_until_stopped_sandbox() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let (address, connected, pool) = start_mock_pool(0);
+    let mut config = mock_pool_config(&address);
+    config.sandbox = true;
+    let mining = mithril::mine_until_stopped(config);
+    let stop = mining.stop_handle();
+    let stopper = thread::spawn(move || {
+        connected.recv_timeout(Duration::from_secs(30)).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        stop.stop();
+    });
+    runtime.block_on(mining).unwrap();
+    stopper.join().unwrap();
+    pool.join().unwrap();
+
+    //the filter stays on the miner thread, the blocking pool threads of the runtime
+    //and the calling thread can still open sockets
+    let blocking = runtime.spawn_blocking(|| TcpListener::bind("127.0.0.1:0").is_ok());
+    assert!(runtime.block_on(blocking).unwrap());
+    assert!(TcpListener::bind("127.0.0.1:0").is_ok());
+}
@@ -0,0 +1,242 @@
+extern crate mithril;
+
+use mithril::metric;
+use std::env;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn test_rotate_if_needed() {
+    let mut path = env::temp_dir();
+    path.push(format!("mithril_metric_rotate_{}.csv", std::process::id()));
+    let report_file = path.to_str().unwrap().to_string();
+    let rotated_file = format!("{}.1", report_file);
+
+    fs::write(&report_file, "1;100\n").unwrap();
+    assert!(!metric::rotate_if_needed(&report_file, 1024).unwrap());
+
+    fs::write(&rotated_file, "old").unwrap();
+    assert!(metric::rotate_if_needed(&report_file, 6).unwrap());
+    assert!(fs::metadata(&report_file).is_err());
+    assert_eq!(fs::read_to_string(&rotated_file).unwrap(), "1;100\n");
+
+    fs::remove_file(&rotated_file).unwrap();
+}
+
+#[test]
+fn test_rotate_if_needed_missing_file() {
+    let mut path = env::temp_dir();
+    path.push("mithril_metric_rotate_missing.csv");
+    assert!(!metric::rotate_if_needed(path.to_str().unwrap(), 0).unwrap());
+}
+
+#[test]
+fn test_uptime() {
+    let miner_start = Instant::now();
+    thread::sleep(Duration::from_millis(1100));
+
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 16,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (_sndr, rcvr) = metric::channel(&conf);
+    let m = metric::start_since(conf, rcvr, miner_start);
+
+    assert_eq!(m.session_uptime_secs(), 0);
+    assert!(m.total_uptime_secs() >= 1);
+
+    m.stop();
+    m.join();
+}
+
+#[test]
+fn test_ema_hashrate_khs() {
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 16,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (sndr, rcvr) = metric::channel(&conf);
+    let m = metric::start(conf, rcvr);
+
+    assert_eq!(m.ema_hashrate_khs(), 0.0);
+
+    //the first sample starts at the raw hashrate, 1000 hashes in one sample interval
+    sndr.send_hash_count(1000);
+    thread::sleep(Duration::from_millis(
+        metric::EMA_SAMPLE_INTERVAL_MS * 3 / 2,
+    ));
+    let first = m.ema_hashrate_khs();
+    assert!(first > 0.9 && first <= 1.0, "{}", first);
+    //reading does not update the average
+    assert_eq!(m.ema_hashrate_khs(), first);
+    assert_eq!(m.snapshot().hashrate_khs, first);
+    assert_eq!(m.raw_hashrate_khs(), first);
+
+    m.stop();
+    m.join();
+}
+
+#[test]
+fn test_ema_alpha() {
+    //with a smoothing factor of 1 the average is the raw hashrate of every sample
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 16,
+        ema_alpha: 1.0,
+    };
+    let (sndr, rcvr) = metric::channel(&conf);
+    let m = metric::start(conf, rcvr);
+
+    sndr.send_hash_count(1000);
+    thread::sleep(Duration::from_millis(
+        metric::EMA_SAMPLE_INTERVAL_MS * 3 / 2,
+    ));
+    sndr.send_hash_count(5000);
+    thread::sleep(Duration::from_millis(metric::EMA_SAMPLE_INTERVAL_MS));
+    let raw = m.raw_hashrate_khs();
+    assert!(raw > 4.0, "{}", raw);
+    assert_eq!(m.ema_hashrate_khs(), raw);
+
+    m.stop();
+    m.join();
+}
+
+#[test]
+fn test_channel_capacity() {
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 2,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (sndr, rcvr) = metric::channel(&conf);
+    //nothing is received before `start`, the third count does not fit
+    sndr.send_hash_count(1);
+    sndr.send_hash_count(2);
+    sndr.send_hash_count(4);
+
+    let m = metric::start(conf, rcvr);
+    thread::sleep(Duration::from_millis(100));
+    //the third count bypassed the channel
+    assert_eq!(m.hash_count(), 7);
+    assert_eq!(m.dropped_metric_events(), 1);
+
+    m.stop();
+    m.join();
+}
+
+#[test]
+fn test_efficacy_ratio() {
+    assert_eq!(metric::efficacy_ratio(0, 0), 0.0);
+    assert_eq!(metric::efficacy_ratio(0, 1000), 0.0);
+    assert_eq!(metric::efficacy_ratio(1, 1000), 0.001);
+    assert_eq!(metric::efficacy_ratio(5, 5), 1.0);
+}
+
+#[test]
+fn test_snapshot_display() {
+    let snapshot = metric::Snapshot {
+        raw_hashrate_khs: 43.01,
+        hashrate_khs: 42.34,
+        hash_count: 1000,
+        accepted_shares: 15,
+        rejected_shares: 0,
+        uptime_secs: 3600 + 23 * 60 + 59,
+    };
+    assert_eq!(
+        snapshot.to_string(),
+        "kH/s: 43.0 (EMA 42.3) | accepted: 15 | rejected: 0 | uptime: 1h23m"
+    );
+}
+
+#[test]
+fn test_hash_latency_histogram() {
+    let histogram = metric::HashLatencyHistogram::new();
+    assert_eq!(histogram.percentile_us(0.5), 0);
+
+    for _ in 0..90 {
+        histogram.record(Duration::from_micros(700)); //bucket [512, 1024)
+    }
+    for _ in 0..9 {
+        histogram.record(Duration::from_millis(3)); //bucket [2048, 4096)
+    }
+    histogram.record(Duration::from_secs(2)); //last bucket
+    histogram.record(Duration::from_nanos(10)); //first bucket
+
+    assert_eq!(histogram.count(), 101);
+    assert_eq!(histogram.percentile_us(0.0), 2);
+    assert_eq!(histogram.percentile_us(0.50), 1024);
+    assert_eq!(histogram.percentile_us(0.95), 4096);
+    assert_eq!(histogram.percentile_us(1.0), 1 << 20);
+
+    histogram.reset();
+    assert_eq!(histogram.count(), 0);
+}
+
+#[test]
+fn test_hash_durations_per_metric() {
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 16,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (sndr_a, rcvr_a) = metric::channel(&conf);
+    let (_sndr_b, rcvr_b) = metric::channel(&conf);
+    let a = metric::start(conf.clone(), rcvr_a);
+    let b = metric::start(conf, rcvr_b);
+
+    sndr_a.record_hash_duration(Duration::from_micros(700));
+    assert_eq!(a.p50_hash_duration_us(), 1024);
+    assert_eq!(b.p50_hash_duration_us(), 0);
+
+    for m in [a, b] {
+        m.stop();
+        m.join();
+    }
+}
+
+#[test]
+fn test_submitted_hashes() {
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 16,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (sndr, rcvr) = metric::channel(&conf);
+    let m = metric::start(conf, rcvr);
+
+    sndr.send_hash_count(1000);
+    sndr.record_submitted_hash();
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(m.submitted_hashes(), 1);
+    assert_eq!(m.efficacy_ratio(), 0.001);
+
+    m.stop();
+    m.join();
+}
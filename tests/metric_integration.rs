@@ -0,0 +1,72 @@
+extern crate mithril;
+
+use mithril::metric;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Waits for the sample thread to change the average from `previous`
+fn next_sample(m: &metric::Metric, previous: f64) -> f64 {
+    let deadline = Instant::now() + Duration::from_millis(metric::EMA_SAMPLE_INTERVAL_MS * 3);
+    loop {
+        let khs = m.ema_hashrate_khs();
+        if khs != previous {
+            return khs;
+        }
+        assert!(
+            Instant::now() < deadline,
+            "no new sample after {} kH/s",
+            previous
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn assert_within_5_percent(khs: f64, expected_khs: f64) {
+    assert!(
+        (khs - expected_khs).abs() <= expected_khs * 0.05,
+        "{} kH/s, expected {}",
+        khs,
+        expected_khs
+    );
+}
+
+#[test]
+fn test_metric_with_real_time() {
+    //the expected values below are computed for these
+    assert_eq!(metric::EMA_SAMPLE_INTERVAL_MS, 1000);
+    assert_eq!(metric::EMA_ALPHA, 0.2);
+
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: metric::DEFAULT_CHANNEL_CAPACITY,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (sndr, rcvr) = metric::channel(&conf);
+    let m = metric::start(conf, rcvr);
+
+    for _ in 0..10_000 {
+        sndr.send_hash_count(1);
+    }
+    //the first sample is the raw hashrate since `start`: 10000 H in 1 s
+    let first = next_sample(&m, 0.0);
+    assert_eq!(m.hash_count(), 10_000);
+    assert_within_5_percent(first, 10.0);
+
+    //no hashes: 0.2 * 0 + 0.8 * 10
+    let second = next_sample(&m, first);
+    assert_within_5_percent(second, 8.0);
+
+    //5000 H in 1 s: 0.2 * 5 + 0.8 * 8
+    for _ in 0..5_000 {
+        sndr.send_hash_count(1);
+    }
+    let third = next_sample(&m, second);
+    assert_within_5_percent(third, 7.4);
+
+    m.stop();
+    m.join();
+}
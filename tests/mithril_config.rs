@@ -0,0 +1,399 @@
+extern crate mithril;
+
+use mithril::bandit_tools::BanditStrategy;
+use mithril::metric::statsd::StatsdConfig;
+use mithril::metric::{self, MetricConfig};
+use mithril::mithril_config::{
+    self, ConfigError, ConfigWarning, DonationConfig, FatalErrorConfig, MithrilConfig,
+    MithrilConfigBuilder,
+};
+use mithril::stratum::stratum_data::PoolConfig;
+use mithril::worker::worker_pool::{self, WorkerConfig};
+use std::env;
+use std::fs;
+use std::io;
+
+fn config(num_threads: u64, auto_tune: bool, percentage: f64) -> MithrilConfig {
+    MithrilConfig {
+        pool_conf: PoolConfig {
+            pool_address: "localhost:3334".to_string(),
+            wallet_address: "wallet".to_string(),
+            pool_password: "x".to_string(),
+        },
+        worker_conf: WorkerConfig {
+            num_threads,
+            auto_tune,
+            auto_tune_interval_minutes: 15,
+            auto_tune_log: "/log/file".to_string(),
+            bandit_strategy: BanditStrategy::AnnealingSoftmax,
+            share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+            warm_swap: false,
+        },
+        metric_conf: MetricConfig {
+            enabled: false,
+            resolution: 1000,
+            sample_interval_seconds: 5,
+            report_file: "/dev/null".to_string(),
+            max_file_size_bytes: 1024,
+            channel_capacity: 16,
+            ema_alpha: metric::EMA_ALPHA,
+        },
+        donation_conf: DonationConfig {
+            percentage,
+            custom_pool: None,
+        },
+        fatal_error_conf: FatalErrorConfig::default(),
+        statsd_conf: StatsdConfig::default(),
+        sandbox: false,
+    }
+}
+
+#[test]
+fn test_validate_no_contradictions() {
+    assert_eq!(config(4, false, 1.0).validate(), vec![]);
+    assert_eq!(config(1, true, 1.0).validate(), vec![]);
+}
+
+#[test]
+fn test_validate_auto_tune_with_num_threads() {
+    let issues = config(8, true, 0.0).validate();
+    assert_eq!(issues.len(), 1);
+    assert!(!issues[0].is_error());
+}
+
+#[test]
+fn test_validate_auto_tune_with_full_donation() {
+    let issues = config(1, true, 100.0).validate();
+    assert_eq!(issues.len(), 1);
+    assert!(issues[0].is_error());
+}
+
+#[test]
+fn test_validate_donation_out_of_range() {
+    let issues = config(4, false, 101.0).validate();
+    match issues.as_slice() {
+        [ConfigWarning::Error(_)] => {}
+        _ => panic!("unexpected issues {:?}", issues),
+    }
+}
+
+#[test]
+fn test_validate_sandbox() {
+    let mut config = config(1, true, 1.0);
+    config.sandbox = true;
+    config.statsd_conf.enabled = true;
+    let issues = config.validate();
+    assert_eq!(issues.len(), 3);
+    assert!(issues.iter().all(|issue| !issue.is_error()));
+
+    config.worker_conf.auto_tune = false;
+    config.donation_conf.percentage = 0.0;
+    config.statsd_conf.enabled = false;
+    assert_eq!(config.validate(), vec![]);
+}
+
+#[test]
+fn test_builder() {
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .num_threads(2)
+        .metric_enabled(true)
+        .metric_resolution(500)
+        .donation_percentage(1.0)
+        .build()
+        .unwrap();
+
+    assert_eq!(config.pool_conf.pool_address, "localhost:3334");
+    assert_eq!(config.pool_conf.wallet_address, "wallet");
+    assert_eq!(config.worker_conf.num_threads, 2);
+    assert!(!config.worker_conf.auto_tune);
+    assert_eq!(
+        config.worker_conf.bandit_strategy,
+        BanditStrategy::AnnealingSoftmax
+    );
+    assert_eq!(
+        config.worker_conf.share_timeout_secs,
+        worker_pool::DEFAULT_SHARE_TIMEOUT_SECS
+    );
+    assert!(config.metric_conf.enabled);
+    assert_eq!(config.metric_conf.resolution, 500);
+    assert_eq!(
+        config.metric_conf.channel_capacity,
+        metric::DEFAULT_CHANNEL_CAPACITY
+    );
+    assert_eq!(config.metric_conf.ema_alpha, metric::EMA_ALPHA);
+    assert_eq!(config.donation_conf.percentage, 1.0);
+}
+
+#[test]
+fn test_builder_missing_pool_address() {
+    assert!(MithrilConfigBuilder::new()
+        .wallet("wallet")
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_builder_zero_threads() {
+    let result = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .num_threads(0)
+        .build();
+    let err = result.err().unwrap();
+    assert_eq!(
+        err.to_string(),
+        "config error: field 'num_threads' has invalid value '0': must be positive"
+    );
+    match err {
+        ConfigError::InvalidField { field, value, .. } => {
+            assert_eq!(field, "num_threads");
+            assert_eq!(value, "0");
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn test_builder_zero_share_timeout() {
+    let err = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .share_timeout_secs(0)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "config error: field 'share_timeout_secs' has invalid value '0': must be positive"
+    );
+}
+
+#[test]
+fn test_builder_fatal_validation() {
+    let result = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .auto_tune(true)
+        .donation_percentage(100.0)
+        .build();
+    match result.err().unwrap() {
+        ConfigError::InvalidField { field, value, .. } => {
+            assert_eq!(field, "donation_percentage");
+            assert_eq!(value, "100");
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
+}
+
+#[test]
+fn test_builder_donation_out_of_range() {
+    let err = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .donation_percentage(101.0)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "config error: field 'donation_percentage' has invalid value '101': has to be between 0 and 100"
+    );
+}
+
+#[test]
+fn test_custom_donation_pool() {
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .donation_percentage(1.0)
+        .build()
+        .unwrap();
+    assert_eq!(
+        config.active_pool_conf(true).pool_address,
+        mithril_config::donation_conf().pool_address
+    );
+
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .donation_percentage(1.0)
+        .donation_pool(PoolConfig {
+            pool_address: "donation.pool:3333".to_string(),
+            wallet_address: "donation wallet".to_string(),
+            pool_password: "x".to_string(),
+        })
+        .build()
+        .unwrap();
+    assert_eq!(
+        config.active_pool_conf(true).pool_address,
+        "donation.pool:3333"
+    );
+    assert_eq!(
+        config.active_pool_conf(true).wallet_address,
+        "donation wallet"
+    );
+    assert_eq!(
+        config.active_pool_conf(false).pool_address,
+        "localhost:3334"
+    );
+}
+
+#[test]
+fn test_fatal_error_config() {
+    let default = FatalErrorConfig::default();
+    assert!(default.is_fatal(24));
+    assert!(default.is_fatal(25));
+    assert!(!default.is_fatal(-1));
+
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .fatal_error_codes(vec![-1])
+        .build()
+        .unwrap();
+    assert!(config.fatal_error_conf.is_fatal(-1));
+    assert!(!config.fatal_error_conf.is_fatal(24));
+
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .exit_on_fatal_error(false)
+        .build()
+        .unwrap();
+    assert!(!config.fatal_error_conf.is_fatal(24));
+}
+#[test]
+fn test_builder_ema_alpha() {
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .metric_ema_alpha(0.5)
+        .build()
+        .unwrap();
+    //also used with the metric disabled, the EMA hashrate is always printed
+    assert!(!config.metric_conf.enabled);
+    assert_eq!(config.metric_conf.ema_alpha, 0.5);
+
+    let err = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .metric_ema_alpha(0.0)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(
+        err.to_string(),
+        "config error: field 'metric_ema_alpha' has invalid value '0': has to be greater than 0 and at most 1"
+    );
+}
+
+#[test]
+fn test_read_config_ema_alpha() {
+    let path = env::temp_dir().join(format!(
+        "mithril_ema_alpha_config_{}.toml",
+        std::process::id()
+    ));
+    let filename = path.to_str().unwrap();
+    fs::write(&path, mithril_config::EXAMPLE_CONFIG).unwrap();
+    let config = mithril_config::read_config(&path, filename).unwrap();
+    assert_eq!(config.metric_conf.ema_alpha, metric::EMA_ALPHA);
+
+    let invalid = mithril_config::EXAMPLE_CONFIG.replace("ema_alpha = 0.2", "ema_alpha = 1.5");
+    fs::write(&path, invalid).unwrap();
+    match mithril_config::read_config(&path, filename) {
+        Err(ConfigError::InvalidField { field, .. }) => assert_eq!(field, "metric.ema_alpha"),
+        result => panic!("unexpected result {:?}", result.err()),
+    }
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_warm_swap_config() {
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .build()
+        .unwrap();
+    assert!(!config.worker_conf.warm_swap);
+    let config = MithrilConfigBuilder::new()
+        .pool_address("localhost:3334")
+        .wallet("wallet")
+        .warm_swap(true)
+        .build()
+        .unwrap();
+    assert!(config.worker_conf.warm_swap);
+
+    let path = env::temp_dir().join(format!(
+        "mithril_warm_swap_config_{}.toml",
+        std::process::id()
+    ));
+    let filename = path.to_str().unwrap();
+    let enabled = mithril_config::EXAMPLE_CONFIG.replace("warm_swap = false", "warm_swap = true");
+    fs::write(&path, enabled).unwrap();
+    let config = mithril_config::read_config(&path, filename).unwrap();
+    assert!(config.worker_conf.warm_swap);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_write_default_config() {
+    let path = env::temp_dir().join(format!(
+        "mithril_generated_config_{}.toml",
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&path);
+
+    mithril_config::write_default_config(&path).unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        mithril_config::EXAMPLE_CONFIG
+    );
+
+    //an existing config is never overwritten
+    fs::write(&path, "[pool]\n").unwrap();
+    let err = mithril_config::write_default_config(&path).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "[pool]\n");
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_builder_from_json() {
+    let json = r#"{
+        "pool_address": "localhost:3334",
+        "wallet_address": "wallet",
+        "num_threads": 2,
+        "bandit_strategy": "ucb1",
+        "donation_pool": {
+            "pool_address": "localhost:3335",
+            "wallet_address": "donation",
+            "pool_password": "x"
+        },
+        "fatal_error_conf": {"fatal_error_codes": [42]}
+    }"#;
+    let config = MithrilConfigBuilder::from_json(json)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    assert_eq!(config.pool_conf.pool_address, "localhost:3334");
+    assert_eq!(config.worker_conf.num_threads, 2);
+    assert_eq!(config.worker_conf.bandit_strategy, BanditStrategy::Ucb1);
+    assert!(!config.metric_conf.enabled);
+    assert_eq!(
+        config.donation_conf.custom_pool.unwrap().wallet_address,
+        "donation"
+    );
+    assert!(config.fatal_error_conf.is_fatal(42));
+    assert!(!config.fatal_error_conf.is_fatal(24));
+
+    match MithrilConfigBuilder::from_json("{\"num_thread\": 2}") {
+        Err(ConfigError::Json(_)) => {}
+        _ => panic!("unknown field accepted"),
+    }
+}
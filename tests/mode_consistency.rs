@@ -0,0 +1,56 @@
+extern crate mithril;
+
+use mithril::byte_string::{string_to_u8_array, u8_array_to_string};
+use mithril::randomx::memory::VmMemory;
+use mithril::randomx::vm::new_vm;
+use std::sync::Arc;
+
+/// key, input and hash of the test vectors of the RandomX reference implementation
+fn test_vectors() -> Vec<(&'static [u8], Vec<u8>, &'static str)> {
+    vec![
+        (
+            b"test key 000",
+            b"This is a test".to_vec(),
+            "639183aae1bf4c9a35884cb46b09cad9175f04efd7684e7262a0ac1c2f0b4e3f",
+        ),
+        (
+            b"test key 000",
+            b"Lorem ipsum dolor sit amet".to_vec(),
+            "300a0adb47603dedb42228ccb2b211104f4da45af709cd7547cd049e9489c969",
+        ),
+        (
+            b"test key 000",
+            b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua".to_vec(),
+            "c36d4ed4191e617309867ed66a443be4075014e2b061bcdaf9ce7b721d2b77a8",
+        ),
+        (
+            b"test key 001",
+            b"sed do eiusmod tempor incididunt ut labore et dolore magna aliqua".to_vec(),
+            "e9ff4503201c0c2cca26d285c93ae883f9b1d30c9eb240b820756f2d5a7905fc",
+        ),
+        (
+            b"test key 001",
+            string_to_u8_array("0b0b98bea7e805e0010a2126d287a2a0cc833d312cb786385a7c2f9de69d25537f584a9bc9977b00000000666fd8753bf61a8631f12984e3fd44f4014eca629276817b56f32e9b68bd82f416"),
+            "c56414121acda1713c2f2a819d8ae38aed7c80c35c2a769298d34f03833cd5f1",
+        ),
+    ]
+}
+
+#[test]
+fn test_light_and_full_mode_hashes_match() {
+    let vectors = test_vectors();
+    let mut keys: Vec<&[u8]> = vectors.iter().map(|(key, _, _)| *key).collect();
+    keys.dedup();
+
+    //one key at a time, a full dataset takes a few GiB
+    for key in keys {
+        let mut light = new_vm(Arc::new(VmMemory::light(key)));
+        let mut full = new_vm(Arc::new(VmMemory::full(key)));
+        for (_, input, expected) in vectors.iter().filter(|(k, _, _)| *k == key) {
+            let light_hash = light.calculate_hash(input);
+            let full_hash = full.calculate_hash(input);
+            assert_eq!(light_hash, full_hash, "input {:?}", input);
+            assert_eq!(u8_array_to_string(light_hash.as_bytes()), *expected);
+        }
+    }
+}
@@ -1,6 +1,11 @@
 extern crate mithril;
 
-use mithril::randomx::hash::{gen_program_aes_1rx4, gen_program_aes_4rx4};
+#[cfg(target_arch = "x86_64")]
+use mithril::randomx::hash::hash_aes_1rx4_avx512;
+use mithril::randomx::hash::{
+    fill_aes_1rx4_u64, fill_aes_1rx4_u64_alloc, gen_program_aes_1rx4, gen_program_aes_4rx4,
+    hash_aes_1rx4, hash_aes_1rx4_scalar, hash_aes_nrx4, HashAes1rx4Hasher,
+};
 use mithril::randomx::m128::m128i;
 
 #[test]
@@ -110,3 +115,85 @@ fn test_gen_program_aes_4rx4() {
         m128i::from_i32(0x3f7fdb2f, 0x565cd0c7, 0xbe72f8e3, 0x5da409a1)
     );
 }
+
+#[test]
+fn test_gen_program_aes_4rx4_misaligned_size() {
+    let input = [m128i::zero(); 4];
+
+    assert_eq!(gen_program_aes_4rx4(&input, 8).len(), 8);
+    assert!(gen_program_aes_4rx4(&input, 6).is_empty());
+    assert!(gen_program_aes_4rx4(&input, 137).is_empty());
+}
+
+#[cfg(target_arch = "x86_64")]
+#[test]
+fn test_hash_aes_1rx4_avx512_matches_scalar() {
+    if !(is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("vaes")) {
+        return;
+    }
+    let input = scratchpad_input(256);
+
+    let scalar = hash_aes_1rx4_scalar(&input);
+    let avx512 = unsafe { hash_aes_1rx4_avx512(&input) };
+    assert_eq!(scalar, avx512);
+    assert_eq!(hash_aes_1rx4(&input), scalar);
+}
+
+#[test]
+fn test_hash_aes_1rx4_hasher_matches_one_shot() {
+    let input = scratchpad_input(1024);
+
+    let mut hasher = HashAes1rx4Hasher::new();
+    //512 byte chunks
+    for chunk in input.chunks(64) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finalize(), hash_aes_1rx4_scalar(&input));
+
+    let mut uneven = HashAes1rx4Hasher::new();
+    uneven.update(&input[..8]);
+    uneven.update(&input[8..520]);
+    uneven.update(&input[520..]);
+    assert_eq!(uneven.finalize(), hash_aes_1rx4(&input));
+}
+
+#[test]
+fn test_fill_aes_1rx4_u64_alloc() {
+    let input = [
+        m128i::from_u64(1, 2),
+        m128i::from_u64(3, 4),
+        m128i::from_u64(5, 6),
+        m128i::from_u64(7, 8),
+    ];
+    let mut into = vec![0; 64];
+    fill_aes_1rx4_u64(&input, &mut into);
+
+    let allocated = fill_aes_1rx4_u64_alloc(&input, 64);
+    assert_eq!(allocated.len(), 64);
+    assert_eq!(allocated, into);
+}
+
+#[test]
+fn test_hash_aes_nrx4() {
+    let input = scratchpad_input(256);
+
+    //output of the hash_aes_1rx4 implementation before the const generic refactoring
+    let expected = [
+        m128i::from_u64(0x117306749ba4cd7a, 0xa598fcf24586d49e),
+        m128i::from_u64(0x1a106d845ba5c3ba, 0xe59d238e5d50174b),
+        m128i::from_u64(0xd90f0c9717f2cdf2, 0xeaf42cd9a37c4a6d),
+        m128i::from_u64(0xa659e90099ea0cf4, 0xe6f721642fe20d56),
+    ];
+    assert_eq!(hash_aes_nrx4::<4>(&input), expected);
+    assert_eq!(hash_aes_1rx4(&input), expected);
+    assert_eq!(hash_aes_1rx4_scalar(&input), expected);
+}
+
+//helper
+
+/// `len` pseudo-random u64 values for the scratchpad hash functions
+fn scratchpad_input(len: u64) -> Vec<u64> {
+    (0..len)
+        .map(|i| i.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+        .collect()
+}
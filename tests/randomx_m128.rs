@@ -1,6 +1,9 @@
 extern crate mithril;
 
 use mithril::randomx::m128::{m128d, m128i};
+use mithril::randomx::{capabilities, m128i_soft};
+use std::arch::x86_64::{_mm_aesdec_si128, _mm_aesenc_si128};
+use std::collections::HashSet;
 
 #[test]
 #[allow(overflowing_literals)]
@@ -16,6 +19,35 @@ fn test_m128i_from_u8() {
     assert_eq!(m32, m8);
 }
 
+#[test]
+fn test_soft_aes_constant_state() {
+    //a constant column is unchanged by (Inv)MixColumns, only the S-box is applied
+    assert_eq!(m128i_soft::aesenc(0, 0), 0x63636363636363636363636363636363);
+    assert_eq!(m128i_soft::aesdec(0, 0), 0x52525252525252525252525252525252);
+}
+
+#[test]
+fn test_soft_aes_matches_aes_ni() {
+    if !capabilities::has_aes() {
+        return;
+    }
+    let mut x: u128 = 0x31903876bb7a2914b370f616d6f7e4f3;
+    for _ in 0..1000 {
+        let state = x;
+        x = x
+            .wrapping_mul(0x2545f4914f6cdd1d)
+            .wrapping_add(0x9e3779b97f4a7c15);
+        let key = x;
+        let (m_state, m_key) = (m128i::from(state), m128i::from(key));
+
+        let enc = unsafe { m128i(_mm_aesenc_si128(m_state.0, m_key.0)) };
+        let dec = unsafe { m128i(_mm_aesdec_si128(m_state.0, m_key.0)) };
+        assert_eq!(m128i_soft::aesenc(state, key), u128::from(enc));
+        assert_eq!(m128i_soft::aesdec(state, key), u128::from(dec));
+        assert_eq!(m_state.aesenc(m_key), enc);
+    }
+}
+
 #[test]
 #[allow(overflowing_literals)]
 fn test_m128i_eq() {
@@ -38,6 +70,29 @@ fn test_m128i_to_i64() {
     assert_eq!(p2, 0xb370f616d6f7e4f3);
 }
 
+#[test]
+#[allow(overflowing_literals)]
+fn test_m128i_debug() {
+    let m = m128i::from_i32(0x31903876, 0xbb7a2914, 0xb370f616, 0xd6f7e4f3);
+    assert_eq!(
+        format!("{:?}", m),
+        "m128i(0x31903876, 0xbb7a2914, 0xb370f616, 0xd6f7e4f3)"
+    );
+    assert_eq!(
+        format!("{:?}", m128i::from_i32(0, 1, 0, 0)),
+        "m128i(0x00000000, 0x00000001, 0x00000000, 0x00000000)"
+    );
+}
+
+#[test]
+#[allow(overflowing_literals)]
+fn test_m128i_lower_hex() {
+    let m = m128i::from_i32(0x31903876, 0xbb7a2914, 0xb370f616, 0xd6f7e4f3);
+    assert_eq!(format!("{:x}", m), "31903876bb7a2914b370f616d6f7e4f3");
+    assert_eq!(format!("{}", m), "31903876bb7a2914b370f616d6f7e4f3");
+    assert_eq!(format!("{:x}", m128i::zero()).len(), 32);
+}
+
 #[test]
 #[allow(overflowing_literals)]
 fn test_m128d_eq() {
@@ -168,3 +223,34 @@ fn test_m128_sqrt() {
         m128d::from_u64(0x40d30e573fa3ba8d, 0x40212a610b301fe8)
     );
 }
+
+#[test]
+#[allow(overflowing_literals)]
+fn test_m128i_eq_same_from_i32() {
+    let a = m128i::from_i32(0x31903876, 0xbb7a2914, 0xb370f616, 0xd6f7e4f3);
+    let b = m128i::from_i32(0x31903876, 0xbb7a2914, 0xb370f616, 0xd6f7e4f3);
+    let c = m128i::from_i32(0x31903876, 0xbb7a2914, 0xb370f616, 0xd6f7e4f4);
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
+
+#[test]
+fn test_m128i_u128_conversion() {
+    let u = 0x31903876bb7a2914b370f616d6f7e4f3u128;
+    let m = m128i::from(u);
+    assert_eq!(m.as_i64(), (0x31903876bb7a2914, 0xb370f616d6f7e4f3u64 as i64));
+    assert_eq!(u128::from(m), u);
+}
+
+#[test]
+fn test_m128i_hash_set_dedup() {
+    let mut set = HashSet::new();
+    set.insert(m128i::from_i32(1, 2, 3, 4));
+    set.insert(m128i::from_i32(1, 2, 3, 4));
+    set.insert(m128i::from_u64(0x0000000100000002, 0x0000000300000004));
+    set.insert(m128i::from(5u128));
+
+    assert_eq!(set.len(), 2);
+    assert!(set.contains(&m128i::from_i32(1, 2, 3, 4)));
+    assert!(set.contains(&m128i::from_i32(0, 0, 0, 5)));
+}
@@ -2,7 +2,11 @@ extern crate lazy_static;
 extern crate mithril;
 
 use lazy_static::lazy_static;
-use mithril::randomx::memory::{init_dataset_item, SeedMemory};
+use mithril::randomx::memory::{
+    init_dataset_item, init_dataset_item_pair, CacheMode, CacheStats, MemoryError, SeedMemory,
+    VmMemory, VmMemoryAllocator, DATASET_ITEM_COUNT,
+};
+use std::sync::{Arc, RwLock};
 
 lazy_static! {
     static ref TEST_SEED_MEM: SeedMemory = SeedMemory::new_initialised(b"test key 000");
@@ -15,6 +19,22 @@ fn test_seed_memory_new_initialised() {
     assert_eq!(TEST_SEED_MEM.blocks[262143][127], 0x1f47f056d05cd99b);
 }
 
+#[test]
+fn test_seed_memory_new_initialised_checked() {
+    let seed_mem = SeedMemory::new_initialised_checked(b"test key 001").unwrap();
+    assert_eq!(seed_mem.blocks.len(), 262144);
+    assert_eq!(seed_mem.programs.len(), 8);
+
+    let err = MemoryError::BlockCount {
+        expected: 262144,
+        actual: 131072,
+    };
+    assert_eq!(
+        err.to_string(),
+        "seed memory has 131072 argon2 blocks, expected 262144"
+    );
+}
+
 #[test]
 fn test_init_dataset_item() {
     let item = init_dataset_item(&TEST_SEED_MEM, 0);
@@ -29,3 +49,170 @@ fn test_init_dataset_item() {
     let item = init_dataset_item(&TEST_SEED_MEM, 30000000);
     assert_eq!(item[0], 0x145a5091f7853099);
 }
+
+#[test]
+fn test_init_dataset_item_pair() {
+    //spread over the whole dataset, including the last pair
+    let step = DATASET_ITEM_COUNT as u64 / 997;
+    let mut item_num = 0;
+    while item_num + 1 < DATASET_ITEM_COUNT as u64 {
+        let pair = init_dataset_item_pair(&TEST_SEED_MEM, item_num);
+        assert_eq!(pair[0], init_dataset_item(&TEST_SEED_MEM, item_num));
+        assert_eq!(pair[1], init_dataset_item(&TEST_SEED_MEM, item_num + 1));
+        item_num += step + (item_num % 7);
+    }
+    let last = DATASET_ITEM_COUNT as u64 - 2;
+    let pair = init_dataset_item_pair(&TEST_SEED_MEM, last);
+    assert_eq!(pair[1], init_dataset_item(&TEST_SEED_MEM, last + 1));
+}
+
+#[test]
+fn test_vm_memory_cache_hit_rate() {
+    let vm_memory = VmMemory {
+        seed_memory: SeedMemory::no_memory(),
+        dataset_memory: RwLock::new(vec![None; 2]),
+        mode: CacheMode::FullLazy,
+    };
+    let mut stats = CacheStats::default();
+    assert_eq!(stats.hit_rate(), 0.0);
+
+    let mut reg = [0; 8];
+    vm_memory.dataset_read(0, &mut reg, &mut stats);
+    assert_eq!(stats.hit_rate(), 0.0);
+
+    vm_memory.dataset_read(0, &mut reg, &mut stats);
+    vm_memory.dataset_read(0, &mut reg, &mut stats);
+    vm_memory.dataset_read(64, &mut reg, &mut stats);
+    assert_eq!(stats.hit_rate(), 0.5);
+
+    //the counts of another VM reading the same memory are separate
+    let mut other = CacheStats::default();
+    vm_memory.dataset_read(64, &mut reg, &mut other);
+    assert_eq!(other, CacheStats { hits: 1, misses: 0 });
+    stats.add(other);
+    assert_eq!(stats, CacheStats { hits: 3, misses: 2 });
+}
+
+#[test]
+fn test_dataset_read_light_and_full() {
+    let light = VmMemory::light(b"test key 000");
+    let full = VmMemory::full(b"test key 000");
+    let mut light_stats = CacheStats::default();
+    let mut full_stats = CacheStats::default();
+    //xorshift, fixed seed for reproducible runs
+    let mut x: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    for _ in 0..100 {
+        let offset = (next() % DATASET_ITEM_COUNT as u64) * 64;
+        let mut start = [0; 8];
+        start.iter_mut().for_each(|r| *r = next());
+        let mut regs_a = start;
+        light.dataset_read(offset, &mut regs_a, &mut light_stats);
+        let mut regs_b = start;
+        full.dataset_read(offset, &mut regs_b, &mut full_stats);
+        assert_eq!(regs_a, regs_b, "offset {}", offset);
+
+        //the second read of the full memory is a cache hit
+        let mut regs_b = start;
+        full.dataset_read(offset, &mut regs_b, &mut full_stats);
+        assert_eq!(regs_a, regs_b, "cached offset {}", offset);
+    }
+    assert_eq!(full_stats.hits, 100);
+    assert_eq!(light_stats, CacheStats::default());
+}
+
+#[test]
+fn test_dataset_read_full_preloaded() {
+    let items: Vec<[u64; 8]> = (0..4)
+        .map(|item_num| init_dataset_item(&TEST_SEED_MEM, item_num))
+        .collect();
+    let vm_memory = VmMemory {
+        seed_memory: SeedMemory::no_memory(),
+        dataset_memory: RwLock::new(items.iter().cloned().map(Some).collect()),
+        mode: CacheMode::FullPreloaded,
+    };
+
+    let mut reg = [0; 8];
+    let mut stats = CacheStats::default();
+    vm_memory.dataset_read(3 * 64, &mut reg, &mut stats);
+    assert_eq!(reg, items[3]);
+    vm_memory.dataset_read(3 * 64, &mut reg, &mut stats);
+    assert_eq!(reg, [0; 8]);
+}
+
+#[test]
+#[should_panic(expected = "preloaded dataset item")]
+fn test_dataset_read_full_preloaded_missing_item() {
+    let vm_memory = VmMemory {
+        seed_memory: SeedMemory::no_memory(),
+        dataset_memory: RwLock::new(vec![None; 2]),
+        mode: CacheMode::FullPreloaded,
+    };
+    vm_memory.dataset_read(0, &mut [0; 8], &mut CacheStats::default());
+}
+
+#[test]
+fn test_vm_memory_dataset_prefetch() {
+    let vm_memory = VmMemory {
+        seed_memory: SeedMemory::no_memory(),
+        dataset_memory: RwLock::new(vec![None; 4]),
+        mode: CacheMode::FullLazy,
+    };
+
+    let mut stats = CacheStats::default();
+    vm_memory.dataset_prefetch(64, 0, &mut stats);
+    assert!(vm_memory
+        .dataset_memory
+        .read()
+        .unwrap()
+        .iter()
+        .all(|item| item.is_none()));
+
+    //the lookahead stops at the end of the dataset
+    vm_memory.dataset_prefetch(128, 8, &mut stats);
+    assert_eq!(stats, CacheStats { hits: 0, misses: 2 });
+    {
+        let mem = vm_memory.dataset_memory.read().unwrap();
+        assert!(mem[0].is_none() && mem[1].is_none());
+        assert!(mem[2].is_some() && mem[3].is_some());
+    }
+
+    //the prefetched items are hits, but their prefetch was a miss
+    let mut reg = [0; 8];
+    vm_memory.dataset_read(128, &mut reg, &mut stats);
+    vm_memory.dataset_read(192, &mut reg, &mut stats);
+    vm_memory.dataset_read(0, &mut reg, &mut stats);
+    assert_eq!(stats, CacheStats { hits: 2, misses: 3 });
+
+    let mut expected = init_dataset_item(&vm_memory.seed_memory, 2);
+    for (e, i) in expected
+        .iter_mut()
+        .zip(init_dataset_item(&vm_memory.seed_memory, 3).iter())
+    {
+        *e ^= i;
+    }
+    for (e, i) in expected
+        .iter_mut()
+        .zip(init_dataset_item(&vm_memory.seed_memory, 0).iter())
+    {
+        *e ^= i;
+    }
+    assert_eq!(reg, expected);
+}
+
+#[test]
+fn test_worker_memory_without_numa_shards() {
+    //without a dataset per NUMA node every worker shares `vm_memory`
+    let allocator = VmMemoryAllocator::initial();
+    for worker in 0..4 {
+        assert!(Arc::ptr_eq(
+            &allocator.worker_memory(worker),
+            &allocator.vm_memory
+        ));
+    }
+}
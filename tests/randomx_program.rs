@@ -1,10 +1,15 @@
 extern crate mithril;
 #[macro_use(assert_diff)]
 extern crate difference;
+extern crate proptest;
 
 use mithril::randomx::hash::{gen_program_aes_1rx4, gen_program_aes_4rx4};
 use mithril::randomx::m128::m128i;
-use mithril::randomx::program::{decode_instruction, Opcode, Program, MAX_REG};
+use mithril::randomx::program::{
+    decode_instruction, encode_instruction, DecodeError, Opcode, Program, ProgramError, Store,
+    MAX_REG,
+};
+use proptest::prelude::*;
 
 #[test]
 fn test_decode_instruction_imul_rcp() {
@@ -128,6 +133,171 @@ fn test_decode_program_2_4rx4_and_register_usage() {
     assert_eq!(program.register_usage[7], 254);
 }
 
+#[test]
+#[allow(overflowing_literals)]
+fn test_disassemble_program_from_seed() {
+    let input0 = m128i::from_i32(0xb53a90c9, 0xf56f1bc9, 0x25a4424b, 0x727ab1b2);
+    let input1 = m128i::from_i32(0x70152fd1, 0x377f234d, 0xe8027504, 0xfed70bc4);
+    let input2 = m128i::from_i32(0xae1f977a, 0x841fdb02, 0x85b20930, 0xf22cf15b);
+    let input3 = m128i::from_i32(0x2fd5f11, 0x28e94c44, 0x8a756cec, 0x33c0d189);
+
+    let program = Program::from_seed(&[input0, input1, input2, input3]);
+    let lines = program.disassemble();
+
+    assert_eq!(lines.len(), 256);
+    let mut out = lines.join("\n");
+    out.push('\n');
+    assert_diff!(EXPECTED_OUT_WITH_REGISTER_USAGE_1, &out, "\n", 0);
+}
+
+#[test]
+fn test_validate_random_programs() {
+    //xorshift, fixed seed for reproducible runs
+    let mut x: u64 = 0x2545f4914f6cdd1d;
+    let mut next = move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    for _ in 0..200 {
+        let len = 8 + (next() % 129) as usize;
+        let bytes: Vec<m128i> = (0..len).map(|_| m128i::from_u64(next(), next())).collect();
+        let program = Program::from_bytes(bytes);
+        //the decoder maps every byte pattern into range
+        assert_eq!(program.validate(), Ok(()));
+    }
+}
+
+#[test]
+fn test_validate_rejects_out_of_range() {
+    let program = || Program::from_bytes(gen_test_program_nonce_1000());
+    assert_eq!(program().validate(), Ok(()));
+
+    let mut invalid = program();
+    invalid.program[3].dst = Store::R(15);
+    assert_eq!(
+        invalid.validate(),
+        Err(ProgramError::InvalidRegister {
+            instr: 3,
+            register: "r15".to_string()
+        })
+    );
+
+    let mut invalid = program();
+    invalid.program[7].src = Store::F(4);
+    assert!(invalid.validate().is_err());
+
+    let mut invalid = program();
+    invalid.program[0].src = Store::L1(Box::new(Store::E(0)));
+    assert_eq!(
+        invalid.validate(),
+        Err(ProgramError::InvalidMemoryOperand { instr: 0 })
+    );
+
+    let mut invalid = program();
+    invalid.program[10].target = Some(256);
+    assert_eq!(
+        invalid.validate(),
+        Err(ProgramError::InvalidBranchTarget {
+            instr: 10,
+            target: 256
+        })
+    );
+
+    let mut invalid = program();
+    invalid.entropy.pop();
+    assert_eq!(
+        invalid.validate(),
+        Err(ProgramError::InvalidEntropyLength(15))
+    );
+}
+
+#[test]
+fn test_encode_decode_program() {
+    let bytes = gen_test_program_nonce_1000();
+    let program = Program::from_bytes(bytes.clone());
+
+    let encoded = program.encode();
+    assert_eq!(encoded.len(), bytes.len());
+    assert_eq!(Program::decode(&encoded), Ok(program));
+    //the entropy is copied unchanged
+    assert_eq!(encoded[..8], bytes[..8]);
+}
+
+#[test]
+fn test_encode_instruction_nop() {
+    let mut register_usage = [-1; MAX_REG];
+    //ISWAP_R r3, r3 (op 0x77, dst 3, src 3)
+    let nop = decode_instruction(0x1234_5678_0003_0377, 0, &mut register_usage);
+    assert_eq!(nop.op, Opcode::NOP);
+
+    let decoded = decode_instruction(encode_instruction(&nop), 0, &mut register_usage);
+    assert_eq!(decoded, nop);
+    assert_eq!(register_usage, [-1; MAX_REG]);
+}
+
+#[test]
+fn test_decode_missing_entropy() {
+    let bytes = vec![m128i::zero(); 7];
+    assert_eq!(
+        Program::decode(&bytes),
+        Err(DecodeError::MissingEntropy { len: 7 })
+    );
+}
+
+proptest! {
+    #[test]
+    fn prop_decode_encode_roundtrip(values in prop::collection::vec(any::<(u64, u64)>(), 8..137)) {
+        //the decoder maps every byte pattern into a valid program
+        let bytes: Vec<m128i> = values.iter().map(|&(u1, u0)| m128i::from_u64(u1, u0)).collect();
+        let program = Program::from_bytes(bytes);
+
+        let decoded = Program::decode(&program.encode());
+        prop_assert_eq!(decoded, Ok(program));
+    }
+}
+
+#[test]
+fn test_max_execution_cycles() {
+    let mut program = Program::from_bytes(gen_test_program_nonce_1000());
+    for instr in program.program.iter_mut() {
+        instr.target = None;
+    }
+    assert_eq!(program.max_execution_cycles(), 256);
+
+    program.program[100].target = Some(49);
+    assert_eq!(program.max_execution_cycles(), 256 + 2 * 51);
+
+    program.program[200].target = Some(-1);
+    assert_eq!(program.max_execution_cycles(), 256 + 2 * 51 + 2 * 201);
+}
+
+#[test]
+fn test_max_execution_cycles_random_programs() {
+    //xorshift, fixed seed for reproducible runs
+    let mut x: u64 = 0x9e3779b97f4a7c15;
+    let mut next = move || {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x
+    };
+    for _ in 0..200 {
+        let seed = [
+            m128i::from_u64(next(), next()),
+            m128i::from_u64(next(), next()),
+            m128i::from_u64(next(), next()),
+            m128i::from_u64(next(), next()),
+        ];
+        let program = Program::from_seed(&seed);
+        let len = program.program.len() as u64;
+        //CBRANCH loops never overlap, so all bodies together are at most the program
+        let cycles = program.max_execution_cycles();
+        assert!(cycles >= len && cycles <= 3 * len, "{} cycles", cycles);
+    }
+}
+
 //helper
 
 #[allow(overflowing_literals)]
@@ -2,7 +2,10 @@ extern crate mithril;
 #[macro_use(assert_diff)]
 extern crate difference;
 
-use mithril::randomx::superscalar::{Blake2Generator, ScProgram};
+use mithril::randomx::memory::superscalar_programs;
+use mithril::randomx::superscalar::{
+	Blake2Generator, Blake2GeneratorState, InvalidProgram, ScInstr, ScOpcode, ScProgram,
+};
 
 #[test]
 fn test_generate_1() {
@@ -12,6 +15,49 @@ fn test_generate_1() {
 	assert_diff!(EXPECTED_SUPERSCALAR_PROG_1, &prog.to_string(), "\n", 0);
 }
 
+#[test]
+fn test_superscalar_programs_sequential() {
+	let key_str = b"test key 000";
+	let programs = superscalar_programs(key_str);
+	assert_eq!(programs.len(), 8);
+	assert_diff!(EXPECTED_SUPERSCALAR_PROG_1, &programs[0].to_string(), "\n", 0);
+
+	let mut gen = Blake2Generator::new(key_str, 0);
+	for program in &programs {
+		assert_eq!(program.to_string(), ScProgram::generate(&mut gen).to_string());
+	}
+
+	//a generator per program index does not give the same programs (no parallel generation)
+	let mut independent_gen = Blake2Generator::new(key_str, 1);
+	let independent = ScProgram::generate(&mut independent_gen);
+	assert_ne!(programs[1].to_string(), independent.to_string());
+}
+
+#[test]
+fn test_blake2_generator_checkpoint_restore() {
+	let key_str = b"test key 000";
+	let mut gen = Blake2Generator::new(key_str, 0);
+	for _ in 0..100 {
+		gen.get_byte();
+	}
+	let state: Blake2GeneratorState = gen.checkpoint();
+	assert_eq!(state.data.len(), 64);
+	let expected: Vec<u32> = (0..100).map(|_| gen.get_u32()).collect();
+
+	let mut restored = Blake2Generator::restore(state.clone());
+	let actual: Vec<u32> = (0..100).map(|_| restored.get_u32()).collect();
+	assert_eq!(expected, actual);
+	assert_eq!(restored.checkpoint(), gen.checkpoint());
+
+	//a restored generator produces the same programs
+	let mut gen = Blake2Generator::new(key_str, 0);
+	let _ = ScProgram::generate(&mut gen);
+	let state = gen.checkpoint();
+	let prog = ScProgram::generate(&mut gen);
+	let restored_prog = ScProgram::generate(&mut Blake2Generator::restore(state));
+	assert_eq!(prog.to_string(), restored_prog.to_string());
+}
+
 #[test]
 fn test_generate_666() {
 	let key_str = b"666";
@@ -29,6 +75,97 @@ fn test_generate_rage() {
 	assert_diff!(EXPECTED_SUPERSCALAR_PROG_RAGE, &prog.to_string(), "\n", 0);
 }
 
+#[test]
+fn test_new_validates_address_reg() {
+	let mut gen = Blake2Generator::new(b"test key 000", 0);
+	let generated = ScProgram::generate(&mut gen);
+	let expected = generated.to_string();
+
+	let prog = ScProgram::new(generated.prog, 7).unwrap();
+	assert_eq!(prog.address_reg, 7);
+	assert_eq!(prog.to_string(), expected);
+
+	match ScProgram::new(Vec::new(), 8) {
+		Err(err) => assert_eq!(err, InvalidProgram { address_reg: 8 }),
+		Ok(_) => panic!("address register 8 accepted"),
+	}
+}
+
+#[test]
+fn test_critical_path_latency() {
+	let mut gen = Blake2Generator::new(b"test key 000", 0);
+	let generated = ScProgram::generate(&mut gen);
+	let cpu_latency = *generated.cpu_latencies.iter().max().unwrap();
+	let critical_path = generated.critical_path_latency();
+	assert!(critical_path > 0);
+	assert!(critical_path as usize <= cpu_latency, "{} {}", critical_path, cpu_latency);
+
+	//IMUL_R r0, r1 (3 cycles) waits for ISUB_R r1, r2 (1 cycle), ISUB_R r3, r4 does not
+	let find = |op: ScOpcode| *generated.prog.iter().find(|i| i.info.op == op).unwrap();
+	let instr = |op: ScOpcode, dst: i32, src: i32| ScInstr { dst, src, ..find(op) };
+	let chain = vec![
+		instr(ScOpcode::ISUB_R, 1, 2),
+		instr(ScOpcode::IMUL_R, 0, 1),
+		instr(ScOpcode::ISUB_R, 3, 4),
+	];
+	assert_eq!(chain[0].latency_cycles(), 1);
+	assert_eq!(chain[1].latency_cycles(), 3);
+	let prog = ScProgram::new(chain, 0).unwrap();
+	assert_eq!(prog.critical_path_latency(), 4);
+}
+
+#[test]
+fn test_estimated_ipc() {
+	let mut gen = Blake2Generator::new(b"estimated ipc", 0);
+	for _ in 0..100 {
+		let program = ScProgram::generate(&mut gen);
+		let ipc = program.estimated_ipc(4);
+		assert!(ipc >= 2.0, "ipc {} on a 4-wide pipeline", ipc);
+		assert!(ipc <= 4.0);
+		assert!(program.estimated_ipc(1) <= 1.0);
+	}
+
+	//ISUB_R r1, r2 and ISUB_R r3, r4 issue together, IMUL_R r0, r1 waits a cycle for r1
+	let generated = ScProgram::generate(&mut gen);
+	let find = |op: ScOpcode| *generated.prog.iter().find(|i| i.info.op == op).unwrap();
+	let instr = |op: ScOpcode, dst: i32, src: i32| ScInstr { dst, src, ..find(op) };
+	let chain = vec![
+		instr(ScOpcode::ISUB_R, 1, 2),
+		instr(ScOpcode::ISUB_R, 3, 4),
+		instr(ScOpcode::IMUL_R, 0, 1),
+	];
+	let prog = ScProgram::new(chain, 0).unwrap();
+	assert_eq!(prog.estimated_ipc(4), 1.5);
+	assert_eq!(prog.estimated_ipc(1), 1.0);
+	assert_eq!(ScProgram::new(Vec::new(), 0).unwrap().estimated_ipc(4), 0.0);
+}
+
+#[test]
+fn test_instruction_count_distribution() {
+	const PROGRAM_COUNT: usize = 1000;
+	const BUCKET_SIZE: usize = 10;
+
+	let mut gen = Blake2Generator::new(b"instruction count", 0);
+	let counts: Vec<usize> = (0..PROGRAM_COUNT)
+		.map(|_| ScProgram::generate(&mut gen).instruction_count())
+		.collect();
+
+	let min = *counts.iter().min().unwrap();
+	let max = *counts.iter().max().unwrap();
+	let mut histogram = vec![0; max / BUCKET_SIZE - min / BUCKET_SIZE + 1];
+	for count in &counts {
+		histogram[count / BUCKET_SIZE - min / BUCKET_SIZE] += 1;
+	}
+	for (i, n) in histogram.iter().enumerate() {
+		let bucket = (min / BUCKET_SIZE + i) * BUCKET_SIZE;
+		println!("{:>4}-{:<4} {:>4} {}", bucket, bucket + BUCKET_SIZE - 1, n, "#".repeat(n / 4));
+	}
+
+	let avg = counts.iter().sum::<usize>() as f64 / PROGRAM_COUNT as f64;
+	println!("average instruction count: {:.1}", avg);
+	assert!(avg > 405.0 && avg < 495.0, "average {} deviates more than 10% from 450", avg);
+}
+
 //helper + testdata
 
 const EXPECTED_SUPERSCALAR_PROG_1: &str = r#"op: IMUL_R, src: 0, dst: 3
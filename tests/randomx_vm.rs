@@ -3,7 +3,9 @@ extern crate mithril;
 
 use self::blake2b_simd::blake2b;
 use mithril::byte_string::{string_to_u8_array, u8_array_to_string};
-use mithril::randomx::common::randomx_reciprocal;
+use mithril::randomx::common::{
+    randomx_reciprocal, RANDOMX_SCRATCHPAD_L1, RANDOMX_SCRATCHPAD_L2, RANDOMX_SCRATCHPAD_L3,
+};
 use mithril::randomx::hash::gen_program_aes_4rx4;
 use mithril::randomx::m128::m128d;
 use mithril::randomx::memory::VmMemory;
@@ -11,8 +13,15 @@ use mithril::randomx::program::{
     a_reg, e_reg, f_reg, r_reg, Instr, Mode, Opcode, Program, Store, REG_NEEDS_DISPLACEMENT,
     REG_NEEDS_DISPLACEMENT_IX,
 };
-use mithril::randomx::vm::{hash_to_m128i_array, new_register, new_vm, Vm};
-use std::sync::Arc;
+use mithril::randomx::vm::{
+    generate_imul_rcp, hash_to_m128i_array, new_register, new_vm, try_new_vm, Vm, VmBuilder,
+    SCRATCHPAD_L1_MASK, SCRATCHPAD_L2_MASK, SCRATCHPAD_L3_MASK, SCRATCHPAD_SIZE,
+};
+#[allow(deprecated)]
+use std::arch::x86_64::{_mm_getcsr, _mm_setcsr};
+use std::io;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
 
 #[allow(overflowing_literals)]
 const IMM32: i32 = 0xc0cb96d2; //3234567890
@@ -121,6 +130,37 @@ fn test_calculate_hash_3_with_full_memory() {
     );
 }
 
+#[test]
+fn test_vm_builder_soft_aes() {
+    let mut vm = VmBuilder::new(Arc::new(VmMemory::light(b"test key 000")))
+        .soft_aes(true)
+        .sanitize_floats(true)
+        .build()
+        .unwrap();
+    //same hash as with AES-NI, the float registers stay finite
+    let result = vm.try_calculate_hash(b"This is a test").unwrap();
+    assert_eq!(
+        "639183aae1bf4c9a35884cb46b09cad9175f04efd7684e7262a0ac1c2f0b4e3f",
+        u8_array_to_string(result.as_bytes())
+    );
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_calculate_hash_restores_mxcsr() {
+    let mut vm = new_vm(Arc::new(VmMemory::light(b"test key 000")));
+    //MXCSR reset value with rounding down, the VM starts every hash rounding to nearest
+    let mxcsr = 0x1F80 | (ROUND_DOWN << 13);
+    unsafe { _mm_setcsr(mxcsr) };
+    //every hash runs CFROUND many times, light memory hashes are too slow for more
+    for i in 0..10u32 {
+        vm.calculate_hash(&i.to_le_bytes());
+        let after = unsafe { _mm_getcsr() };
+        assert_eq!(after, mxcsr, "MXCSR changed by hash {}", i);
+    }
+    unsafe { _mm_setcsr(0x1F80) };
+}
+
 #[test]
 fn test_init_scratchpad() {
     let mut vm = new_test_vm();
@@ -1830,6 +1870,27 @@ fn test_exec_imul_rcp_zero_imm() {
     assert_eq!(vm.reg.r[0], 0x666);
 }
 
+#[test]
+fn test_exec_imul_rcp_power_of_2_imm() {
+    //0x80000000 as unsigned imm32
+    let instr = Instr {
+        op: Opcode::IMUL_RCP,
+        dst: r_reg(0),
+        src: Store::NONE,
+        imm: Some(i32::MIN),
+        unsigned_imm: true,
+        mode: Mode::None,
+        target: None,
+        effect: Vm::exec_imul_rcp,
+    };
+    let mut vm = new_test_vm();
+    vm.reg.r[0] = 0x666;
+
+    instr.execute(&mut vm);
+
+    assert_eq!(vm.reg.r[0], 0x666);
+}
+
 #[test]
 fn test_exec_ixor_m_l1() {
     let instr = Instr {
@@ -2011,8 +2072,153 @@ fn test_randomx_reciprocal() {
     assert_eq!(result, 0xa9f671ed1d69b73c);
 }
 
+#[test]
+fn test_generate_imul_rcp() {
+    assert_eq!(generate_imul_rcp(0xc0cb96d2), 0xa9f671ed1d69b73c);
+    assert_eq!(generate_imul_rcp(3), 0xaaaaaaaaaaaaaaaa);
+    //nop for a zero or power of 2 divisor
+    assert_eq!(generate_imul_rcp(0), 0);
+    assert_eq!(generate_imul_rcp(1), 0);
+    assert_eq!(generate_imul_rcp(1 << 31), 0);
+}
+
+#[test]
+fn test_try_new_vm() {
+    let vm = try_new_vm(Arc::new(VmMemory::no_memory())).unwrap();
+    assert_eq!(vm.scratchpad.len(), 262144);
+    assert!(vm.scratchpad.iter().all(|v| *v == 0));
+}
+
 //helper
 
+#[test]
+fn test_exec_fdiv_m_fsqrt_r_stay_finite() {
+    //no NaN/Inf masking after the instructions: the divisor is masked to a positive
+    //normal number and E registers stay positive, so NaN and Inf cannot come up
+    let fdiv_m = Instr {
+        op: Opcode::FDIV_M,
+        dst: e_reg(0),
+        src: Store::L1(Box::new(r_reg(1))),
+        imm: Some(IMM32),
+        unsigned_imm: false,
+        mode: Mode::None,
+        target: None,
+        effect: Vm::exec_fdiv_m,
+    };
+    let fsqrt_r = Instr {
+        op: Opcode::FSQRT_R,
+        dst: e_reg(0),
+        src: Store::NONE,
+        imm: Some(IMM32),
+        unsigned_imm: false,
+        mode: Mode::None,
+        target: None,
+        effect: Vm::exec_fsqrt_r,
+    };
+    let nan_inputs = [
+        0x7ff8000000000000, //NaN
+        0xfff8000000000001, //negative NaN
+        0x7ff0000000000000, //Inf
+        0xfff0000000000000, //-Inf
+        0x0000000000000000, //0 (division by zero)
+        0x8000000000000000, //-0
+        0x0000000000000001, //subnormal
+    ];
+    for &input in nan_inputs.iter() {
+        let mut vm = new_test_vm();
+        vm.reg.r[1] = 0xFFFFFFFFFFFFE930;
+        vm.reg.e[0] = m128d::from_u64(0x41937f76fede16ee, 0x411b414296ce93b6);
+        vm.config.e_mask[0] = 0x3a0000000005d11a;
+        vm.config.e_mask[1] = 0x39000000001ba31e;
+        vm.scratchpad[0] = input;
+
+        fdiv_m.execute(&mut vm);
+        let (h, l) = vm.reg.e[0].as_f64();
+        assert!(h.is_finite() && h > 0.0, "{:x}: {}", input, h);
+        assert!(l.is_finite() && l > 0.0, "{:x}: {}", input, l);
+
+        fsqrt_r.execute(&mut vm);
+        let (h, l) = vm.reg.e[0].as_f64();
+        assert!(h.is_finite() && h > 0.0, "{:x}: {}", input, h);
+        assert!(l.is_finite() && l > 0.0, "{:x}: {}", input, l);
+    }
+}
+
 fn new_test_vm() -> Vm {
     new_vm(Arc::new(VmMemory::no_memory()))
 }
+
+/// Keeps the first `limit` trace lines and counts all of them
+#[derive(Clone)]
+struct TraceCapture {
+    lines: Arc<Mutex<(Vec<String>, usize)>>,
+    limit: usize,
+    partial: String,
+}
+
+impl Write for TraceCapture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.push_str(&String::from_utf8_lossy(buf));
+        while let Some(ix) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=ix).collect();
+            let mut lines = self.lines.lock().unwrap();
+            if lines.0.len() < self.limit {
+                lines.0.push(line.trim_end().to_string());
+            }
+            lines.1 += 1;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_run_with_trace() {
+    let hash = blake2b("This is a test".as_bytes());
+
+    let mut vm = new_test_vm();
+    let seed = vm.init_scratchpad(&hash_to_m128i_array(&hash));
+    vm.run(&seed);
+    let expected_reg = vm.reg.to_bytes().to_vec();
+
+    let capture = TraceCapture {
+        lines: Arc::new(Mutex::new((Vec::new(), 0))),
+        limit: 4,
+        partial: String::new(),
+    };
+    let mut traced_vm = new_test_vm();
+    traced_vm.enable_trace(Box::new(capture.clone()));
+    let seed = traced_vm.init_scratchpad(&hash_to_m128i_array(&hash));
+    traced_vm.run(&seed);
+    traced_vm.disable_trace();
+
+    //tracing must not change the result
+    assert_eq!(traced_vm.reg.to_bytes().to_vec(), expected_reg);
+
+    let prog = Program::from_seed(&seed);
+    let lines = capture.lines.lock().unwrap();
+    assert_eq!(
+        lines.0[0],
+        "step,opcode,dst_reg_before,src_reg_before,dst_reg_after,memory_address"
+    );
+    assert!(lines.0[1].starts_with(&format!("0,{},", prog.program[0].op)));
+    assert!(lines.0[2].starts_with(&format!("1,{},", prog.program[1].op)));
+    //header + one line per executed instruction (taken branches execute some twice)
+    assert!(lines.1 > 2048 * 256);
+}
+
+const _: () = assert!(RANDOMX_SCRATCHPAD_L1 < RANDOMX_SCRATCHPAD_L2);
+const _: () = assert!(RANDOMX_SCRATCHPAD_L2 < RANDOMX_SCRATCHPAD_L3);
+
+#[test]
+fn test_scratchpad_sizes_consistent() {
+    assert_eq!(SCRATCHPAD_L1_MASK, 0x3ff8);
+    assert_eq!(SCRATCHPAD_L2_MASK, 0x3fff8);
+    assert_eq!(SCRATCHPAD_L3_MASK, 0x1ffff8);
+
+    let vm = new_vm(Arc::new(VmMemory::no_memory()));
+    assert_eq!(vm.scratchpad.len(), SCRATCHPAD_SIZE);
+    assert_eq!(vm.scratchpad.len() * 8, RANDOMX_SCRATCHPAD_L3);
+}
@@ -0,0 +1,152 @@
+#![cfg(target_os = "linux")]
+extern crate libc;
+extern crate mithril;
+
+use mithril::channel::unbounded;
+use mithril::metric;
+use mithril::randomx::memory::VmMemoryAllocator;
+use mithril::sandbox;
+use mithril::stratum::{stratum_data, StratumClient, StratumCmd};
+use mithril::worker::worker_pool;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+const LOGIN_OK: &str = r#"{"id":1,"jsonrpc":"2.0","error":null,"result":{"id":"miner","job":{"blob":"0707","job_id":"job1","target":"711b0d00","seed_hash":"cafe"},"status":"OK"}}"#;
+
+#[test]
+fn test_apply_seccomp_filter() {
+    //the filter can't be removed, so it is installed on a thread of its own
+    let result = thread::spawn(|| {
+        sandbox::apply_seccomp_filter().unwrap();
+
+        //mkdir, reading files and new sockets are not on the allow list
+        let dir = env::temp_dir().join(format!("mithril_sandbox_{}", std::process::id()));
+        let err = fs::create_dir(&dir).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        let err = fs::File::open("/etc/hostname").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        let err = TcpListener::bind("127.0.0.1:0").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+
+        //starting threads is
+        thread::spawn(|| 42).join().unwrap()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(result, 42);
+}
+
+/// What the miner does around the filter, the exit code of the forked child
+fn run_sandboxed_miner_steps(pool_address: String, report_file: &str) -> i32 {
+    let (err_sndr, _err_rcvr) = unbounded();
+    let (action_sndr, _action_rcvr) = unbounded();
+    //the name resolution and the connection happen before the filter is installed
+    let pool_conf = stratum_data::PoolConfig {
+        pool_address,
+        wallet_address: "wallet".to_string(),
+        pool_password: "x".to_string(),
+    };
+    let client = match StratumClient::login(pool_conf, err_sndr, action_sndr) {
+        Ok(client) => client,
+        Err(_) => return 1,
+    };
+    if sandbox::apply_seccomp_filter_to_process().is_err() {
+        return 2;
+    }
+
+    //the report file is rotated before every sample
+    let metric_conf = metric::MetricConfig {
+        enabled: true,
+        resolution: 100,
+        sample_interval_seconds: 1,
+        report_file: report_file.to_string(),
+        max_file_size_bytes: 1,
+        channel_capacity: 16,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (metric_sndr, metric_rcvr) = metric::channel(&metric_conf);
+    let metric = metric::start(metric_conf, metric_rcvr);
+    let pool = match worker_pool::start(
+        1,
+        &client.new_cmd_channel(),
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    ) {
+        Ok(pool) => pool,
+        Err(_) => return 3,
+    };
+    thread::sleep(Duration::from_millis(1500));
+    pool.stop();
+    pool.join();
+    metric.stop();
+    metric.join();
+
+    //the connection of the login is still usable, a new one is not possible
+    let share_result = client.new_cmd_channel().send(StratumCmd::KeepAlive {
+        miner_id: "miner".to_string(),
+    });
+    if share_result.is_err() {
+        return 4;
+    }
+    thread::sleep(Duration::from_millis(100));
+    match TcpStream::connect("127.0.0.1:1") {
+        Err(ref err) if err.kind() == ErrorKind::PermissionDenied => {}
+        _ => return 5,
+    }
+    client.stop();
+    0
+}
+
+#[test]
+fn test_sandboxed_miner_after_login() {
+    let report_file = env::temp_dir().join(format!("mithril_sandbox_{}.csv", std::process::id()));
+    fs::write(&report_file, "timestamp,hashes\n").unwrap();
+    let report_file = report_file.to_str().unwrap().to_string();
+
+    //answers the login, returns the requests after it
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let pool = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut requests = Vec::new();
+        let mut line = String::new();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            if requests.is_empty() && line.contains("login") {
+                writeln!(writer, "{}", LOGIN_OK).unwrap();
+            } else {
+                requests.push(line.clone());
+            }
+            line.clear();
+        }
+        requests
+    });
+
+    //the whole process is filtered, so the steps run in a forked child
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let code = run_sandboxed_miner_steps(format!("localhost:{}", port), &report_file);
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "child killed, status {}", status);
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+
+    let requests = pool.join().unwrap();
+    assert!(requests.iter().any(|request| request.contains("keepalived")));
+    let report = fs::read_to_string(&report_file).unwrap();
+    assert!(report.lines().count() > 0, "no sample written");
+    assert!(!report.starts_with("timestamp"), "report file not rotated");
+    let rotated_file = format!("{}.1", report_file);
+    assert!(fs::metadata(&rotated_file).is_ok());
+    fs::remove_file(report_file).unwrap();
+    fs::remove_file(rotated_file).unwrap();
+}
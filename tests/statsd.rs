@@ -0,0 +1,71 @@
+extern crate mithril;
+
+use mithril::metric;
+use mithril::metric::statsd::{statsd_datagrams, StatsdConfig, StatsdReporter};
+use std::net::UdpSocket;
+use std::time::Duration;
+
+#[test]
+fn test_statsd_datagrams() {
+    assert_eq!(
+        statsd_datagrams("mithril", 1.5, 2, 0),
+        vec![
+            "mithril.hashrate_khs:1.500|g",
+            "mithril.accepted:2|c",
+            "mithril.rejected:0|c"
+        ]
+    );
+}
+
+#[test]
+fn test_statsd_reporter() {
+    let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    let conf = metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: 16,
+        ema_alpha: metric::EMA_ALPHA,
+    };
+    let (sndr, rcvr) = metric::channel(&conf);
+    let m = metric::start(conf, rcvr);
+    let statsd_conf = StatsdConfig {
+        enabled: true,
+        host: server.local_addr().unwrap().to_string(),
+        prefix: "test".to_string(),
+        sample_interval_seconds: 1,
+    };
+    let reporter = StatsdReporter::start(&statsd_conf, &m).unwrap();
+
+    sndr.send_hash_count(5000);
+    m.record_accepted_share();
+    m.record_accepted_share();
+    m.record_rejected_share();
+
+    let mut buf = [0; 512];
+    let datagrams: Vec<String> = (0..3)
+        .map(|_| {
+            let len = server.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        })
+        .collect();
+    let hashrate_khs: f64 = datagrams[0]
+        .strip_prefix("test.hashrate_khs:")
+        .and_then(|value| value.strip_suffix("|g"))
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(hashrate_khs > 0.0, "{}", hashrate_khs);
+    assert_eq!(datagrams[1..], ["test.accepted:2|c", "test.rejected:1|c"]);
+
+    reporter.stop();
+    reporter.join();
+    m.stop();
+    m.join();
+}
@@ -1,15 +1,26 @@
-extern crate crossbeam_channel;
 extern crate mithril;
 extern crate serde;
 extern crate serde_json;
-
-use self::crossbeam_channel::unbounded;
+#[cfg(feature = "websocket")]
+extern crate tungstenite;
+#[cfg(feature = "zeroize")]
+extern crate zeroize;
+
+use mithril::channel::unbounded;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use mithril::byte_string::HexError;
 use mithril::stratum;
 use mithril::stratum::stratum_data;
+use mithril::stratum::ConnectionState;
 
 #[test]
 fn test_ser_submit_json() {
@@ -178,10 +189,13 @@ fn test_parse_line_dispatch_unknown_method() {
 
     let result = rx.recv().unwrap();
 
-    match result {
-        stratum::StratumAction::Error { .. } => assert!(true),
-        _ => assert!(false, "Wrong result returned: {:?}", result),
-    }
+    assert_eq!(
+        result,
+        stratum::StratumAction::Unknown {
+            method: "UNKNOWN".to_string(),
+            params: serde_json::json!({"arg": "unknown"}),
+        }
+    );
 }
 
 #[test]
@@ -294,3 +308,651 @@ fn test_start_tick_thread_shutdown() {
     assert_eq!(stratum::Tick::Stop, result);
     hnd.join().expect("tick thread join");
 }
+
+#[test]
+fn test_complete_pending_request() {
+    let pending_requests = Arc::new(Mutex::new(HashMap::new()));
+    pending_requests.lock().unwrap().insert(
+        7,
+        stratum::PendingRequest {
+            submitted_at: Instant::now(),
+            nonce: 0x2a,
+            retry_attempt: 0,
+        },
+    );
+
+    let other = r#"{"id":8,"jsonrpc":"2.0","error":null,"result":{"status":"OK"}}"#;
+    assert!(stratum::complete_pending_request(other, &pending_requests).is_none());
+    assert_eq!(pending_requests.lock().unwrap().len(), 1);
+
+    let line = r#"{"id":7,"jsonrpc":"2.0","error":null,"result":{"status":"OK"}}"#;
+    let completed = stratum::complete_pending_request(line, &pending_requests);
+    assert_eq!(completed.unwrap().nonce, 0x2a);
+    assert!(pending_requests.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_complete_pending_request_without_id() {
+    let pending_requests = Arc::new(Mutex::new(HashMap::new()));
+    let line = r#"{"jsonrpc":"2.0","method":"job","params":{}}"#;
+    assert!(stratum::complete_pending_request(line, &pending_requests).is_none());
+}
+
+#[test]
+fn test_is_auth_error() {
+    assert!(stratum::is_auth_error(-1));
+    assert!(stratum::is_auth_error(-32001));
+    assert!(!stratum::is_auth_error(-32600));
+    assert!(!stratum::is_auth_error(-32700));
+    assert!(!stratum::is_auth_error(24));
+    assert!(!stratum::is_auth_error(25));
+}
+
+const LOGIN_REJECTED: &str =
+    r#"{"id":1,"jsonrpc":"2.0","error":{"code":-1,"message":"Unauthenticated"}}"#;
+const LOGIN_OK: &str = r#"{"id":2,"jsonrpc":"2.0","error":null,"result":{"id":"miner","job":{"blob":"0707","job_id":"job1","target":"711b0d00","seed_hash":"cafe"},"status":"OK"}}"#;
+#[cfg(feature = "websocket")]
+const WS_JOB: &str = r#"{"jsonrpc":"2.0","method":"job","params":{"blob":"0808","job_id":"job2","target":"711b0d00","seed_hash":"cafe"}}"#;
+
+/// Answers the login requests with `responses`, then waits for the client to disconnect
+fn start_mock_pool(responses: Vec<&'static str>) -> (String, thread::JoinHandle<usize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let hnd = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut requests = 0;
+        for response in responses {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap() == 0 {
+                break;
+            }
+            requests += 1;
+            writeln!(writer, "{}", response).unwrap();
+        }
+        let mut rest = String::new();
+        while reader.read_line(&mut rest).unwrap_or(0) > 0 {
+            rest.clear();
+        }
+        requests
+    });
+    (address, hnd)
+}
+
+fn pool_config(pool_address: String) -> stratum_data::PoolConfig {
+    stratum_data::PoolConfig {
+        pool_address,
+        wallet_address: "wallet".to_string(),
+        pool_password: "x".to_string(),
+    }
+}
+
+fn hash_of<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[test]
+fn test_pool_config_eq_and_hash() {
+    let pool = pool_config("pool.example:3333".to_string());
+    let same = pool_config("pool.example:3333".to_string());
+    assert_eq!(pool, same);
+    assert_eq!(hash_of(&pool), hash_of(&same));
+
+    let other = pool_config("pool.example:4444".to_string());
+    assert_ne!(pool, other);
+
+    let pools: HashSet<stratum_data::PoolConfig> = vec![pool, same, other].into_iter().collect();
+    assert_eq!(pools.len(), 2);
+}
+
+#[test]
+fn test_pool_config_display() {
+    let mut pool = pool_config("pool.example:3333".to_string());
+    assert_eq!(pool.to_string(), "pool.example:3333 (wallet)");
+
+    pool.wallet_address = "48y3RCT5SzSS4jumHm9rRL91eWWzd6xcVGSCF1KUZGWYJ6npqwFxHee".to_string();
+    assert_eq!(pool.to_string(), "pool.example:3333 (48y3RCT5Sz...)");
+}
+
+#[test]
+fn test_login_with_retry_after_auth_error() {
+    let (address, pool) = start_mock_pool(vec![LOGIN_REJECTED, LOGIN_OK]);
+    let (err_sndr, _err_rcvr) = unbounded();
+    let (action_sndr, action_rcvr) = unbounded();
+
+    let client =
+        stratum::StratumClient::login_with_retry(pool_config(address), 2, err_sndr, action_sndr)
+            .unwrap();
+
+    match client.initial_job() {
+        Some(stratum::StratumJob {
+            miner_id, job_id, ..
+        }) => {
+            assert_eq!(miner_id, "miner");
+            assert_eq!(job_id, "job1");
+        }
+        job => panic!("unexpected initial job {:?}", job),
+    }
+    //only the state changes, the job of the login response is not dispatched
+    assert_eq!(
+        action_rcvr.try_iter().collect::<Vec<_>>(),
+        vec![
+            stratum::StratumAction::StateChange(ConnectionState::Connecting),
+            stratum::StratumAction::StateChange(ConnectionState::LoggedIn)
+        ]
+    );
+    assert_eq!(client.state(), ConnectionState::LoggedIn);
+    assert_eq!(client.next_request_id(), 3);
+    client.stop();
+    assert_eq!(pool.join().unwrap(), 2);
+}
+
+#[test]
+fn test_login_with_retry_gives_up() {
+    let (address, pool) = start_mock_pool(vec![LOGIN_REJECTED, LOGIN_REJECTED]);
+    let (err_sndr, _err_rcvr) = unbounded();
+    let (action_sndr, _action_rcvr) = unbounded();
+
+    let result =
+        stratum::StratumClient::login_with_retry(pool_config(address), 1, err_sndr, action_sndr);
+
+    let err = result.err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(stratum::login_rejected_code(&err), Some(-1));
+    assert_eq!(pool.join().unwrap(), 2);
+}
+
+#[test]
+fn test_login_with_retry_stops_on_ban() {
+    let banned = r#"{"id":1,"jsonrpc":"2.0","error":{"code":24,"message":"Unauthorized worker"}}"#;
+    let (address, pool) = start_mock_pool(vec![banned, LOGIN_OK]);
+    let (err_sndr, _err_rcvr) = unbounded();
+    let (action_sndr, _action_rcvr) = unbounded();
+
+    let result =
+        stratum::StratumClient::login_with_retry(pool_config(address), 3, err_sndr, action_sndr);
+
+    let err = result.err().unwrap();
+    assert_eq!(stratum::login_rejected_code(&err), Some(24));
+    assert_eq!(pool.join().unwrap(), 1);
+}
+
+#[test]
+fn test_stratum_transport_from_url() {
+    assert_eq!(
+        stratum_data::StratumTransport::from_url("xmrpool.eu:3333").unwrap(),
+        stratum_data::StratumTransport::Tcp("xmrpool.eu:3333".to_string())
+    );
+    assert_eq!(
+        pool_config("ws://pool.example:8080/stratum".to_string())
+            .transport()
+            .unwrap(),
+        stratum_data::StratumTransport::WebSocket("ws://pool.example:8080/stratum".to_string())
+    );
+    let err = stratum_data::StratumTransport::from_url("wss://pool.example/stratum").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+#[cfg(not(feature = "websocket"))]
+fn test_websocket_needs_feature() {
+    let (err_sndr, _err_rcvr) = unbounded();
+    let (action_sndr, _action_rcvr) = unbounded();
+    let result = stratum::StratumClient::login(
+        pool_config("ws://127.0.0.1:1".to_string()),
+        err_sndr,
+        action_sndr,
+    );
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::Unsupported);
+}
+
+#[test]
+#[cfg(feature = "websocket")]
+fn test_websocket_host_port() {
+    use mithril::stratum::websocket::host_port;
+    assert_eq!(
+        host_port("ws://pool.example:8080/stratum").unwrap(),
+        "pool.example:8080"
+    );
+    assert_eq!(host_port("ws://pool.example").unwrap(), "pool.example:80");
+    assert_eq!(host_port("ws://[::1]/stratum").unwrap(), "[::1]:80");
+    assert_eq!(host_port("ws://[::1]:3333").unwrap(), "[::1]:3333");
+    assert!(host_port("wss://pool.example").is_err());
+    assert!(host_port("ws:///stratum").is_err());
+}
+
+#[test]
+#[cfg(feature = "websocket")]
+fn test_login_over_websocket() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}/stratum", listener.local_addr().unwrap());
+    let pool = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut ws = tungstenite::accept(stream).unwrap();
+        let login = ws.read().unwrap().into_text().unwrap();
+        let response = LOGIN_OK.replace(r#""id":2"#, r#""id":1"#);
+        ws.send(tungstenite::Message::Text(response)).unwrap();
+        ws.send(tungstenite::Message::Ping(vec![1, 2])).unwrap();
+        ws.send(tungstenite::Message::Text(WS_JOB.to_string()))
+            .unwrap();
+        //until the client closes the connection
+        let mut messages = vec![tungstenite::Message::Text(login)];
+        while let Ok(message) = ws.read() {
+            messages.push(message);
+        }
+        messages
+    });
+    let (err_sndr, _err_rcvr) = unbounded();
+    let (action_sndr, action_rcvr) = unbounded();
+
+    let client = stratum::StratumClient::login(pool_config(url), err_sndr, action_sndr).unwrap();
+
+    match client.initial_job() {
+        Some(stratum::StratumJob { job_id, .. }) => assert_eq!(job_id, "job1"),
+        job => panic!("unexpected initial job {:?}", job),
+    }
+    //the job pushed after the ping arrives as a line of its own
+    loop {
+        match action_rcvr.recv_timeout(Duration::from_secs(5)).unwrap() {
+            stratum::StratumAction::Job { ref job_id, .. } if job_id == "job2" => break,
+            _ => {}
+        }
+    }
+    client
+        .new_cmd_channel()
+        .send(stratum::StratumCmd::KeepAlive {
+            miner_id: "miner".to_string(),
+        })
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    client.stop();
+
+    let messages = pool.join().unwrap();
+    let texts: Vec<String> = messages
+        .iter()
+        .filter_map(|message| match message {
+            tungstenite::Message::Text(text) => Some(text.clone()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(texts.len(), 2, "one frame per request {:?}", texts);
+    assert!(texts[0].contains("login"));
+    assert!(texts[1].contains("keepalived"));
+    assert!(!texts[1].ends_with('\n'));
+    assert!(messages
+        .iter()
+        .any(|message| *message == tungstenite::Message::Pong(vec![1, 2])));
+}
+
+#[test]
+#[cfg(feature = "zeroize")]
+fn test_pool_config_zeroize() {
+    use zeroize::Zeroize;
+    let mut conf = pool_config("localhost:3333".to_string());
+    let copy = conf.clone();
+    conf.zeroize();
+    assert!(conf.pool_address.is_empty());
+    assert!(conf.wallet_address.is_empty());
+    assert!(conf.pool_password.is_empty());
+    assert_eq!(copy.wallet_address, "wallet");
+}
+
+#[test]
+fn test_reconnect_keeps_channels() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    //answers the login on two connections, returns the requests of every connection
+    let pool = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for _ in 0..2 {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            let mut line = String::new();
+            let mut count = 0;
+            while reader.read_line(&mut line).unwrap_or(0) > 0 {
+                if count == 0 {
+                    writeln!(writer, "{}", LOGIN_OK).unwrap();
+                }
+                count += 1;
+                line.clear();
+            }
+            requests.push(count);
+        }
+        requests
+    });
+    let (err_sndr, err_rcvr) = unbounded();
+    let (action_sndr, action_rcvr) = unbounded();
+
+    let mut client =
+        stratum::StratumClient::login(pool_config(address), err_sndr, action_sndr).unwrap();
+    let cmd_sndr = client.new_cmd_channel();
+    thread::sleep(Duration::from_millis(100));
+
+    client.reconnect().unwrap();
+    //the closed connection reported its end
+    assert!(err_rcvr.try_iter().count() > 0);
+    //the job of every login response was dispatched after the state change
+    let actions: Vec<String> = action_rcvr
+        .try_iter()
+        .map(|action| match action {
+            stratum::StratumAction::Job { job_id, .. } => job_id,
+            stratum::StratumAction::StateChange(state) => format!("{:?}", state),
+            action => panic!("unexpected action {:?}", action),
+        })
+        .collect();
+    assert_eq!(
+        actions,
+        vec![
+            "Connecting",
+            "LoggedIn",
+            "job1",
+            "Reconnecting",
+            "LoggedIn",
+            "job1"
+        ]
+    );
+    assert_eq!(client.state(), ConnectionState::LoggedIn);
+    assert_eq!(client.initial_job().unwrap().miner_id, "miner");
+
+    cmd_sndr
+        .send(stratum::StratumCmd::KeepAlive {
+            miner_id: "miner".to_string(),
+        })
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    client.stop();
+    assert_eq!(
+        action_rcvr.try_recv().unwrap(),
+        stratum::StratumAction::StateChange(ConnectionState::Stopped)
+    );
+    assert_eq!(pool.join().unwrap(), vec![1, 2]);
+}
+
+#[test]
+fn test_reconnect_resubmits_shares() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    //closes the first connection after the login, returns the requests of the second
+    //one, which gets no message after the login response
+    let pool = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        writeln!(writer, "{}", LOGIN_OK).unwrap();
+        drop(reader);
+        drop(writer);
+
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = stream;
+        let mut requests = Vec::new();
+        line.clear();
+        while reader.read_line(&mut line).unwrap_or(0) > 0 {
+            if requests.is_empty() {
+                writeln!(writer, "{}", LOGIN_OK).unwrap();
+            }
+            requests.push(line.clone());
+            line.clear();
+        }
+        requests
+    });
+    let (err_sndr, err_rcvr) = unbounded();
+    let (action_sndr, _action_rcvr) = unbounded();
+
+    let mut client =
+        stratum::StratumClient::login(pool_config(address), err_sndr, action_sndr).unwrap();
+    err_rcvr.recv_timeout(Duration::from_secs(5)).unwrap();
+    //the first writes on the closed connection may still succeed
+    let cmd_sndr = client.new_cmd_channel();
+    let start = Instant::now();
+    while client.retry_share_count() == 0 {
+        assert!(start.elapsed() < Duration::from_secs(5), "share not queued");
+        cmd_sndr
+            .send(stratum::StratumCmd::SubmitShare {
+                share: stratum_data::Share {
+                    miner_id: "miner".to_string(),
+                    job_id: "job1".to_string(),
+                    nonce: "2a000000".to_string(),
+                    hash: "00ff10".to_string(),
+                },
+            })
+            .unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    client.reconnect().unwrap();
+    thread::sleep(Duration::from_millis(200));
+    client.stop();
+    let requests = pool.join().unwrap();
+    assert!(requests[0].contains("login"));
+    assert!(
+        requests[1..]
+            .iter()
+            .any(|request| request.contains("submit") && request.contains("2a000000")),
+        "share not re-submitted {:?}",
+        requests
+    );
+}
+
+#[test]
+fn test_keep_alive_due() {
+    let interval = stratum::KEEP_ALIVE_INTERVAL_MS;
+    let last = 1_000;
+    assert!(!stratum::keep_alive_due(last, last, interval));
+    assert!(!stratum::keep_alive_due(
+        last,
+        last + interval - 1,
+        interval
+    ));
+    assert!(stratum::keep_alive_due(last, last + interval, interval));
+    //clock went backwards
+    assert!(!stratum::keep_alive_due(last, last - 500, interval));
+}
+
+#[test]
+fn test_rtt_stats() {
+    use stratum::rtt::{RTT_SAMPLE_COUNT, RTT_WARN_THRESHOLD_MS};
+
+    let mut stats = stratum::RttStats::new();
+    assert_eq!(stats.sample_count(), 0);
+    assert_eq!(stats.avg_rtt_ms(), None);
+    assert!(!stats.is_slow());
+
+    for ms in &[30, 10, 20] {
+        stats.add_sample(Duration::from_millis(*ms));
+    }
+    assert_eq!(stats.min_rtt_ms(), Some(10.0));
+    assert_eq!(stats.avg_rtt_ms(), Some(20.0));
+    assert_eq!(stats.max_rtt_ms(), Some(30.0));
+
+    //only the last samples are kept
+    for _ in 0..RTT_SAMPLE_COUNT {
+        stats.add_sample(Duration::from_millis(300));
+    }
+    assert_eq!(stats.sample_count(), RTT_SAMPLE_COUNT);
+    assert_eq!(stats.min_rtt_ms(), Some(300.0));
+    assert!(stats.avg_rtt_ms().unwrap() > RTT_WARN_THRESHOLD_MS);
+    assert!(stats.is_slow());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_socket_rtt() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut server, _) = listener.accept().unwrap();
+    client.write_all(b"ping\n").unwrap();
+    let mut line = String::new();
+    BufReader::new(&mut server).read_line(&mut line).unwrap();
+
+    let rtt = stratum::rtt::socket_rtt(&client).unwrap();
+    assert!(rtt < Duration::from_secs(1), "loopback rtt {:?}", rtt);
+}
+
+#[test]
+fn test_target_difficulty() {
+    assert_eq!(
+        stratum::target_difficulty("711b0d00"),
+        Ok(0xffffffff / 0x000d1b71)
+    );
+    assert_eq!(stratum::target_difficulty("ffffffff"), Ok(1));
+    assert_eq!(stratum::target_difficulty("00000000"), Ok(0));
+    assert_eq!(
+        stratum::target_difficulty("711b"),
+        Err(HexError::TooShort { len: 4, min: 8 })
+    );
+    assert_eq!(
+        stratum::target_difficulty("711b0d0x"),
+        Err(HexError::InvalidChar { index: 7, c: 'x' })
+    );
+}
+
+#[test]
+fn test_stratum_error_code() {
+    use stratum::StratumErrorCode;
+
+    assert_eq!(
+        StratumErrorCode::from_code(21),
+        StratumErrorCode::LowDifficulty
+    );
+    assert_eq!(
+        StratumErrorCode::from_code(22),
+        StratumErrorCode::DuplicateShare
+    );
+    assert_eq!(
+        StratumErrorCode::from_code(23),
+        StratumErrorCode::InvalidNonce
+    );
+    assert_eq!(
+        StratumErrorCode::from_code(-1),
+        StratumErrorCode::PoolRejected
+    );
+    assert_eq!(
+        StratumErrorCode::from_code(-32601),
+        StratumErrorCode::MethodNotFound
+    );
+    assert_eq!(
+        StratumErrorCode::from_code(42),
+        StratumErrorCode::Unknown(42)
+    );
+    assert_eq!(
+        StratumErrorCode::LowDifficulty.description(),
+        "low difficulty share"
+    );
+}
+
+#[test]
+fn test_warn_pool_error() {
+    let line = r#"{"id":1,"jsonrpc":"2.0","error":{"code":22,"message":"Duplicate share"}}"#;
+    assert_eq!(
+        stratum::warn_pool_error(line),
+        Some(stratum::StratumErrorCode::DuplicateShare)
+    );
+    assert_eq!(
+        stratum::warn_pool_error(
+            r#"{"id":1,"jsonrpc":"2.0","error":null,"result":{"status":"OK"}}"#
+        ),
+        None
+    );
+}
+
+/// Framer reading what `write_chunks` writes to the other end of a local connection
+fn framer_for<F>(write_chunks: F) -> (stratum::StratumFramer, thread::JoinHandle<()>)
+where
+    F: FnOnce(std::net::TcpStream) + Send + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let hnd = thread::spawn(move || write_chunks(server));
+    (stratum::StratumFramer::new(client), hnd)
+}
+
+#[test]
+fn test_framer_split_and_joined_messages() {
+    let (mut framer, hnd) = framer_for(|mut server| {
+        server.write_all(br#"{"id":1,"res"#).unwrap();
+        server.flush().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        server
+            .write_all(b"ult\":null}\r\n{\"id\":2}\n{\"id\"")
+            .unwrap();
+        server.flush().unwrap();
+        thread::sleep(Duration::from_millis(50));
+        server.write_all(b":3}\n").unwrap();
+    });
+
+    assert_eq!(framer.next_message().unwrap(), r#"{"id":1,"result":null}"#);
+    assert_eq!(framer.next_message().unwrap(), r#"{"id":2}"#);
+    assert_eq!(framer.next_message().unwrap(), r#"{"id":3}"#);
+    hnd.join().unwrap();
+    let eof = framer.next_message().unwrap_err();
+    assert_eq!(eof.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_framer_max_message_length() {
+    let (mut framer, hnd) = framer_for(|mut server| {
+        let longest = vec![b'x'; stratum::MAX_STRATUM_MESSAGE_BYTES];
+        server.write_all(&longest).unwrap();
+        server.write_all(b"\n").unwrap();
+        //no newline in sight, must not be buffered without limit
+        let _ = server.write_all(&vec![b'y'; 4 * stratum::MAX_STRATUM_MESSAGE_BYTES]);
+    });
+
+    let message = framer.next_message().unwrap();
+    assert_eq!(message.len(), stratum::MAX_STRATUM_MESSAGE_BYTES);
+    let too_long = framer.next_message().unwrap_err();
+    assert_eq!(too_long.kind(), io::ErrorKind::InvalidData);
+    drop(framer);
+    hnd.join().unwrap();
+}
+
+#[test]
+fn test_retry_share() {
+    let share = stratum_data::Share {
+        miner_id: "old miner".to_string(),
+        job_id: "job1".to_string(),
+        nonce: "2a000000".to_string(),
+        hash: "00ff10".to_string(),
+    };
+    let retry = stratum::RetryShare::from_share(&share).unwrap();
+    assert_eq!(retry.nonce, 0x2a000000);
+    assert_eq!(retry.result, vec![0x00, 0xff, 0x10]);
+    assert_eq!(retry.attempts, 0);
+
+    //re-submitted with the miner id of the new login
+    let resubmitted = retry.to_share("new miner");
+    assert_eq!(resubmitted.miner_id, "new miner");
+    assert_eq!(resubmitted.job_id, share.job_id);
+    assert_eq!(resubmitted.nonce, share.nonce);
+    assert_eq!(resubmitted.hash, share.hash);
+
+    let invalid = stratum_data::Share {
+        hash: "00fg10".to_string(),
+        ..share
+    };
+    assert_eq!(
+        stratum::RetryShare::from_share(&invalid),
+        Err(HexError::InvalidChar { index: 3, c: 'g' })
+    );
+}
+
+#[test]
+fn test_share_response_only_changes_errors() {
+    let err = "error received: Low difficulty share".to_string();
+    assert_eq!(
+        stratum::share_response(stratum::StratumAction::Error { err: err.clone() }),
+        stratum::StratumAction::ShareRejected { err }
+    );
+    assert_eq!(
+        stratum::share_response(stratum::StratumAction::Ok),
+        stratum::StratumAction::Ok
+    );
+}
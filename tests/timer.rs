@@ -1,8 +1,17 @@
+#[cfg(target_os = "linux")]
+extern crate libc;
 extern crate mithril;
 
+use mithril::bandit_tools::BanditStrategy;
+use mithril::channel::unbounded;
+use mithril::metric;
+use mithril::metric::MetricConfig;
 use mithril::mithril_config::DonationConfig;
+use mithril::randomx::memory::VmMemoryAllocator;
 use mithril::timer;
+use mithril::worker::worker_pool;
 use mithril::worker::worker_pool::WorkerConfig;
+use std::time::Duration;
 
 #[test]
 fn test_interval_mod_setup_donation_disabled_auto_tune_enabled() {
@@ -10,9 +19,15 @@ fn test_interval_mod_setup_donation_disabled_auto_tune_enabled() {
         auto_tune: true,
         auto_tune_interval_minutes: 15,
         auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
         num_threads: 8,
     };
-    let donation_conf = DonationConfig { percentage: 0.0 };
+    let donation_conf = DonationConfig {
+        percentage: 0.0,
+        custom_pool: None,
+    };
 
     let (interval, donation_mod) = timer::interval_mod_setup(&worker_conf, &donation_conf);
     assert_eq!(interval, 60 * 15);
@@ -25,10 +40,14 @@ fn test_interval_mod_setup_donation_below_threshold_auto_tune_enabled() {
         auto_tune: true,
         auto_tune_interval_minutes: 15,
         auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
         num_threads: 8,
     };
     let donation_conf = DonationConfig {
         percentage: 1.0 / 10.0 - std::f64::EPSILON,
+        custom_pool: None,
     };
 
     let (interval, donation_mod) = timer::interval_mod_setup(&worker_conf, &donation_conf);
@@ -42,9 +61,15 @@ fn test_interval_mod_setup_donation_disabled_auto_tune_disabled() {
         auto_tune: false,
         auto_tune_interval_minutes: 15,
         auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
         num_threads: 8,
     };
-    let donation_conf = DonationConfig { percentage: 0.0 };
+    let donation_conf = DonationConfig {
+        percentage: 0.0,
+        custom_pool: None,
+    };
 
     let (interval, donation_mod) = timer::interval_mod_setup(&worker_conf, &donation_conf);
     assert_eq!(interval, std::u64::MAX);
@@ -57,9 +82,15 @@ fn test_interval_mod_setup_donation_enabled_auto_tune_disabled() {
         auto_tune: false,
         auto_tune_interval_minutes: 15,
         auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
         num_threads: 8,
     };
-    let donation_conf = DonationConfig { percentage: 2.5 };
+    let donation_conf = DonationConfig {
+        percentage: 2.5,
+        custom_pool: None,
+    };
 
     let (interval, donation_mod) = timer::interval_mod_setup(&worker_conf, &donation_conf);
     assert_eq!(interval, 100 * 60);
@@ -72,9 +103,15 @@ fn test_interval_mod_setup_donation_enabled_auto_tune_enabled() {
         auto_tune: true,
         auto_tune_interval_minutes: 15,
         auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
         num_threads: 8,
     };
-    let donation_conf = DonationConfig { percentage: 2.5 };
+    let donation_conf = DonationConfig {
+        percentage: 2.5,
+        custom_pool: None,
+    };
 
     let (interval, donation_mod) = timer::interval_mod_setup(&worker_conf, &donation_conf);
     assert_eq!(interval, 15 * 60);
@@ -87,11 +124,130 @@ fn test_interval_mod_setup_donation_100_percent_enabled_auto_tune_enabled() {
         auto_tune: true,
         auto_tune_interval_minutes: 15,
         auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
         num_threads: 8,
     };
-    let donation_conf = DonationConfig { percentage: 100.0 };
+    let donation_conf = DonationConfig {
+        percentage: 100.0,
+        custom_pool: None,
+    };
 
     let (interval, donation_mod) = timer::interval_mod_setup(&worker_conf, &donation_conf);
     assert_eq!(interval, 15 * 60);
     assert_eq!(donation_mod, Some(1));
 }
+
+#[test]
+fn test_handle_tick() {
+    let worker_conf = WorkerConfig {
+        auto_tune: false,
+        auto_tune_interval_minutes: 15,
+        auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
+        num_threads: 1,
+    };
+    let donation_conf = DonationConfig {
+        percentage: 0.0,
+        custom_pool: None,
+    };
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric::channel(&MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: metric::DEFAULT_CHANNEL_CAPACITY,
+        ema_alpha: metric::EMA_ALPHA,
+    });
+    let mut pool = worker_pool::start(
+        worker_conf.num_threads,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+    let (clock_sndr, clock_rcvr) = unbounded();
+    timer::setup_with_sender(&worker_conf, &donation_conf, clock_sndr.clone());
+
+    let new_config = WorkerConfig {
+        num_threads: 2,
+        ..worker_conf.clone()
+    };
+    clock_sndr
+        .send(timer::TickAction::Reconfigure { new_config })
+        .unwrap();
+    let tick = clock_rcvr.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(
+        timer::handle_tick(tick, &mut pool),
+        timer::TickOutcome::Continue
+    );
+    assert_eq!(pool.thread_count(), 2);
+
+    //the pool keeps running, the event loop ends the session or switches the pool
+    clock_sndr.send(timer::TickAction::DonationHashing).unwrap();
+    let tick = clock_rcvr.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(
+        timer::handle_tick(tick, &mut pool),
+        timer::TickOutcome::SwitchPool
+    );
+    clock_sndr.send(timer::TickAction::ArmChange).unwrap();
+    let tick = clock_rcvr.recv_timeout(Duration::from_secs(1)).unwrap();
+    assert_eq!(
+        timer::handle_tick(tick, &mut pool),
+        timer::TickOutcome::DrawNewBanditArm
+    );
+    assert_eq!(pool.thread_count(), 2);
+
+    pool.resize(1).unwrap();
+    assert_eq!(pool.thread_count(), 1);
+    assert!(pool.resize(0).is_err());
+
+    pool.stop();
+    pool.join();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn test_reconfigure_on_sighup() {
+    //SIGHUP has to be blocked in every thread, so the steps run in a forked child
+    let pid = unsafe { libc::fork() };
+    assert!(pid >= 0, "fork failed");
+    if pid == 0 {
+        let code = reconfigure_on_sighup_steps();
+        unsafe { libc::_exit(code) };
+    }
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    assert!(libc::WIFEXITED(status), "child killed, status {}", status);
+    assert_eq!(libc::WEXITSTATUS(status), 0);
+}
+
+/// Sends a SIGHUP to the process, the exit code of the forked child
+#[cfg(target_os = "linux")]
+fn reconfigure_on_sighup_steps() -> i32 {
+    let new_config = WorkerConfig {
+        auto_tune: false,
+        auto_tune_interval_minutes: 15,
+        auto_tune_log: "/log/file".to_string(),
+        bandit_strategy: BanditStrategy::AnnealingSoftmax,
+        share_timeout_secs: worker_pool::DEFAULT_SHARE_TIMEOUT_SECS,
+        warm_swap: false,
+        num_threads: 3,
+    };
+    let (clock_sndr, clock_rcvr) = unbounded();
+    let reload = move || Some(new_config.clone());
+    if timer::reconfigure_on_sighup(clock_sndr, reload).is_err() {
+        return 1;
+    }
+    unsafe { libc::kill(libc::getpid(), libc::SIGHUP) };
+    match clock_rcvr.recv_timeout(Duration::from_secs(5)) {
+        Ok(timer::TickAction::Reconfigure { new_config }) if new_config.num_threads == 3 => 0,
+        _ => 2,
+    }
+}
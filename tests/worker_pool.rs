@@ -1,9 +1,34 @@
 #![allow(unknown_lints)]
 #![allow(clippy::unreadable_literal)]
 
+extern crate log;
 extern crate mithril;
+extern crate serde_json;
 
+use mithril::channel::unbounded;
+use mithril::metric;
+use mithril::randomx::memory::{CacheStats, VmMemory, VmMemoryAllocator};
+use mithril::stratum::StratumCmd;
+use mithril::worker::priority::ThreadPriority;
+use mithril::worker::thread_info::cpu_list;
 use mithril::worker::worker_pool;
+use mithril::worker::worker_pool::{ThreadConfig, ThreadStat, WorkerConfig};
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Metric channel of the worker pool, the hash counts are not read
+fn metric_channel() -> (metric::MetricSender, metric::MetricReceiver) {
+    metric::channel(&metric::MetricConfig {
+        enabled: false,
+        resolution: 100,
+        sample_interval_seconds: 60,
+        report_file: "/dev/null".to_string(),
+        max_file_size_bytes: u64::MAX,
+        channel_capacity: metric::DEFAULT_CHANNEL_CAPACITY,
+        ema_alpha: metric::EMA_ALPHA,
+    })
+}
 
 #[test]
 fn test_with_nonce() {
@@ -32,3 +57,597 @@ fn test_job_target_value() {
 fn test_nonce_hex() {
     assert_eq!(worker_pool::nonce_hex(666), "0000029a");
 }
+
+#[test]
+fn test_share_found_event() {
+    let event = worker_pool::ShareFoundEvent::new("job1", 5000, Duration::from_millis(2500));
+    assert_eq!(event.job_id, "job1");
+    assert_eq!(event.hashes_attempted, 5000);
+    assert_eq!(event.solution_time_ms, 2500);
+    assert_eq!(event.hashrate_khs, 2.0);
+}
+
+#[test]
+fn test_share_found_event_zero_duration() {
+    let event = worker_pool::ShareFoundEvent::new("job1", 1, Duration::from_secs(0));
+    assert_eq!(event.hashrate_khs, 0.0);
+}
+
+#[test]
+fn test_thread_priority_default_normal() {
+    assert_eq!(ThreadPriority::default(), ThreadPriority::Normal);
+}
+
+#[test]
+fn test_fork_without_job() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let pool = worker_pool::start(
+        2,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+    assert_eq!(pool.current_job(), None);
+
+    let (fork_share_sndr, _fork_share_rcvr) = unbounded();
+    let forked = pool.fork(&fork_share_sndr).unwrap();
+
+    pool.stop();
+    forked.stop();
+    pool.join();
+    forked.join();
+}
+
+#[test]
+fn test_swap_share_sender() {
+    let (share_sndr, share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let mut pool = worker_pool::start(
+        2,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+    drop(share_sndr);
+
+    let (new_share_sndr, _new_share_rcvr) = unbounded();
+    pool.swap_share_sender(new_share_sndr);
+
+    //the workers dropped their old senders, so the old channel gets disconnected
+    let result = share_rcvr.recv_timeout(Duration::from_secs(5));
+    match result {
+        Err(mithril::channel::RecvTimeoutError::Disconnected) => {}
+        _ => panic!("old share channel still connected"),
+    }
+
+    pool.stop();
+    pool.join();
+}
+
+/// Polls until `cond` holds, returns how long that took
+fn wait_until<F: Fn() -> bool>(cond: F) -> Duration {
+    let start = Instant::now();
+    while !cond() {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "condition not reached"
+        );
+        thread::sleep(Duration::from_micros(100));
+    }
+    start.elapsed()
+}
+
+/// `wait_until` for conditions that need hashes, hashing on light memory is slow in
+/// debug builds
+fn wait_while_hashing<F: Fn() -> bool>(cond: F) {
+    let start = Instant::now();
+    while !cond() {
+        assert!(
+            start.elapsed() < Duration::from_secs(120),
+            "condition not reached"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_pause_resume_latency() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let pool = worker_pool::start(
+        2,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+
+    //no hard latency bound, it fails on loaded machines. The workers only have to
+    //react before the timeout of `wait_until`, in the right order.
+    pool.pause();
+    assert!(pool.is_paused());
+    let pause_latency = wait_until(|| pool.paused_workers() == 2);
+    //paused workers stay paused until `resume`
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(pool.paused_workers(), 2);
+
+    pool.resume();
+    assert!(!pool.is_paused());
+    let resume_latency = wait_until(|| pool.paused_workers() == 0);
+    println!(
+        "pause latency {:?}, resume latency {:?}",
+        pause_latency, resume_latency
+    );
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_stop_while_paused() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let pool = worker_pool::start(
+        2,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+
+    pool.pause();
+    wait_until(|| pool.paused_workers() == 2);
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_worker_panic_recovery() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let mut pool = worker_pool::start(
+        1,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+
+    //a blob shorter than the nonce offset panics in the worker
+    pool.job_change("miner", "", "00", "job", "ffffffff");
+    wait_until(|| pool.panic_count() == 1);
+
+    //the restarted worker still handles commands
+    pool.pause();
+    wait_until(|| pool.paused_workers() == 1);
+    pool.resume();
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_job_change_converges_to_last_job() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let mut pool = worker_pool::start(
+        2,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+
+    //queue all jobs while the workers are paused
+    pool.pause();
+    wait_until(|| pool.paused_workers() == 2);
+    for i in 0..100 {
+        pool.job_change("miner", "", "00", &format!("job {}", i), "ffffffff");
+    }
+    assert_eq!(pool.current_job().unwrap().job_id, "job 99");
+    pool.resume();
+
+    wait_until(|| {
+        pool.thread_stats()
+            .iter()
+            .all(|stat| stat.job_sequence == 100)
+    });
+    //the blob is too short to hash, every started job panics. One panic per worker
+    //means the workers skipped the 99 older jobs.
+    wait_until(|| pool.panic_count() == 2);
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(pool.panic_count(), 2);
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_drop_stops_workers() {
+    let (share_sndr, share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let pool = worker_pool::start(
+        2,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    )
+    .unwrap();
+    drop(share_sndr);
+
+    let start = Instant::now();
+    drop(pool);
+    //the workers dropped their share senders when they exited
+    match share_rcvr.recv_timeout(Duration::from_secs(1)) {
+        Err(mithril::channel::RecvTimeoutError::Disconnected) => {}
+        _ => panic!("workers still running"),
+    }
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_start_headless() {
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let memory = Arc::new(VmMemory::light(b"test key 000"));
+    let pool = worker_pool::start_headless(1, memory, &metric_sndr, &[0; 76]).unwrap();
+
+    //hashing on light memory is slow in debug builds, wait_until would give up too early
+    //the first hash of a job is written to the thread stats at once
+    let start = Instant::now();
+    while pool.thread_stats()[0].hashes_since_last_job == 0 {
+        assert!(start.elapsed() < Duration::from_secs(120), "no hashes");
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(pool.hash_count() > 0);
+    assert_eq!(pool.panic_count(), 0);
+
+    let stats = pool.thread_stats();
+    assert_eq!(stats.len(), 1);
+    assert_eq!(stats[0].thread_id, 0);
+    assert!(stats[0].hashes_since_last_job > 0);
+    assert!(stats[0].last_hash_at.is_some());
+    assert!(stats[0].nonce_range.0 <= stats[0].nonce_range.1);
+    assert_eq!(stats[0].panics, 0);
+
+    //the hashes not written to the thread stats yet are written when the worker stops
+    pool.stop();
+    wait_while_hashing(|| pool.thread_stats()[0].hashes_since_last_job == pool.hash_count());
+    pool.join();
+}
+
+#[test]
+fn test_warm_swap() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let old_seed = "00".repeat(32);
+    let new_seed = "11".repeat(32);
+    let newest_seed = "22".repeat(32);
+    let memory = Arc::new(VmMemory::light(b"test key 000"));
+    let allocator = VmMemoryAllocator::preallocated(old_seed.clone(), memory.clone());
+    let mut pool = worker_pool::start(1, &share_sndr, 100, &metric_sndr, allocator).unwrap();
+    pool.set_warm_swap(true);
+    //every build waits for a message on the gate, it uses the light memory of the test
+    let (gate_sndr, gate_rcvr) = unbounded::<()>();
+    let gate_rcvr = Mutex::new(gate_rcvr);
+    pool.set_shadow_memory_builder(Arc::new(move |seed| {
+        gate_rcvr.lock().unwrap().recv().expect("build gate");
+        VmMemoryAllocator::preallocated(seed, memory.clone())
+    }));
+    let blob = "00".repeat(76);
+    pool.job_change("miner", &old_seed, &blob, "job 1", "ffffffff");
+    wait_while_hashing(|| pool.hash_count() > 0);
+
+    //the worker keeps hashing the old job while the new dataset is built
+    pool.job_change("miner", &new_seed, &blob, "job 2", "ffffffff");
+    assert_eq!(pool.shadow_memory_seed(), Some(new_seed.as_str()));
+    assert_eq!(pool.current_job().unwrap().job_id, "job 2");
+    assert_eq!(pool.vm_memory_allocator.vm_memory_seed, old_seed);
+    assert!(!pool.poll_memory_swap());
+    let hashes = pool.hash_count();
+    wait_while_hashing(|| pool.hash_count() > hashes);
+    assert_eq!(pool.thread_stats()[0].job_sequence, 1);
+
+    //a seed change during the build waits for it instead of starting a second build
+    pool.job_change("miner", &newest_seed, &blob, "job 3", "ffffffff");
+    assert_eq!(pool.shadow_memory_seed(), Some(new_seed.as_str()));
+    assert_eq!(pool.current_job().unwrap().job_id, "job 3");
+    gate_sndr.send(()).unwrap();
+    wait_for_memory_swap(&mut pool);
+    assert_eq!(pool.shadow_memory_seed(), Some(newest_seed.as_str()));
+    assert_eq!(pool.vm_memory_allocator.vm_memory_seed, old_seed);
+
+    gate_sndr.send(()).unwrap();
+    wait_for_memory_swap(&mut pool);
+    assert_eq!(pool.shadow_memory_seed(), None);
+    assert_eq!(pool.vm_memory_allocator.vm_memory_seed, newest_seed);
+    wait_while_hashing(|| pool.thread_stats()[0].job_sequence == 3);
+    assert_eq!(pool.panic_count(), 0);
+
+    pool.stop();
+    pool.join();
+}
+
+/// Polls the pool until the running shadow memory build is done
+fn wait_for_memory_swap(pool: &mut worker_pool::WorkerPool) {
+    let building = pool.shadow_memory_seed().map(str::to_string);
+    let start = Instant::now();
+    while pool.shadow_memory_seed().map(str::to_string) == building {
+        pool.poll_memory_swap();
+        assert!(
+            start.elapsed() < Duration::from_secs(60),
+            "dataset not built"
+        );
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_start_heterogeneous() {
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let memory = Arc::new(VmMemory::light(b"test key 000"));
+    let mut share_rcvrs = Vec::new();
+    let mut configs = Vec::new();
+    for nonce_start in 0..2 {
+        let (share_sndr, share_rcvr) = unbounded();
+        share_rcvrs.push(share_rcvr);
+        configs.push(ThreadConfig {
+            vm_memory: memory.clone(),
+            share_sndr,
+            nonce_start,
+            nonce_stride: 2,
+        });
+    }
+    let mut pool = worker_pool::start_heterogeneous(configs, 100, &metric_sndr).unwrap();
+    //every hash is a share
+    pool.job_change("miner", "seed", &"00".repeat(76), "job", "ffffffff");
+
+    //each worker hashes its own nonces and submits to its own share sender
+    for (worker, share_rcvr) in share_rcvrs.iter().enumerate() {
+        for i in 0..2 {
+            match share_rcvr.recv_timeout(Duration::from_secs(120)) {
+                Ok(StratumCmd::SubmitShare { share }) => {
+                    assert_eq!(share.nonce, worker_pool::nonce_hex(worker as u32 + 2 * i))
+                }
+                other => panic!("unexpected share command {:?}", other),
+            }
+        }
+    }
+    match pool.resize(4) {
+        Err(worker_pool::PoolError::NotResizable) => {}
+        other => panic!("unexpected resize result {:?}", other),
+    }
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_time_since_last_share() {
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let (share_sndr, share_rcvr) = unbounded();
+    let configs = vec![ThreadConfig {
+        vm_memory: Arc::new(VmMemory::light(b"test key 000")),
+        share_sndr,
+        nonce_start: 0,
+        nonce_stride: 1,
+    }];
+    let mut pool = worker_pool::start_heterogeneous(configs, 100, &metric_sndr).unwrap();
+    //counted from the pool start until the first share
+    thread::sleep(Duration::from_millis(1100));
+    assert!(pool.time_since_last_share() >= Duration::from_secs(1));
+
+    //every hash is a share
+    pool.job_change("miner", "seed", &"00".repeat(76), "job", "ffffffff");
+    share_rcvr.recv_timeout(Duration::from_secs(120)).unwrap();
+    wait_while_hashing(|| pool.time_since_last_share() < Duration::from_secs(1));
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_thread_stats_table() {
+    let stats = vec![ThreadStat {
+        thread_id: 3,
+        job_sequence: 2,
+        hashes_since_last_job: 1234,
+        last_hash_at: None,
+        nonce_range: (7, 42),
+        dataset_cache: CacheStats::default(),
+        panics: 1,
+    }];
+    let table = worker_pool::thread_stats_table(&stats);
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines.len(), 2);
+    let columns: Vec<&str> = lines[1].split_whitespace().collect();
+    assert_eq!(columns, vec!["3", "1234", "-", "7-42", "1"]);
+}
+
+#[test]
+fn test_start_headless_blob_too_short() {
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let memory = Arc::new(VmMemory::no_memory());
+    match worker_pool::start_headless(1, memory, &metric_sndr, &[0; 42]) {
+        Err(worker_pool::PoolError::BlobTooShort(42)) => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("pool started with a too short blob"),
+    }
+}
+
+#[test]
+fn test_start_zero_threads() {
+    let (share_sndr, _share_rcvr) = unbounded();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let result = worker_pool::start(
+        0,
+        &share_sndr,
+        100,
+        &metric_sndr,
+        VmMemoryAllocator::initial(),
+    );
+    match result {
+        Err(worker_pool::PoolError::ZeroThreads) => {}
+        _ => panic!("expected ZeroThreads error"),
+    }
+}
+
+#[test]
+fn test_max_safe_threads() {
+    let gib = 1024 * 1024 * 1024;
+    assert_eq!(worker_pool::WorkerPool::max_safe_threads(0), 0);
+    assert_eq!(worker_pool::WorkerPool::max_safe_threads(2 * gib), 0);
+    //2 GiB + 32 MiB dataset, 512 MiB overhead, 2 MiB per scratchpad
+    assert_eq!(worker_pool::WorkerPool::max_safe_threads(3 * gib), 240);
+    assert_eq!(worker_pool::WorkerPool::max_safe_threads(16 * gib), 6896);
+}
+
+#[test]
+fn test_max_threads_for() {
+    let gib = 1024 * 1024 * 1024;
+    //no dataset before the first job and in light mode, 512 MiB overhead
+    let initial = VmMemoryAllocator::initial();
+    assert_eq!(
+        worker_pool::WorkerPool::max_threads_for(3 * gib, &initial),
+        1280
+    );
+    let light =
+        VmMemoryAllocator::preallocated("cafe".to_string(), Arc::new(VmMemory::no_memory()));
+    assert_eq!(
+        worker_pool::WorkerPool::max_threads_for(3 * gib, &light),
+        1280
+    );
+    assert_eq!(worker_pool::WorkerPool::max_threads_for(0, &light), 0);
+}
+
+#[test]
+fn test_parse_mem_available() {
+    let meminfo = "MemTotal:       16316412 kB\nMemFree:         1123456 kB\nMemAvailable:    8158206 kB\nBuffers:          123456 kB\n";
+    assert_eq!(
+        worker_pool::parse_mem_available(meminfo),
+        Some(8158206 * 1024)
+    );
+    assert_eq!(worker_pool::parse_mem_available("MemTotal: 1 kB\n"), None);
+}
+
+#[test]
+fn test_worker_config_from_json() {
+    let json = r#"{"num_threads":2,"auto_tune":true,"auto_tune_interval_minutes":5,"auto_tune_log":"./bandit.log"}"#;
+    let conf: WorkerConfig = serde_json::from_str(json).unwrap();
+    assert_eq!(conf.num_threads, 2);
+    assert!(conf.auto_tune);
+    assert_eq!(conf.auto_tune_interval_minutes, 5);
+    assert_eq!(conf.auto_tune_log, "./bandit.log");
+    assert_eq!(
+        conf.share_timeout_secs,
+        worker_pool::DEFAULT_SHARE_TIMEOUT_SECS
+    );
+
+    let missing_field = r#"{"num_threads":2}"#;
+    assert!(serde_json::from_str::<WorkerConfig>(missing_field).is_err());
+}
+
+/// Keeps the messages of all info records logged in this test binary
+struct CapturingLogger {
+    messages: Mutex<Vec<String>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.messages
+                .lock()
+                .unwrap()
+                .push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    messages: Mutex::new(Vec::new()),
+};
+
+fn capture_logs() -> &'static CapturingLogger {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(log::LevelFilter::Info);
+    });
+    &LOGGER
+}
+
+/// The log line of the worker with `prefix` (other tests log workers too)
+fn worker_setup_line(logger: &CapturingLogger, prefix: &str) -> String {
+    let find = || {
+        logger
+            .messages
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|msg| msg.starts_with(prefix))
+            .cloned()
+    };
+    wait_until(|| find().is_some());
+    find().unwrap()
+}
+
+#[test]
+fn test_worker_setup_log() {
+    let logger = capture_logs();
+    let (metric_sndr, _metric_rcvr) = metric_channel();
+    let memory = Arc::new(VmMemory::no_memory());
+    let mut configs = Vec::new();
+    for nonce_start in 0..2 {
+        let (share_sndr, _share_rcvr) = unbounded();
+        configs.push(ThreadConfig {
+            vm_memory: memory.clone(),
+            share_sndr,
+            nonce_start,
+            nonce_stride: 3,
+        });
+    }
+    let pool = worker_pool::start_heterogeneous(configs, 100, &metric_sndr).unwrap();
+
+    //the last nonce of a stride worker is the last one it hashes
+    worker_setup_line(
+        logger,
+        "spawned worker thread 0: nonce_range=[0..65535], nonce_step=3, affinity=",
+    );
+    let line = worker_setup_line(
+        logger,
+        "spawned worker thread 1: nonce_range=[1..65533], nonce_step=3, affinity=",
+    );
+    if cfg!(target_os = "linux") {
+        let stack = line.rsplit("stack=").next().unwrap();
+        let kb: usize = stack.trim_end_matches("kB").parse().unwrap();
+        assert!(kb > 0, "stack {}", stack);
+        assert!(!line.contains("affinity=unknown"), "{}", line);
+    }
+
+    pool.stop();
+    pool.join();
+}
+
+#[test]
+fn test_cpu_list() {
+    assert_eq!(cpu_list(&[0, 1, 2, 3, 8]), "0-3,8");
+    assert_eq!(cpu_list(&[1, 3, 4]), "1,3-4");
+    assert_eq!(cpu_list(&[5]), "5");
+    assert_eq!(cpu_list(&[]), "");
+}